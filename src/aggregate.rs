@@ -0,0 +1,169 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::hash::{Hash, Hasher};
+
+use crate::clinical_data::{CDEValue, ClinicalDatumVariant, PatientSlice};
+use crate::diff::DiffOptions;
+use crate::metadata::Labels;
+
+/// Statistics of the values of a CDE across an export's current ('cdes')
+/// clinical data
+#[derive(Debug, Default)]
+pub struct CdeStats {
+    values: usize,
+    nulls: usize,
+    /// Hashes of the distinct values, as free text can be long
+    distinct: HashSet<u64>,
+    numbers: usize,
+    sum: f64,
+    min: Option<f64>,
+    max: Option<f64>,
+}
+
+impl CdeStats {
+    fn add(&mut self, value: &CDEValue) {
+        self.values += 1;
+        match value {
+            CDEValue::Null | CDEValue::EmptyString | CDEValue::EmptyRange => self.nulls += 1,
+            value => {
+                let mut hasher = DefaultHasher::new();
+                value.to_string().hash(&mut hasher);
+                self.distinct.insert(hasher.finish());
+            }
+        }
+
+        if let CDEValue::Number(n) = value {
+            self.numbers += 1;
+            self.sum += n;
+            self.min = Some(self.min.map_or(*n, |m| m.min(*n)));
+            self.max = Some(self.max.map_or(*n, |m| m.max(*n)));
+        }
+    }
+
+    /// The fraction of the values that are null or empty
+    pub fn null_rate(&self) -> f64 {
+        match self.values {
+            0 => 0.0,
+            n => self.nulls as f64 / n as f64,
+        }
+    }
+
+    pub fn mean(&self) -> Option<f64> {
+        match self.numbers {
+            0 => None,
+            n => Some(self.sum / n as f64),
+        }
+    }
+}
+
+/// The statistics of each CDE of an export, by code
+#[derive(Debug, Default)]
+pub struct Aggregates {
+    pub cdes: BTreeMap<String, CdeStats>,
+}
+
+impl Aggregates {
+    /// Add the CDEs of the current clinical data of each slice, leaving out
+    /// the forms, sections and CDEs the options ignore
+    pub fn from(slices: impl Iterator<Item=PatientSlice>, options: &DiffOptions) -> Aggregates {
+        let mut aggregates = Aggregates::default();
+
+        for slice in slices {
+            slice.clinical_data()
+                .map(|(_, datum)| datum)
+                .filter(|datum| matches!(datum.variant, ClinicalDatumVariant::CDEs))
+                .for_each(|datum| {
+                    datum.located_cdes()
+                        .filter(|(form, section, cde)| !options.ignores(form) && !options.ignores(section) && !options.ignores(cde.code()))
+                        .for_each(|(_, _, cde)| match aggregates.cdes.get_mut(cde.code()) {
+                            Some(stats) => stats.add(cde.value()),
+                            None => aggregates.cdes.entry(cde.code().to_string()).or_default().add(cde.value()),
+                        });
+                });
+        }
+
+        aggregates
+    }
+}
+
+/// How far the statistics of a CDE can diverge before it's flagged
+#[derive(Debug, Clone, Copy)]
+pub struct Thresholds {
+    /// The most the null rate can change by, as a fraction of the values
+    pub null_rate: f64,
+    /// The most the number of distinct values can change by, relative to the old number
+    pub distinct: f64,
+    /// The most the mean can change by, relative to the old mean
+    pub mean: f64,
+    /// The most the min and max can change by
+    pub tolerance: f64,
+}
+
+/// A statistic of a CDE that diverges between the exports beyond its threshold
+#[derive(Debug)]
+pub struct Divergence {
+    pub code: String,
+    pub statistic: &'static str,
+    pub old: String,
+    pub new: String,
+}
+
+/// Change of new relative to old, which is infinite for a change from 0
+fn relative_change(old: f64, new: f64) -> f64 {
+    match old == 0.0 {
+        true if new == 0.0 => 0.0,
+        true => f64::INFINITY,
+        false => ((new - old) / old).abs(),
+    }
+}
+
+/// The statistics of each CDE that diverge beyond the thresholds, by code
+pub fn compare(old: &Aggregates, new: &Aggregates, thresholds: &Thresholds) -> Vec<Divergence> {
+    let codes = old.cdes.keys().chain(new.cdes.keys()).collect::<BTreeSet<&String>>();
+    let mut divergences = vec![];
+
+    for code in codes {
+        let divergence = |statistic, old: String, new: String| Divergence { code: code.to_string(), statistic, old, new };
+        let (s1, s2) = match (old.cdes.get(code), new.cdes.get(code)) {
+            (Some(s1), Some(s2)) => (s1, s2),
+            (s1, s2) => {
+                let values = |s: Option<&CdeStats>| s.map_or("absent".to_string(), |s| format!("{} values", s.values));
+                divergences.push(divergence("presence", values(s1), values(s2)));
+                continue;
+            }
+        };
+
+        if (s1.null_rate() - s2.null_rate()).abs() > thresholds.null_rate {
+            divergences.push(divergence("null rate", format!("{:.4}", s1.null_rate()), format!("{:.4}", s2.null_rate())));
+        }
+        if relative_change(s1.distinct.len() as f64, s2.distinct.len() as f64) > thresholds.distinct {
+            divergences.push(divergence("distinct values", s1.distinct.len().to_string(), s2.distinct.len().to_string()));
+        }
+
+        let number = |n: Option<f64>| n.map_or("none".to_string(), |n| n.to_string());
+        match (s1.mean(), s2.mean()) {
+            (Some(m1), Some(m2)) if relative_change(m1, m2) <= thresholds.mean => {}
+            (None, None) => {}
+            (m1, m2) => divergences.push(divergence("mean", number(m1), number(m2))),
+        }
+        [("min", s1.min, s2.min), ("max", s1.max, s2.max)].iter().for_each(|(statistic, n1, n2)| match (n1, n2) {
+            (Some(n1), Some(n2)) if (n1 - n2).abs() <= thresholds.tolerance => {}
+            (None, None) => {}
+            (n1, n2) => divergences.push(divergence(statistic, number(*n1), number(*n2))),
+        });
+    }
+
+    divergences
+}
+
+/// Print the statistics that diverge, and how many CDEs were compared
+pub fn print(old: &Aggregates, new: &Aggregates, divergences: &[Divergence], labels: &Labels) {
+    let compared = old.cdes.keys().chain(new.cdes.keys()).collect::<BTreeSet<&String>>().len();
+    let diverging = divergences.iter().map(|d| &d.code).collect::<BTreeSet<&String>>().len();
+
+    if !divergences.is_empty() {
+        println!("  {:<30} {:<16} {:>16} {:>16}", "CDE", "Statistic", labels.old_heading(), labels.new_heading());
+        divergences.iter().for_each(|d| println!("  {:<30} {:<16} {:>16} {:>16}", d.code, d.statistic, d.old, d.new));
+    }
+    println!("Compared the aggregates of {} CDEs, {} diverge", compared, diverging);
+}
@@ -0,0 +1,119 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+use zip::ZipArchive;
+use zip::read::ZipFile;
+use zip::result::ZipError;
+
+use crate::entries::Concatenated;
+use crate::prefetch::PrefetchReader;
+use crate::split::SplitArchive;
+
+/// The zip of an export, opening its entries with a password if they're
+/// encrypted (with ZipCrypto or AES), so exports of PHI needn't be decrypted
+/// to disk to be read
+pub struct Archive<R> {
+    zip: ZipArchive<R>,
+    /// Where it was opened from and how, to open it again
+    path: String,
+    read_ahead: Option<usize>,
+    password: Option<String>,
+    split: bool,
+}
+
+/// The file of an archive, or the parts of a split one
+pub enum ArchiveFile {
+    Whole(File),
+    /// A file read ahead on a thread of its own
+    Prefetched(PrefetchReader),
+    Split(SplitArchive),
+}
+
+impl Read for ArchiveFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            ArchiveFile::Whole(file) => file.read(buf),
+            ArchiveFile::Prefetched(file) => file.read(buf),
+            ArchiveFile::Split(split) => split.read(buf),
+        }
+    }
+}
+
+impl Seek for ArchiveFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            ArchiveFile::Whole(file) => file.seek(pos),
+            ArchiveFile::Prefetched(file) => file.seek(pos),
+            ArchiveFile::Split(split) => split.seek(pos),
+        }
+    }
+}
+
+impl Archive<BufReader<ArchiveFile>> {
+    /// Open the archive at zip_path, or if it's the last part of a split
+    /// archive (eg. export.zip after export.z01, export.z02), all its parts
+    pub fn open(zip_path: &str, password: Option<&str>) -> Result<Archive<BufReader<ArchiveFile>>, Box<dyn Error>> {
+        Archive::open_prefetching(zip_path, password, None)
+    }
+
+    /// Open the archive, reading up to read_ahead bytes ahead of where it's
+    /// read on a thread of its own if it's given (and the archive isn't split)
+    pub fn open_prefetching(zip_path: &str, password: Option<&str>, read_ahead: Option<usize>) -> Result<Archive<BufReader<ArchiveFile>>, Box<dyn Error>> {
+        let file = match SplitArchive::open(zip_path)? {
+            Some(split) => ArchiveFile::Split(split),
+            None => {
+                let file = File::open(Path::new(zip_path)).map_err(|e| format!("Failed opening {}: {}", zip_path, e))?;
+                match read_ahead {
+                    Some(capacity) => ArchiveFile::Prefetched(PrefetchReader::new(file, capacity)?),
+                    None => ArchiveFile::Whole(file),
+                }
+            }
+        };
+        let split = matches!(file, ArchiveFile::Split(_));
+
+        Ok(Archive {
+            zip: ZipArchive::new(BufReader::new(file))?,
+            path: zip_path.to_string(),
+            read_ahead,
+            password: password.map(String::from),
+            split,
+        })
+    }
+
+    /// Read the entries one after another as one, from the archive opened
+    /// again on a thread of its own
+    pub fn concatenated(&self, names: Vec<String>) -> Concatenated {
+        let (path, password, read_ahead) = (self.path.clone(), self.password.clone(), self.read_ahead);
+        Concatenated::new(move || Archive::open_prefetching(&path, password.as_deref(), read_ahead), names)
+    }
+}
+
+impl<R: Read + Seek> Archive<R> {
+    pub fn file_names(&self) -> impl Iterator<Item=&str> {
+        self.zip.file_names()
+    }
+
+    /// Whether the archive is read from the parts of a split one, so the
+    /// offsets of its entries aren't offsets into any one file
+    pub fn split(&self) -> bool {
+        self.split
+    }
+
+    /// Whether an entry needs the password to be read
+    pub fn encrypted(&mut self, name: &str) -> bool {
+        matches!(self.zip.by_name(name), Err(ZipError::UnsupportedArchive(ZipError::PASSWORD_REQUIRED)))
+    }
+
+    pub fn by_name(&mut self, name: &str) -> Result<ZipFile<'_>, Box<dyn Error>> {
+        match (self.encrypted(name), &self.password) {
+            (false, _) => Ok(self.zip.by_name(name)?),
+            (true, None) => Err(format!("{} is encrypted, give its password with --password or DIFFMIG_ZIP_PASSWORD", name).into()),
+            // ZipCrypto only checks a byte of the password, so a wrong one can instead fail the CRC once read
+            (true, Some(password)) => self.zip.by_name_decrypt(name, password.as_bytes()).map_err(|e| match e {
+                ZipError::InvalidPassword => format!("Wrong password for {}", name).into(),
+                e => e.into(),
+            }),
+        }
+    }
+}
@@ -0,0 +1,229 @@
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::env;
+use std::error::Error;
+use std::fs::{self, File};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+/// The most diffs run at once if neither the batch file nor --concurrency gives it
+pub const DEFAULT_CONCURRENCY: usize = 2;
+
+/// The diffs of several registries, run by `diffmig batch`
+///
+/// ```yaml
+/// concurrency: 3
+/// runs:
+///   - old: exports/dm1-old.zip
+///     new: exports/dm1-new.zip
+///     registry_code: DM1
+///     config: dm1.toml
+///   - old: exports/fh-old.zip
+///     new: exports/fh-new.zip
+///     registry_code: FH
+///     args: ["--tolerance", "0.001"]
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Batch {
+    /// The most diffs run at once, unless given by --concurrency
+    pub concurrency: Option<usize>,
+    pub runs: Vec<Run>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Run {
+    pub old: String,
+    pub new: String,
+    pub registry_code: String,
+    /// The config file of the diff [default: ./diffmig.toml if present]
+    pub config: Option<String>,
+    /// Further arguments of the diff, eg. its outputs
+    #[serde(default)]
+    pub args: Vec<String>,
+}
+
+/// The totals of a diff, as its summary file gives them
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunSummary {
+    pub patients: usize,
+    pub differing: usize,
+    pub diffs: usize,
+    pub by_severity: BTreeMap<String, usize>,
+    pub truncated: bool,
+}
+
+/// How a registry's diff went, and where its output and summary were written
+#[derive(Debug, Serialize)]
+pub struct Outcome {
+    pub registry_code: String,
+    pub log: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub summary: Option<RunSummary>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// The combined summary of a batch, written to batch-summary.json
+#[derive(Debug, Serialize)]
+struct BatchSummary<'a> {
+    registries: usize,
+    failed: usize,
+    patients: usize,
+    differing: usize,
+    diffs: usize,
+    by_severity: BTreeMap<String, usize>,
+    runs: &'a [Outcome],
+}
+
+impl Batch {
+    pub fn load(path: &str) -> Result<Batch, Box<dyn Error>> {
+        let text = fs::read_to_string(path).map_err(|e| format!("Failed reading batch {}: {}", path, e))?;
+        let batch = serde_yaml::from_str::<Batch>(&text).map_err(|e| format!("Invalid batch {}: {}", path, e))?;
+
+        // Each run's files are named by its registry code
+        let mut codes = HashSet::new();
+        if let Some(run) = batch.runs.iter().find(|r| !codes.insert(&r.registry_code)) {
+            return Err(format!("Invalid batch {}: registry {} is run more than once", path, run.registry_code).into());
+        }
+
+        Ok(batch)
+    }
+
+    /// Run each diff as a process of its own, at most concurrency at once,
+    /// writing its output and summary to the directory
+    pub fn run(&self, dir: &Path, concurrency: usize, password: Option<&str>, debug: bool) -> Result<Vec<Outcome>, Box<dyn Error>> {
+        fs::create_dir_all(dir).map_err(|e| format!("Failed creating {}: {}", dir.display(), e))?;
+        let exe = env::current_exe()?;
+
+        let next = AtomicUsize::new(0);
+        let outcomes = Mutex::new(Vec::with_capacity(self.runs.len()));
+        thread::scope(|scope| {
+            for _ in 0..concurrency.clamp(1, self.runs.len().max(1)) {
+                scope.spawn(|| {
+                    while let Some(run) = self.runs.get(next.fetch_add(1, Ordering::Relaxed)) {
+                        println!("Diffing {}", run.registry_code);
+                        let outcome = run.run(&exe, dir, password, debug);
+                        match &outcome.error {
+                            Some(e) => println!("Diffing {} failed: {}", run.registry_code, e),
+                            None => println!("Diffed {}", run.registry_code),
+                        }
+                        outcomes.lock().unwrap().push(outcome);
+                    }
+                });
+            }
+        });
+
+        // In the batch file's order, however the runs finished
+        let mut outcomes = outcomes.into_inner().unwrap();
+        outcomes.sort_by_key(|o| self.runs.iter().position(|r| r.registry_code == o.registry_code));
+
+        Ok(outcomes)
+    }
+}
+
+impl Run {
+    fn run(&self, exe: &Path, dir: &Path, password: Option<&str>, debug: bool) -> Outcome {
+        let path = |extension: &str| dir.join(format!("{}.{}", self.registry_code, extension));
+        let (log, summary, manifest) = (path("log"), path("summary.json"), path("run-manifest.json"));
+        let outcome = |summary: Option<RunSummary>, error: Option<String>| Outcome {
+            registry_code: self.registry_code.clone(),
+            log: log.display().to_string(),
+            summary,
+            error,
+        };
+
+        // An earlier batch's summary isn't this run's
+        let _ = fs::remove_file(&summary);
+        match self.spawn(exe, &log, &summary, &manifest, password, debug).and_then(|_| read_summary(&summary)) {
+            Ok(s) => outcome(Some(s), None),
+            Err(e) => outcome(None, Some(e.to_string())),
+        }
+    }
+
+    fn spawn(&self, exe: &Path, log: &Path, summary: &Path, manifest: &Path, password: Option<&str>, debug: bool) -> Result<(), Box<dyn Error>> {
+        let output = File::create(log).map_err(|e| format!("Failed creating {}: {}", log.display(), e))?;
+
+        let mut command = Command::new(exe);
+        command.arg("diff").arg(&self.old).arg(&self.new)
+            .args(["--registry", &self.registry_code, "--old-code", &self.registry_code, "--new-code", &self.registry_code])
+            // Grouped by CDE, so the diff doesn't wait on a prompt
+            .args(["--group-by", "cde"])
+            .arg("--summary-out").arg(summary)
+            // Beside the summary, so the runs don't write over each other's
+            .arg("--run-manifest").arg(manifest)
+            .args(&self.args)
+            .stdin(Stdio::null())
+            .stdout(output.try_clone()?)
+            .stderr(output);
+        if let Some(config) = &self.config {
+            command.args(["--config", config]);
+        }
+        if debug {
+            command.arg("--debug");
+        }
+        // Given by the environment, so it isn't seen in the process list
+        if let Some(password) = password {
+            command.env("DIFFMIG_ZIP_PASSWORD", password);
+        }
+
+        let status = command.status()?;
+        match status.success() {
+            true => Ok(()),
+            false => Err(format!("{}, see {}", status, log.display()).into()),
+        }
+    }
+}
+
+fn read_summary(path: &Path) -> Result<RunSummary, Box<dyn Error>> {
+    let text = fs::read_to_string(path).map_err(|e| format!("Failed reading {}: {}", path.display(), e))?;
+    Ok(serde_json::from_str(&text).map_err(|e| format!("Invalid summary {}: {}", path.display(), e))?)
+}
+
+/// Print the totals of each registry and of the batch, and write them to
+/// batch-summary.json in the directory
+pub fn summarize(outcomes: &[Outcome], dir: &Path) -> Result<(), Box<dyn Error>> {
+    let summaries = outcomes.iter().filter_map(|o| o.summary.as_ref()).collect::<Vec<&RunSummary>>();
+    let mut by_severity = BTreeMap::new();
+    summaries.iter().flat_map(|s| &s.by_severity).for_each(|(severity, count)| *by_severity.entry(severity.clone()).or_insert(0) += count);
+    let batch = BatchSummary {
+        registries: outcomes.len(),
+        failed: outcomes.len() - summaries.len(),
+        patients: summaries.iter().map(|s| s.patients).sum(),
+        differing: summaries.iter().map(|s| s.differing).sum(),
+        diffs: summaries.iter().map(|s| s.diffs).sum(),
+        by_severity,
+        runs: outcomes,
+    };
+
+    println!();
+    println!("{:<16} {:>10} {:>10} {:>12}  By severity", "Registry", "Patients", "Differing", "Differences");
+    outcomes.iter().for_each(|o| match (&o.summary, &o.error) {
+        (Some(s), _) => {
+            let truncated = match s.truncated {
+                true => " (partial)",
+                false => "",
+            };
+            println!("{:<16} {:>10} {:>10} {:>12}  {}{}", o.registry_code, s.patients, s.differing, s.diffs, severities(&s.by_severity), truncated);
+        }
+        (None, error) => println!("{:<16} failed: {}", o.registry_code, error.as_deref().unwrap_or_default()),
+    });
+    println!("{:<16} {:>10} {:>10} {:>12}  {}", "Total", batch.patients, batch.differing, batch.diffs, severities(&batch.by_severity));
+
+    let path = dir.join("batch-summary.json");
+    fs::write(&path, serde_json::to_string_pretty(&batch)?).map_err(|e| format!("Failed writing {}: {}", path.display(), e))?;
+
+    match batch.failed {
+        0 => Ok(()),
+        failed => Err(format!("{} of {} registries failed to diff", failed, batch.registries).into()),
+    }
+}
+
+fn severities(by_severity: &BTreeMap<String, usize>) -> String {
+    by_severity.iter().map(|(severity, count)| format!("{} {}", severity, count)).join(", ")
+}
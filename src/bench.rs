@@ -0,0 +1,136 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::clinical_data::ClinicalDatum;
+use crate::diff::{Diff, DiffOptions};
+use crate::fixture::ClinicalDatumRecord;
+use crate::interner::Interner;
+use crate::migrated_registry::{MigratedRegistry, OnParseError, RecordFilter};
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+static ALLOCATED_BYTES: AtomicU64 = AtomicU64::new(0);
+
+/// The system allocator, counting allocations so benchmarks can report them
+///
+/// Counting is a relaxed atomic add per allocation, which doesn't show up
+/// next to the allocation itself
+pub struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(layout.size() as u64, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(new_size.saturating_sub(layout.size()) as u64, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// A pass over an export, each doing more of the work of a diff than the last
+#[derive(Debug, Clone, Copy)]
+enum Pass {
+    /// Deserialize every record of the registry
+    Parse,
+    /// Deserialize and construct the clinical datum of every record of the registry
+    Construct,
+    /// Read the export into patient slices twice and diff them against each other
+    Diff,
+}
+
+const PASSES: [(Pass, &str); 3] = [
+    (Pass::Parse, "Parse"),
+    (Pass::Construct, "Parse + construct"),
+    (Pass::Diff, "Full diff"),
+];
+
+/// The timings and allocations of the iterations of a pass
+struct Measurement {
+    times: Vec<Duration>,
+    allocations: u64,
+    allocated_bytes: u64,
+}
+
+/// Benchmark each pass over the clinical data of an export, read into memory
+/// first so reading the zip isn't measured
+pub fn run(data: &[u8], registry_code: &str, iterations: usize) {
+    let records = MigratedRegistry::read_array_file_to_records(data).collect::<Vec<String>>();
+    println!("{} records, {} bytes, {} iterations", records.len(), data.len(), iterations);
+    println!("  {:<20} {:>12} {:>12} {:>14} {:>14}", "Pass", "Mean", "Min", "Allocs/iter", "Bytes/iter");
+
+    for (pass, name) in PASSES.iter() {
+        let m = measure(iterations, || match pass {
+            Pass::Parse => parse(&records, registry_code),
+            Pass::Construct => construct(&records, registry_code),
+            Pass::Diff => diff(data, registry_code),
+        });
+
+        let mean = m.times.iter().sum::<Duration>() / iterations as u32;
+        let min = m.times.iter().min().copied().unwrap_or_default();
+        println!("  {:<20} {:>12.3?} {:>12.3?} {:>14} {:>14}",
+            name, mean, min, m.allocations / iterations as u64, m.allocated_bytes / iterations as u64);
+    }
+}
+
+fn measure(iterations: usize, mut f: impl FnMut() -> usize) -> Measurement {
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let allocated_bytes = ALLOCATED_BYTES.load(Ordering::Relaxed);
+
+    let times = (0..iterations).map(|_| {
+        let start = Instant::now();
+        let count = f();
+        let elapsed = start.elapsed();
+        log::debug!("{} items in {:?}", count, elapsed);
+        elapsed
+    }).collect();
+
+    Measurement {
+        times,
+        allocations: ALLOCATIONS.load(Ordering::Relaxed) - allocations,
+        allocated_bytes: ALLOCATED_BYTES.load(Ordering::Relaxed) - allocated_bytes,
+    }
+}
+
+/// Whether a record is of the registry, or of an older export that doesn't give the code
+fn of_registry(record: &ClinicalDatumRecord, registry_code: &str) -> bool {
+    record.fields.registry_code.as_deref().is_none_or(|code| code == registry_code)
+}
+
+fn parse(records: &[String], registry_code: &str) -> usize {
+    records.iter()
+        .filter_map(|text| ClinicalDatumRecord::parse(text).ok())
+        .filter(|record| of_registry(record, registry_code))
+        .count()
+}
+
+fn construct(records: &[String], registry_code: &str) -> usize {
+    let mut interner = Interner::new();
+    records.iter()
+        .filter_map(|text| ClinicalDatumRecord::parse(text).ok())
+        .filter(|record| of_registry(record, registry_code))
+        .filter_map(|record| ClinicalDatum::from(&record, &mut interner, false).ok().flatten())
+        .count()
+}
+
+fn diff(data: &[u8], registry_code: &str) -> usize {
+    let registry = || {
+        let filter = RecordFilter { registry_code: Some(registry_code.to_string()), ..RecordFilter::default() };
+        MigratedRegistry::from(data, filter, OnParseError::Skip, Interner::new(), false)
+    };
+    let options = DiffOptions::default();
+
+    registry().zip(registry())
+        .map(|(old, new)| old.diff(&new, &options).map_or(0, |d| d.len()))
+        .sum()
+}
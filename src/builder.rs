@@ -0,0 +1,173 @@
+use std::collections::HashMap;
+
+use crate::clinical_data::{CDE, CDESVariant, CDEValue, ClinicalDatum, ClinicalDatumVariant, ContextKey, Form, PatientSlice, Section};
+use crate::interner::{Code, Interner};
+
+/// Builds a section of clinical data in memory rather than reading it from
+/// an export, eg. for tests of comparison rules
+///
+/// ```
+/// # use diffmig::builder::SectionBuilder;
+/// # use diffmig::clinical_data::CDEValue;
+/// let section = SectionBuilder::new("sec1")
+///     .cde("CDEAge", CDEValue::Number(32.0))
+///     .cde("CDENote", CDEValue::String("hello".to_string()));
+/// let rows = SectionBuilder::multiple("msec")
+///     .row().cde("CDEM", CDEValue::String("a".to_string()))
+///     .row().cde("CDEM", CDEValue::String("b".to_string()));
+/// ```
+#[derive(Debug)]
+pub struct SectionBuilder {
+    code: String,
+    allow_multiple: bool,
+    rows: Vec<Vec<(String, CDEValue)>>,
+}
+
+impl SectionBuilder {
+    /// A section of a single row of CDEs
+    pub fn new(code: &str) -> SectionBuilder {
+        SectionBuilder { code: code.to_string(), allow_multiple: false, rows: vec![vec![]] }
+    }
+
+    /// A section allowing multiple rows of CDEs, each started with row()
+    pub fn multiple(code: &str) -> SectionBuilder {
+        SectionBuilder { code: code.to_string(), allow_multiple: true, rows: vec![] }
+    }
+
+    /// Start another row of a multiple section, a single section's CDEs all
+    /// being of its one row
+    pub fn row(mut self) -> SectionBuilder {
+        if self.allow_multiple {
+            self.rows.push(vec![]);
+        }
+        self
+    }
+
+    /// Add a CDE to the last row
+    pub fn cde(mut self, code: &str, value: CDEValue) -> SectionBuilder {
+        match self.rows.last_mut() {
+            Some(row) => row.push((code.to_string(), value)),
+            None => self.rows.push(vec![(code.to_string(), value)]),
+        }
+        self
+    }
+
+    fn build(self, index: u32, interner: &mut Interner) -> (Code, Section) {
+        let mut rows = self.rows.into_iter().map(|row| {
+            row.into_iter().enumerate().map(|(i, (code, value))| {
+                let code = interner.intern_cde(&code);
+                (interner.key(&code), CDE::new(code, value, i as u32))
+            }).collect::<HashMap<Code, CDE>>()
+        }).collect::<Vec<HashMap<Code, CDE>>>();
+        let cdes = match self.allow_multiple {
+            true => CDESVariant::Multiple(rows),
+            false => CDESVariant::Single(rows.pop().unwrap_or_default()),
+        };
+
+        let code = interner.intern_section(&self.code);
+        (interner.key(&code), Section::new(code, cdes, index))
+    }
+}
+
+/// Builds a clinical datum in memory rather than reading it from an export,
+/// its forms and sections in the order they're added
+///
+/// ```
+/// # use diffmig::builder::{ClinicalDatumBuilder, SectionBuilder};
+/// # use diffmig::clinical_data::CDEValue;
+/// let datum = ClinicalDatumBuilder::new(1, 10)
+///     .timestamp("2021-03-01T10:00:00")
+///     .section("FormA", SectionBuilder::new("sec1").cde("CDEAge", CDEValue::Number(32.0)))
+///     .build();
+/// ```
+#[derive(Debug)]
+pub struct ClinicalDatumBuilder {
+    id: u32,
+    patient: u32,
+    context_id: Option<u32>,
+    variant: ClinicalDatumVariant,
+    timestamp: Option<String>,
+    forms: Vec<(String, Vec<SectionBuilder>)>,
+}
+
+impl ClinicalDatumBuilder {
+    /// A 'cdes' clinical datum of a patient
+    pub fn new(id: u32, patient: u32) -> ClinicalDatumBuilder {
+        ClinicalDatumBuilder { id, patient, context_id: None, variant: ClinicalDatumVariant::CDEs, timestamp: None, forms: vec![] }
+    }
+
+    pub fn context(mut self, context_id: u32) -> ClinicalDatumBuilder {
+        self.context_id = Some(context_id);
+        self
+    }
+
+    /// Make the datum a 'history' snapshot
+    pub fn history(mut self) -> ClinicalDatumBuilder {
+        self.variant = ClinicalDatumVariant::History;
+        self
+    }
+
+    /// When the datum was last saved (ISO 8601)
+    pub fn timestamp(mut self, timestamp: &str) -> ClinicalDatumBuilder {
+        self.timestamp = Some(timestamp.to_string());
+        self
+    }
+
+    /// Add an empty form, eg. to compare with one that has sections
+    pub fn form(mut self, name: &str) -> ClinicalDatumBuilder {
+        if !self.forms.iter().any(|(n, _)| n == name) {
+            self.forms.push((name.to_string(), vec![]));
+        }
+        self
+    }
+
+    /// Add a section to a form, adding the form if it's new
+    pub fn section(self, form: &str, section: SectionBuilder) -> ClinicalDatumBuilder {
+        let mut builder = self.form(form);
+        if let Some((_, sections)) = builder.forms.iter_mut().find(|(n, _)| n == form) {
+            sections.push(section);
+        }
+        builder
+    }
+
+    pub fn build(self) -> ClinicalDatum {
+        self.build_with(&mut Interner::new())
+    }
+
+    /// Build the datum with an interner, eg. one normalizing keys or giving renames
+    pub fn build_with(self, interner: &mut Interner) -> ClinicalDatum {
+        let forms = self.forms.into_iter().enumerate().map(|(i, (name, sections))| {
+            let sections = sections.into_iter().enumerate()
+                .map(|(j, s)| s.build(j as u32, interner))
+                .collect::<HashMap<Code, Section>>();
+            let name = interner.intern_form(&name);
+            (interner.key(&name), Form::new(name, sections, i as u32))
+        }).collect::<HashMap<Code, Form>>();
+
+        ClinicalDatum::new(self.id, self.patient, self.context_id, self.variant, self.timestamp, forms)
+    }
+}
+
+/// A slice of a patient's clinical data, to compare with another as the diff
+/// does, each datum keyed by its forms as when an export's contexts aren't known
+///
+/// ```
+/// # use diffmig::builder::{slice, ClinicalDatumBuilder, SectionBuilder};
+/// # use diffmig::clinical_data::CDEValue;
+/// # use diffmig::diff::{Diff, DiffOptions};
+/// # use diffmig::report::{DifferenceKind, DifferenceRecord};
+/// let datum = |age: f64| ClinicalDatumBuilder::new(1, 10)
+///     .section("FormA", SectionBuilder::new("sec1").cde("CDEAge", CDEValue::Number(age)))
+///     .build();
+/// let (old, new) = (slice(10, vec![datum(32.0)]), slice(10, vec![datum(33.0)]));
+/// let records = old.diff(&new, &DiffOptions::default()).unwrap_or_default().iter()
+///     .flat_map(|d| d.records())
+///     .collect::<Vec<DifferenceRecord>>();
+/// assert_eq!(records[0].kind, DifferenceKind::Equality);
+/// ```
+pub fn slice(patient: u32, data: impl IntoIterator<Item=ClinicalDatum>) -> PatientSlice {
+    data.into_iter().fold(PatientSlice::from(patient), |mut slice, datum| {
+        slice.add(ContextKey::Forms(datum.proto_context()), datum);
+        slice
+    })
+}
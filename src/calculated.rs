@@ -0,0 +1,35 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, Read};
+
+use crate::cde_definitions;
+use crate::fixture::ParseError;
+
+/// The codes of the calculated CDEs of a CDE definition fixture, whose values
+/// are recomputed by the new system rather than migrated
+pub fn from_definitions(reader: impl Read) -> Result<HashSet<String>, ParseError> {
+    let mut codes = HashSet::new();
+
+    for definition in cde_definitions::read(reader)? {
+        if definition.datatype.as_deref() != Some("calculated") {
+            continue;
+        }
+
+        match definition.code {
+            Some(code) => codes.insert(code),
+            None => return Err(ParseError::new("/fields/code", format!("calculated CDE {} has no code", definition.pk))),
+        };
+    }
+
+    Ok(codes)
+}
+
+/// The codes of a file listing calculated CDEs, one per line, skipping blank
+/// lines and # comments
+pub fn from_file(path: &str) -> io::Result<HashSet<String>> {
+    Ok(fs::read_to_string(path)?.lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(String::from)
+        .collect())
+}
@@ -0,0 +1,54 @@
+use serde::Deserialize;
+use serde_json::Value;
+use std::io::Read;
+
+use crate::fixture::{self, ParseError};
+use crate::migrated_registry::MigratedRegistry;
+
+/// A record of a CDE definition fixture
+#[derive(Debug, Deserialize)]
+struct CDERecord {
+    model: String,
+    pk: Value,
+    #[serde(default)]
+    fields: CDEFields,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct CDEFields {
+    code: Option<String>,
+    datatype: Option<String>,
+    pv_group: Option<String>,
+}
+
+/// The definition of a CDE, of the fields diffmig uses
+#[derive(Debug)]
+pub struct CDEDefinition {
+    /// None if the definition gives no code, whose primary key is then given
+    pub code: Option<String>,
+    pub pk: Value,
+    pub datatype: Option<String>,
+    pub pv_group: Option<String>,
+}
+
+/// The CDE definitions of a CDE definition fixture, skipping its records of other models
+pub fn read(reader: impl Read) -> Result<Vec<CDEDefinition>, ParseError> {
+    let mut definitions = vec![];
+
+    for text in MigratedRegistry::read_array_file_to_records(reader) {
+        let record = fixture::parse_at::<CDERecord>(&text, "")?;
+        if !record.model.ends_with("commondataelement") {
+            continue;
+        }
+
+        // The code is the primary key of CDEs, but some exports give it as a field
+        let code = match (record.fields.code, &record.pk) {
+            (Some(code), _) => Some(code),
+            (None, Value::String(code)) => Some(code.clone()),
+            (None, _) => None,
+        };
+        definitions.push(CDEDefinition { code, pk: record.pk, datatype: record.fields.datatype, pv_group: record.fields.pv_group });
+    }
+
+    Ok(definitions)
+}
@@ -0,0 +1,56 @@
+use std::collections::BTreeSet;
+use std::io::Read;
+
+use crate::clinical_data::ClinicalDatum;
+use crate::fixture::{ClinicalDatumRecord, ParseError};
+use crate::interner::Interner;
+use crate::migrated_registry::MigratedRegistry;
+
+/// The first records of an export, parsed to check it can be diffed and to
+/// estimate how long that will take
+#[derive(Debug, Default)]
+pub struct Sample {
+    pub records: usize,
+    pub bytes: usize,
+    pub parse_errors: Vec<ParseError>,
+    /// The "form/section/cde" paths used by the sampled clinical data of the registry
+    pub definitions: BTreeSet<String>,
+}
+
+impl Sample {
+    pub fn from(reader: impl Read, registry_code: &str, records: usize) -> Sample {
+        let mut sample = Sample::default();
+        let mut interner = Interner::new();
+
+        MigratedRegistry::read_array_file_to_records(reader).take(records).for_each(|text| {
+            sample.records += 1;
+            sample.bytes += text.len() + 1;
+
+            let datum = ClinicalDatumRecord::parse(&text).and_then(|record| {
+                match &record.fields.registry_code {
+                    Some(code) if code == registry_code => ClinicalDatum::from(&record, &mut interner, false),
+                    _ => Ok(None)
+                }
+            });
+
+            match datum {
+                Ok(Some(datum)) => {
+                    sample.definitions.extend(datum.cde_paths().map(|(f, s, c)| format!("{}/{}/{}", f, s, c)))
+                }
+                Ok(None) => {}
+                Err(e) => sample.parse_errors.push(e),
+            }
+        });
+
+        sample
+    }
+
+    /// The number of records in an export of size bytes, going by the average
+    /// size of the sampled records
+    pub fn estimate_records(&self, size: u64) -> u64 {
+        match self.records {
+            0 => 0,
+            records => size * records as u64 / self.bytes as u64
+        }
+    }
+}
@@ -0,0 +1,713 @@
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
+
+use crate::json_diff::ArrayAlignment;
+use crate::migrated_registry::{Collection, ExportFormat, OnParseError};
+use crate::numbers::NumberLocale;
+use crate::triage::{Disposition, DEFAULT_TRIAGE};
+use crate::report::{Detail, DifferenceKind};
+use crate::text::Collation;
+
+/// Find differences between two registry migrations of the same data
+#[derive(Debug, Parser)]
+#[command(name = "diffmig", version)]
+pub struct Cli {
+    /// Print debug output
+    #[arg(long, global = true)]
+    pub debug: bool,
+
+    /// The password of encrypted (ZipCrypto or AES) exports, best given by the environment variable so it isn't seen in the process list
+    #[arg(long, global = true, env = "DIFFMIG_ZIP_PASSWORD", hide_env_values = true)]
+    pub password: Option<String>,
+
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Find differences between the clinical data of two exports
+    Diff(Box<DiffArgs>),
+    /// Quickly check that two exports look diffable before a long diff
+    Check(CheckArgs),
+    /// Check that every record of an export parses
+    Validate(ValidateArgs),
+    /// Print the structure observed in the first records of an export
+    Inspect(InspectArgs),
+    /// Write an index of where each patient's clinical data is in an export, so explain reads only theirs
+    Index(IndexArgs),
+    /// Diff the exports of several registries listed in a YAML file, a few at a time, and print their combined summary
+    Batch(BatchArgs),
+    /// Print the summary of a report written with --output sqlite:<path>
+    Report(ReportArgs),
+    /// Print the distribution of values of CDEs in an export
+    Histogram(HistogramArgs),
+    /// Print the forms, sections and CDEs of an export's clinical data, with the types and null rates of each CDE's values
+    Schema(SchemaArgs),
+    /// Record what a reviewer decided about a difference of a JSON report
+    Annotate(AnnotateArgs),
+    /// Print a difference of a JSON report with the clinical data it was found in, read back from both exports
+    Explain(ExplainArgs),
+    /// Show which differences of an earlier JSON report were fixed, are new or persist in a later one
+    CompareReports(CompareReportsArgs),
+    /// Time the parsing, construction and diffing of an export's clinical data, and count their allocations
+    Bench(BenchArgs),
+    /// Write a pair of synthetic exports with known differences, and a JSON report of exactly those differences
+    GenFixture(GenFixtureArgs),
+    /// Check that a diff with the config finds random changes injected into a copy of an export
+    Selftest(SelftestArgs),
+    /// Print a completion script for a shell
+    Completions {
+        shell: Shell,
+    },
+}
+
+/// How the differences of a diff are shown
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum GroupBy {
+    /// Each patient's differences as they're found, asking whether to continue
+    Patient,
+    /// Every patient's differences of each CDE once the diff is finished
+    Cde,
+}
+
+/// How often a diff shown patient by patient asks whether to continue
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum PromptEvery {
+    /// After each slice of a patient's clinical data that differs
+    Slice,
+    /// Once all of a differing patient's slices are shown
+    Patient,
+    /// Once at least --prompt-diffs differences are shown
+    NDiffs,
+    /// After the differences of each form
+    Form,
+}
+
+/// A model of the exports that can be compared
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum Model {
+    /// The clinical data fixture
+    Clinical,
+    /// The patients fixture
+    Patients,
+    /// The consent question and value fixtures
+    Consents,
+}
+
+#[derive(Debug, Args)]
+pub struct DiffArgs {
+    #[command(flatten)]
+    pub inputs: Inputs,
+
+    #[command(flatten)]
+    pub comparison: Comparison,
+
+    #[command(flatten)]
+    pub reporting: Reporting,
+}
+
+/// Where the exports are, each falling back to the config when not given
+#[derive(Debug, Args)]
+#[command(next_help_heading = "Inputs")]
+pub struct Inputs {
+    /// The path of the old zip file (the .zip part of a split one), if not set in the config
+    #[arg(env = "DIFFMIG_OLD_ZIP")]
+    pub old_zip: Option<String>,
+
+    /// The path of the new zip file (the .zip part of a split one), if not set in the config
+    #[arg(env = "DIFFMIG_NEW_ZIP")]
+    pub new_zip: Option<String>,
+
+    /// The path of a config file of defaults [default: ./diffmig.toml if present]
+    #[arg(long, env = "DIFFMIG_CONFIG")]
+    pub config: Option<String>,
+
+    /// The registry code whose overrides in the config file and plugin apply
+    #[arg(long, env = "DIFFMIG_REGISTRY")]
+    pub registry: Option<String>,
+
+    /// Only compare the clinical data of this registry code in the old zip
+    #[arg(long, value_name = "CODE")]
+    pub old_code: Option<String>,
+
+    /// Only compare the clinical data of this registry code in the new zip
+    #[arg(long, value_name = "CODE")]
+    pub new_code: Option<String>,
+
+    /// The format of the old zip's clinical data, eg. a legacy Mongo RDRF's dump to compare with a Django export
+    #[arg(long, value_enum, default_value = "django")]
+    pub old_format: ExportFormat,
+
+    /// Diff the clinical data of all the entries of each zip matching this glob, read one after another in order of name, for registries that shard it into several files, eg. '*/clinical_data/*.json'
+    #[arg(long, value_name = "GLOB")]
+    pub entry_glob: Option<String>,
+
+    /// The path within the old zip of its clinical data, eg. when one zip bundles the clinical data from before and after the migration [default: where --old-format has it]
+    #[arg(long, value_name = "ENTRY", conflicts_with = "entry_glob")]
+    pub old_entry: Option<String>,
+
+    /// The path within the new zip of its clinical data [default: its rdrf_clinicaldata.json]
+    #[arg(long, value_name = "ENTRY", conflicts_with = "entry_glob")]
+    pub new_entry: Option<String>,
+
+    /// A YAML file mapping the old names of renamed forms, sections and CDEs to their new names
+    #[arg(long, value_name = "FILE")]
+    pub renames: Option<String>,
+
+    /// A CSV file of old_id,new_id lines, giving the new ids of patients whose ids the new system reassigned
+    #[arg(long, value_name = "FILE")]
+    pub patient_map: Option<String>,
+
+    /// Read uncompressed (stored) clinical data straight from a memory map of each zip
+    #[arg(long)]
+    pub mmap: bool,
+
+    /// Read each zip this many megabytes ahead of the parser on a thread of its own, in large reads, eg. from network storage where small reads are slow
+    #[arg(long, value_name = "MB")]
+    pub read_buffer_mb: Option<usize>,
+
+    /// Read, parse and diff both exports on one thread, rather than reading and parsing each export on threads of its own
+    #[arg(long)]
+    pub sequential: bool,
+
+    /// Sort each export's records by patient before they're compared, for exports that aren't ordered by patient id, spilling sorted runs to the temp directory so the export needn't fit in memory
+    #[arg(long)]
+    pub presort: bool,
+
+    /// The megabytes of records sorted in memory at a time by --presort, before they're spilled
+    #[arg(long, value_name = "MB", default_value_t = 256, requires = "presort", value_parser = clap::value_parser!(u64).range(1..))]
+    pub presort_run_mb: u64,
+}
+
+#[derive(Debug, Args)]
+#[command(next_help_heading = "Comparison")]
+pub struct Comparison {
+    /// The models to compare
+    #[arg(long, value_enum, value_delimiter = ',', default_value = "clinical")]
+    pub models: Vec<Model>,
+
+    /// Show the values of identifying patient fields (names, DOB, addresses) in differences
+    #[arg(long)]
+    pub show_identifying: bool,
+
+    /// The most seconds consent first save and last update times can differ by
+    #[arg(long, value_name = "SECONDS", default_value_t = 0)]
+    pub consent_time_tolerance: i64,
+
+    /// Only compare 'cdes' clinical datum variants
+    #[arg(long = "cdes", conflicts_with = "collections")]
+    pub cdes_only: bool,
+
+    /// The clinical data collections to parse and compare
+    #[arg(long, value_enum, value_delimiter = ',', default_values = ["cdes", "history"])]
+    pub collections: Vec<Collection>,
+
+    /// Count and report the records of each side in collections other than 'cdes' and 'history', instead of silently skipping them
+    #[arg(long)]
+    pub strict_collections: bool,
+
+    /// Check that each patient's clinical data is contiguous, pks are unique and history has 'cdes' clinical data, reporting violations of each side
+    #[arg(long)]
+    pub check_integrity: bool,
+
+    /// Also compare the members of each clinical data record that aren't read into its data, eg. fields.django_model, as JSON, so metadata the migration dropped or changed is reported
+    #[arg(long)]
+    pub extra_fields: bool,
+
+    /// The largest difference between numeric CDE values that's considered equal [default: 0.01]
+    #[arg(long, env = "DIFFMIG_TOLERANCE")]
+    pub tolerance: Option<f64>,
+
+    /// Report numeric CDE values, or numbers written as strings, that are equal once rounded to this many decimal places as formatting differences rather than changed values [default: only those that are the same number, eg. "1.50" and 1.5]
+    #[arg(long, value_name = "DECIMALS")]
+    pub formatting_precision: Option<u32>,
+
+    /// Read the old export's numbers stored as strings with the decimal and thousands separators of this locale, so eg. "1,5" with de equals the 1.5 the migration converted it to [default: the config's number_locale]
+    #[arg(long, value_enum, value_name = "LOCALE")]
+    pub number_locale: Option<NumberLocale>,
+
+    /// The code of a form, section or CDE to leave out of the comparison
+    #[arg(long)]
+    pub ignore: Vec<String>,
+
+    /// Compare free text CDEs ignoring line endings, whitespace, HTML markup and entities
+    #[arg(long)]
+    pub normalize_text: bool,
+
+    /// Match forms, sections and CDEs by their codes trimmed and in lower case, reporting codes that only match so as key_case differences
+    #[arg(long)]
+    pub normalize_keys: bool,
+
+    /// A file of calculated CDE codes, one per line, besides those defined as calculated in the exports
+    #[arg(long, value_name = "FILE")]
+    pub calculated_cdes: Option<String>,
+
+    /// Leave calculated CDEs out of the comparison, rather than reporting them as calculated
+    #[arg(long)]
+    pub skip_calculated: bool,
+
+    /// Compare the forms the registry definitions flag as questionnaires or abbreviated, rather than skipping them as not migrated on purpose
+    #[arg(long)]
+    pub compare_skipped_forms: bool,
+
+    /// How string CDEs are collated before they're compared, applied in the order given
+    #[arg(long, value_enum, value_delimiter = ',')]
+    pub collation: Vec<Collation>,
+
+    /// Report clinical data saved earlier in the new migration than in the old
+    #[arg(long)]
+    pub timestamps: bool,
+
+    /// Pair each patient's history snapshots by timestamp, reporting dropped or reordered snapshots and changed sequences of values
+    #[arg(long)]
+    pub history_sequence: bool,
+
+    /// Compare the username, timestamp and context of each pair of history snapshots, so their attribution is checked as well as their forms
+    #[arg(long)]
+    pub history_metadata: bool,
+
+    /// The most seconds the timestamps of paired history snapshots can differ by with --history-metadata
+    #[arg(long, value_name = "SECONDS", default_value_t = 0, requires = "history_metadata")]
+    pub history_time_tolerance: i64,
+
+    /// Check that the rows of multiple sections keep their order, reporting rows that moved or are only on one side rather than the values they changed
+    #[arg(long)]
+    pub row_order: bool,
+
+    /// Match the rows of a multiple section by the value of a key CDE rather than by position, reporting rows that moved apart from changed values
+    #[arg(long, value_name = "SECTION=CDE")]
+    pub row_key: Vec<String>,
+
+    /// Only report and count differences of these kinds, eg. missing,variant for structural problems
+    #[arg(long, value_enum, value_delimiter = ',', value_name = "KINDS", conflicts_with = "exclude_types")]
+    pub only_types: Vec<DifferenceKind>,
+
+    /// Neither report nor count differences of these kinds, eg. equality
+    #[arg(long, value_enum, value_delimiter = ',', value_name = "KINDS")]
+    pub exclude_types: Vec<DifferenceKind>,
+
+    /// Compare the forms of each clinical datum in parallel on this many threads
+    #[arg(long, value_name = "THREADS")]
+    pub inner_parallelism: Option<usize>,
+
+    /// Only diff this patient (by their new id), reading just their records from each zip if it has an index written with `diffmig index`
+    #[arg(long, value_name = "ID")]
+    pub patient: Vec<u32>,
+
+    /// Stop once this many patients differ, reporting only what was found so far
+    #[arg(long, value_name = "N")]
+    pub max_differing_patients: Option<usize>,
+
+    /// Stop at the first differing patient, same as --max-differing-patients 1
+    #[arg(long, conflicts_with = "max_differing_patients")]
+    pub fail_fast: bool,
+
+    /// An SQLite report of an earlier run, whose differences are reused for patients whose clinical data hasn't changed since, if it compared the exports with the same options, config and files
+    #[arg(long, value_name = "REPORT")]
+    pub since: Option<String>,
+
+    /// What to do with records that fail to parse
+    #[arg(long, value_enum, default_value = "panic")]
+    pub on_parse_error: OnParseError,
+
+    /// Instead of diffing each patient, compare each CDE's null rate, distinct values and numeric mean, min and max between the exports, as a fast first pass
+    #[arg(long)]
+    pub aggregate_check: bool,
+
+    /// The most a CDE's fraction of null or empty values can change by in --aggregate-check
+    #[arg(long, value_name = "FRACTION", default_value_t = 0.01)]
+    pub max_null_rate_change: f64,
+
+    /// The most a CDE's number of distinct values can change by in --aggregate-check, as a fraction of the old number
+    #[arg(long, value_name = "FRACTION", default_value_t = 0.05)]
+    pub max_distinct_change: f64,
+
+    /// The most a numeric CDE's mean can change by in --aggregate-check, as a fraction of the old mean
+    #[arg(long, value_name = "FRACTION", default_value_t = 0.01)]
+    pub max_mean_change: f64,
+
+    /// Instead of diffing each patient, compare this entry of each zip as plain JSON, path by path, for fixtures diffmig doesn't model, eg. 'export/registry_data/contexts/rdrf_rdrfcontext.json'
+    #[arg(long, value_name = "ENTRY")]
+    pub raw_diff: Vec<String>,
+
+    /// How the items of arrays are paired in --raw-diff and --extra-fields, where they aren't paired by --raw-key
+    #[arg(long, value_enum, value_name = "ALIGNMENT", default_value = "index")]
+    pub raw_arrays: ArrayAlignment,
+
+    /// Pair the objects of arrays by this member in --raw-diff and --extra-fields, where every item on both sides has it, eg. pk
+    #[arg(long, value_name = "MEMBER")]
+    pub raw_key: Vec<String>,
+
+    /// Leave out this JSON pointer in --raw-diff and --extra-fields, where * is any one member or item, eg. '/*/fields/last_updated'
+    #[arg(long, value_name = "PATH")]
+    pub raw_ignore: Vec<String>,
+}
+
+#[derive(Debug, Args)]
+#[command(next_help_heading = "Reporting")]
+pub struct Reporting {
+    /// Report structural differences between the first records of each export before diffing
+    #[arg(long)]
+    pub schema_check: bool,
+
+    /// The number of records scanned by --schema-check
+    #[arg(long, default_value_t = 1000)]
+    pub schema_records: usize,
+
+    /// How differences are grouped when shown
+    #[arg(long, value_enum, default_value = "patient")]
+    pub group_by: GroupBy,
+
+    /// Show each patient's differences one per line, rather than as a tree of their forms, sections and CDEs
+    #[arg(long)]
+    pub flat: bool,
+
+    /// How often to ask whether to continue as each patient's differences are shown
+    #[arg(long, value_enum, default_value = "slice")]
+    pub prompt_every: PromptEvery,
+
+    /// The number of differences shown between prompts with --prompt-every n-diffs
+    #[arg(long, value_name = "N", default_value_t = 20)]
+    pub prompt_diffs: usize,
+
+    /// Include the raw JSON of the affected section from both exports with each difference, keeping it in memory as the exports are read
+    #[arg(long)]
+    pub raw_context: bool,
+
+    /// How much of each difference's values is kept, trading memory and report size against detail [default: full values]
+    #[arg(long, value_enum, conflicts_with = "raw_context")]
+    pub detail: Option<Detail>,
+
+    /// As each patient's differences are shown, ask which side is correct for each CDE and write the corrections to this JSON file for the migration's fix-up scripts
+    #[arg(long, value_name = "FILE", conflicts_with = "detail")]
+    pub patch: Option<String>,
+
+    /// After the diff, print this many randomly chosen identical patients side by side for spot checks
+    #[arg(long, value_name = "PATIENTS")]
+    pub review_sample: Option<usize>,
+
+    /// Give up to this many example (patient, old, new) differences of each CDE that differs in many patients in the summaries of reports
+    #[arg(long, value_name = "EXAMPLES")]
+    pub examples: Option<usize>,
+
+    /// The number of patients a CDE must differ in more than to be given --examples
+    #[arg(long, value_name = "N", default_value_t = 10, requires = "examples")]
+    pub examples_min_patients: usize,
+
+    /// Report each change of a CDE from one value to another once, with the number of patients it was found in and a sample of their ids, rather than every time it's found
+    #[arg(long)]
+    pub dedupe: bool,
+
+    /// After the diff, look for patients left unmatched who are likely renumbered: an old and a new patient with different ids whose clinical data has the same forms and nearly the same CDE values, eg. from a broken id mapping
+    #[arg(long)]
+    pub find_renumbered: bool,
+
+    /// The fraction of their CDE values an old and a new unmatched patient must share to be given by --find-renumbered
+    #[arg(long, value_name = "FRACTION", default_value_t = 0.9, requires = "find_renumbered")]
+    pub renumbered_similarity: f64,
+
+    /// Warn of each patient's slice that takes longer than this to read, parse and diff, listing them with the bytes of their records in the summary, to find records that need handling of their own
+    #[arg(long, value_name = "MS")]
+    pub slow_patient_ms: Option<u64>,
+
+    /// The seed of the random choice of --review-sample, which picks the same patients for the same seed
+    #[arg(long, default_value_t = 0)]
+    pub seed: u64,
+
+    /// Also write the report to <format>:<path>, where format is one of: sqlite, summary, json, csv, html, markdown
+    #[arg(long)]
+    pub output: Vec<String>,
+
+    /// What the old export is called in reports, prompts and column headers, eg. "MongoDB v1.28" [default: old]
+    #[arg(long, value_name = "LABEL")]
+    pub old_label: Option<String>,
+
+    /// What the new export is called in reports, prompts and column headers, eg. "Postgres v5.2" [default: new]
+    #[arg(long, value_name = "LABEL")]
+    pub new_label: Option<String>,
+
+    /// Write a JSON summary of the totals to a file, same as --output summary:<path>
+    #[arg(long, value_name = "PATH")]
+    pub summary_out: Option<String>,
+
+    /// Also write the report with this Handlebars template, given the differences of each patient as `patients` and the totals of --summary-out as `summary`
+    #[arg(long, value_name = "TEMPLATE")]
+    pub template: Option<String>,
+
+    /// Where the report of --template is written [default: the template's path without its extension, eg. report.html for report.html.hbs]
+    #[arg(long, value_name = "PATH", requires = "template")]
+    pub template_out: Option<String>,
+
+    /// Write the effective options, the digests of the inputs and the reproducibility hash given in the reports' metadata to this JSON file
+    #[arg(long, value_name = "PATH", default_value = "run-manifest.json")]
+    pub run_manifest: String,
+
+    /// Write the ids of the patients compared that have no differences to this file, one per line
+    #[arg(long, value_name = "PATH")]
+    pub clean_out: Option<String>,
+
+    /// A YAML file of pass/fail rules for the run's totals, eg. no high severity differences or under 0.1% of patients differing, whose results are printed once the diff finishes, failing the run if any fail
+    #[arg(long, value_name = "GATES")]
+    pub gate: Option<String>,
+
+    /// Post the summary to this URL when the diff finishes or fails
+    #[arg(long, value_name = "URL", env = "DIFFMIG_NOTIFY_WEBHOOK")]
+    pub notify_webhook: Option<String>,
+
+    /// Post to --notify-webhook as a Slack message rather than the summary JSON
+    #[arg(long, requires = "notify_webhook")]
+    pub notify_slack: bool,
+
+    /// A triage file of annotated differences, whose dispositions are shown on the differences found again
+    #[arg(long, value_name = "FILE")]
+    pub triage: Option<String>,
+
+    /// A CSV file of patient ids and their cohort labels (eg. site, study arm), to break the totals down by
+    #[arg(long, value_name = "FILE")]
+    pub cohorts: Option<String>,
+
+    /// The number of patients expected, to show progress by patients compared rather than bytes read [default: the old export's patients, if they're compared]
+    #[arg(long, value_name = "N")]
+    pub expected_patients: Option<u64>,
+
+    /// Print time spent per phase and the slowest patients
+    #[arg(long)]
+    pub profile: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct CheckArgs {
+    /// The path of the old zip file
+    pub old_zip: String,
+
+    /// The path of the new zip file
+    pub new_zip: String,
+
+    /// The code of the registry whose definitions are compared
+    pub registry_code: String,
+
+    /// The number of records sampled from each export
+    #[arg(long, default_value_t = 100)]
+    pub records: usize,
+}
+
+#[derive(Debug, Args)]
+pub struct ValidateArgs {
+    /// The path of the zip file
+    pub zip: String,
+}
+
+#[derive(Debug, Args)]
+pub struct InspectArgs {
+    /// The path of the zip file
+    pub zip: String,
+
+    /// The number of records scanned
+    #[arg(long, default_value_t = 1000)]
+    pub records: usize,
+}
+
+#[derive(Debug, Args)]
+pub struct IndexArgs {
+    /// The path of the zip file
+    pub zip: String,
+
+    /// The code of the registry whose clinical data is indexed
+    pub registry_code: String,
+}
+
+#[derive(Debug, Args)]
+pub struct BenchArgs {
+    /// The path of the zip file
+    pub zip: String,
+
+    /// The code of the registry whose clinical data is benchmarked
+    pub registry_code: String,
+
+    /// The number of times each pass is run
+    #[arg(long, default_value_t = 1)]
+    pub iterations: usize,
+}
+
+#[derive(Debug, Args)]
+pub struct GenFixtureArgs {
+    /// The directory old.zip, new.zip and injected.json are written to
+    pub dir: String,
+
+    /// The registry code of the clinical data
+    #[arg(long, default_value = "gen")]
+    pub registry_code: String,
+
+    /// The number of patients, each with one clinical datum
+    #[arg(long, default_value_t = 100)]
+    pub patients: usize,
+
+    /// The number of forms of each clinical datum
+    #[arg(long, default_value_t = 3)]
+    pub forms: usize,
+
+    /// The number of single sections of each form
+    #[arg(long, default_value_t = 3)]
+    pub sections: usize,
+
+    /// The number of multiple sections of each form
+    #[arg(long, default_value_t = 1)]
+    pub multi_sections: usize,
+
+    /// The number of CDEs of each section
+    #[arg(long, default_value_t = 5)]
+    pub cdes: usize,
+
+    /// The number of differences injected into the new export
+    #[arg(long, default_value_t = 10)]
+    pub differences: usize,
+
+    /// The seed of the choice of differences
+    #[arg(long, default_value_t = 0)]
+    pub seed: u64,
+}
+
+#[derive(Debug, Args)]
+pub struct SelftestArgs {
+    /// The path of the zip file
+    pub zip: String,
+
+    /// The code of the registry whose clinical data is mutated
+    pub registry_code: String,
+
+    /// The number of mutations injected
+    #[arg(long, default_value_t = 20)]
+    pub mutations: usize,
+
+    /// The seed of the choice of mutations
+    #[arg(long, default_value_t = 0)]
+    pub seed: u64,
+
+    /// The path of the config file whose tolerance, ignore rules and expected changes are checked [default: ./diffmig.toml if present]
+    #[arg(long, env = "DIFFMIG_CONFIG")]
+    pub config: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct ReportArgs {
+    /// The path of the SQLite report
+    pub path: String,
+
+    /// The id of the run whose summary is printed, of those written to the report [default: the latest]
+    #[arg(long)]
+    pub run: Option<i64>,
+}
+
+#[derive(Debug, Args)]
+pub struct HistogramArgs {
+    /// The path of the zip file
+    pub zip: String,
+
+    /// The code of the registry whose clinical data is counted
+    pub registry_code: String,
+
+    /// The code of a CDE to count the values of
+    #[arg(long = "cde", required = true)]
+    pub cdes: Vec<String>,
+
+    /// The path of a second zip file to compare counts against
+    #[arg(long = "new")]
+    pub new_zip: Option<String>,
+
+    /// What to do with records that fail to parse, by default counting and listing them once the values are counted
+    #[arg(long, value_enum, default_value = "collect")]
+    pub on_parse_error: OnParseError,
+}
+
+#[derive(Debug, Args)]
+pub struct BatchArgs {
+    /// The path of the YAML file listing the old and new zip, registry code and config of each diff
+    pub batch: String,
+
+    /// The most diffs run at once [default: the batch file's, or 2]
+    #[arg(long)]
+    pub concurrency: Option<usize>,
+
+    /// The directory each diff's output and summary, and the combined summary, are written to
+    #[arg(long, value_name = "DIR", default_value = "batch")]
+    pub out_dir: String,
+}
+
+#[derive(Debug, Args)]
+pub struct SchemaArgs {
+    /// The path of the zip file
+    pub zip: String,
+
+    /// The code of the registry whose clinical data is read
+    pub registry_code: String,
+}
+
+#[derive(Debug, Args)]
+pub struct AnnotateArgs {
+    /// The path of a report written with --output json:<path>
+    pub report: String,
+
+    /// The id of the difference
+    #[arg(long)]
+    pub id: String,
+
+    /// What was decided about the difference
+    #[arg(long, value_enum)]
+    pub state: Disposition,
+
+    /// A note on the decision
+    #[arg(long)]
+    pub note: Option<String>,
+
+    /// The triage file the annotation is written to
+    #[arg(long, value_name = "FILE", default_value = DEFAULT_TRIAGE)]
+    pub triage: String,
+}
+
+#[derive(Debug, Args)]
+pub struct CompareReportsArgs {
+    /// The path of the report of the earlier migration attempt, written with --output json:<path>
+    pub old_report: String,
+
+    /// The path of the report of the later migration attempt
+    pub new_report: String,
+
+    /// List the differences of both reports, rather than only counting them
+    #[arg(long)]
+    pub persisting: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct ExplainArgs {
+    /// The path of a report written with --output json:<path>
+    pub report: String,
+
+    /// The id of the difference
+    #[arg(long)]
+    pub id: String,
+
+    /// The path of the old zip file the report was made from, if not set in the config
+    #[arg(env = "DIFFMIG_OLD_ZIP")]
+    pub old_zip: Option<String>,
+
+    /// The path of the new zip file the report was made from, if not set in the config
+    #[arg(env = "DIFFMIG_NEW_ZIP")]
+    pub new_zip: Option<String>,
+
+    /// The path of the config file the diff was run with [default: ./diffmig.toml if present]
+    #[arg(long, env = "DIFFMIG_CONFIG")]
+    pub config: Option<String>,
+
+    /// The registry code the diff was run with
+    #[arg(long, env = "DIFFMIG_REGISTRY")]
+    pub registry: Option<String>,
+
+    /// The renames file the diff was run with
+    #[arg(long, value_name = "FILE")]
+    pub renames: Option<String>,
+
+    /// The patient map the diff was run with
+    #[arg(long, value_name = "FILE")]
+    pub patient_map: Option<String>,
+}
@@ -1,10 +1,22 @@
 use itertools::Itertools;
+use rayon::prelude::*;
+use serde::{Deserialize, Deserializer};
+use serde::de::{self, MapAccess, SeqAccess, Visitor};
+use serde::de::value::MapAccessDeserializer;
 use serde_json::Value;
-use std::collections::{HashMap, HashSet, BTreeSet};
-use std::error::Error;
+use std::collections::{BTreeMap, HashMap, HashSet, BTreeSet, VecDeque};
+use std::fmt;
 use std::mem::discriminant;
+use std::sync::Arc;
 
-use crate::diff::{Diff, eq_diff, variant_diff};
+use crate::consents;
+use crate::contexts::GroupedContext;
+use crate::diff::{Diff, DiffOptions, eq_diff, variant_diff};
+use crate::fixture::{self, CDERecord, CDEsData, CDEsText, ClinicalDatumRecord, FormRecord, HistoryData, ParseError, SectionRecord};
+use crate::interner::{self, Code, Interner};
+use crate::json_diff::JsonDifference;
+use crate::report::{self, DifferenceKind, DifferenceRecord, Location};
+use crate::text;
 
 #[derive(Debug)]
 pub struct CDEFileValue {
@@ -24,13 +36,136 @@ pub enum CDEValue {
     File(CDEFileValue),
 }
 
+#[derive(Deserialize)]
+struct CDEFileRecord {
+    file_name: String,
+    django_file_id: Option<u32>,
+    gridfs_file_id: Option<String>,
+}
+
+impl fmt::Display for CDEValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CDEValue::Null => write!(f, "null"),
+            CDEValue::Bool(b) => write!(f, "{}", b),
+            CDEValue::EmptyString => write!(f, "\"\""),
+            CDEValue::String(s) => write!(f, "{}", s),
+            CDEValue::Number(n) => write!(f, "{}", n),
+            CDEValue::EmptyRange => write!(f, "[]"),
+            CDEValue::Range(r) => write!(f, "[{}]", r.iter().sorted().join(", ")),
+            CDEValue::File(file) => write!(f, "file {} ({})", file.file_name, file.django_file_id),
+        }
+    }
+}
+
+impl CDEValue {
+    /// The name of the value's type, as it was inferred from the fixture
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            CDEValue::Null => "null",
+            CDEValue::Bool(_) => "bool",
+            CDEValue::EmptyString => "empty string",
+            CDEValue::String(_) => "string",
+            CDEValue::Number(_) => "number",
+            CDEValue::EmptyRange => "empty range",
+            CDEValue::Range(_) => "range",
+            CDEValue::File(_) => "file",
+        }
+    }
+}
+
+/// CDE values are untyped in the fixture, so the variant is decided by the
+/// JSON type of the value
+impl<'de> Deserialize<'de> for CDEValue {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(CDEValueVisitor)
+    }
+}
+
+struct CDEValueVisitor;
+
+impl<'de> Visitor<'de> for CDEValueVisitor {
+    type Value = CDEValue;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a null, bool, number, string, list of strings or file CDE value")
+    }
+
+    fn visit_bool<E: de::Error>(self, b: bool) -> Result<CDEValue, E> {
+        Ok(CDEValue::Bool(b))
+    }
+
+    fn visit_i64<E: de::Error>(self, n: i64) -> Result<CDEValue, E> {
+        Ok(CDEValue::Number(n as f64))
+    }
+
+    fn visit_u64<E: de::Error>(self, n: u64) -> Result<CDEValue, E> {
+        Ok(CDEValue::Number(n as f64))
+    }
+
+    fn visit_f64<E: de::Error>(self, n: f64) -> Result<CDEValue, E> {
+        Ok(CDEValue::Number(n))
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<CDEValue, E> {
+        Ok(CDEValue::Null)
+    }
+
+    fn visit_str<E: de::Error>(self, s: &str) -> Result<CDEValue, E> {
+        match s {
+            "" => Ok(CDEValue::EmptyString),
+            s => Ok(CDEValue::String(s.to_string()))
+        }
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<CDEValue, A::Error> {
+        let mut range = HashSet::new();
+        while let Some(s) = seq.next_element::<String>()? {
+            range.insert(s);
+        }
+
+        match range.is_empty() {
+            true => Ok(CDEValue::EmptyRange),
+            false => Ok(CDEValue::Range(range))
+        }
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<CDEValue, A::Error> {
+        let file = CDEFileRecord::deserialize(MapAccessDeserializer::new(map))?;
+
+        match (file.django_file_id, file.gridfs_file_id) {
+            (Some(django_file_id), _) => Ok(CDEValue::File(CDEFileValue { file_name: file.file_name, django_file_id })),
+            (None, Some(_)) => Ok(CDEValue::File(CDEFileValue { file_name: file.file_name, django_file_id: 0 })),
+            (None, None) => Err(de::Error::custom("file CDE value has neither django_file_id nor gridfs_file_id")),
+        }
+    }
+}
+
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug)]
 pub struct CDE {
-    code: String,
+    code: Code,
     value: CDEValue,
+    /// Its position in its section's list (or row's list) of CDEs
+    index: u32,
+}
+
+impl CDE {
+    /// A CDE at a position of its section (or row), eg. built rather than read from an export
+    pub fn new(code: Code, value: CDEValue, index: u32) -> CDE {
+        CDE { code, value, index }
+    }
+
+    pub fn code(&self) -> &str {
+        &self.code
+    }
+
+    pub fn value(&self) -> &CDEValue {
+        &self.value
+    }
 }
 
-type CDEMap = HashMap<String, CDE>;
+type CDEMap = HashMap<Code, CDE>;
 
 #[derive(Debug)]
 pub enum CDESVariant {
@@ -38,17 +173,61 @@ pub enum CDESVariant {
     Multiple(Vec<CDEMap>),
 }
 
-#[derive(Debug)]
+impl CDESVariant {
+    /// Every CDE of the section, across all rows of a multiple section
+    pub fn iter(&self) -> Box<dyn Iterator<Item=&CDE> + '_> {
+        match self {
+            CDESVariant::Single(m) => Box::new(m.values()),
+            CDESVariant::Multiple(v) => Box::new(v.iter().flat_map(|m| m.values())),
+        }
+    }
+}
+
 pub struct Section {
-    code: String,
+    code: Code,
     allow_multiple: bool,
     cdes: CDESVariant,
+    /// The section's CDEs as they were in the export, if they're kept to show with its differences
+    raw: Option<String>,
+    /// Its position in its form's list of sections
+    index: u32,
+}
+
+impl Section {
+    pub fn new(code: Code, cdes: CDESVariant, index: u32) -> Section {
+        let allow_multiple = matches!(cdes, CDESVariant::Multiple(_));
+        Section { code, allow_multiple, cdes, raw: None, index }
+    }
+
+    /// The section's CDEs as they were in the export, if they were kept
+    pub fn raw(&self) -> Option<&str> {
+        self.raw.as_deref()
+    }
+}
+
+/// The raw JSON is left out, as it repeats the CDEs
+impl fmt::Debug for Section {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Section")
+            .field("code", &self.code)
+            .field("allow_multiple", &self.allow_multiple)
+            .field("cdes", &self.cdes)
+            .finish()
+    }
 }
 
 #[derive(Debug)]
 pub struct Form {
-    name: String,
-    sections: HashMap<String, Section>,
+    name: Code,
+    sections: HashMap<Code, Section>,
+    /// Its position in its datum's list of forms
+    index: u32,
+}
+
+impl Form {
+    pub fn new(name: Code, sections: HashMap<Code, Section>, index: u32) -> Form {
+        Form { name, sections, index }
+    }
 }
 
 #[derive(Debug)]
@@ -58,283 +237,616 @@ pub enum ClinicalDatumVariant { History, CDEs }
 pub struct ClinicalDatum {
     pub id: u32,
     pub patient: u32,
+    pub context_id: Option<u32>,
     pub variant: ClinicalDatumVariant,
-    forms: HashMap<String, Form>,
-}
-
-type ProtoContext = BTreeSet<String>;
-
-impl<'a> ClinicalDatum {
-    pub fn from(datum: &'a serde_json::Value) -> Result<Option<ClinicalDatum>, Box<dyn Error>> {
-        let map = datum.as_object()
-            .ok_or("Not an object")?;
-        let fields = map.get("fields")
-            .ok_or("Missing fields")?;
-        let data = fields.get("data")
-            .ok_or("Missing data")?;
-
-        let id = map.get("pk")
-            .ok_or("Missing PK")?
-            .as_i64().ok_or("Invalid PK")? as u32;
-        let patient = fields.get("django_id")
-            .ok_or("Missing patient")?
-            .as_i64().ok_or("Invalid patient")? as u32;
-        let variant = fields.get("collection")
-            .ok_or("Missing collection")?
-            .as_str().ok_or("Invalid collection")?;
-        let variant = match variant {
+    /// When the datum was last saved, as written by the registry (ISO 8601)
+    pub timestamp: Option<String>,
+    /// Who saved the datum, which only history snapshots give
+    pub username: Option<String>,
+    /// A hash of the datum's record as it was in the export, to tell whether it changed between exports
+    pub hash: u64,
+    /// The position of the datum's record in the export's list of records,
+    /// or in its entry's if the export's clinical data is of several
+    pub record: u32,
+    /// The zip entry of the datum's record, if the export's clinical data is of several
+    pub entry: Option<Arc<str>>,
+    /// The length of the datum's record in the export, in bytes
+    pub size: u32,
+    /// The members of the datum's record that aren't read into it, by their
+    /// path in the record, if they're kept to be compared
+    pub extra: BTreeMap<String, Value>,
+    forms: HashMap<Code, Form>,
+}
+
+pub type ProtoContext = BTreeSet<Code>;
+
+/// What a clinical datum is paired with the other export's datum by
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ContextKey {
+    /// Its context's form group and sequence, when the export's contexts are known
+    Grouped(GroupedContext),
+    /// The set of its forms, which is ambiguous when two of a patient's contexts have the same forms
+    Forms(ProtoContext),
+}
+
+impl fmt::Display for ContextKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ContextKey::Grouped(context) => write!(f, "{}", context),
+            ContextKey::Forms(forms) => write!(f, "{}", forms.iter().join(",")),
+        }
+    }
+}
+
+impl ClinicalDatum {
+    /// Construct the clinical datum of a record, keeping each section's raw
+    /// JSON if keep_raw is set
+    pub fn from(record: &ClinicalDatumRecord, interner: &mut Interner, keep_raw: bool) -> Result<Option<ClinicalDatum>, ParseError> {
+        let id = record.pk as u32;
+        let patient = record.fields.django_id as u32;
+        let context_id = record.fields.context_id.map(|c| c as u32);
+        let variant = match record.fields.collection.as_ref() {
             "cdes" => ClinicalDatumVariant::CDEs,
             "history" => ClinicalDatumVariant::History,
             _ => return Ok(None) // Ignore non history & cdes entries
         };
 
-        let forms = match variant {
-            ClinicalDatumVariant::CDEs => data.get("forms"),
-            ClinicalDatumVariant::History => data.get("record")
-                .ok_or("Missing record")?
-                .get("forms")
+        let data = record.fields.data.get();
+        let parsed = match variant {
+            ClinicalDatumVariant::CDEs => fixture::parse_at::<CDEsData>(data, "/fields/data")
+                .map(|d| (d.forms, d.timestamp, None, "/fields/data/forms")),
+            ClinicalDatumVariant::History => fixture::parse_at::<HistoryData>(data, "/fields/data")
+                .map(|d| (d.record.forms, d.timestamp.or(d.record.timestamp), d.username, "/fields/data/record/forms")),
         };
-        let forms = Self::get_forms(forms
-            .ok_or("Missing forms")?
-            .as_array().ok_or("Invalid forms")?
-        )?;
+        let (forms, timestamp, username) = parsed
+            .and_then(|(forms, timestamp, username, pointer)| Ok((Self::get_forms(&forms, pointer, interner, keep_raw)?, timestamp, username)))
+            .map_err(|e| e.with_record(record.pk, record.fields.django_id))?;
+        let (timestamp, username) = (timestamp.map(String::from), username.map(String::from));
+
+        Ok(Some(ClinicalDatum { id, patient, context_id, variant, timestamp, username, hash: 0, record: 0, entry: None, size: 0, extra: BTreeMap::new(), forms }))
+    }
+
+    /// A clinical datum of forms keyed by their names, eg. built rather than read from an export
+    pub fn new(id: u32, patient: u32, context_id: Option<u32>, variant: ClinicalDatumVariant, timestamp: Option<String>, forms: HashMap<Code, Form>) -> ClinicalDatum {
+        ClinicalDatum { id, patient, context_id, variant, timestamp, username: None, hash: 0, record: 0, entry: None, size: 0, extra: BTreeMap::new(), forms }
+    }
 
-        Ok(Some(ClinicalDatum { id, patient, variant, forms }))
+    pub fn timestamp(&self) -> Option<&str> {
+        self.timestamp.as_deref()
+    }
+
+    /// The position of the datum's record, and its entry's name if the
+    /// export's clinical data is of several, eg. "12 of clinical_data/part2.json"
+    pub fn position(&self) -> String {
+        match &self.entry {
+            Some(entry) => format!("{} of {}", self.record, entry),
+            None => self.record.to_string(),
+        }
+    }
+
+    /// A JSON pointer (RFC 6901) to the datum's record in its export, as the
+    /// fragment of its entry, eg. "clinical_data/part2.json#/12", if the
+    /// export's clinical data is of several
+    pub fn pointer(&self) -> String {
+        match &self.entry {
+            Some(entry) => format!("{}#/{}", entry, self.record),
+            None => format!("/{}", self.record),
+        }
+    }
+
+    /// A JSON pointer to the record's list of forms
+    fn forms_pointer(&self) -> String {
+        match self.variant {
+            ClinicalDatumVariant::CDEs => format!("{}/fields/data/forms", self.pointer()),
+            ClinicalDatumVariant::History => format!("{}/fields/data/record/forms", self.pointer()),
+        }
+    }
+
+    /// Whether the comp datum was saved before this one, comparing the
+    /// instants of their timestamps whatever their offsets
+    pub fn timestamp_regressed(&self, comp: &ClinicalDatum) -> bool {
+        match (self.timestamp().and_then(consents::instant), comp.timestamp().and_then(consents::instant)) {
+            (Some(i1), Some(i2)) => i2 < i1,
+            _ => false
+        }
     }
 
     pub fn proto_context(&self) -> ProtoContext {
-        self.forms.keys().map(|k| k.to_string()).collect()
+        self.forms.keys().cloned().collect()
+    }
+
+    /// A section of a form of the datum
+    pub fn section(&self, form: &str, section: &str) -> Option<&Section> {
+        self.forms.get(form).and_then(|f| f.sections.get(section))
+    }
+
+    /// Every CDE of every form and section of the datum
+    pub fn cdes(&self) -> impl Iterator<Item=&CDE> {
+        self.forms.values()
+            .flat_map(|f| f.sections.values())
+            .flat_map(|s| s.cdes.iter())
+    }
+
+    /// The form name, section code and CDE code of every CDE of the datum
+    pub fn cde_paths(&self) -> impl Iterator<Item=(&str, &str, &str)> {
+        self.located_cdes().map(|(form, section, c)| (form, section, &*c.code))
+    }
+
+    /// The form name and section code of every section of the datum
+    pub fn section_paths(&self) -> impl Iterator<Item=(&str, &str)> {
+        self.forms.values().flat_map(|f| f.sections.values().map(move |s| (&*f.name, &*s.code)))
+    }
+
+    /// Every CDE of the datum, with the name of its form and code of its section
+    pub fn located_cdes(&self) -> impl Iterator<Item=(&str, &str, &CDE)> {
+        self.forms.values().flat_map(|f| {
+            f.sections.values().flat_map(move |s| s.cdes.iter().map(move |c| (&*f.name, &*s.code, c)))
+        })
     }
 
-    fn get_forms(forms: &[serde_json::Value]) -> Result<HashMap<String, Form>, Box<dyn Error>> {
-        let forms_map = forms.iter().map(|data| {
-            let form = data.as_object().ok_or("Invalid form")?;
-            let name = form.get("name")
-                .ok_or("Missing form name")?
-                .as_str().ok_or("Invalid form name")?
-                .to_string();
-            let sections = Self::get_sections(form.get("sections")
-                .ok_or("Missing form sections")?
-                .as_array().ok_or("Invalid form sections")?
-            )?;
+    fn get_forms(forms: &[FormRecord], pointer: &str, interner: &mut Interner, keep_raw: bool) -> Result<HashMap<Code, Form>, ParseError> {
+        let forms_map = forms.iter().enumerate().map(|(i, form)| {
+            let name = interner.intern_form(&form.name);
+            let sections = Self::get_sections(&form.sections, &format!("{}/{}/sections", pointer, i), interner, keep_raw)?;
 
-            Ok((name.clone(), Form { name, sections }))
-        }).collect::<Result<HashMap<String, Form>, Box<dyn Error>>>()?;
+            Ok((interner.key(&name), Form { name, sections, index: i as u32 }))
+        }).collect::<Result<HashMap<Code, Form>, ParseError>>()?;
 
         match forms.len() != forms_map.len() {
-            true => Err("List of forms contains duplicates".into()),
+            true => Err(ParseError::new(pointer, "List of forms contains duplicates")),
             false => Ok(forms_map)
         }
     }
 
-    fn get_sections(sections: &[serde_json::Value]) -> Result<HashMap<String, Section>, Box<dyn Error>> {
-        let sections_map = sections.iter().map(|data| {
-            let section = data.as_object().ok_or("Invalid section")?;
-            let code = section.get("code")
-                .ok_or("Missing section code")?
-                .as_str().ok_or("Invalid section code")?
-                .to_string();
-            let allow_multiple = section.get("allow_multiple")
-                .ok_or("Missing section allow_multiple")?
-                .as_bool().ok_or("Invalid section allow_multiple")?;
-            let cdes = section.get("cdes")
-                .ok_or("Missing section cdes")?
-                .as_array().ok_or("Invalid section cdes")?;
+    fn get_sections(sections: &[SectionRecord], pointer: &str, interner: &mut Interner, keep_raw: bool) -> Result<HashMap<Code, Section>, ParseError> {
+        let sections_map = sections.iter().enumerate().map(|(i, section)| {
+            let code = interner.intern_section(&section.code);
+            let allow_multiple = section.allow_multiple;
+            let mut cdes = CDEsText::new(section.cdes.get());
+            let pointer = format!("{}/{}/cdes", pointer, i);
             let cdes = match allow_multiple {
-                false => CDESVariant::Single(Self::get_cdes(cdes)?),
-                true => CDESVariant::Multiple(cdes.iter().map(|l| {
-                    Self::get_cdes(l.as_array().ok_or("Invalid section cdes list")?)
-                }).collect::<Result<Vec<HashMap<String, CDE>>, Box<dyn Error>>>()?),
+                false => CDESVariant::Single(Self::get_cdes(cdes.parse_at(&pointer)?, &pointer, interner)?),
+                true => CDESVariant::Multiple(cdes.parse_at::<Vec<Vec<CDERecord>>>(&pointer)?.into_iter().enumerate().map(|(j, l)| {
+                    Self::get_cdes(l, &format!("{}/{}", pointer, j), interner)
+                }).collect::<Result<Vec<CDEMap>, ParseError>>()?),
+            };
+
+            // Compacted, as the export is indented deeply by the time it reaches a section
+            let raw = match keep_raw {
+                true => Some(serde_json::from_str::<serde_json::Value>(section.cdes.get()).map_err(|e| ParseError::new(&pointer, e))?.to_string()),
+                false => None
             };
 
-            Ok((code.clone(), Section { code, allow_multiple, cdes }))
-        }).collect::<Result<HashMap<String, Section>, Box<dyn Error>>>()?;
+            Ok((interner.key(&code), Section { code, allow_multiple, cdes, raw, index: i as u32 }))
+        }).collect::<Result<HashMap<Code, Section>, ParseError>>()?;
 
         match sections.len() != sections_map.len() {
-            true => Err("List of sections contains duplicates".into()),
+            true => Err(ParseError::new(pointer, "List of sections contains duplicates")),
             false => Ok(sections_map)
         }
     }
 
-    fn get_cdes(cdes: &[serde_json::Value]) -> Result<HashMap<String, CDE>, Box<dyn Error>> {
-        let cde_map = cdes.iter().map(|data| {
-            let cde = data.as_object().ok_or("Invalid cde")?;
-            let code = cde.get("code")
-                .ok_or("Missing cde code")?
-                .as_str().ok_or("Invalid cde code")?
-                .to_string();
-            let value = cde.get("value")
-                .ok_or("Missing cde value")?;
-            let value = Self::get_cde_value(value)?.ok_or("Invalid cde value")?;
+    fn get_cdes(cdes: Vec<CDERecord>, pointer: &str, interner: &mut Interner) -> Result<CDEMap, ParseError> {
+        let cdes_len = cdes.len();
+        let cde_map = cdes.into_iter().enumerate().map(|(i, cde)| {
+            let code = interner.intern_cde(&cde.code);
 
-            Ok((code.clone(), CDE { code, value }))
-        }).collect::<Result<HashMap<String, CDE>, Box<dyn Error>>>()?;
+            (interner.key(&code), CDE { code, value: cde.value, index: i as u32 })
+        }).collect::<CDEMap>();
 
-        if cde_map.len() != cdes.len() {
-            Err("List of CDEs contains duplicates".into())
+        if cde_map.len() != cdes_len {
+            Err(ParseError::new(pointer, "List of CDEs contains duplicates"))
         } else {
             Ok(cde_map)
         }
     }
-
-    fn get_cde_value(value: &serde_json::Value) -> Result<Option<CDEValue>, Box<dyn Error>> {
-        let cde_value = match value {
-            Value::Bool(b) => Some(CDEValue::Bool(*b)),
-            Value::Object(o) => {
-                let file_name = o.get("file_name");
-                let django_file_id = o.get("django_file_id");
-                let gridfs_file_id = o.get("gridfs_file_id");
-
-                match (file_name, django_file_id, gridfs_file_id) {
-                    (Some(Value::String(file_name)), Some(Value::Number(django_file_id)), _) => {
-                        let django_file_id = django_file_id.as_u64().unwrap() as u32;
-                        Some(CDEValue::File(CDEFileValue { file_name: file_name.to_string(), django_file_id }))
-                    }
-                    (Some(Value::String(file_name)), _, Some(Value::String(_))) => {
-                        Some(CDEValue::File(CDEFileValue { file_name: file_name.to_string(), django_file_id: 0 }))
-                    }
-                    _ => None,
-                }
-            }
-            Value::Null => Some(CDEValue::Null),
-            Value::Number(n) => Some(CDEValue::Number(n.as_f64().unwrap())),
-            Value::String(s) => match s.as_str() {
-                "" => Some(CDEValue::EmptyString),
-                s => Some(CDEValue::String(s.to_string()))
-            },
-            Value::Array(a) => {
-                let range = a.iter().map(|s| {
-                    Ok(s.as_str().ok_or("Invalid range cde value")?.to_string())
-                }).collect::<Result<HashSet<String>, Box<dyn Error>>>()?;
-
-                match range.is_empty() {
-                    true => Some(CDEValue::EmptyRange),
-                    false => Some(CDEValue::Range(range))
-                }
-            }
-        };
-
-        Ok(cde_value)
-    }
 }
 
 #[derive(Debug)]
 pub struct PatientSlice {
-    patient: u32,
-    clinical_data: HashMap<ProtoContext, ClinicalDatum>,
+    pub patient: u32,
+    clinical_data: HashMap<ContextKey, ClinicalDatum>,
 }
 
-impl<'a> PatientSlice {
+impl PatientSlice {
     pub fn from(patient: u32) -> PatientSlice {
         PatientSlice { patient, clinical_data: HashMap::new() }
     }
 
-    pub fn can_add(&mut self, datum: &ClinicalDatum) -> bool {
-        let proto_context = datum.proto_context();
-        !self.clinical_data.contains_key(&proto_context) && datum.patient == self.patient
+    pub fn clinical_data(&self) -> impl Iterator<Item=(&ContextKey, &ClinicalDatum)> {
+        self.clinical_data.iter()
     }
 
-    pub fn add(&mut self, datum: ClinicalDatum) {
-        let proto_context = datum.proto_context();
-        self.clinical_data.insert(proto_context, datum);
+    /// The pks of the slice's clinical data
+    pub fn ids(&self) -> String {
+        self.clinical_data.values().map(|k| k.id).sorted().join(",")
+    }
+
+    /// A hash of the records of the slice's clinical data, in order of pk
+    pub fn hash(&self) -> u64 {
+        self.clinical_data.values()
+            .sorted_by_key(|d| d.id)
+            .fold(report::FNV_OFFSET, |hash, d| report::fnv1a(hash, &d.hash.to_le_bytes()))
+    }
+
+    /// The bytes of the records of the slice's clinical data
+    pub fn size(&self) -> usize {
+        self.clinical_data.values().map(|d| d.size as usize).sum()
+    }
+
+    pub fn can_add(&self, context: &ContextKey, datum: &ClinicalDatum) -> bool {
+        !self.clinical_data.contains_key(context) && datum.patient == self.patient
+    }
+
+    pub fn add(&mut self, context: ContextKey, datum: ClinicalDatum) {
+        self.clinical_data.insert(context, datum);
+    }
+
+    /// The slice as the patient with another id, eg. their id in the other export
+    pub fn renumbered(mut self, patient: u32) -> PatientSlice {
+        self.patient = patient;
+        self.clinical_data.values_mut().for_each(|d| d.patient = patient);
+        self
     }
 }
 
-#[derive(Debug)]
 pub enum CDEDifferenceType<'a> {
     Missing(Option<&'a CDE>, Option<&'a CDE>),
     Variant(&'a CDEValue, &'a CDEValue),
     Equality(&'a CDEValue, &'a CDEValue),
+    /// Free text that differs once normalized, with an inline diff of the normalized text
+    Text(&'a CDEValue, &'a CDEValue, String),
+    /// Differing values of a calculated CDE, other than by formatting or truncation
+    Calculated(&'a CDEValue, &'a CDEValue),
+    /// A new value other than the expected change of the old value, which is given last
+    Unexpected(&'a CDEValue, &'a CDEValue, String),
+    /// Codes that differ only by case or surrounding whitespace
+    KeyCase(&'a str, &'a str),
+    /// Numbers, or strings of them, that are equal once rounded to the formatting precision
+    Formatting(&'a CDEValue, &'a CDEValue),
+    /// Strings where one is the other cut short, with their lengths in characters
+    Truncated { old: &'a CDEValue, new: &'a CDEValue, old_len: usize, new_len: usize, common_prefix_len: usize },
+    /// Values exchanged with the CDE of the section whose code is given last,
+    /// the new value being its old value and its new value this old value
+    SwappedValues(&'a CDEValue, &'a CDEValue, &'a str),
+}
+
+/// Long strings are shown as an inline diff, as they tend to be notes that
+/// differ by a few words
+impl<'a> fmt::Debug for CDEDifferenceType<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CDEDifferenceType::Missing(c1, c2) => f.debug_tuple("Missing").field(c1).field(c2).finish(),
+            CDEDifferenceType::Variant(v1, v2) => f.debug_tuple("Variant").field(v1).field(v2).finish(),
+            CDEDifferenceType::Equality(v1, v2) => match long_text_diff(v1, v2) {
+                Some(inline) => f.debug_tuple("Equality").field(&inline).finish(),
+                None => f.debug_tuple("Equality").field(v1).field(v2).finish(),
+            },
+            CDEDifferenceType::Text(_, _, inline) => f.debug_tuple("Text").field(inline).finish(),
+            CDEDifferenceType::Calculated(v1, v2) => f.debug_tuple("Calculated").field(v1).field(v2).finish(),
+            CDEDifferenceType::Unexpected(v1, v2, expected) => f.debug_tuple("Unexpected").field(v1).field(v2).field(expected).finish(),
+            CDEDifferenceType::KeyCase(c1, c2) => f.debug_tuple("KeyCase").field(c1).field(c2).finish(),
+            CDEDifferenceType::Formatting(v1, v2) => f.debug_tuple("Formatting").field(v1).field(v2).finish(),
+            CDEDifferenceType::SwappedValues(v1, v2, other) => f.debug_tuple("SwappedValues").field(v1).field(v2).field(other).finish(),
+            CDEDifferenceType::Truncated { old_len, new_len, common_prefix_len, .. } => f.debug_struct("Truncated")
+                .field("old_len", old_len)
+                .field("new_len", new_len)
+                .field("common_prefix_len", common_prefix_len)
+                .finish(),
+        }
+    }
+}
+
+/// An inline diff of two string values, if either is long
+fn long_text_diff(v1: &CDEValue, v2: &CDEValue) -> Option<String> {
+    match (v1, v2) {
+        (CDEValue::String(s1), CDEValue::String(s2)) if text::is_long(s1) || text::is_long(s2) => {
+            Some(text::inline_diff(s1, s2))
+        }
+        _ => None
+    }
 }
 
 #[derive(Debug)]
 pub struct CDEDifference<'a> {
     code: &'a str,
     diff: CDEDifferenceType<'a>,
+    /// The CDE's position on each side, if it's there
+    indices: (Option<u32>, Option<u32>),
 }
 
 impl<'a> Diff<'a> for CDE {
     type Difference = CDEDifference<'a>;
 
-    fn diff(&'a self, comp: &'a Self) -> Option<Vec<Self::Difference>> {
-        let mut diffs = vec![];
+    fn diff(&'a self, comp: &'a Self, options: &DiffOptions) -> Option<Vec<Self::Difference>> {
+        let key_case = match differ_by_key(&self.code, &comp.code) {
+            true => Some(CDEDifference { code: &self.code, diff: CDEDifferenceType::KeyCase(&self.code, &comp.code), indices: (Some(self.index), Some(comp.index)) }),
+            false => None,
+        };
+
+        match (key_case, self.diff_value(comp, options)) {
+            (None, diffs) => diffs,
+            (Some(key_case), None) => Some(vec![key_case]),
+            (Some(key_case), Some(diffs)) => Some(std::iter::once(key_case).chain(diffs).collect()),
+        }
+    }
+}
 
-        variant_diff!(&self.value, &comp.value, diffs, CDEDifferenceType::Variant);
+/// Whether codes that were matched differ, which with --normalize-keys is
+/// only by case or surrounding whitespace
+fn differ_by_key(c1: &str, c2: &str) -> bool {
+    c1 != c2 && interner::normalize_key(c1) == interner::normalize_key(c2)
+}
 
-        match (&self.value, &comp.value) {
-            (CDEValue::Null, CDEValue::Null) => {}
-            (CDEValue::EmptyString, CDEValue::EmptyString) => {}
-            (CDEValue::EmptyRange, CDEValue::EmptyRange) => {}
-            (CDEValue::Bool(b1), CDEValue::Bool(b2)) => {
-                eq_diff!(b1 != b2, &self.value, &comp.value, diffs, CDEDifferenceType::Equality);
-            }
-            (CDEValue::String(s1), CDEValue::String(s2)) => {
-                eq_diff!(s1 != s2, &self.value, &comp.value, diffs, CDEDifferenceType::Equality);
+impl CDE {
+    fn diff_value<'a>(&'a self, comp: &'a CDE, options: &DiffOptions) -> Option<Vec<CDEDifference<'a>>> {
+        if let Some(transform) = options.expect.get(&*self.code) {
+            let expected = transform.apply(&self.value.to_string());
+            return match expected == comp.value.to_string() {
+                true => None,
+                false => Some(vec![CDEDifference {
+                    code: &self.code,
+                    diff: CDEDifferenceType::Unexpected(&self.value, &comp.value, expected),
+                    indices: (Some(self.index), Some(comp.index)),
+                }])
+            };
+        }
+
+        // A calculated CDE's values that differ are Calculated, unless they
+        // only differ in formatting or one is cut short
+        let diffs = match options.calculated.contains(&*self.code) {
+            true => diff_values(&self.value, &comp.value, options).into_iter().map(|d| match d {
+                CDEDifferenceType::Equality(v1, v2) | CDEDifferenceType::Variant(v1, v2) | CDEDifferenceType::Text(v1, v2, _) => CDEDifferenceType::Calculated(v1, v2),
+                d => d,
+            }).collect(),
+            false => diff_values(&self.value, &comp.value, options),
+        };
+
+        // Values that only differ by a registry quirk are equal once the plugin normalizes them
+        let normalized_eq = || options.plugin.as_ref().is_some_and(|p| {
+            match (p.normalize(&self.code, &self.value), p.normalize(&comp.code, &comp.value)) {
+                (None, None) => false,
+                (n1, n2) => diff_values(n1.as_ref().unwrap_or(&self.value), n2.as_ref().unwrap_or(&comp.value), options).is_empty()
             }
-            (CDEValue::Number(n1), CDEValue::Number(n2)) => {
-                eq_diff!((n1 - n2).abs() > 0.01, &self.value, &comp.value, diffs, CDEDifferenceType::Equality);
+        });
+
+        // Permitted values may be given by code on one side and by label on the other
+        let labelled_eq = || {
+            let permitted = &options.permitted_values;
+            match (permitted.labelled(&self.code, &self.value), permitted.labelled(&comp.code, &comp.value)) {
+                (None, None) => false,
+                (l1, l2) => diff_values(l1.as_ref().unwrap_or(&self.value), l2.as_ref().unwrap_or(&comp.value), options).is_empty()
             }
-            (CDEValue::Range(r1), CDEValue::Range(r2)) => {
-                eq_diff!(r1 != r2, &self.value, &comp.value, diffs, CDEDifferenceType::Equality);
+        };
+
+        match diffs.is_empty() || normalized_eq() || labelled_eq() {
+            true => None,
+            false => Some(diffs.into_iter().map(|d| CDEDifference { code: &self.code, diff: d, indices: (Some(self.index), Some(comp.index)) }).collect())
+        }
+    }
+}
+
+/// The difference of two strings where one is a prefix of the other, as a
+/// column too short for the value leaves it, unless they're both numbers, eg.
+/// "10" and "100"
+fn truncated<'a>(v1: &'a CDEValue, v2: &'a CDEValue) -> Option<CDEDifferenceType<'a>> {
+    if number(v1).is_some() && number(v2).is_some() {
+        return None;
+    }
+
+    match (v1, v2) {
+        (CDEValue::String(s1), CDEValue::String(s2)) if s1.starts_with(s2.as_str()) || s2.starts_with(s1.as_str()) => {
+            let (old_len, new_len) = (s1.chars().count(), s2.chars().count());
+            Some(CDEDifferenceType::Truncated { old: v1, new: v2, old_len, new_len, common_prefix_len: old_len.min(new_len) })
+        }
+        _ => None
+    }
+}
+
+/// Replace the differences of each two CDEs whose values were exchanged, the
+/// old value of each being the new value of the other, with one difference,
+/// as when the migration maps each CDE's field to the other's
+fn swap_values<'a>(c1: &'a CDEMap, c2: &'a CDEMap, diffs: &mut Vec<CDEDifference<'a>>, options: &DiffOptions) {
+    // The CDEs on both sides whose values differ, and their values on each
+    let mut changed = diffs.iter()
+        .filter(|d| matches!(d.diff, CDEDifferenceType::Equality(..) | CDEDifferenceType::Variant(..)
+            | CDEDifferenceType::Text(..) | CDEDifferenceType::Truncated { .. }))
+        .filter_map(|d| {
+            let (key, v1) = c1.iter().find(|(_, c)| &*c.code == d.code)?;
+            Some((d.code, &v1.value, &c2.get(key)?.value))
+        })
+        .collect::<Vec<(&str, &CDEValue, &CDEValue)>>();
+    // Taken from the end, so a swap is reported under the first of its codes
+    changed.sort_by(|(a, _, _), (b, _, _)| b.cmp(a));
+
+    let mut swapped = vec![];
+    while let Some((a, old_a, new_a)) = changed.pop() {
+        let exchanged = |&(_, old_b, new_b): &(&str, &CDEValue, &CDEValue)| {
+            diff_values(old_a, new_b, options).is_empty() && diff_values(old_b, new_a, options).is_empty()
+        };
+        if let Some(i) = changed.iter().position(exchanged) {
+            let (b, _, _) = changed.remove(i);
+            swapped.push((a, b, old_a, new_a));
+        }
+    }
+
+    for (a, b, old, new) in swapped {
+        let indices = match diffs.iter().find(|d| d.code == a) {
+            Some(d) => d.indices,
+            None => continue,
+        };
+        diffs.retain(|d| d.code != a && d.code != b);
+        diffs.push(CDEDifference { code: a, diff: CDEDifferenceType::SwappedValues(old, new, b), indices });
+    }
+}
+
+/// The number a value is, or is a string of
+fn number(v: &CDEValue) -> Option<f64> {
+    match v {
+        CDEValue::Number(n) => Some(*n),
+        CDEValue::String(s) => s.trim().parse::<f64>().ok().filter(|n| n.is_finite()),
+        _ => None
+    }
+}
+
+/// The difference of two values that are numbers or strings of numbers, if
+/// they're written differently but are the same number, eg. "1.50" and 1.5,
+/// or with a formatting precision, equal once rounded to it
+fn formatting<'a>(v1: &'a CDEValue, v2: &'a CDEValue, options: &DiffOptions) -> Option<CDEDifferenceType<'a>> {
+    let (n1, n2) = (number(v1)?, number(v2)?);
+
+    let differ = match (v1, v2) {
+        (CDEValue::Number(_), CDEValue::Number(_)) => (n1 - n2).abs() > options.tolerance,
+        (CDEValue::String(s1), CDEValue::String(s2)) => s1 != s2,
+        _ => true
+    };
+    let equal = match options.formatting_precision {
+        Some(precision) => {
+            let scale = 10f64.powi(precision as i32);
+            (n1 * scale).round() == (n2 * scale).round()
+        }
+        None => n1 == n2,
+    };
+    match differ && equal {
+        true => Some(CDEDifferenceType::Formatting(v1, v2)),
+        false => None
+    }
+}
+
+/// Whether an old string of a number written in the old export's locale is
+/// the new number, or the new string of it, eg. "1,5" and 1.5 with
+/// NumberLocale::De
+fn locale_eq(v1: &CDEValue, v2: &CDEValue, options: &DiffOptions) -> bool {
+    let old = match (options.number_locale, v1) {
+        (Some(locale), CDEValue::String(s)) => locale.parse(s),
+        _ => None
+    };
+    let new = match v2 {
+        CDEValue::Number(n) => Some(*n),
+        CDEValue::String(s) => s.trim().parse::<f64>().ok(),
+        _ => None
+    };
+
+    match (old, new) {
+        (Some(n1), Some(n2)) => (n1 - n2).abs() <= options.tolerance,
+        _ => false
+    }
+}
+
+fn diff_values<'a>(v1: &'a CDEValue, v2: &'a CDEValue, options: &DiffOptions) -> Vec<CDEDifferenceType<'a>> {
+    if let Some(formatting) = formatting(v1, v2, options) {
+        return vec![formatting];
+    }
+    if locale_eq(v1, v2, options) {
+        return vec![];
+    }
+
+    let mut diffs = vec![];
+
+    variant_diff!(v1, v2, diffs, CDEDifferenceType::Variant);
+
+    match (v1, v2) {
+        (CDEValue::Null, CDEValue::Null) => {}
+        (CDEValue::EmptyString, CDEValue::EmptyString) => {}
+        (CDEValue::EmptyRange, CDEValue::EmptyRange) => {}
+        (CDEValue::Bool(b1), CDEValue::Bool(b2)) => {
+            eq_diff!(b1 != b2, v1, v2, diffs, CDEDifferenceType::Equality);
+        }
+        (CDEValue::String(s1), CDEValue::String(s2)) if options.normalize_text => {
+            if s1 != s2 {
+                let (n1, n2) = (text::collate(&text::normalize(s1), &options.collation), text::collate(&text::normalize(s2), &options.collation));
+                if n1 != n2 {
+                    diffs.push(truncated(v1, v2).unwrap_or_else(|| CDEDifferenceType::Text(v1, v2, text::inline_diff(&n1, &n2))));
+                }
             }
-            (CDEValue::File(f1), CDEValue::File(f2)) => {
-                eq_diff!(f1.file_name != f2.file_name || f1.django_file_id != f2.django_file_id,
-                    &self.value, &comp.value, diffs, CDEDifferenceType::Equality);
+        }
+        (CDEValue::String(s1), CDEValue::String(s2)) => {
+            if s1 != s2 && text::collate(s1, &options.collation) != text::collate(s2, &options.collation) {
+                diffs.push(truncated(v1, v2).unwrap_or(CDEDifferenceType::Equality(v1, v2)));
             }
-            (_, _) => {}
         }
-
-        match diffs.is_empty() {
-            true => None,
-            false => Some(diffs.into_iter().map(|d| CDEDifference { code: self.code.as_str(), diff: d }).collect())
+        (CDEValue::Number(n1), CDEValue::Number(n2)) => {
+            eq_diff!((n1 - n2).abs() > options.tolerance, v1, v2, diffs, CDEDifferenceType::Equality);
+        }
+        (CDEValue::Range(r1), CDEValue::Range(r2)) => {
+            eq_diff!(r1 != r2, v1, v2, diffs, CDEDifferenceType::Equality);
         }
+        (CDEValue::File(f1), CDEValue::File(f2)) => {
+            eq_diff!(f1.file_name != f2.file_name || f1.django_file_id != f2.django_file_id,
+                v1, v2, diffs, CDEDifferenceType::Equality);
+        }
+        (_, _) => {}
     }
+
+    diffs
 }
 
 #[derive(Debug)]
 pub enum SectionDifferenceType<'a> {
     Missing(Option<&'a Section>, Option<&'a Section>),
     Code(&'a str, &'a str),
+    /// Codes that differ only by case or surrounding whitespace
+    KeyCase(&'a str, &'a str),
     AllowMultiple(bool, bool),
     Variant(&'a CDESVariant, &'a CDESVariant),
-    CDEs(Vec<CDEDifference<'a>>),
+    /// A row of a multiple section on only one side, or at a different position on each
+    Row(Option<usize>, Option<usize>),
+    /// The differing CDEs, and their row on each side if the section allows multiple
+    CDEs(Option<(usize, usize)>, Vec<CDEDifference<'a>>),
 }
 
-#[derive(Debug)]
 pub struct SectionDifference<'a> {
     code: &'a str,
     diff: SectionDifferenceType<'a>,
+    /// The raw JSON of the section's CDEs on each side, if it was kept
+    raw: (Option<&'a str>, Option<&'a str>),
+    /// The section's position on each side, if it's there
+    indices: (Option<u32>, Option<u32>),
+}
+
+/// The raw JSON is only shown when it was kept
+impl<'a> fmt::Debug for SectionDifference<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut s = f.debug_struct("SectionDifference");
+        s.field("code", &self.code).field("diff", &self.diff);
+        if self.raw.0.is_some() || self.raw.1.is_some() {
+            s.field("raw", &self.raw);
+        }
+        s.finish()
+    }
 }
 
 impl<'a> Diff<'a> for Section {
     type Difference = SectionDifference<'a>;
 
-    fn diff(&'a self, comp: &'a Self) -> Option<Vec<Self::Difference>> {
+    fn diff(&'a self, comp: &'a Self, options: &DiffOptions) -> Option<Vec<Self::Difference>> {
         let mut diffs = vec![];
 
-        eq_diff!(self.code.as_str(), comp.code.as_str(), diffs, SectionDifferenceType::Code);
+        match differ_by_key(&self.code, &comp.code) {
+            true => diffs.push(SectionDifferenceType::KeyCase(&self.code, &comp.code)),
+            false => eq_diff!(&*self.code, &*comp.code, diffs, SectionDifferenceType::Code),
+        }
         eq_diff!(self.allow_multiple, comp.allow_multiple, diffs, SectionDifferenceType::AllowMultiple);
         variant_diff!(&self.cdes, &comp.cdes, diffs, SectionDifferenceType::Variant);
 
-        fn diff_cdes<'a>(c1: &'a CDEMap, c2: &'a CDEMap) -> Option<Vec<CDEDifference<'a>>> {
+        fn diff_cdes<'a>(c1: &'a CDEMap, c2: &'a CDEMap, options: &DiffOptions) -> Option<Vec<CDEDifference<'a>>> {
             let mut diffs = vec![];
 
-            c1.iter().for_each(|(k, v1)| {
+            c1.iter().filter(|(_, v)| !options.ignores(&v.code)).for_each(|(k, v1)| {
                 match c2.get(k) {
-                    None => diffs.push(CDEDifference { code: k, diff: CDEDifferenceType::Missing(Some(v1), None) }),
-                    Some(v2) => match v1.diff(v2) {
+                    None if options.retired.allows_absent(&v1.code) => {}
+                    None => diffs.push(CDEDifference { code: &v1.code, diff: CDEDifferenceType::Missing(Some(v1), None), indices: (Some(v1.index), None) }),
+                    Some(v2) => match v1.diff(v2, options) {
                         None => {}
                         Some(cde_diffs) => diffs.extend(cde_diffs)
                     }
                 }
             });
 
-            c2.iter().for_each(|(k, v)| {
-                match c1.get(k) {
-                    None => diffs.push(CDEDifference { code: k, diff: CDEDifferenceType::Missing(None, Some(v)) }),
-                    Some(_) => {}
-                }
+            c2.iter().filter(|(k, v)| !c1.contains_key(*k) && !options.ignores(&v.code)).for_each(|(_, v)| {
+                diffs.push(CDEDifference { code: &v.code, diff: CDEDifferenceType::Missing(None, Some(v)), indices: (None, Some(v.index)) })
             });
+            swap_values(c1, c2, &mut diffs, options);
 
             match diffs.is_empty() {
                 true => None,
@@ -342,19 +854,73 @@ impl<'a> Diff<'a> for Section {
             }
         }
 
+        /// Pair the rows of each side with the same value of the key CDE, in
+        /// order where several have the same value
+        fn keyed_rows(v1: &[CDEMap], v2: &[CDEMap], key: &str) -> Vec<(Option<usize>, Option<usize>)> {
+            let key_of = |row: &CDEMap| row.values().find(|c| &*c.code == key).map(|c| c.value.to_string());
+            let mut unpaired = HashMap::<Option<String>, VecDeque<usize>>::new();
+            v2.iter().enumerate().for_each(|(r2, row)| unpaired.entry(key_of(row)).or_default().push_back(r2));
+
+            let mut rows = v1.iter().enumerate()
+                .map(|(r1, row)| (Some(r1), unpaired.get_mut(&key_of(row)).and_then(|r| r.pop_front())))
+                .collect::<Vec<_>>();
+            rows.extend(unpaired.into_values().flatten().sorted().map(|r2| (None, Some(r2))));
+            rows
+        }
+
+        /// Pair the rows of each side that are still in place, then those that
+        /// moved to a row with the same values, then the rest by position
+        fn moved_rows(v1: &[CDEMap], v2: &[CDEMap], options: &DiffOptions) -> Vec<(Option<usize>, Option<usize>)> {
+            let same = |r1: usize, r2: usize| diff_cdes(&v1[r1], &v2[r2], options).is_none();
+            let mut paired = vec![None; v1.len()];
+            let mut claimed = vec![false; v2.len()];
+
+            (0..v1.len().min(v2.len())).filter(|&r| same(r, r)).for_each(|r| {
+                paired[r] = Some(r);
+                claimed[r] = true;
+            });
+            paired.iter_mut().enumerate().filter(|(_, r2)| r2.is_none()).for_each(|(r1, paired)| {
+                if let Some(r2) = (0..v2.len()).find(|&r2| !claimed[r2] && same(r1, r2)) {
+                    *paired = Some(r2);
+                    claimed[r2] = true;
+                }
+            });
+            paired.iter_mut().enumerate().take(v2.len()).filter(|(_, r2)| r2.is_none()).for_each(|(r1, paired)| {
+                if !claimed[r1] {
+                    *paired = Some(r1);
+                    claimed[r1] = true;
+                }
+            });
+
+            let mut rows = paired.into_iter().enumerate().map(|(r1, r2)| (Some(r1), r2)).collect::<Vec<_>>();
+            rows.extend((0..v2.len()).filter(|&r2| !claimed[r2]).map(|r2| (None, Some(r2))));
+            rows
+        }
+
         match (&self.cdes, &comp.cdes) {
             (CDESVariant::Single(c1), CDESVariant::Single(c2)) => {
-                match diff_cdes(c1, c2) {
+                match diff_cdes(c1, c2, options) {
                     None => {}
-                    Some(d) => diffs.push(SectionDifferenceType::CDEs(d))
+                    Some(d) => diffs.push(SectionDifferenceType::CDEs(None, d))
                 }
             }
             (CDESVariant::Multiple(v1), CDESVariant::Multiple(v2)) => {
-                v1.iter().zip(v2.iter()).for_each(|(c1, c2)| {
-                    match diff_cdes(c1, c2) {
-                        None => {}
-                        Some(d) => diffs.push(SectionDifferenceType::CDEs(d))
+                let rows = match options.row_keys.get(&*self.code) {
+                    Some(key) => keyed_rows(v1, v2, key),
+                    None if options.row_order => moved_rows(v1, v2, options),
+                    None => (0..v1.len().min(v2.len())).map(|row| (Some(row), Some(row))).collect(),
+                };
+
+                rows.into_iter().for_each(|rows| match rows {
+                    (Some(r1), Some(r2)) => {
+                        if r1 != r2 {
+                            diffs.push(SectionDifferenceType::Row(Some(r1), Some(r2)));
+                        }
+                        if let Some(d) = diff_cdes(&v1[r1], &v2[r2], options) {
+                            diffs.push(SectionDifferenceType::CDEs(Some((r1, r2)), d));
+                        }
                     }
+                    (r1, r2) => diffs.push(SectionDifferenceType::Row(r1, r2)),
                 })
             }
             (_, _) => {}
@@ -362,7 +928,12 @@ impl<'a> Diff<'a> for Section {
 
         match diffs.is_empty() {
             true => None,
-            false => Some(diffs.into_iter().map(|d| SectionDifference { code: self.code.as_str(), diff: d }).collect())
+            false => Some(diffs.into_iter().map(|d| SectionDifference {
+                code: &self.code,
+                diff: d,
+                raw: (self.raw.as_deref(), comp.raw.as_deref()),
+                indices: (Some(self.index), Some(comp.index)),
+            }).collect())
         }
     }
 }
@@ -371,6 +942,8 @@ impl<'a> Diff<'a> for Section {
 pub enum FormDifferenceType<'a> {
     Missing(Option<&'a Form>, Option<&'a Form>),
     Name(&'a str, &'a str),
+    /// Names that differ only by case or surrounding whitespace
+    KeyCase(&'a str, &'a str),
     Sections(Vec<SectionDifference<'a>>),
 }
 
@@ -378,22 +951,27 @@ pub enum FormDifferenceType<'a> {
 pub struct FormDifference<'a> {
     name: &'a str,
     diff: FormDifferenceType<'a>,
+    /// The form's position on each side, if it's there
+    indices: (Option<u32>, Option<u32>),
 }
 
 impl<'a> Diff<'a> for Form {
     type Difference = FormDifference<'a>;
 
-    fn diff(&'a self, comp: &'a Self) -> Option<Vec<Self::Difference>> {
+    fn diff(&'a self, comp: &'a Self, options: &DiffOptions) -> Option<Vec<Self::Difference>> {
         let mut diffs = vec![];
 
-        eq_diff!(self.name.as_str(), comp.name.as_str(), diffs, FormDifferenceType::Name);
+        match differ_by_key(&self.name, &comp.name) {
+            true => diffs.push(FormDifferenceType::KeyCase(&self.name, &comp.name)),
+            false => eq_diff!(&*self.name, &*comp.name, diffs, FormDifferenceType::Name),
+        }
 
         let mut section_diffs = vec![];
-        self.sections.iter().for_each(|(k, v1)| {
+        self.sections.iter().filter(|(_, v)| !options.ignores(&v.code)).for_each(|(k, v1)| {
             match comp.sections.get(k) {
-                None => section_diffs.push(SectionDifference { code: k, diff: SectionDifferenceType::Missing(Some(v1), None) }),
+                None => section_diffs.push(SectionDifference { code: &v1.code, diff: SectionDifferenceType::Missing(Some(v1), None), raw: (v1.raw.as_deref(), None), indices: (Some(v1.index), None) }),
                 Some(v2) => {
-                    match v1.diff(v2) {
+                    match v1.diff(v2, options) {
                         None => {}
                         Some(d) => section_diffs.extend(d)
                     }
@@ -401,11 +979,8 @@ impl<'a> Diff<'a> for Form {
             }
         });
 
-        comp.sections.iter().for_each(|(k, v)| {
-            match self.sections.get(k) {
-                None => section_diffs.push(SectionDifference { code: k, diff: SectionDifferenceType::Missing(None, Some(v)) }),
-                Some(_) => {}
-            }
+        comp.sections.iter().filter(|(k, v)| !self.sections.contains_key(*k) && !options.ignores(&v.code)).for_each(|(_, v)| {
+            section_diffs.push(SectionDifference { code: &v.code, diff: SectionDifferenceType::Missing(None, Some(v)), raw: (None, v.raw.as_deref()), indices: (None, Some(v.index)) })
         });
 
         if !section_diffs.is_empty() {
@@ -414,7 +989,7 @@ impl<'a> Diff<'a> for Form {
 
         match diffs.is_empty() {
             true => None,
-            false => Some(diffs.into_iter().map(|d| FormDifference { name: self.name.as_str(), diff: d }).collect())
+            false => Some(diffs.into_iter().map(|d| FormDifference { name: &self.name, diff: d, indices: (Some(self.index), Some(comp.index)) }).collect())
         }
     }
 }
@@ -424,52 +999,109 @@ pub enum ClinicalDatumDifferenceType<'a> {
     Missing(Option<&'a ClinicalDatum>, Option<&'a ClinicalDatum>),
     Patient(u32, u32),
     Variant(&'a ClinicalDatumVariant, &'a ClinicalDatumVariant),
+    TimestampRegressed(&'a str, &'a str),
+    /// A field of paired history snapshots' records, and its values
+    Metadata(&'static str, Option<String>, Option<String>),
+    /// A member of the records that isn't read into their data, by its path in the record, and its values
+    Extra(JsonDifference<'a>),
     Forms(Vec<FormDifference<'a>>),
 }
 
-#[derive(Debug)]
 pub struct ClinicalDatumDifference<'a> {
-    proto_context: ProtoContext,
+    context: ContextKey,
+    timestamps: (Option<&'a str>, Option<&'a str>),
     diff: ClinicalDatumDifferenceType<'a>,
+    /// The datum on each side, if it's there, to point into its record
+    data: (Option<&'a ClinicalDatum>, Option<&'a ClinicalDatum>),
+}
+
+/// The data are left out, as they repeat every form
+impl<'a> fmt::Debug for ClinicalDatumDifference<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ClinicalDatumDifference")
+            .field("context", &self.context)
+            .field("timestamps", &self.timestamps)
+            .field("diff", &self.diff)
+            .finish()
+    }
 }
 
 impl<'a> Diff<'a> for ClinicalDatum {
     type Difference = ClinicalDatumDifference<'a>;
 
-    fn diff(&'a self, comp: &'a Self) -> Option<Vec<Self::Difference>> {
+    fn diff(&'a self, comp: &'a Self, options: &DiffOptions) -> Option<Vec<Self::Difference>> {
         let mut diffs = vec![];
 
         eq_diff!(self.patient, comp.patient, diffs, ClinicalDatumDifferenceType::Patient);
         variant_diff!(&self.variant, &comp.variant, diffs, ClinicalDatumDifferenceType::Variant);
 
-        let mut form_diffs = vec![];
+        if options.timestamps && self.timestamp_regressed(comp) {
+            diffs.push(ClinicalDatumDifferenceType::TimestampRegressed(self.timestamp().unwrap(), comp.timestamp().unwrap()));
+        }
 
-        self.forms.iter().for_each(|(k, v1)| {
-            match comp.forms.get(k) {
-                None => form_diffs.push(FormDifference { name: k, diff: FormDifferenceType::Missing(Some(v1), None) }),
-                Some(v2) => {
-                    match v1.diff(v2) {
-                        None => {}
-                        Some(d) => form_diffs.extend(d)
-                    }
-                }
+        if options.history_metadata && matches!((&self.variant, &comp.variant), (ClinicalDatumVariant::History, ClinicalDatumVariant::History)) {
+            let metadata = |field, old: Option<String>, new: Option<String>| ClinicalDatumDifferenceType::Metadata(field, old, new);
+            if self.username != comp.username {
+                diffs.push(metadata("username", self.username.clone(), comp.username.clone()));
             }
-        });
+            let timestamps_differ = match (self.timestamp(), comp.timestamp()) {
+                (Some(t1), Some(t2)) => consents::timestamps_differ(t1, t2, options.history_time_tolerance),
+                (t1, t2) => t1 != t2,
+            };
+            if timestamps_differ {
+                diffs.push(metadata("timestamp", self.timestamp.clone(), comp.timestamp.clone()));
+            }
+            if self.context_id != comp.context_id {
+                diffs.push(metadata("context_id", self.context_id.map(|c| c.to_string()), comp.context_id.map(|c| c.to_string())));
+            }
+        }
 
-        comp.forms.iter().for_each(|(k, v)| {
-            match self.forms.get(k) {
-                None => form_diffs.push(FormDifference { name: k, diff: FormDifferenceType::Missing(None, Some(v)) }),
-                Some(_) => {}
+        // Members of the records besides their data are compared as they're given
+        let mut extra = vec![];
+        self.extra.keys().chain(comp.extra.keys()).collect::<BTreeSet<&String>>().into_iter()
+            .filter(|path| !path.rsplit('/').next().is_some_and(|name| options.ignores(name)))
+            .for_each(|path| extra.extend(options.json_diff.diff(&format!("/{}", path), self.extra.get(path), comp.extra.get(path))));
+        diffs.extend(extra.into_iter().map(ClinicalDatumDifferenceType::Extra));
+
+        let diff_form = |(k, v1): (&'a Code, &'a Form)| -> Vec<FormDifference<'a>> {
+            match comp.forms.get(k) {
+                None => vec![FormDifference { name: &v1.name, diff: FormDifferenceType::Missing(Some(v1), None), indices: (Some(v1.index), None) }],
+                Some(v2) => v1.diff(v2, options).unwrap_or_default()
             }
+        };
+
+        // Forms that aren't migrated on purpose are counted once for the pair, whichever side has them
+        self.forms.values()
+            .chain(comp.forms.iter().filter(|(k, _)| !self.forms.contains_key(*k)).map(|(_, v)| v))
+            .for_each(|v| options.skipped_forms.skip(&v.name));
+
+        let mut form_diffs = match &options.form_pool {
+            Some(pool) => pool.install(|| {
+                self.forms.par_iter().filter(|(_, v)| !options.skips_form(&v.name)).flat_map_iter(diff_form).collect::<Vec<FormDifference>>()
+            }),
+            None => self.forms.iter().filter(|(_, v)| !options.skips_form(&v.name)).flat_map(diff_form).collect()
+        };
+
+        comp.forms.iter().filter(|(k, v)| !self.forms.contains_key(*k) && !options.skips_form(&v.name)).for_each(|(_, v)| {
+            form_diffs.push(FormDifference { name: &v.name, diff: FormDifferenceType::Missing(None, Some(v)), indices: (None, Some(v.index)) })
         });
 
+        // Forms come out of their maps (and the pool) in any order
+        form_diffs.sort_by_key(|d| d.name);
+
         if !form_diffs.is_empty() {
             diffs.push(ClinicalDatumDifferenceType::Forms(form_diffs));
         }
 
         match diffs.is_empty() {
             true => None,
-            false => Some(diffs.into_iter().map(|d| ClinicalDatumDifference { proto_context: self.forms.keys().map(|k| k.to_string()).collect(), diff: d }).collect())
+            // The slice gives the context it was paired by
+            false => Some(diffs.into_iter().map(|d| ClinicalDatumDifference {
+                context: ContextKey::Forms(self.proto_context()),
+                timestamps: (self.timestamp(), comp.timestamp()),
+                diff: d,
+                data: (Some(self), Some(comp)),
+            }).collect())
         }
     }
 }
@@ -490,28 +1122,50 @@ pub struct PatientSliceDifference<'a> {
 impl<'a> Diff<'a> for PatientSlice {
     type Difference = PatientSliceDifference<'a>;
 
-    fn diff(&'a self, comp: &'a Self) -> Option<Vec<Self::Difference>> {
+    fn diff(&'a self, comp: &'a Self, options: &DiffOptions) -> Option<Vec<Self::Difference>> {
         let mut diffs = vec![];
 
         eq_diff!(self.patient, comp.patient, diffs, PatientSliceDifferenceType::Patient);
 
         let mut clinical_data_diffs = vec![];
 
+        // Contexts of forms are matched as the registry's plugin maps them, if it has one, and
+        // without the forms that aren't migrated on purpose
+        let context = |k: &ContextKey| match k {
+            ContextKey::Forms(forms) => {
+                let forms = forms.iter().filter(|f| !options.skipped_forms.contains(f)).cloned().collect::<ProtoContext>();
+                ContextKey::Forms(match &options.plugin {
+                    Some(plugin) => plugin.context(forms),
+                    None => forms,
+                })
+            }
+            k => k.clone(),
+        };
+        let contexts = self.clinical_data.keys().map(context).collect::<HashSet<ContextKey>>();
+        let comp_data = comp.clinical_data.iter().map(|(k, v)| (context(k), v)).collect::<HashMap<ContextKey, &ClinicalDatum>>();
+
         self.clinical_data.iter().for_each(|(k, v1)| {
-            match comp.clinical_data.get(k) {
-                None => clinical_data_diffs.push(ClinicalDatumDifference { proto_context: v1.proto_context(), diff: ClinicalDatumDifferenceType::Missing(Some(v1), None) }),
-                Some(v2) => match v1.diff(&v2) {
+            match comp_data.get(&context(k)) {
+                None => clinical_data_diffs.push(ClinicalDatumDifference {
+                    context: k.clone(),
+                    timestamps: (v1.timestamp(), None),
+                    diff: ClinicalDatumDifferenceType::Missing(Some(v1), None),
+                    data: (Some(v1), None),
+                }),
+                Some(v2) => match v1.diff(v2, options) {
                     None => {}
-                    Some(d) => clinical_data_diffs.extend(d)
+                    Some(d) => clinical_data_diffs.extend(d.into_iter().map(|d| ClinicalDatumDifference { context: k.clone(), ..d }))
                 }
             }
         });
 
-        comp.clinical_data.iter().for_each(|(k, v)| {
-            match self.clinical_data.get(k) {
-                None => clinical_data_diffs.push(ClinicalDatumDifference { proto_context: v.proto_context(), diff: ClinicalDatumDifferenceType::Missing(None, Some(v)) }),
-                Some(_) => {}
-            }
+        comp.clinical_data.iter().filter(|(k, _)| !contexts.contains(&context(k))).for_each(|(k, v)| {
+            clinical_data_diffs.push(ClinicalDatumDifference {
+                context: k.clone(),
+                timestamps: (None, v.timestamp()),
+                diff: ClinicalDatumDifferenceType::Missing(None, Some(v)),
+                data: (None, Some(v)),
+            })
         });
 
         if !clinical_data_diffs.is_empty() {
@@ -520,7 +1174,211 @@ impl<'a> Diff<'a> for PatientSlice {
 
         match diffs.is_empty() {
             true => None,
-            false => Some(diffs.into_iter().map(|d| PatientSliceDifference { patient: self.patient, ids: self.clinical_data.values().map(|k| k.id).sorted().join(","), diff: d }).collect())
+            false => Some(diffs.into_iter().map(|d| PatientSliceDifference { patient: self.patient, ids: self.ids(), diff: d }).collect())
+        }
+    }
+}
+
+fn record(location: &Location, kind: DifferenceKind, old: Option<String>, new: Option<String>) -> DifferenceRecord {
+    DifferenceRecord { location: location.clone(), kind, old, new, detail: None, triage: None }
+}
+
+fn both(old: impl ToString, new: impl ToString) -> (Option<String>, Option<String>) {
+    (Some(old.to_string()), Some(new.to_string()))
+}
+
+/// The location with each side's JSON pointer followed down to a member,
+/// eg. a form's position in its list, for the sides that have it
+fn below(location: Location, old: Option<impl fmt::Display>, new: Option<impl fmt::Display>) -> Location {
+    let down = |pointer: &Option<String>, member: Option<String>| pointer.as_ref().zip(member).map(|(p, m)| format!("{}/{}", p, m));
+    Location {
+        old_pointer: down(&location.old_pointer, old.map(|m| m.to_string())),
+        new_pointer: down(&location.new_pointer, new.map(|m| m.to_string())),
+        ..location
+    }
+}
+
+/// The location with both sides' JSON pointers followed down to a field
+fn at(location: Location, field: &str) -> Location {
+    below(location, Some(field), Some(field))
+}
+
+impl CDESVariant {
+    fn name(&self) -> &'static str {
+        match self {
+            CDESVariant::Single(_) => "single",
+            CDESVariant::Multiple(_) => "multiple",
+        }
+    }
+}
+
+impl<'a> CDEDifference<'a> {
+    fn flatten(&self, location: &Location, records: &mut Vec<DifferenceRecord>) {
+        let location = below(Location { cde: Some(self.code.to_string()), ..location.clone() }, self.indices.0, self.indices.1);
+        let location = match &self.diff {
+            CDEDifferenceType::Missing(_, _) => location,
+            CDEDifferenceType::KeyCase(_, _) => at(location, "code"),
+            _ => at(location, "value"),
+        };
+        let (kind, (old, new)) = match &self.diff {
+            CDEDifferenceType::Missing(c1, c2) => {
+                (DifferenceKind::Missing, (c1.map(|c| c.value.to_string()), c2.map(|c| c.value.to_string())))
+            }
+            CDEDifferenceType::Variant(v1, v2) => (DifferenceKind::Variant, both(v1, v2)),
+            CDEDifferenceType::Calculated(v1, v2) => (DifferenceKind::Calculated, both(v1, v2)),
+            CDEDifferenceType::KeyCase(c1, c2) => (DifferenceKind::KeyCase, both(c1, c2)),
+            CDEDifferenceType::Formatting(v1, v2) => (DifferenceKind::Formatting, both(v1, v2)),
+            CDEDifferenceType::Unexpected(v1, v2, expected) => {
+                let (old, new) = both(v1, v2);
+                let detail = Some(format!("expected {}", expected));
+                return records.push(DifferenceRecord { detail, ..record(&location, DifferenceKind::Unexpected, old, new) });
+            }
+            CDEDifferenceType::Equality(v1, v2) => {
+                let (old, new) = both(v1, v2);
+                let detail = long_text_diff(v1, v2);
+                return records.push(DifferenceRecord { detail, ..record(&location, DifferenceKind::Equality, old, new) });
+            }
+            CDEDifferenceType::Text(v1, v2, inline) => {
+                let (old, new) = both(v1, v2);
+                return records.push(DifferenceRecord { detail: Some(inline.clone()), ..record(&location, DifferenceKind::Text, old, new) });
+            }
+            CDEDifferenceType::SwappedValues(v1, v2, other) => {
+                let (old, new) = both(v1, v2);
+                let detail = Some(format!("swapped with {}, which went from {} to {}", other, v2, v1));
+                return records.push(DifferenceRecord { detail, ..record(&location, DifferenceKind::SwappedValues, old, new) });
+            }
+            CDEDifferenceType::Truncated { old, new, old_len, new_len, common_prefix_len } => {
+                let (old, new) = both(old, new);
+                let detail = Some(format!("{} characters in the old, {} in the new, {} in common", old_len, new_len, common_prefix_len));
+                return records.push(DifferenceRecord { detail, ..record(&location, DifferenceKind::Truncated, old, new) });
+            }
+        };
+
+        records.push(record(&location, kind, old, new));
+    }
+}
+
+impl<'a> SectionDifference<'a> {
+    fn flatten(&self, location: &Location, records: &mut Vec<DifferenceRecord>) {
+        let location = Location {
+            section: Some(self.code.to_string()),
+            old_raw: self.raw.0.map(String::from),
+            new_raw: self.raw.1.map(String::from),
+            ..location.clone()
+        };
+        let location = below(location, self.indices.0, self.indices.1);
+        let (location, kind, (old, new)) = match &self.diff {
+            SectionDifferenceType::Missing(s1, s2) => {
+                (location, DifferenceKind::Missing, (s1.map(|s| s.code.to_string()), s2.map(|s| s.code.to_string())))
+            }
+            SectionDifferenceType::Code(c1, c2) => (at(location, "code"), DifferenceKind::Code, both(c1, c2)),
+            SectionDifferenceType::KeyCase(c1, c2) => (at(location, "code"), DifferenceKind::KeyCase, both(c1, c2)),
+            SectionDifferenceType::AllowMultiple(a1, a2) => (at(location, "allow_multiple"), DifferenceKind::AllowMultiple, both(a1, a2)),
+            SectionDifferenceType::Variant(v1, v2) => (at(location, "cdes"), DifferenceKind::Variant, both(v1.name(), v2.name())),
+            SectionDifferenceType::Row(r1, r2) => {
+                let row = |r: &Option<usize>| r.map(|r| format!("row {}", r));
+                let kind = match (r1, r2) {
+                    (Some(_), Some(_)) => DifferenceKind::Reordered,
+                    _ => DifferenceKind::Missing,
+                };
+                (below(at(location, "cdes"), *r1, *r2), kind, (row(r1), row(r2)))
+            }
+            SectionDifferenceType::CDEs(rows, diffs) => {
+                let location = match rows {
+                    Some((r1, r2)) => below(at(location, "cdes"), Some(r1), Some(r2)),
+                    None => at(location, "cdes"),
+                };
+                return diffs.iter().for_each(|d| d.flatten(&location, records));
+            }
+        };
+
+        records.push(record(&location, kind, old, new));
+    }
+}
+
+impl<'a> FormDifference<'a> {
+    fn flatten(&self, location: &Location, records: &mut Vec<DifferenceRecord>) {
+        let location = below(Location { form: Some(self.name.to_string()), ..location.clone() }, self.indices.0, self.indices.1);
+        let (location, kind, (old, new)) = match &self.diff {
+            FormDifferenceType::Missing(f1, f2) => {
+                (location, DifferenceKind::Missing, (f1.map(|f| f.name.to_string()), f2.map(|f| f.name.to_string())))
+            }
+            FormDifferenceType::Name(n1, n2) => (at(location, "name"), DifferenceKind::Name, both(n1, n2)),
+            FormDifferenceType::KeyCase(n1, n2) => (at(location, "name"), DifferenceKind::KeyCase, both(n1, n2)),
+            FormDifferenceType::Sections(diffs) => {
+                let location = at(location, "sections");
+                return diffs.iter().for_each(|d| d.flatten(&location, records));
+            }
+        };
+
+        records.push(record(&location, kind, old, new));
+    }
+}
+
+impl<'a> ClinicalDatumDifference<'a> {
+    fn flatten(&self, location: &Location, records: &mut Vec<DifferenceRecord>) {
+        let location = Location {
+            context: self.context.to_string(),
+            old_timestamp: self.timestamps.0.map(String::from),
+            new_timestamp: self.timestamps.1.map(String::from),
+            old_pointer: self.data.0.map(ClinicalDatum::pointer),
+            new_pointer: self.data.1.map(ClinicalDatum::pointer),
+            ..location.clone()
+        };
+        let (kind, (old, new)) = match &self.diff {
+            ClinicalDatumDifferenceType::Missing(d1, d2) => {
+                (DifferenceKind::Missing, (d1.map(|d| d.id.to_string()), d2.map(|d| d.id.to_string())))
+            }
+            ClinicalDatumDifferenceType::Patient(p1, p2) => (DifferenceKind::Patient, both(p1, p2)),
+            ClinicalDatumDifferenceType::Variant(v1, v2) => {
+                (DifferenceKind::Variant, both(format!("{:?}", v1), format!("{:?}", v2)))
+            }
+            ClinicalDatumDifferenceType::TimestampRegressed(t1, t2) => (DifferenceKind::Timestamp, both(t1, t2)),
+            ClinicalDatumDifferenceType::Metadata(field, old, new) => {
+                // The username and timestamp are of the history's data, the context of its record
+                let member = match *field {
+                    "context_id" => "fields/context_id".to_string(),
+                    field => format!("fields/data/{}", field),
+                };
+                let location = Location { field: Some(field.to_string()), ..at(location, &member) };
+                return records.push(record(&location, DifferenceKind::Attribution, old.clone(), new.clone()));
+            }
+            ClinicalDatumDifferenceType::Extra(diff) => {
+                let path = diff.path.trim_start_matches('/');
+                let (old, new) = diff.values();
+                let location = Location { field: Some(path.to_string()), ..at(location, path) };
+                return records.push(record(&location, diff.kind(), old, new));
+            }
+            ClinicalDatumDifferenceType::Forms(diffs) => {
+                let location = Location {
+                    old_pointer: self.data.0.map(ClinicalDatum::forms_pointer),
+                    new_pointer: self.data.1.map(ClinicalDatum::forms_pointer),
+                    ..location
+                };
+                return diffs.iter().for_each(|d| d.flatten(&location, records));
+            }
+        };
+
+        records.push(record(&location, kind, old, new));
+    }
+}
+
+impl<'a> PatientSliceDifference<'a> {
+    /// Flatten the difference into one record per leaf difference
+    pub fn records(&self) -> Vec<DifferenceRecord> {
+        let location = Location { patient: self.patient, ids: self.ids.clone(), ..Location::default() };
+        let mut records = vec![];
+
+        match &self.diff {
+            PatientSliceDifferenceType::Patient(p1, p2) => {
+                let (old, new) = both(p1, p2);
+                records.push(record(&location, DifferenceKind::Patient, old, new));
+            }
+            PatientSliceDifferenceType::ClinicalData(diffs) => {
+                diffs.iter().for_each(|d| d.flatten(&location, &mut records));
+            }
         }
+
+        records
     }
 }
@@ -0,0 +1,79 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+
+/// Labels of patients from an external CSV file, so totals can be broken down
+/// by cohort, as migration bugs often cluster by the site the data came from
+///
+/// The header names the dimensions, and each row gives a patient's labels
+///
+/// ```csv
+/// patient,site,arm,enrolled
+/// 1,Perth,placebo,2019
+/// 2,Sydney,treatment,2020
+/// ```
+#[derive(Debug, Default)]
+pub struct Cohorts {
+    dimensions: Vec<String>,
+    labels: HashMap<u32, Vec<String>>,
+}
+
+/// The label of patients a cohort file doesn't give one
+const UNLABELLED: &str = "(none)";
+
+impl Cohorts {
+    pub fn load(path: &str) -> Result<Cohorts, Box<dyn Error>> {
+        let text = fs::read_to_string(path)?;
+        let mut lines = text.lines().enumerate().filter(|(_, l)| !l.trim().is_empty());
+
+        let header = lines.next().ok_or("Cohort file is empty")?.1;
+        let dimensions = fields(header).into_iter().skip(1).collect::<Vec<String>>();
+        if dimensions.is_empty() {
+            return Err("Cohort file has no columns of labels after the patient id".into());
+        }
+
+        let labels = lines.map(|(i, line)| {
+            let mut row = fields(line);
+            if row.len() != dimensions.len() + 1 {
+                return Err(format!("Line {} has {} columns, not {}", i + 1, row.len(), dimensions.len() + 1));
+            }
+            let patient = row.remove(0).parse::<u32>().map_err(|e| format!("Line {} has an invalid patient id: {}", i + 1, e))?;
+
+            Ok((patient, row))
+        }).collect::<Result<HashMap<u32, Vec<String>>, String>>()?;
+
+        Ok(Cohorts { dimensions, labels })
+    }
+
+    /// The cohorts a patient is in, one per dimension, as "dimension=label"
+    pub fn of(&self, patient: u32) -> Vec<String> {
+        let labels = self.labels.get(&patient);
+        self.dimensions.iter().enumerate().map(|(i, dimension)| {
+            let label = labels.map_or(UNLABELLED, |l| l[i].as_str());
+            format!("{}={}", dimension, label)
+        }).collect()
+    }
+}
+
+/// Split a CSV line into its fields, unquoting quoted ones
+fn fields(line: &str) -> Vec<String> {
+    let mut fields = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.trim_end_matches('\r').chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match (c, quoted) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            ('"', _) => quoted = !quoted,
+            (',', false) => fields.push(std::mem::take(&mut field)),
+            (c, _) => field.push(c),
+        }
+    }
+    fields.push(field);
+
+    fields.into_iter().map(|f| f.trim().to_string()).collect()
+}
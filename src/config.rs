@@ -0,0 +1,132 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use crate::expect::TransformSpec;
+use crate::metadata;
+use crate::numbers::NumberLocale;
+use crate::suppressions::IgnoreRule;
+
+/// The name of the config file looked for in the working directory
+pub const DEFAULT_CONFIG: &str = "diffmig.toml";
+
+/// Defaults for command line options, each overridden by the option when it's given
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct Settings {
+    pub old_zip: Option<String>,
+    pub new_zip: Option<String>,
+    /// What the exports are called in reports and prompts, rather than "old" and "new"
+    pub old_label: Option<String>,
+    pub new_label: Option<String>,
+    pub tolerance: Option<f64>,
+    /// The decimal places numbers are rounded to when telling whether they only differ by formatting
+    pub formatting_precision: Option<u32>,
+    /// The locale the old export writes numbers stored as strings in, eg. "de" for "1,5"
+    pub number_locale: Option<NumberLocale>,
+    /// Codes left out of the comparison, each optionally with who left it out, why and until when
+    pub ignore: Option<Vec<IgnoreRule>>,
+    pub output: Option<Vec<String>>,
+    /// The intentional changes of CDE values, by CDE code
+    pub expect: Option<HashMap<String, TransformSpec>>,
+    /// How much the differences of each CDE count toward a patient's score, by CDE code
+    pub weights: Option<HashMap<String, f64>>,
+    /// The CDE the rows of each multiple section are matched by, by section code
+    pub row_keys: Option<HashMap<String, String>>,
+    /// Whether forms, sections and CDEs are matched by their codes trimmed and in lower case
+    pub normalize_keys: Option<bool>,
+    /// CDE codes retired in the new system, which may be absent from the new export but are compared where it has them
+    pub retired: Option<Vec<String>>,
+}
+
+impl Settings {
+    /// Take each setting from self, falling back to base
+    fn or(self, base: Settings) -> Settings {
+        Settings {
+            old_zip: self.old_zip.or(base.old_zip),
+            new_zip: self.new_zip.or(base.new_zip),
+            old_label: self.old_label.or(base.old_label),
+            new_label: self.new_label.or(base.new_label),
+            tolerance: self.tolerance.or(base.tolerance),
+            formatting_precision: self.formatting_precision.or(base.formatting_precision),
+            number_locale: self.number_locale.or(base.number_locale),
+            ignore: self.ignore.or(base.ignore),
+            output: self.output.or(base.output),
+            expect: self.expect.or(base.expect),
+            weights: self.weights.or(base.weights),
+            row_keys: self.row_keys.or(base.row_keys),
+            normalize_keys: self.normalize_keys.or(base.normalize_keys),
+            retired: self.retired.or(base.retired),
+        }
+    }
+}
+
+/// A diffmig.toml file
+///
+/// ```toml
+/// old_label = "MongoDB v1.28"
+/// new_label = "Postgres v5.2"
+/// tolerance = 0.01
+/// formatting_precision = 6
+/// ignore = [
+///     "CDEPatientNextOfKin",
+///     { code = "CDEWeight", author = "jsmith", reason = "Rounded by the migration", expires = "2025-06-30" },
+/// ]
+/// retired = ["CDEFamilyHistoryNotes"]
+///
+/// [expect.CDE_SEX]
+/// M = "Male"
+/// F = "Female"
+///
+/// [weights]
+/// CDEDiagnosis = 5.0
+///
+/// [row_keys]
+/// MedicationSection = "CDEMedicationName"
+///
+/// [registries.DM1]
+/// tolerance = 0.001
+/// number_locale = "de"
+/// normalize_keys = true
+/// ```
+#[derive(Debug, Default, Deserialize)]
+pub struct Config {
+    #[serde(flatten)]
+    pub defaults: Settings,
+    #[serde(default)]
+    pub registries: HashMap<String, Settings>,
+    /// The file the config was read from, if any
+    #[serde(skip)]
+    pub path: Option<String>,
+    /// The SHA-256 of the file the config was read from, if any
+    #[serde(skip)]
+    pub sha256: Option<String>,
+}
+
+impl Config {
+    /// Read the config at path, or diffmig.toml in the working directory if
+    /// there is one, or else an empty config
+    pub fn load(path: Option<&str>) -> Result<Config, Box<dyn Error>> {
+        let path = match path {
+            Some(path) => path,
+            None if Path::new(DEFAULT_CONFIG).exists() => DEFAULT_CONFIG,
+            None => return Ok(Config::default())
+        };
+
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("Failed reading config {}: {}", path, e))?;
+
+        let config = toml::from_str::<Config>(&text).map_err(|e| format!("Invalid config {}: {}", path, e))?;
+
+        Ok(Config { path: Some(path.to_string()), sha256: Some(metadata::sha256(text.as_bytes())), ..config })
+    }
+
+    /// The settings for a registry, with its overrides applied over the defaults
+    pub fn settings(&self, registry_code: Option<&str>) -> Settings {
+        match registry_code.and_then(|code| self.registries.get(code)) {
+            Some(overrides) => overrides.clone().or(self.defaults.clone()),
+            None => self.defaults.clone()
+        }
+    }
+}
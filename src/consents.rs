@@ -0,0 +1,241 @@
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::Read;
+use time::format_description::well_known::Rfc3339;
+use time::macros::format_description;
+use time::{Date, Duration, OffsetDateTime, PrimitiveDateTime};
+
+use crate::diff::{Diff, DiffOptions};
+use crate::fixture::{self, ParseError};
+use crate::migrated_registry::MigratedRegistry;
+use crate::patients::{ModelDifference, PatientModel};
+use crate::report::{DifferenceKind, DifferenceRecord, Location};
+
+/// A record of a consent fixture, which holds questions, values or both
+/// depending on where the export put them
+#[derive(Debug, Deserialize)]
+struct ConsentRecord {
+    model: String,
+    pk: i64,
+    fields: HashMap<String, Value>,
+}
+
+/// A patient's answer to a consent question
+#[derive(Debug)]
+pub struct ConsentValue {
+    pub patient: u32,
+    pub code: String,
+    pub answer: bool,
+    pub first_save: Option<String>,
+    pub last_update: Option<String>,
+}
+
+/// The consent questions and values of the fixtures of an export, read one
+/// fixture at a time
+///
+/// Records are told apart by their model rather than their fixture, as
+/// consents have moved between tables across versions
+#[derive(Debug, Default)]
+pub struct ConsentFixtures {
+    questions: HashMap<i64, String>,
+    values: Vec<ConsentRecord>,
+}
+
+impl ConsentFixtures {
+    pub fn read(&mut self, reader: impl Read) -> Result<(), ParseError> {
+        for text in MigratedRegistry::read_array_file_to_records(reader) {
+            let record = fixture::parse_at::<ConsentRecord>(&text, "")?;
+            match record.model.rsplit('.').next() {
+                Some("consentquestion") => {
+                    let code = record.fields.get("code").and_then(Value::as_str)
+                        .ok_or_else(|| ParseError::new("/fields/code", "consent question has no code").with_pk(record.pk))?;
+                    self.questions.insert(record.pk, code.to_string());
+                }
+                Some("consentvalue") => self.values.push(record),
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Match each value to its question's code
+    pub fn consents(self) -> Result<Consents, ParseError> {
+        let questions = self.questions;
+        let mut values = BTreeMap::new();
+
+        for record in self.values {
+            let field = |name: &str| record.fields.get(name);
+            let patient = field("patient").and_then(Value::as_i64)
+                .ok_or_else(|| ParseError::new("/fields/patient", "consent value has no patient").with_pk(record.pk))?;
+            let code = field("consent_question").and_then(Value::as_i64).and_then(|q| questions.get(&q))
+                .ok_or_else(|| ParseError::new("/fields/consent_question", "consent value has no known question").with_record(record.pk, patient))?;
+
+            let value = ConsentValue {
+                patient: patient as u32,
+                code: code.clone(),
+                answer: field("answer").and_then(Value::as_bool).unwrap_or(false),
+                first_save: field("first_save").and_then(Value::as_str).map(String::from),
+                last_update: field("last_update").and_then(Value::as_str).map(String::from),
+            };
+
+            // A second answer to the same question can't be told apart from the first
+            if values.insert((value.patient, value.code.clone()), value).is_some() {
+                return Err(ParseError::new("/fields/consent_question", format!("consent value duplicates another for question {}", code)).with_record(record.pk, patient));
+            }
+        }
+
+        Ok(Consents { values })
+    }
+}
+
+/// The consent values of an export, keyed by patient and consent question code
+#[derive(Debug, Default)]
+pub struct Consents {
+    values: BTreeMap<(u32, String), ConsentValue>,
+}
+
+impl Consents {
+    fn patient(&self, id: u32) -> impl Iterator<Item=(&String, &ConsentValue)> {
+        self.values.range((id, String::new())..(id + 1, String::new())).map(|((_, code), v)| (code, v))
+    }
+
+    /// The consents with their patients' ids changed, eg. to their ids in the other export
+    pub fn renumbered(self, id: impl Fn(u32) -> u32) -> Consents {
+        Consents { values: self.values.into_iter().map(|((patient, code), v)| ((id(patient), code), v)).collect() }
+    }
+}
+
+/// The instant of an RFC 3339 timestamp, or of an ISO 8601 date or date and
+/// time without an offset, which is taken to be UTC
+///
+/// The date and time may be separated by a space, and a time may have a
+/// fraction of a second.
+pub fn instant(timestamp: &str) -> Option<OffsetDateTime> {
+    let timestamp = match timestamp.get(10..11) {
+        Some(" ") => format!("{}T{}", &timestamp[..10], &timestamp[11..]),
+        _ => timestamp.to_string(),
+    };
+
+    OffsetDateTime::parse(&timestamp, &Rfc3339).ok()
+        .or_else(|| PrimitiveDateTime::parse(&timestamp, format_description!("[year]-[month]-[day]T[hour]:[minute]:[second][optional [.[subsecond]]]")).ok().map(PrimitiveDateTime::assume_utc))
+        .or_else(|| Date::parse(&timestamp, format_description!("[year]-[month]-[day]")).ok().map(|d| d.midnight().assume_utc()))
+}
+
+/// Seconds since the epoch of a timestamp, as read by [`instant`]
+pub fn seconds(timestamp: &str) -> Option<i64> {
+    instant(timestamp).map(OffsetDateTime::unix_timestamp)
+}
+
+/// Whether two timestamps are further apart than the tolerance (in seconds),
+/// comparing them as text if either can't be read
+pub fn timestamps_differ(t1: &str, t2: &str, tolerance: i64) -> bool {
+    match (instant(t1), instant(t2)) {
+        (Some(i1), Some(i2)) => (i1 - i2).abs() > Duration::seconds(tolerance),
+        _ => t1 != t2
+    }
+}
+
+#[derive(Debug)]
+pub enum ConsentDifferenceType<'a> {
+    Missing(Option<&'a ConsentValue>, Option<&'a ConsentValue>),
+    Answer(bool, bool),
+    FirstSave(Option<&'a str>, Option<&'a str>),
+    LastUpdate(Option<&'a str>, Option<&'a str>),
+}
+
+#[derive(Debug)]
+pub struct ConsentDifference<'a> {
+    patient: u32,
+    code: &'a str,
+    diff: ConsentDifferenceType<'a>,
+}
+
+impl<'a> Diff<'a> for ConsentValue {
+    type Difference = ConsentDifference<'a>;
+
+    fn diff(&'a self, comp: &'a Self, options: &DiffOptions) -> Option<Vec<Self::Difference>> {
+        let mut diffs = vec![];
+
+        if self.answer != comp.answer {
+            diffs.push(ConsentDifferenceType::Answer(self.answer, comp.answer));
+        }
+
+        let differ = |t1: &Option<String>, t2: &Option<String>| match (t1, t2) {
+            (Some(t1), Some(t2)) => timestamps_differ(t1, t2, options.consent_time_tolerance),
+            (t1, t2) => t1 != t2
+        };
+        if differ(&self.first_save, &comp.first_save) {
+            diffs.push(ConsentDifferenceType::FirstSave(self.first_save.as_deref(), comp.first_save.as_deref()));
+        }
+        if differ(&self.last_update, &comp.last_update) {
+            diffs.push(ConsentDifferenceType::LastUpdate(self.last_update.as_deref(), comp.last_update.as_deref()));
+        }
+
+        match diffs.is_empty() {
+            true => None,
+            false => Some(diffs.into_iter().map(|d| ConsentDifference { patient: self.patient, code: &self.code, diff: d }).collect())
+        }
+    }
+}
+
+impl<'a> ConsentDifference<'a> {
+    /// Flatten the difference into a record, under the "consents" context
+    pub fn record(&self) -> DifferenceRecord {
+        let location = Location {
+            patient: self.patient,
+            context: "consents".to_string(),
+            field: Some(self.code.to_string()),
+            ..Location::default()
+        };
+        let with_field = |suffix: &str| Location { field: Some(format!("{}.{}", self.code, suffix)), ..location.clone() };
+        let text = |t: &Option<&str>| t.map(String::from);
+
+        let (location, kind, old, new) = match &self.diff {
+            ConsentDifferenceType::Missing(v1, v2) => {
+                (location.clone(), DifferenceKind::Missing, v1.map(|v| v.answer.to_string()), v2.map(|v| v.answer.to_string()))
+            }
+            ConsentDifferenceType::Answer(a1, a2) => {
+                (location.clone(), DifferenceKind::Equality, Some(a1.to_string()), Some(a2.to_string()))
+            }
+            ConsentDifferenceType::FirstSave(t1, t2) => (with_field("first_save"), DifferenceKind::Timestamp, text(t1), text(t2)),
+            ConsentDifferenceType::LastUpdate(t1, t2) => (with_field("last_update"), DifferenceKind::Timestamp, text(t1), text(t2)),
+        };
+
+        DifferenceRecord { location, kind, old, new, detail: None, triage: None }
+    }
+}
+
+/// The consents of both exports
+pub struct ConsentModel {
+    old: Consents,
+    new: Consents,
+}
+
+impl ConsentModel {
+    pub fn new(old: Consents, new: Consents) -> ConsentModel {
+        ConsentModel { old, new }
+    }
+}
+
+impl PatientModel for ConsentModel {
+    fn ids(&self) -> BTreeSet<u32> {
+        self.old.values.keys().chain(self.new.values.keys()).map(|(id, _)| *id).collect()
+    }
+
+    fn diff_patient(&self, id: u32, options: &DiffOptions) -> Vec<ModelDifference> {
+        let old = self.old.patient(id).collect::<BTreeMap<&String, &ConsentValue>>();
+        let new = self.new.patient(id).collect::<BTreeMap<&String, &ConsentValue>>();
+        let codes = old.keys().chain(new.keys()).filter(|c| !options.ignores(c)).collect::<BTreeSet<&&String>>();
+
+        let diffs = codes.into_iter().flat_map(|code| {
+            match (old.get(*code), new.get(*code)) {
+                (Some(v1), Some(v2)) => v1.diff(v2, options).unwrap_or_default(),
+                (v1, v2) => vec![ConsentDifference { patient: id, code, diff: ConsentDifferenceType::Missing(v1.copied(), v2.copied()) }],
+            }
+        }).collect::<Vec<ConsentDifference>>();
+
+        diffs.iter().map(|d| ModelDifference { record: d.record() }).collect()
+    }
+}
@@ -0,0 +1,105 @@
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::io::Read;
+
+use crate::clinical_data::{ClinicalDatum, ContextKey};
+use crate::fixture::{self, ParseError};
+use crate::migrated_registry::MigratedRegistry;
+
+/// A record of the rdrf_rdrfcontext.json fixture
+#[derive(Debug, Deserialize)]
+struct ContextRecord {
+    pk: i64,
+    fields: ContextFields,
+}
+
+#[derive(Debug, Deserialize)]
+struct ContextFields {
+    /// The patient the context is of
+    object_id: i64,
+    #[serde(default)]
+    created_at: Option<String>,
+    #[serde(default)]
+    context_form_group: Option<i64>,
+}
+
+/// A record of the rdrf_contextformgroup.json fixture
+#[derive(Debug, Deserialize)]
+struct FormGroupRecord {
+    pk: i64,
+    fields: FormGroupFields,
+}
+
+#[derive(Debug, Deserialize)]
+struct FormGroupFields {
+    name: String,
+}
+
+/// A context by its form group, and where it is among its patient's contexts
+/// of the group, which (unlike its pk) a migration keeps
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct GroupedContext {
+    /// The name of the form group, or its pk if the form groups aren't known
+    group: Option<String>,
+    sequence: usize,
+}
+
+impl fmt::Display for GroupedContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} #{}", self.group.as_deref().unwrap_or("(no form group)"), self.sequence + 1)
+    }
+}
+
+/// The contexts of an export, by pk, so clinical data can be paired by their
+/// contexts' form groups rather than by the forms they have, which two
+/// contexts of a patient (eg. follow ups) can share
+#[derive(Debug, Default)]
+pub struct Contexts(HashMap<u32, GroupedContext>);
+
+impl Contexts {
+    /// Read the contexts of the context fixture, naming their form groups by
+    /// the form group fixture if there is one
+    ///
+    /// A patient's contexts of a form group are numbered in the order they
+    /// were created
+    pub fn read(contexts: impl Read, form_groups: Option<impl Read>) -> Result<Contexts, ParseError> {
+        let names = match form_groups {
+            Some(reader) => MigratedRegistry::read_array_file_to_records(reader).map(|text| {
+                let record = fixture::parse_at::<FormGroupRecord>(&text, "")?;
+                Ok((record.pk, record.fields.name))
+            }).collect::<Result<HashMap<i64, String>, ParseError>>()?,
+            None => HashMap::new(),
+        };
+
+        let records = MigratedRegistry::read_array_file_to_records(contexts)
+            .map(|text| fixture::parse_at::<ContextRecord>(&text, ""))
+            .collect::<Result<Vec<ContextRecord>, ParseError>>()?;
+
+        let mut by_group = BTreeMap::<(i64, Option<i64>), Vec<&ContextRecord>>::new();
+        records.iter().for_each(|r| by_group.entry((r.fields.object_id, r.fields.context_form_group)).or_default().push(r));
+
+        let contexts = by_group.into_iter().flat_map(|((_, group), mut contexts)| {
+            contexts.sort_by(|c1, c2| (&c1.fields.created_at, c1.pk).cmp(&(&c2.fields.created_at, c2.pk)));
+            let group = group.map(|pk| names.get(&pk).cloned().unwrap_or_else(|| pk.to_string()));
+            contexts.into_iter().enumerate()
+                .map(|(sequence, c)| (c.pk as u32, GroupedContext { group: group.clone(), sequence }))
+                .collect::<Vec<(u32, GroupedContext)>>()
+        }).collect();
+
+        Ok(Contexts(contexts))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The key a clinical datum is paired with the other export's by, its
+    /// forms if its context isn't known
+    pub fn key(&self, datum: &ClinicalDatum) -> ContextKey {
+        match datum.context_id.and_then(|id| self.0.get(&id)) {
+            Some(context) => ContextKey::Grouped(context.clone()),
+            None => ContextKey::Forms(datum.proto_context()),
+        }
+    }
+}
@@ -0,0 +1,53 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::report::{DedupedChange, DifferenceKind, DifferenceRecord};
+
+/// The patients given as a sample of each deduplicated change
+const SAMPLE: usize = 10;
+
+/// A CDE's change of kind from one value to another
+type Change = (String, DifferenceKind, Option<String>, Option<String>);
+
+/// The changes of CDE values found so far, so a change found the same in
+/// many patients (eg. a systematic transformation) is reported once
+#[derive(Debug, Default)]
+pub struct Dedupe {
+    changes: HashMap<Change, (HashSet<u32>, usize, Vec<u32>)>,
+}
+
+impl Dedupe {
+    /// Whether a difference repeats a change already reported, counting it either way
+    pub fn repeats(&mut self, patient: u32, record: &DifferenceRecord) -> bool {
+        let cde = match &record.location.cde {
+            Some(cde) => cde.clone(),
+            None => return false,
+        };
+
+        let (patients, differences, sample) = self.changes.entry((cde, record.kind, record.old.clone(), record.new.clone())).or_default();
+        if patients.insert(patient) && sample.len() < SAMPLE {
+            sample.push(patient);
+        }
+        *differences += 1;
+
+        *differences > 1
+    }
+
+    /// The changes that were found more than once, in the most patients first
+    pub fn changes(&self) -> Vec<DedupedChange> {
+        let mut changes = self.changes.iter()
+            .filter(|(_, (_, differences, _))| *differences > 1)
+            .map(|((cde, kind, old, new), (patients, differences, sample))| DedupedChange {
+                cde: cde.clone(),
+                kind: *kind,
+                old: old.clone(),
+                new: new.clone(),
+                patients: patients.len(),
+                differences: *differences,
+                sample: sample.clone(),
+            })
+            .collect::<Vec<DedupedChange>>();
+        changes.sort_by(|a, b| b.patients.cmp(&a.patients).then_with(|| (&a.cde, &a.old, &a.new).cmp(&(&b.cde, &b.old, &b.new))));
+
+        changes
+    }
+}
@@ -1,7 +1,101 @@
+use rayon::ThreadPool;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use crate::expect::Transform;
+use crate::json_diff::JsonDiff;
+use crate::numbers::NumberLocale;
+use crate::permitted::PermittedValues;
+use crate::report::DifferenceKind;
+use crate::text::Collation;
+use crate::plugins::RegistryPlugin;
+use crate::retired::RetiredCdes;
+use crate::skipped_forms::SkippedForms;
+use crate::suppressions::Suppressions;
+
 pub trait Diff<'a> {
     type Difference;
 
-    fn diff(&'a self, comp: &'a Self) -> Option<Vec<Self::Difference>>;
+    fn diff(&'a self, comp: &'a Self, options: &DiffOptions) -> Option<Vec<Self::Difference>>;
+}
+
+/// Settings that change what counts as a difference
+#[derive(Debug, Clone)]
+pub struct DiffOptions {
+    /// The largest difference between two numbers that are still considered equal
+    pub tolerance: f64,
+    /// The decimal places numbers are rounded to when telling whether they only differ by formatting, if they are
+    pub formatting_precision: Option<u32>,
+    /// The locale the old export writes numbers stored as strings in, if they're read as numbers
+    pub number_locale: Option<NumberLocale>,
+    /// Form names, section codes and CDE codes whose differences are ignored
+    pub ignore: Arc<Suppressions>,
+    /// Whether a clinical datum whose timestamp is earlier in the new migration is a difference
+    pub timestamps: bool,
+    /// Whether each patient's history snapshots are paired by timestamp and compared as sequences
+    pub history_sequence: bool,
+    /// Whether the username, timestamp and context of paired history snapshots are compared
+    pub history_metadata: bool,
+    /// The most seconds the timestamps of paired history snapshots can differ by
+    pub history_time_tolerance: i64,
+    /// Whether free text is compared after normalizing its whitespace and HTML
+    pub normalize_text: bool,
+    /// How strings are collated before they're compared
+    pub collation: Vec<Collation>,
+    /// The pool the forms of a clinical datum are compared on in parallel, if any
+    pub form_pool: Option<Arc<ThreadPool>>,
+    /// Whether values that identify a patient are hidden from differences
+    pub redact: bool,
+    /// The most seconds consent value timestamps can differ by and still be considered equal
+    pub consent_time_tolerance: i64,
+    /// The quirks of the registry being compared, if it has any
+    pub plugin: Option<Arc<dyn RegistryPlugin>>,
+    /// CDE codes calculated from other CDEs, whose differences are reported apart from the rest
+    pub calculated: HashSet<String>,
+    /// Whether calculated CDEs are left out of the comparison
+    pub skip_calculated: bool,
+    /// The permitted values of the exports' CDEs, whose codes and labels are equal
+    pub permitted_values: Arc<PermittedValues>,
+    /// Intentional changes of CDE values by code, whose new values are checked against the transformed old values
+    pub expect: Arc<HashMap<String, Transform>>,
+    /// How much the differences of each CDE count toward a patient's score
+    pub weights: HashMap<String, f64>,
+    /// Whether the rows of multiple sections are checked to keep their order, rather than only compared row by row
+    pub row_order: bool,
+    /// The CDE the rows of a multiple section are matched by, by section code
+    pub row_keys: HashMap<String, String>,
+    /// The kinds of differences that are neither reported nor counted
+    pub excluded_kinds: HashSet<DifferenceKind>,
+    /// The forms the registry definitions flag as not migrated, which are left out of the comparison
+    pub skipped_forms: Arc<SkippedForms>,
+    /// CDE codes retired in the new system, which may be absent from the new export
+    pub retired: Arc<RetiredCdes>,
+    /// How members of the records that aren't modelled are compared
+    pub json_diff: Arc<JsonDiff>,
+}
+
+impl Default for DiffOptions {
+    fn default() -> DiffOptions {
+        DiffOptions { tolerance: 0.01, formatting_precision: None, number_locale: None, ignore: Arc::default(), timestamps: false, history_sequence: false, history_metadata: false, history_time_tolerance: 0, normalize_text: false, collation: vec![], form_pool: None, redact: true, consent_time_tolerance: 0, plugin: None, calculated: HashSet::new(), skip_calculated: false, permitted_values: Arc::default(), expect: Arc::default(), weights: HashMap::new(), row_order: false, row_keys: HashMap::new(), excluded_kinds: HashSet::new(), skipped_forms: Arc::default(), retired: Arc::default(), json_diff: Arc::default() }
+    }
+}
+
+impl DiffOptions {
+    pub fn ignores(&self, code: &str) -> bool {
+        self.ignore.suppresses(code)
+            || (self.skip_calculated && self.calculated.contains(code))
+            || self.plugin.as_ref().is_some_and(|p| p.ignores(code))
+    }
+
+    /// Whether a form is left out of the comparison, by name
+    pub fn skips_form(&self, name: &str) -> bool {
+        self.skipped_forms.contains(name) || self.ignores(name)
+    }
+
+    /// Whether differences of a kind are reported
+    pub fn reports(&self, kind: DifferenceKind) -> bool {
+        !self.excluded_kinds.contains(&kind)
+    }
 }
 
 /// If a and b are not equal, add the difference to the list of differences
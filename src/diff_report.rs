@@ -0,0 +1,110 @@
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufReader, Read, Write};
+
+use crate::metadata::RunMetadata;
+use crate::output::JsonWriter;
+use crate::report::{DifferenceKind, DifferenceRecord};
+
+/// A JSON report (written with --output json:<path>) read back whole, for
+/// tools that post-process saved reports
+///
+/// ```no_run
+/// # use diffmig::diff_report::DiffReport;
+/// let report = DiffReport::open("nightly.json")?;
+/// for patient in report.patients_with_diffs() {
+///     println!("{}: {}", patient, report.diffs_for_patient(patient).count());
+/// }
+/// let ages = report.diffs_for_cde("CDEAge").collect::<Vec<_>>();
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct DiffReport {
+    /// The differences, in the order they were written
+    pub records: Vec<DifferenceRecord>,
+    /// The metadata of the run, which reports written before it was kept don't have
+    pub metadata: Option<RunMetadata>,
+}
+
+impl DiffReport {
+    pub fn open(path: &str) -> Result<DiffReport, Box<dyn Error>> {
+        let file = File::open(path).map_err(|e| format!("Failed opening {}: {}", path, e))?;
+        DiffReport::from_reader(BufReader::new(file)).map_err(|e| format!("Invalid report {}: {}", path, e).into())
+    }
+
+    /// Read a report, told apart from its metadata by its only key
+    pub fn from_reader(reader: impl Read) -> Result<DiffReport, Box<dyn Error>> {
+        let mut report = DiffReport::default();
+        for (i, mut element) in serde_json::from_reader::<_, Vec<Value>>(reader)?.into_iter().enumerate() {
+            match element.as_object().map(|o| o.len() == 1 && o.contains_key("metadata")) {
+                Some(true) => report.metadata = Some(serde_json::from_value(element["metadata"].take())?),
+                _ => report.records.push(serde_json::from_value(element).map_err(|e| format!("element {}: {}", i, e))?),
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Write the report as diffmig does, so it can be read by its commands,
+    /// eg. `diffmig annotate` and `diffmig compare-reports`
+    pub fn to_writer(&self, writer: impl Write) -> Result<(), Box<dyn Error>> {
+        let mut writer = JsonWriter::new(writer)?;
+        writer.records(&self.records)?;
+        writer.close(self.metadata.as_ref())
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, DifferenceRecord> {
+        self.records.iter()
+    }
+
+    /// The patients with any differences, in order
+    pub fn patients_with_diffs(&self) -> BTreeSet<u32> {
+        self.records.iter().map(|r| r.location.patient).collect()
+    }
+
+    pub fn diffs_for_patient(&self, patient: u32) -> impl Iterator<Item=&DifferenceRecord> {
+        self.records.iter().filter(move |r| r.location.patient == patient)
+    }
+
+    /// The differences in a CDE, by its code, in whichever form and section it is
+    pub fn diffs_for_cde<'a>(&'a self, cde: &'a str) -> impl Iterator<Item=&'a DifferenceRecord> {
+        self.records.iter().filter(move |r| r.location.cde.as_deref() == Some(cde))
+    }
+
+    pub fn diffs_of_kind(&self, kind: DifferenceKind) -> impl Iterator<Item=&DifferenceRecord> {
+        self.records.iter().filter(move |r| r.kind == kind)
+    }
+
+    /// The difference with the id, as `diffmig annotate` gives it
+    pub fn diff_with_id(&self, id: &str) -> Option<&DifferenceRecord> {
+        self.records.iter().find(|r| r.id() == id)
+    }
+}
+
+impl IntoIterator for DiffReport {
+    type Item = DifferenceRecord;
+    type IntoIter = std::vec::IntoIter<DifferenceRecord>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.records.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a DiffReport {
+    type Item = &'a DifferenceRecord;
+    type IntoIter = std::slice::Iter<'a, DifferenceRecord>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.records.iter()
+    }
+}
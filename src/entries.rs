@@ -0,0 +1,169 @@
+use itertools::Itertools;
+use regex::Regex;
+use std::error::Error;
+use std::io::{self, BufReader, Read, Seek};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::archive::{Archive, ArchiveFile};
+use crate::migrated_registry::MigratedRegistry;
+
+/// The size of each read of an entry
+const CHUNK: usize = 1 << 20;
+
+/// A glob of the zip entries of an export's clinical data, for registries
+/// that shard it into several files, where * matches any characters
+/// (including /) and ? any one
+#[derive(Debug, Clone)]
+pub struct EntryGlob {
+    glob: String,
+    regex: Regex,
+}
+
+impl EntryGlob {
+    pub fn new(glob: &str) -> Result<EntryGlob, Box<dyn Error>> {
+        let pattern = glob.chars().map(|c| match c {
+            '*' => ".*".to_string(),
+            '?' => ".".to_string(),
+            c => regex::escape(&c.to_string()),
+        }).collect::<String>();
+        let regex = Regex::new(&format!("^{}$", pattern)).map_err(|e| format!("Invalid --entry-glob {}: {}", glob, e))?;
+
+        Ok(EntryGlob { glob: glob.to_string(), regex })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.glob
+    }
+
+    /// The names of the archive's entries that match, in lexicographic order
+    pub fn entries(&self, archive: &Archive<impl Read + Seek>) -> Result<Vec<String>, Box<dyn Error>> {
+        let names = archive.file_names().filter(|name| self.regex.is_match(name)).map(String::from).sorted().collect::<Vec<String>>();
+        match names.is_empty() {
+            true => Err(format!("No entries of the zip match --entry-glob {}", self.glob).into()),
+            false => Ok(names),
+        }
+    }
+}
+
+/// The entries the records of an export's clinical data are read from one
+/// after another, and where each's records start among them all, so a
+/// record's position among them can be given as its entry and its position
+/// in it
+///
+/// It's shared between where the records are read and where they're parsed,
+/// which may be on different threads.
+#[derive(Debug, Clone, Default)]
+pub struct Entries(Arc<Mutex<EntryStarts>>);
+
+#[derive(Debug, Default)]
+struct EntryStarts {
+    names: Vec<Arc<str>>,
+    /// The position of the first record of each entry read so far
+    starts: Vec<usize>,
+}
+
+impl Entries {
+    /// Read the records of the entries of the names, in order
+    pub fn read(&self, names: &[String]) {
+        let mut entries = self.0.lock().unwrap();
+        *entries = EntryStarts { names: names.iter().map(|name| Arc::from(name.as_str())).collect(), starts: vec![] };
+    }
+
+    /// The text of each record of the entries, read one after another from
+    /// the reader, noting where each entry's records start
+    pub fn records<'a>(&self, reader: impl Read + 'a) -> impl Iterator<Item=String> + 'a {
+        let entries = self.0.clone();
+        MigratedRegistry::read_array_files_to_records(reader).enumerate().map(move |(position, (entry, record))| {
+            // Entries without records start where the next does
+            let starts = &mut entries.lock().unwrap().starts;
+            while starts.len() <= entry {
+                starts.push(position);
+            }
+            record
+        })
+    }
+
+    /// The entry of the record at a position among the records of all the
+    /// entries, and its position in its entry, if several entries are read
+    pub fn locate(&self, position: usize) -> Option<(Arc<str>, usize)> {
+        let entries = self.0.lock().unwrap();
+        let entry = entries.starts.partition_point(|&start| start <= position).checked_sub(1)?;
+        Some((entries.names.get(entry)?.clone(), position - entries.starts[entry]))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.lock().unwrap().names.is_empty()
+    }
+}
+
+/// Entries of an archive read one after another as if they were one, each
+/// followed by a newline so the lines of one don't run into the next's
+///
+/// They're read on a thread with the archive opened again, as an entry's
+/// reader borrows the archive it's read from, so can't be kept with it
+pub struct Concatenated {
+    chunks: Receiver<io::Result<Vec<u8>>>,
+    chunk: Vec<u8>,
+    offset: usize,
+}
+
+impl Concatenated {
+    pub fn new<F>(open: F, names: Vec<String>) -> Concatenated
+        where F: FnOnce() -> Result<Archive<BufReader<ArchiveFile>>, Box<dyn Error>> + Send + 'static
+    {
+        let (sender, chunks) = mpsc::sync_channel(4);
+        thread::spawn(move || {
+            let read = || -> Result<(), Box<dyn Error>> {
+                let mut archive = open()?;
+                for name in names {
+                    log::debug!("Reading clinical data from {}", name);
+                    let mut entry = archive.by_name(&name)?;
+                    loop {
+                        let mut chunk = vec![0; CHUNK];
+                        let read = entry.read(&mut chunk)?;
+                        if read == 0 {
+                            break;
+                        }
+                        chunk.truncate(read);
+                        // Stopped once the reader's dropped
+                        if sender.send(Ok(chunk)).is_err() {
+                            return Ok(());
+                        }
+                    }
+                    if sender.send(Ok(b"\n".to_vec())).is_err() {
+                        return Ok(());
+                    }
+                }
+                Ok(())
+            };
+            if let Err(e) = read() {
+                let _ = sender.send(Err(io::Error::other(e.to_string())));
+            }
+        });
+
+        Concatenated { chunks, chunk: vec![], offset: 0 }
+    }
+}
+
+impl Read for Concatenated {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.offset == self.chunk.len() {
+            match self.chunks.recv().ok().transpose()? {
+                Some(chunk) => {
+                    self.chunk = chunk;
+                    self.offset = 0;
+                }
+                // The end of the last entry
+                None => return Ok(0),
+            }
+        }
+
+        let read = buf.len().min(self.chunk.len() - self.offset);
+        buf[..read].copy_from_slice(&self.chunk[self.offset..self.offset + read]);
+        self.offset += read;
+
+        Ok(read)
+    }
+}
@@ -0,0 +1,57 @@
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::error::Error;
+
+/// An intentional change of a CDE's values as written in the config, either a
+/// map of old values to new or a regex rewrite
+///
+/// ```toml
+/// [expect.CDE_SEX]
+/// M = "Male"
+/// F = "Female"
+///
+/// [expect.CDEPhone]
+/// regex = '^0(\d{9})$'
+/// replace = "+61$1"
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum TransformSpec {
+    Rewrite(RewriteSpec),
+    Map(HashMap<String, String>),
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RewriteSpec {
+    regex: String,
+    replace: String,
+}
+
+#[derive(Debug)]
+pub enum Transform {
+    Map(HashMap<String, String>),
+    Rewrite(Regex, String),
+}
+
+impl Transform {
+    pub fn compile(code: &str, spec: TransformSpec) -> Result<Transform, Box<dyn Error>> {
+        match spec {
+            TransformSpec::Map(map) => Ok(Transform::Map(map)),
+            TransformSpec::Rewrite(RewriteSpec { regex, replace }) => {
+                let regex = Regex::new(&regex).map_err(|e| format!("Invalid regex expected of {}: {}", code, e))?;
+                Ok(Transform::Rewrite(regex, replace))
+            }
+        }
+    }
+
+    /// The value the old value is expected to become, which is the old value
+    /// itself if the transform doesn't apply to it
+    pub fn apply(&self, old: &str) -> String {
+        match self {
+            Transform::Map(map) => map.get(old).cloned().unwrap_or_else(|| old.to_string()),
+            Transform::Rewrite(regex, replace) => regex.replace(old, replace.as_str()).into_owned(),
+        }
+    }
+}
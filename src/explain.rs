@@ -0,0 +1,95 @@
+use itertools::Itertools;
+use std::sync::Arc;
+
+use crate::clinical_data::{ClinicalDatum, ContextKey, PatientSlice};
+use crate::metadata::Labels;
+use crate::plugins::RegistryPlugin;
+use crate::report::DifferenceRecord;
+
+/// Print a difference of a report and the comparison that found it, with
+/// the settings of the comparison that applied (eg. the tolerance)
+pub fn print_difference(record: &DifferenceRecord, settings: &[String], labels: &Labels) {
+    let l = &record.location;
+
+    println!("Difference {} ({}, {:?} severity)", record.id(), record.kind, record.kind.severity());
+    match l.ids.is_empty() {
+        true => println!("  Patient: {}", l.patient),
+        false => println!("  Patient: {} (clinical data {})", l.patient, l.ids),
+    }
+    println!("  Where: {}", l.path());
+    if l.old_pointer.is_some() || l.new_pointer.is_some() {
+        let pointer = |p: &Option<String>| p.clone().unwrap_or_else(|| "(none)".to_string());
+        println!("  JSON pointers: {} ({}), {} ({})", pointer(&l.old_pointer), labels.old, pointer(&l.new_pointer), labels.new);
+    }
+    println!("  {}: {}", labels.old_heading(), record.old.as_deref().unwrap_or("(none)"));
+    println!("  {}: {}", labels.new_heading(), record.new.as_deref().unwrap_or("(none)"));
+    if let Some(detail) = &record.detail {
+        println!("  Detail: {}", detail);
+    }
+    if let Some(triage) = &record.triage {
+        println!("  Triage: {}", triage);
+    }
+
+    println!();
+    println!("Rule ({}): {}", record.kind, record.kind.rule());
+    settings.iter().for_each(|s| println!("  {}", s));
+}
+
+/// The clinical datum of each slice the difference was found in, paired as
+/// the diff pairs them, by context and the registry's plugin
+pub fn paired_data<'a>(
+    record: &DifferenceRecord,
+    old: Option<&'a PatientSlice>,
+    new: Option<&'a PatientSlice>,
+    plugin: &Option<Arc<dyn RegistryPlugin>>,
+) -> (Option<&'a ClinicalDatum>, Option<&'a ClinicalDatum>) {
+    let context = |k: &ContextKey| match (k, plugin) {
+        (ContextKey::Forms(forms), Some(plugin)) => ContextKey::Forms(plugin.context(forms.clone())),
+        (k, _) => k.clone(),
+    };
+    // The report names the old datum's context, or the new one's if it's missing from the old export
+    let named = |slice: Option<&'a PatientSlice>| slice.into_iter()
+        .flat_map(|s| s.clinical_data())
+        .find(|(k, _)| k.to_string() == record.location.context);
+    let paired = |slice: Option<&'a PatientSlice>, key: &ContextKey| slice.into_iter()
+        .flat_map(|s| s.clinical_data())
+        .find(|(k, _)| context(k) == context(key))
+        .map(|(_, d)| d);
+
+    match (named(old), named(new)) {
+        (Some((key, datum)), _) => (Some(datum), paired(new, key)),
+        (None, Some((key, datum))) => (paired(old, key), Some(datum)),
+        (None, None) => (None, None),
+    }
+}
+
+/// Print a side's clinical datum of a difference, with the section the
+/// difference is in both as it was parsed and as it was in the export
+pub fn print_datum(side: &str, record: &DifferenceRecord, datum: Option<&ClinicalDatum>) {
+    println!();
+    let datum = match datum {
+        Some(datum) => datum,
+        None => return println!("{}: no clinical data of {} for patient {}", side, record.location.context, record.location.patient),
+    };
+
+    println!("{}: clinical datum {} ({:?}, saved {})", side, datum.id, datum.variant, datum.timestamp().unwrap_or("at an unknown time"));
+    println!("  Forms: {}", datum.proto_context().iter().join(", "));
+
+    let (form, section) = match (&record.location.form, &record.location.section) {
+        (Some(form), Some(section)) => (form, section),
+        _ => return,
+    };
+    match datum.section(form, section) {
+        Some(s) => {
+            println!("  Parsed {} > {}: {:#?}", form, section, s);
+            println!("  Raw {} > {}: {}", form, section, s.raw().unwrap_or("(not kept)"));
+        }
+        None => println!("  No section {} > {}", form, section),
+    }
+}
+
+/// Whether a difference is of clinical data rather than of the patients or
+/// consents models, which are compared whole
+pub fn is_clinical(record: &DifferenceRecord) -> bool {
+    !matches!(record.location.context.as_str(), "patients" | "consents")
+}
@@ -0,0 +1,239 @@
+use serde::Deserialize;
+use serde_json::Value;
+use serde_json::value::RawValue;
+use serde_path_to_error::Segment;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+
+use crate::clinical_data::CDEValue;
+
+/// A record of the rdrf_clinicaldata.json fixture
+///
+/// Strings are borrowed from the record's buffer where possible, and parts
+/// whose layout depends on another field (the collection's data, a section's
+/// cdes) are kept raw until that field is known
+#[derive(Debug, Deserialize)]
+pub struct ClinicalDatumRecord<'a> {
+    pub pk: i64,
+    #[serde(borrow)]
+    pub fields: ClinicalDatumFields<'a>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ClinicalDatumFields<'a> {
+    #[serde(borrow, default)]
+    pub registry_code: Option<Cow<'a, str>>,
+    pub django_id: i64,
+    /// The context the data was entered in, which older exports don't give
+    #[serde(default)]
+    pub context_id: Option<i64>,
+    #[serde(borrow)]
+    pub collection: Cow<'a, str>,
+    #[serde(borrow)]
+    pub data: &'a RawValue,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CDEsData<'a> {
+    #[serde(borrow)]
+    pub forms: Vec<FormRecord<'a>>,
+    #[serde(borrow, default, alias = "last_updated")]
+    pub timestamp: Option<Cow<'a, str>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct HistoryData<'a> {
+    #[serde(borrow)]
+    pub record: CDEsData<'a>,
+    #[serde(borrow, default, alias = "last_updated")]
+    pub timestamp: Option<Cow<'a, str>>,
+    /// Who saved the snapshot
+    #[serde(borrow, default)]
+    pub username: Option<Cow<'a, str>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FormRecord<'a> {
+    #[serde(borrow)]
+    pub name: Cow<'a, str>,
+    #[serde(borrow)]
+    pub sections: Vec<SectionRecord<'a>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SectionRecord<'a> {
+    #[serde(borrow)]
+    pub code: Cow<'a, str>,
+    pub allow_multiple: bool,
+    #[serde(borrow)]
+    pub cdes: &'a RawValue,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CDERecord<'a> {
+    #[serde(borrow)]
+    pub code: Cow<'a, str>,
+    pub value: CDEValue,
+}
+
+/// The patient a record is of, read without the rest of it
+#[derive(Deserialize)]
+struct Keyed {
+    fields: KeyFields,
+}
+
+#[derive(Deserialize)]
+struct KeyFields {
+    django_id: i64,
+}
+
+/// The patient a clinical data record is of, if it gives one
+pub fn patient(record: &str) -> Option<i64> {
+    serde_json::from_str::<Keyed>(record).ok().map(|k| k.fields.django_id)
+}
+
+/// The members of a clinical data record that ClinicalDatum::from doesn't
+/// read, eg. the record's model or a field dropped by the migration, by their
+/// path in the record
+pub fn extra_fields(record: &str) -> BTreeMap<String, Value> {
+    let mut record = match serde_json::from_str::<Value>(record) {
+        Ok(Value::Object(record)) => record,
+        _ => return BTreeMap::new(),
+    };
+    let fields = match record.remove("fields") {
+        Some(Value::Object(fields)) => fields,
+        _ => Default::default(),
+    };
+
+    record.into_iter()
+        .filter(|(name, _)| name != "pk")
+        .chain(fields.into_iter()
+            .filter(|(name, _)| !["registry_code", "django_id", "collection", "data"].contains(&name.as_str()))
+            .map(|(name, value)| (format!("fields/{}", name), value)))
+        .collect()
+}
+
+impl<'a> ClinicalDatumRecord<'a> {
+    pub fn parse(record: &'a str) -> Result<ClinicalDatumRecord<'a>, ParseError> {
+        parse_at(record, "").map_err(|e| {
+            // The record didn't deserialize, so pick out what identifies it leniently
+            match serde_json::from_str::<Value>(record) {
+                Ok(value) => ParseError {
+                    pk: value.pointer("/pk").and_then(Value::as_i64),
+                    patient: value.pointer("/fields/django_id").and_then(Value::as_i64),
+                    ..e
+                },
+                Err(_) => e
+            }
+        })
+    }
+}
+
+/// A failure to parse a fixture record, locating the failure within the export
+#[derive(Debug)]
+pub struct ParseError {
+    pub pk: Option<i64>,
+    pub patient: Option<i64>,
+    pub form: Option<usize>,
+    /// RFC 6901 JSON pointer to the offending value within the record
+    pub pointer: String,
+    pub message: String,
+    /// The record's text, kept to compare it as JSON with the other export's
+    pub raw: Option<String>,
+}
+
+impl ParseError {
+    pub fn new(pointer: &str, message: impl ToString) -> ParseError {
+        let form = pointer.split('/')
+            .skip_while(|segment| *segment != "forms")
+            .nth(1)
+            .and_then(|index| index.parse().ok());
+
+        ParseError { pk: None, patient: None, form, pointer: pointer.to_string(), message: message.to_string(), raw: None }
+    }
+
+    pub fn with_record(self, pk: i64, patient: i64) -> ParseError {
+        ParseError { pk: Some(pk), patient: Some(patient), ..self }
+    }
+
+    pub fn with_pk(self, pk: i64) -> ParseError {
+        ParseError { pk: Some(pk), ..self }
+    }
+
+    pub fn with_raw(self, raw: String) -> ParseError {
+        ParseError { raw: Some(raw), ..self }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fn or_unknown<T: ToString>(v: &Option<T>) -> String {
+            v.as_ref().map(|v| v.to_string()).unwrap_or_else(|| "?".to_string())
+        }
+
+        write!(f, "pk {}, patient {}, form {}, at '{}': {}",
+               or_unknown(&self.pk), or_unknown(&self.patient), or_unknown(&self.form), self.pointer, self.message)
+    }
+}
+
+impl Error for ParseError {}
+
+/// Deserialize a fixture record, or the part of one found at pointer, reporting
+/// the JSON pointer of the field that failed
+pub fn parse_at<'a, T: Deserialize<'a>>(text: &'a str, pointer: &str) -> Result<T, ParseError> {
+    let deserializer = &mut serde_json::Deserializer::from_str(text);
+
+    serde_path_to_error::deserialize(deserializer).map_err(|e| located(e, pointer))
+}
+
+/// A parse error at the path below pointer that a deserializer failed at
+fn located<E: fmt::Display>(e: serde_path_to_error::Error<E>, pointer: &str) -> ParseError {
+    let path = e.path().iter().map(|segment| match segment {
+        Segment::Seq { index } => format!("/{}", index),
+        Segment::Map { key } => format!("/{}", key.replace('~', "~0").replace('/', "~1")),
+        Segment::Enum { variant } => format!("/{}", variant),
+        Segment::Unknown => "/?".to_string(),
+    }).collect::<String>();
+
+    ParseError::new(&format!("{}{}", pointer, path), e.inner())
+}
+
+/// The raw cdes of a section, the bulk of an export, parsed with simd-json when
+/// built with the simd-json feature
+///
+/// simd-json parses in place, so is given a copy of the text that the codes of
+/// the parsed CDEs borrow from instead
+pub struct CDEsText<'a> {
+    #[cfg(not(feature = "simd-json"))]
+    text: &'a str,
+    #[cfg(feature = "simd-json")]
+    buffer: Vec<u8>,
+    #[cfg(feature = "simd-json")]
+    text: std::marker::PhantomData<&'a str>,
+}
+
+impl<'a> CDEsText<'a> {
+    #[cfg(not(feature = "simd-json"))]
+    pub fn new(text: &'a str) -> CDEsText<'a> {
+        CDEsText { text }
+    }
+
+    #[cfg(feature = "simd-json")]
+    pub fn new(text: &'a str) -> CDEsText<'a> {
+        CDEsText { buffer: text.as_bytes().to_vec(), text: std::marker::PhantomData }
+    }
+
+    #[cfg(not(feature = "simd-json"))]
+    pub fn parse_at<'b, T: Deserialize<'b>>(&'b mut self, pointer: &str) -> Result<T, ParseError> {
+        parse_at(self.text, pointer)
+    }
+
+    #[cfg(feature = "simd-json")]
+    pub fn parse_at<'b, T: Deserialize<'b>>(&'b mut self, pointer: &str) -> Result<T, ParseError> {
+        let deserializer = &mut simd_json::Deserializer::from_slice(&mut self.buffer).map_err(|e| ParseError::new(pointer, e))?;
+
+        serde_path_to_error::deserialize(deserializer).map_err(|e| located(e, pointer))
+    }
+}
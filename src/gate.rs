@@ -0,0 +1,135 @@
+use serde::Deserialize;
+use std::error::Error;
+use std::fs;
+
+use crate::report::{DifferenceKind, Severity, Summary};
+
+/// A pass/fail rule a run's summary is held to for sign-off, of a --gate file
+/// listing them
+///
+/// ```yaml
+/// - name: No high severity differences
+///   severity: high
+///   max_differences: 0
+/// - name: Under 0.1% of patients differing
+///   max_differing_percent: 0.1
+/// - name: No differences in consent forms
+///   form: ConsentForm
+///   max_differences: 0
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Gate {
+    /// What the gate is called in its result [default: its rule]
+    #[serde(default)]
+    name: Option<String>,
+    /// Count only the differences of a severity, a kind or a form
+    #[serde(default)]
+    severity: Option<Severity>,
+    #[serde(default)]
+    kind: Option<DifferenceKind>,
+    #[serde(default)]
+    form: Option<String>,
+    /// The limit, on one of the differences, the differing patients or
+    /// their percentage of the patients compared
+    #[serde(default)]
+    max_differences: Option<usize>,
+    #[serde(default)]
+    max_differing_patients: Option<usize>,
+    #[serde(default)]
+    max_differing_percent: Option<f64>,
+}
+
+/// How a run did against a gate
+#[derive(Debug)]
+pub struct GateResult {
+    pub name: String,
+    pub passed: bool,
+    /// The value the gate limits and its limit, as they're shown
+    pub actual: String,
+    pub limit: String,
+}
+
+impl Gate {
+    /// Check the gate has one limit, and that only the differences are counted by severity, kind or form
+    fn validate(&self) -> Result<(), String> {
+        let limits = [self.max_differences.is_some(), self.max_differing_patients.is_some(), self.max_differing_percent.is_some()];
+        let filters = [self.severity.is_some(), self.kind.is_some(), self.form.is_some()];
+        match (limits.iter().filter(|l| **l).count(), filters.iter().filter(|f| **f).count()) {
+            (1, 0) => Ok(()),
+            (1, 1) if self.max_differences.is_some() => Ok(()),
+            (1, 1) => Err("only max_differences can be of a severity, kind or form".to_string()),
+            (1, _) => Err("only one of severity, kind and form can be given".to_string()),
+            _ => Err("one of max_differences, max_differing_patients and max_differing_percent must be given".to_string()),
+        }
+    }
+
+    /// The gate's rule, eg. "at most 0 High severity differences"
+    fn rule(&self) -> String {
+        let of = match (self.severity, self.kind, &self.form) {
+            (Some(severity), _, _) => format!("{:?} severity differences", severity),
+            (_, Some(kind), _) => format!("{} differences", kind),
+            (_, _, Some(form)) => format!("differences in {}", form),
+            _ => "differences".to_string(),
+        };
+        match (self.max_differences, self.max_differing_patients, self.max_differing_percent) {
+            (Some(max), _, _) => format!("at most {} {}", max, of),
+            (_, Some(max), _) => format!("at most {} differing patients", max),
+            (_, _, Some(max)) => format!("at most {}% of patients differing", max),
+            _ => unreachable!("A gate without a limit"),
+        }
+    }
+
+    fn evaluate(&self, summary: &Summary) -> GateResult {
+        let differences = match (self.severity, self.kind, &self.form) {
+            (Some(severity), _, _) => summary.by_severity.get(&severity),
+            (_, Some(kind), _) => summary.by_kind.get(&kind),
+            (_, _, Some(form)) => summary.by_form.get(form),
+            _ => Some(&summary.differences),
+        }.copied().unwrap_or(0);
+        let percent = match summary.patients {
+            0 => 0.0,
+            patients => summary.differing_patients as f64 * 100.0 / patients as f64,
+        };
+
+        let (passed, actual, limit) = match (self.max_differences, self.max_differing_patients, self.max_differing_percent) {
+            (Some(max), _, _) => (differences <= max, differences.to_string(), max.to_string()),
+            (_, Some(max), _) => (summary.differing_patients <= max, summary.differing_patients.to_string(), max.to_string()),
+            (_, _, Some(max)) => (percent <= max, format!("{:.3}%", percent), format!("{}%", max)),
+            _ => unreachable!("A gate without a limit"),
+        };
+        GateResult { name: self.name.clone().unwrap_or_else(|| self.rule()), passed, actual, limit }
+    }
+}
+
+/// The gates of a --gate file
+#[derive(Debug)]
+pub struct Gates(Vec<Gate>);
+
+impl Gates {
+    pub fn load(path: &str) -> Result<Gates, Box<dyn Error>> {
+        let text = fs::read_to_string(path).map_err(|e| format!("Failed reading gates {}: {}", path, e))?;
+        let gates = serde_yaml::from_str::<Vec<Gate>>(&text).map_err(|e| format!("Invalid gates {}: {}", path, e))?;
+        for (i, gate) in gates.iter().enumerate() {
+            gate.validate().map_err(|e| format!("Invalid gate {} of {}: {}", i + 1, path, e))?;
+        }
+
+        Ok(Gates(gates))
+    }
+
+    pub fn evaluate(&self, summary: &Summary) -> Vec<GateResult> {
+        self.0.iter().map(|gate| gate.evaluate(summary)).collect()
+    }
+}
+
+/// Print whether each gate passed, with the value it limits
+pub fn print(results: &[GateResult]) {
+    println!("Gates:");
+    results.iter().for_each(|r| {
+        let outcome = match r.passed {
+            true => "pass",
+            false => "FAIL",
+        };
+        println!("  {} {:<40} {} (limit {})", outcome, r.name, r.actual, r.limit);
+    });
+}
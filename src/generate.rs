@@ -0,0 +1,209 @@
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::Path;
+use zip::ZipWriter;
+use zip::write::SimpleFileOptions;
+
+use crate::metadata::{self, RunMetadata};
+use crate::output::{JsonWriter, ReportWriter};
+use crate::report::{DifferenceKind, DifferenceRecord, Location, Summary};
+use crate::review::SplitMix64;
+
+/// The shape of a synthetic export, and how many differences are injected
+/// into the new one
+#[derive(Debug, Clone)]
+pub struct FixtureSpec {
+    pub registry_code: String,
+    pub patients: usize,
+    pub forms: usize,
+    /// Single sections of each form, which differences are injected into
+    pub sections: usize,
+    /// Multiple sections of each form, each with two entries
+    pub multi_sections: usize,
+    pub cdes: usize,
+    pub differences: usize,
+    pub seed: u64,
+}
+
+/// An injected difference, by the patient, form, section and CDE indexes it's in
+#[derive(Debug, Clone, Copy)]
+struct Injection {
+    patient: usize,
+    form: usize,
+    section: usize,
+    cde: usize,
+    /// Whether the CDE is removed from the new export, rather than its value changed
+    removed: bool,
+}
+
+/// A pair of synthetic exports with known differences, built without any
+/// real patient data
+pub struct Fixture {
+    pub old: Vec<Value>,
+    pub new: Vec<Value>,
+    /// The differences a diff of the exports should find, exactly
+    pub injected: Vec<DifferenceRecord>,
+}
+
+impl FixtureSpec {
+    pub fn generate(&self) -> Result<Fixture, Box<dyn Error>> {
+        let slots = self.patients * self.forms * self.sections * self.cdes;
+        if self.differences > slots {
+            return Err(format!("Can't inject {} differences into {} CDEs of single sections", self.differences, slots).into());
+        }
+
+        // Each slot (a CDE of a single section) is injected at most once
+        let mut rng = SplitMix64(self.seed);
+        let mut chosen = BTreeMap::new();
+        while chosen.len() < self.differences {
+            let slot = (rng.next_u64() % slots as u64) as usize;
+            let removed = rng.next_u64() & 1 == 0;
+            chosen.entry(slot).or_insert(removed);
+        }
+        let injections = chosen.into_iter().map(|(slot, removed)| Injection {
+            patient: slot / self.cdes / self.sections / self.forms,
+            form: slot / self.cdes / self.sections % self.forms,
+            section: slot / self.cdes % self.sections,
+            cde: slot % self.cdes,
+            removed,
+        }).collect::<Vec<Injection>>();
+
+        let old = (0..self.patients).map(|p| self.record(p, &[])).collect();
+        let new = (0..self.patients).map(|p| {
+            let injected = injections.iter().filter(|i| i.patient == p).copied().collect::<Vec<Injection>>();
+            self.record(p, &injected)
+        }).collect();
+        let injected = injections.iter().map(|i| self.difference(i)).collect();
+
+        Ok(Fixture { old, new, injected })
+    }
+
+    fn form_name(&self, form: usize) -> String {
+        format!("Form{}", form + 1)
+    }
+
+    fn section_code(&self, form: usize, section: usize) -> String {
+        format!("F{}S{}", form + 1, section + 1)
+    }
+
+    fn cde_code(&self, cde: usize) -> String {
+        format!("CDE{:03}", cde + 1)
+    }
+
+    /// Every other CDE is a number, the rest strings, unique to the patient
+    fn value(&self, patient: usize, form: usize, section: usize, cde: usize) -> Value {
+        match cde % 2 {
+            0 => json!(patient * 1000 + form * 100 + section * 10 + cde),
+            _ => json!(format!("value {} {} {} {}", patient + 1, form + 1, section + 1, cde + 1)),
+        }
+    }
+
+    fn changed(value: &Value) -> Value {
+        match value {
+            Value::Number(n) => json!(n.as_u64().unwrap_or(0) + 1),
+            // Not a prefix of the value, which would be reported as truncated
+            v => json!(format!("changed {}", v.as_str().unwrap_or(""))),
+        }
+    }
+
+    /// The clinical data record of a patient, with the injected differences
+    fn record(&self, patient: usize, injected: &[Injection]) -> Value {
+        let forms = (0..self.forms).map(|f| {
+            let single = (0..self.sections).map(|s| {
+                let cdes = (0..self.cdes).filter_map(|c| {
+                    let value = self.value(patient, f, s, c);
+                    match injected.iter().find(|i| (i.form, i.section, i.cde) == (f, s, c)) {
+                        Some(i) if i.removed => None,
+                        Some(_) => Some(json!({"code": self.cde_code(c), "value": Self::changed(&value)})),
+                        None => Some(json!({"code": self.cde_code(c), "value": value})),
+                    }
+                }).collect::<Vec<Value>>();
+                json!({"code": self.section_code(f, s), "allow_multiple": false, "cdes": cdes})
+            });
+            let multiple = (0..self.multi_sections).map(|s| {
+                let s = self.sections + s;
+                let entries = (0..2).map(|e| {
+                    (0..self.cdes).map(|c| json!({"code": self.cde_code(c), "value": self.value(patient, f, s, c + e)})).collect::<Vec<Value>>()
+                }).collect::<Vec<Vec<Value>>>();
+                json!({"code": self.section_code(f, s), "allow_multiple": true, "cdes": entries})
+            });
+
+            json!({"name": self.form_name(f), "sections": single.chain(multiple).collect::<Vec<Value>>()})
+        }).collect::<Vec<Value>>();
+
+        json!({
+            "model": "rdrf.clinicaldata",
+            "pk": patient + 1,
+            "fields": {
+                "registry_code": self.registry_code,
+                "collection": "cdes",
+                "data": {"forms": forms},
+                "django_id": patient + 1,
+                "django_model": "Patient",
+                "context_id": patient + 1,
+            }
+        })
+    }
+
+    /// The difference a diff finds for an injection
+    fn difference(&self, i: &Injection) -> DifferenceRecord {
+        let context = (0..self.forms).map(|f| self.form_name(f)).collect::<BTreeSet<String>>();
+        let location = Location {
+            patient: i.patient as u32 + 1,
+            ids: (i.patient + 1).to_string(),
+            context: context.into_iter().collect::<Vec<String>>().join(","),
+            form: Some(self.form_name(i.form)),
+            section: Some(self.section_code(i.form, i.section)),
+            cde: Some(self.cde_code(i.cde)),
+            ..Location::default()
+        };
+        let value = self.value(i.patient, i.form, i.section, i.cde);
+        let text = |v: &Value| v.as_str().map_or(v.to_string(), String::from);
+        let (kind, new) = match i.removed {
+            true => (DifferenceKind::Missing, None),
+            false => (DifferenceKind::Equality, Some(text(&Self::changed(&value)))),
+        };
+
+        DifferenceRecord { location, kind, old: Some(text(&value)), new, detail: None, triage: None }
+    }
+}
+
+/// Write the clinical data of an export to a zip, laid out like a registry export
+fn write_export(path: &Path, records: &[Value]) -> Result<(), Box<dyn Error>> {
+    // Records are read back one at a time by their indentation, which has to match the registry's
+    let mut text = Vec::new();
+    let formatter = serde_json::ser::PrettyFormatter::with_indent(b"    ");
+    records.serialize(&mut serde_json::Serializer::with_formatter(&mut text, formatter))?;
+    text.push(b'\n');
+
+    let mut zip = ZipWriter::new(File::create(path)?);
+    zip.start_file("export/registry_data/clinical_data/rdrf_clinicaldata.json", SimpleFileOptions::default())?;
+    zip.write_all(&text)?;
+    zip.finish()?;
+
+    Ok(())
+}
+
+/// Write old.zip, new.zip and injected.json to a directory, the last being a
+/// JSON report of exactly the differences a diff of the exports should find
+pub fn write(dir: &str, fixture: &Fixture) -> Result<(), Box<dyn Error>> {
+    let dir = Path::new(dir);
+    fs::create_dir_all(dir)?;
+
+    write_export(&dir.join("old.zip"), &fixture.old)?;
+    write_export(&dir.join("new.zip"), &fixture.new)?;
+
+    // The report is of the exports as written, as a diff's report would be
+    let input = |name: &str| metadata::sha256_of_zip(&dir.join(name).to_string_lossy());
+    let metadata = RunMetadata { old_zip: input("old.zip")?, new_zip: input("new.zip")?, finished: metadata::now(), ..RunMetadata::start(None, None) };
+
+    let mut report = JsonWriter::create(&dir.join("injected.json").to_string_lossy())?;
+    report.patient(0, "", &fixture.injected)?;
+    report.finish(&Summary { metadata, ..Summary::default() })?;
+
+    Ok(())
+}
@@ -0,0 +1,69 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::Read;
+
+use crate::interner::Interner;
+use crate::migrated_registry::{Collection, CollectionCounts, MigratedRegistry, OnParseError, ParseErrors, RecordFilter};
+
+/// Counts of each value of the requested CDEs, across the current ('cdes')
+/// clinical data of every patient of a registry
+#[derive(Debug)]
+pub struct Histogram {
+    counts: BTreeMap<String, HashMap<String, usize>>,
+    /// The errors of records that failed to parse, kept with OnParseError::Collect or Compare
+    pub parse_errors: ParseErrors,
+}
+
+impl Histogram {
+    /// Count the values, handling records that fail to parse as a diff does
+    pub fn from(reader: impl Read, registry_code: &str, cdes: &[&str], on_parse_error: OnParseError) -> Histogram {
+        let mut counts = cdes.iter()
+            .map(|c| (c.to_string(), HashMap::new()))
+            .collect::<BTreeMap<String, HashMap<String, usize>>>();
+        let filter = RecordFilter { registry_code: Some(registry_code.to_string()), collections: vec![Collection::Cdes], ..RecordFilter::default() };
+        let parse_errors = ParseErrors::default();
+
+        let records = MigratedRegistry::read_array_file_to_records(reader).enumerate();
+        let data = MigratedRegistry::map_records_to_clinical_data(records, filter, on_parse_error, parse_errors.clone(), CollectionCounts::default(), Interner::new(), false);
+        for datum in data {
+            datum.cdes().for_each(|cde| {
+                if let Some(values) = counts.get_mut(cde.code()) {
+                    *values.entry(cde.value().to_string()).or_insert(0) += 1;
+                }
+            });
+        }
+
+        Histogram { counts, parse_errors }
+    }
+
+    /// Print the distribution of each CDE, most common value first, alongside
+    /// the counts of another export when given
+    pub fn print(&self, comp: Option<&Histogram>) {
+        let empty = HashMap::new();
+
+        self.counts.iter().for_each(|(code, values)| {
+            let comp_values = comp.map(|c| c.counts.get(code).unwrap_or(&empty));
+            let mut rows = values.keys()
+                .chain(comp_values.iter().flat_map(|c| c.keys()))
+                .collect::<Vec<&String>>();
+            rows.sort();
+            rows.dedup();
+            rows.sort_by_key(|v| std::cmp::Reverse(values.get(*v).copied().unwrap_or(0)));
+
+            println!("{}", code);
+            match comp_values {
+                None => {
+                    println!("  {:<40} {:>10}", "Value", "Count");
+                    rows.iter().for_each(|v| println!("  {:<40} {:>10}", v, values[*v]));
+                }
+                Some(comp_values) => {
+                    println!("  {:<40} {:>10} {:>10} {:>10}", "Value", "Old", "New", "Delta");
+                    rows.iter().for_each(|v| {
+                        let old = values.get(*v).copied().unwrap_or(0);
+                        let new = comp_values.get(*v).copied().unwrap_or(0);
+                        println!("  {:<40} {:>10} {:>10} {:>+10}", v, old, new, new as i64 - old as i64);
+                    });
+                }
+            }
+        });
+    }
+}
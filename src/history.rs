@@ -0,0 +1,126 @@
+use itertools::Itertools;
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::clinical_data::{ClinicalDatumVariant, PatientSlice};
+use crate::report::{DifferenceKind, DifferenceRecord, Location};
+
+/// A history record of a clinical datum, reduced to the values of its CDEs
+#[derive(Debug)]
+struct Snapshot {
+    timestamp: String,
+    /// Values by CDE code, with the rows of a multiple section joined
+    values: BTreeMap<String, String>,
+}
+
+/// The history snapshots of a patient on one side, by context, in the order
+/// they were exported
+#[derive(Debug, Default)]
+struct Snapshots(BTreeMap<String, Vec<Snapshot>>);
+
+impl Snapshots {
+    fn add(&mut self, slice: &PatientSlice) {
+        slice.clinical_data().filter(|(_, d)| matches!(d.variant, ClinicalDatumVariant::History)).for_each(|(context, datum)| {
+            // A snapshot without a timestamp can't be paired with the other side's
+            let timestamp = match datum.timestamp() {
+                Some(timestamp) => timestamp.replacen('T', " ", 1),
+                None => return,
+            };
+
+            let mut values = BTreeMap::<String, Vec<String>>::new();
+            datum.cdes().for_each(|cde| values.entry(cde.code().to_string()).or_default().push(cde.value().to_string()));
+            let values = values.into_iter().map(|(code, mut v)| {
+                v.sort();
+                (code, v.join("; "))
+            }).collect();
+
+            self.0.entry(context.to_string()).or_default().push(Snapshot { timestamp, values });
+        });
+    }
+}
+
+/// Pairs the history snapshots of each patient by timestamp, checking the new
+/// migration kept the whole sequence of values of each CDE rather than only
+/// the latest
+///
+/// A patient's snapshots span many slices, so they're kept until the next
+/// patient's slices begin
+#[derive(Debug, Default)]
+pub struct HistoryCheck {
+    patient: Option<u32>,
+    old: Snapshots,
+    new: Snapshots,
+}
+
+impl HistoryCheck {
+    /// Add the snapshots of a pair of slices, giving the differences of the
+    /// previous patient's histories if these are a new patient's
+    pub fn add(&mut self, old: &PatientSlice, new: &PatientSlice) -> Option<(u32, Vec<DifferenceRecord>)> {
+        let finished = match self.patient {
+            Some(patient) if patient != old.patient => self.finish(),
+            _ => None,
+        };
+
+        self.patient = Some(old.patient);
+        self.old.add(old);
+        self.new.add(new);
+
+        finished
+    }
+
+    /// The differences of the current patient's histories, if there is one
+    pub fn finish(&mut self) -> Option<(u32, Vec<DifferenceRecord>)> {
+        let patient = self.patient.take()?;
+        let (old, new) = (std::mem::take(&mut self.old), std::mem::take(&mut self.new));
+        let empty = vec![];
+
+        let contexts = old.0.keys().chain(new.0.keys()).collect::<BTreeSet<&String>>();
+        let records = contexts.into_iter().flat_map(|context| {
+            let location = Location { patient, context: context.clone(), ..Location::default() };
+            compare(&location, old.0.get(context).unwrap_or(&empty), new.0.get(context).unwrap_or(&empty))
+        }).collect();
+
+        Some((patient, records))
+    }
+}
+
+fn history_record(location: &Location, old: Option<String>, new: Option<String>, detail: &str) -> DifferenceRecord {
+    DifferenceRecord { location: location.clone(), kind: DifferenceKind::History, old, new, detail: Some(detail.to_string()), triage: None }
+}
+
+/// The differences between the sequences of snapshots of a context
+fn compare(location: &Location, old: &[Snapshot], new: &[Snapshot]) -> Vec<DifferenceRecord> {
+    let old_times = old.iter().map(|s| &s.timestamp).collect::<BTreeSet<&String>>();
+    let new_times = new.iter().map(|s| &s.timestamp).collect::<BTreeSet<&String>>();
+    let mut records = vec![];
+
+    old_times.difference(&new_times).for_each(|t| records.push(history_record(location, Some(t.to_string()), None, "dropped snapshot")));
+    new_times.difference(&old_times).for_each(|t| records.push(history_record(location, None, Some(t.to_string()), "extra snapshot")));
+
+    // The snapshots on both sides, in the order each side exported them
+    let in_order = |snapshots: &[Snapshot], other: &BTreeSet<&String>| {
+        snapshots.iter().map(|s| &s.timestamp).filter(|t| other.contains(t)).join(", ")
+    };
+    let (old_order, new_order) = (in_order(old, &new_times), in_order(new, &old_times));
+    if old_order != new_order {
+        records.push(history_record(location, Some(old_order), Some(new_order), "reordered snapshots"));
+    }
+
+    // The sequence of each CDE's values over the paired snapshots, oldest first
+    let (old_paired, new_paired) = (paired(old, &new_times), paired(new, &old_times));
+    let codes = old_paired.iter().chain(new_paired.iter()).flat_map(|s| s.values.keys()).collect::<BTreeSet<&String>>();
+    codes.into_iter().for_each(|code| {
+        let sequence = |snapshots: &[&Snapshot]| snapshots.iter().map(|s| s.values.get(code).map_or("(missing)", String::as_str)).join(", ");
+        let (old_sequence, new_sequence) = (sequence(&old_paired), sequence(&new_paired));
+        if old_sequence != new_sequence {
+            let location = Location { cde: Some(code.clone()), ..location.clone() };
+            records.push(history_record(&location, Some(old_sequence), Some(new_sequence), "differing history of values"));
+        }
+    });
+
+    records
+}
+
+/// The snapshots whose timestamps the other side has too, oldest first
+fn paired<'a>(snapshots: &'a [Snapshot], other: &BTreeSet<&String>) -> Vec<&'a Snapshot> {
+    snapshots.iter().filter(|s| other.contains(&s.timestamp)).sorted_by(|a, b| a.timestamp.cmp(&b.timestamp)).collect()
+}
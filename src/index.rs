@@ -0,0 +1,131 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Read};
+use std::path::Path;
+
+use crate::fixture::ClinicalDatumRecord;
+use crate::migrated_registry::MigratedRegistry;
+
+/// Where each patient's clinical data records are in an export's clinical
+/// data entry, so one patient's can be read without parsing the rest
+///
+/// The records are located by their offsets in the decompressed entry, so a
+/// compressed entry is still decompressed up to them, but nothing before
+/// them is parsed and nothing after them is read
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Index {
+    /// The entry indexed, with its size and CRC-32, to tell whether the index
+    /// is of the zip as it is now
+    pub entry: String,
+    pub size: u64,
+    pub crc32: u32,
+    pub registry_code: String,
+    /// The position in the export, offset and length of each of a patient's records, in order, by patient
+    pub patients: BTreeMap<u32, Vec<(usize, u64, u64)>>,
+    /// The records that didn't parse, so aren't given by patient
+    pub unparsed: usize,
+}
+
+impl Index {
+    /// The path of the index of a zip, beside it
+    pub fn path(zip: &str) -> String {
+        format!("{}.index.json", zip)
+    }
+
+    /// Index the records of a registry in a clinical data entry, read as
+    /// MigratedRegistry::read_array_file_to_records reads them
+    pub fn build(entry: &str, size: u64, crc32: u32, registry_code: &str, reader: impl Read) -> io::Result<Index> {
+        let mut index = Index { entry: entry.to_string(), size, crc32, registry_code: registry_code.to_string(), patients: BTreeMap::new(), unparsed: 0 };
+        let mut reader = BufReader::new(reader);
+        let (mut line, mut record) = (String::new(), String::new());
+        let (mut offset, mut start, mut position) = (0, None, 0);
+
+        loop {
+            line.clear();
+            let read = reader.read_line(&mut line)? as u64;
+            if read == 0 {
+                break;
+            }
+
+            match line.trim_end_matches(['\n', '\r']) {
+                "[" | "]" | "" => {}
+                "    }" | "    }," => {
+                    record.push('}');
+                    let start = start.take().unwrap_or(offset);
+                    index.add(&record, (position, start, offset + read - start));
+                    record.clear();
+                    position += 1;
+                }
+                l => {
+                    start.get_or_insert(offset);
+                    record.push_str(l);
+                    record.push('\n');
+                }
+            }
+            offset += read;
+        }
+
+        Ok(index)
+    }
+
+    fn add(&mut self, record: &str, span: (usize, u64, u64)) {
+        match ClinicalDatumRecord::parse(record) {
+            Ok(r) if r.fields.registry_code.as_deref().is_some_and(|code| code != self.registry_code) => {}
+            Ok(r) => self.patients.entry(r.fields.django_id as u32).or_default().push(span),
+            Err(_) => self.unparsed += 1,
+        }
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        let partial = format!("{}.partial", path);
+        let mut writer = BufWriter::new(File::create(&partial)?);
+        serde_json::to_writer(&mut writer, self)?;
+        drop(writer);
+        fs::rename(&partial, path)?;
+
+        Ok(())
+    }
+
+    /// The index beside a zip, if there's one and it's of the entry as it
+    /// is, warning of one that's out of date
+    pub fn load(zip: &str, entry: &str, size: u64, crc32: u32) -> Result<Option<Index>, Box<dyn Error>> {
+        let path = Index::path(zip);
+        if !Path::new(&path).exists() {
+            return Ok(None);
+        }
+
+        let index = serde_json::from_reader::<_, Index>(BufReader::new(File::open(&path)?))
+            .map_err(|e| format!("Failed reading {}: {}", path, e))?;
+        match (index.entry == entry, index.size == size && index.crc32 == crc32) {
+            (true, true) => Ok(Some(index)),
+            (false, _) => Ok(None),
+            (true, false) => {
+                eprintln!("Warning: {} is of an earlier {}, so isn't used", path, zip);
+                Ok(None)
+            }
+        }
+    }
+
+    /// The text of the records of patients and their positions in the export,
+    /// read from the indexed entry in one pass, in the order of the patients given
+    pub fn records(&self, mut reader: impl Read, patients: &[u32]) -> io::Result<Vec<(usize, String)>> {
+        let mut spans = patients.iter().enumerate()
+            .flat_map(|(i, patient)| self.patients.get(patient).into_iter().flatten().map(move |span| (i, *span)))
+            .collect::<Vec<(usize, (usize, u64, u64))>>();
+        spans.sort_unstable_by_key(|(_, (_, offset, _))| *offset);
+
+        let (mut read, mut records) = (0, vec![]);
+        for (i, (position, offset, length)) in spans {
+            io::copy(&mut (&mut reader).take(offset - read), &mut io::sink())?;
+            let mut text = vec![];
+            (&mut reader).take(length).read_to_end(&mut text)?;
+            read = offset + length;
+            records.extend(MigratedRegistry::read_array_file_to_records(&text[..]).map(|record| (i, position, record)));
+        }
+        records.sort_unstable_by_key(|(i, position, _)| (*i, *position));
+
+        Ok(records.into_iter().map(|(_, position, record)| (position, record)).collect())
+    }
+}
@@ -0,0 +1,101 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt;
+
+use crate::clinical_data::{ClinicalDatumVariant, PatientSlice};
+
+/// A broken assumption of how an export's clinical data is ordered, which
+/// otherwise silently pairs the wrong slices of the exports
+#[derive(Debug)]
+pub enum IntegrityViolation {
+    /// A patient's clinical data resumes after other patients', so their slices aren't contiguous
+    Interleaved { patient: u32, record: String },
+    /// Two clinical data records with the same pk
+    DuplicatePk { id: u32, records: (String, String) },
+    /// A history snapshot of a context the patient has no current ('cdes') clinical datum of
+    UnpairedHistory { patient: u32, id: u32, context_id: Option<u32> },
+}
+
+impl fmt::Display for IntegrityViolation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IntegrityViolation::Interleaved { patient, record } =>
+                write!(f, "Patient {} resumes at record {} after other patients' clinical data", patient, record),
+            IntegrityViolation::DuplicatePk { id, records: (first, second) } =>
+                write!(f, "Clinical datum {} is at both record {} and record {}", id, first, second),
+            IntegrityViolation::UnpairedHistory { patient, id, context_id: Some(context) } =>
+                write!(f, "History {} of patient {} is of context {}, which has no 'cdes' clinical datum", id, patient, context),
+            IntegrityViolation::UnpairedHistory { patient, id, context_id: None } =>
+                write!(f, "History {} of patient {} has no context, and the patient has no 'cdes' clinical data", id, patient),
+        }
+    }
+}
+
+/// Checks the patient slices of one export as they're read
+///
+/// A patient's history is only checked against their 'cdes' clinical data
+/// once the next patient's slices begin
+#[derive(Debug, Default)]
+pub struct IntegrityCheck {
+    /// Whether history is checked against 'cdes' clinical data, which needs both collections read
+    pairs: bool,
+    patient: Option<u32>,
+    finished_patients: HashSet<u32>,
+    /// The position of each clinical datum's record, by pk
+    records: HashMap<u32, String>,
+    /// The contexts of the current patient's 'cdes' clinical data, and their history's pks and contexts
+    cdes: BTreeSet<Option<u32>>,
+    history: Vec<(u32, Option<u32>)>,
+    violations: Vec<IntegrityViolation>,
+}
+
+impl IntegrityCheck {
+    pub fn new(pairs: bool) -> IntegrityCheck {
+        IntegrityCheck { pairs, ..IntegrityCheck::default() }
+    }
+
+    pub fn add(&mut self, slice: &PatientSlice) {
+        if self.patient != Some(slice.patient) {
+            self.finish_patient();
+            if !self.finished_patients.insert(slice.patient) {
+                let record = slice.clinical_data().map(|(_, d)| d).min_by_key(|d| (d.entry.clone(), d.record)).map(|d| d.position()).unwrap_or_default();
+                self.violations.push(IntegrityViolation::Interleaved { patient: slice.patient, record });
+            }
+            self.patient = Some(slice.patient);
+        }
+
+        for (_, datum) in slice.clinical_data() {
+            if let Some(first) = self.records.get(&datum.id) {
+                self.violations.push(IntegrityViolation::DuplicatePk { id: datum.id, records: (first.clone(), datum.position()) });
+            } else {
+                self.records.insert(datum.id, datum.position());
+            }
+
+            match datum.variant {
+                ClinicalDatumVariant::CDEs => { self.cdes.insert(datum.context_id); }
+                ClinicalDatumVariant::History => self.history.push((datum.id, datum.context_id)),
+            }
+        }
+    }
+
+    /// Check the current patient's history against their 'cdes' clinical data
+    fn finish_patient(&mut self) {
+        let (cdes, history) = (std::mem::take(&mut self.cdes), std::mem::take(&mut self.history));
+        let patient = match (self.patient, self.pairs) {
+            (Some(patient), true) => patient,
+            _ => return,
+        };
+
+        history.into_iter()
+            .filter(|(_, context_id)| match context_id {
+                Some(_) => !cdes.contains(context_id),
+                None => cdes.is_empty(),
+            })
+            .for_each(|(id, context_id)| self.violations.push(IntegrityViolation::UnpairedHistory { patient, id, context_id }));
+    }
+
+    /// The violations found, once every slice is added
+    pub fn finish(mut self) -> Vec<IntegrityViolation> {
+        self.finish_patient();
+        self.violations
+    }
+}
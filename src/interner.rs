@@ -0,0 +1,78 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use crate::renames::Renames;
+
+/// A form name, section code or CDE code
+///
+/// These repeat across almost every record of an export, so they're
+/// shared rather than allocated per record
+pub type Code = Arc<str>;
+
+#[derive(Debug, Default)]
+pub struct Interner {
+    codes: HashSet<Code>,
+    renames: Arc<Renames>,
+    /// Whether forms, sections and CDEs are keyed by their normalized codes
+    normalize_keys: bool,
+}
+
+/// A code as it's matched with --normalize-keys, trimmed and in lower case
+pub fn normalize_key(code: &str) -> String {
+    code.trim().to_lowercase()
+}
+
+impl Interner {
+    pub fn new() -> Interner {
+        Interner { codes: HashSet::new(), renames: Arc::default(), normalize_keys: false }
+    }
+
+    /// An interner that gives renamed entities their new names
+    pub fn with_renames(renames: Arc<Renames>) -> Interner {
+        Interner { codes: HashSet::new(), renames, normalize_keys: false }
+    }
+
+    /// The interner keying entities by their normalized codes if normalize_keys
+    /// is set, so codes that differ only by case or surrounding whitespace match
+    pub fn normalizing_keys(self, normalize_keys: bool) -> Interner {
+        Interner { normalize_keys, ..self }
+    }
+
+    /// The shared copy of the code a form, section or CDE is matched by
+    pub fn key(&mut self, code: &Code) -> Code {
+        match self.normalize_keys {
+            true => self.intern(&normalize_key(code)),
+            false => code.clone(),
+        }
+    }
+
+    /// The shared copy of a form's name, after any rename
+    pub fn intern_form(&mut self, name: &str) -> Code {
+        let renames = self.renames.clone();
+        self.intern(renames.form(name))
+    }
+
+    /// The shared copy of a section's code, after any rename
+    pub fn intern_section(&mut self, code: &str) -> Code {
+        let renames = self.renames.clone();
+        self.intern(renames.section(code))
+    }
+
+    /// The shared copy of a CDE's code, after any rename
+    pub fn intern_cde(&mut self, code: &str) -> Code {
+        let renames = self.renames.clone();
+        self.intern(renames.cde(code))
+    }
+
+    /// Returns the shared copy of s, allocating it only the first time it's seen
+    pub fn intern(&mut self, s: &str) -> Code {
+        match self.codes.get(s) {
+            Some(code) => code.clone(),
+            None => {
+                let code = Code::from(s);
+                self.codes.insert(code.clone());
+                code
+            }
+        }
+    }
+}
@@ -0,0 +1,45 @@
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+/// Whether the diff has started what an interrupt stops early, comparing
+/// patients or presorting their records, so there's a report to finish
+static STOPPABLE: AtomicBool = AtomicBool::new(false);
+/// Whether the handler is installed, as it can only be once in a process
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+
+/// The exit code of a diff stopped by SIGINT or SIGTERM, the code shells give
+/// a process killed by SIGINT
+pub const EXIT_CODE: i32 = 130;
+
+/// Stop the diff at the next patient on SIGINT or SIGTERM, so what's been
+/// found so far is still reported
+///
+/// A signal before the diff can stop early (eg. while the exports are indexed)
+/// exits straight away, as does a second signal, eg. while waiting at a prompt
+///
+/// Each diff of a process installs it, starting again uninterrupted
+pub fn install() -> Result<(), ctrlc::Error> {
+    INTERRUPTED.store(false, Ordering::Relaxed);
+    STOPPABLE.store(false, Ordering::Relaxed);
+    if INSTALLED.swap(true, Ordering::Relaxed) {
+        return Ok(());
+    }
+
+    ctrlc::set_handler(|| {
+        match INTERRUPTED.swap(true, Ordering::Relaxed) || !STOPPABLE.load(Ordering::Relaxed) {
+            true => process::exit(EXIT_CODE),
+            false => eprintln!("Interrupted, finishing the report of the patients compared so far (interrupt again to exit now)"),
+        }
+    })
+}
+
+/// Mark the diff as stopping early on an interrupt, rather than exiting
+pub fn stoppable() {
+    STOPPABLE.store(true, Ordering::Relaxed);
+}
+
+/// Whether the diff was interrupted
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::Relaxed)
+}
@@ -0,0 +1,234 @@
+use clap::ValueEnum;
+use serde_json::Value;
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::mem::discriminant;
+
+use crate::report::{DifferenceKind, TRUNCATED_LENGTH};
+
+/// How the items of two arrays are paired, where they aren't paired by a key
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum ArrayAlignment {
+    /// By position, the items past the end of the shorter array being missing from it
+    #[default]
+    Index,
+    /// Equal items wherever they are, then the rest by position
+    Unordered,
+}
+
+/// How two JSON values are compared, for what diffmig doesn't model
+#[derive(Debug, Clone, Default)]
+pub struct JsonDiff {
+    pub arrays: ArrayAlignment,
+    /// The members objects in arrays are paired by, the first that every item on both sides has
+    pub keys: Vec<String>,
+    /// The paths left out, split into their members, where * is any one member or item
+    ignore: Vec<Vec<String>>,
+}
+
+/// A path where two JSON values differ, with the value at it on each side
+/// that has one
+#[derive(Debug)]
+pub struct JsonDifference<'a> {
+    /// A JSON pointer (RFC 6901), following the old side's items where both have them
+    pub path: String,
+    pub old: Option<&'a Value>,
+    pub new: Option<&'a Value>,
+}
+
+impl<'a> JsonDifference<'a> {
+    pub fn kind(&self) -> DifferenceKind {
+        match (self.old, self.new) {
+            (Some(v1), Some(v2)) if discriminant(v1) == discriminant(v2) => DifferenceKind::Equality,
+            (Some(_), Some(_)) => DifferenceKind::Variant,
+            _ => DifferenceKind::Missing,
+        }
+    }
+
+    /// The value on each side, strings given as JSON where they're compared
+    /// with values of other types
+    pub fn values(&self) -> (Option<String>, Option<String>) {
+        let kind = self.kind();
+        let value = |v: Option<&Value>| v.map(|v| match (v, kind) {
+            (Value::String(s), DifferenceKind::Equality | DifferenceKind::Missing) => s.clone(),
+            (v, _) => v.to_string(),
+        });
+        (value(self.old), value(self.new))
+    }
+}
+
+/// A member of a JSON pointer, escaped
+fn escape(member: &str) -> String {
+    member.replace('~', "~0").replace('/', "~1")
+}
+
+impl JsonDiff {
+    /// Compare arrays as given, ignoring the paths of the JSON pointers given
+    pub fn new(arrays: ArrayAlignment, keys: Vec<String>, ignore: &[String]) -> JsonDiff {
+        let ignore = ignore.iter()
+            .map(|path| path.trim_start_matches('/').split('/').map(|m| m.replace("~1", "/").replace("~0", "~")).collect())
+            .collect();
+
+        JsonDiff { arrays, keys, ignore }
+    }
+
+    /// The paths below path where the values differ, in order of path
+    pub fn diff<'a>(&self, path: &str, v1: Option<&'a Value>, v2: Option<&'a Value>) -> Vec<JsonDifference<'a>> {
+        let mut diffs = vec![];
+        self.diff_at(path.to_string(), v1, v2, &mut diffs);
+        diffs
+    }
+
+    fn diff_at<'a>(&self, path: String, v1: Option<&'a Value>, v2: Option<&'a Value>, diffs: &mut Vec<JsonDifference<'a>>) {
+        if self.ignores(&path) {
+            return;
+        }
+
+        match (v1, v2) {
+            (Some(Value::Object(o1)), Some(Value::Object(o2))) => {
+                o1.keys().chain(o2.keys()).collect::<BTreeSet<&String>>().into_iter()
+                    .for_each(|k| self.diff_at(format!("{}/{}", path, escape(k)), o1.get(k), o2.get(k), diffs));
+            }
+            (Some(Value::Array(a1)), Some(Value::Array(a2))) => {
+                self.pair_items(a1, a2).into_iter().for_each(|(i1, i2)| {
+                    let index = i1.or(i2).unwrap_or_default();
+                    self.diff_at(format!("{}/{}", path, index), i1.map(|i| &a1[i]), i2.map(|i| &a2[i]), diffs)
+                });
+            }
+            (v1, v2) if v1 != v2 => diffs.push(JsonDifference { path, old: v1, new: v2 }),
+            _ => {}
+        }
+    }
+
+    /// Whether a path is one of those left out, which the root never is, as
+    /// it has no members for a pattern to match
+    fn ignores(&self, path: &str) -> bool {
+        if path.is_empty() {
+            return false;
+        }
+        let members = path.trim_start_matches('/').split('/').map(|m| m.replace("~1", "/").replace("~0", "~")).collect::<Vec<String>>();
+        self.ignore.iter().any(|pattern| {
+            pattern.len() == members.len() && pattern.iter().zip(&members).all(|(p, m)| p == "*" || p == m)
+        })
+    }
+
+    /// The positions of the items of each array paired with each other, and
+    /// of the items only one side has
+    fn pair_items(&self, a1: &[Value], a2: &[Value]) -> Vec<(Option<usize>, Option<usize>)> {
+        let key = self.keys.iter().find(|key| a1.iter().chain(a2).all(|item| item.get(key.as_str()).is_some()));
+        let (mut paired, mut claimed) = (vec![None; a1.len()], vec![false; a2.len()]);
+
+        // Items are paired by their keys or whole values, in order where several are the same
+        let by = |item: &Value| match key {
+            Some(key) => item[key.as_str()].to_string(),
+            None => item.to_string(),
+        };
+        if key.is_some() || self.arrays == ArrayAlignment::Unordered {
+            let mut unpaired = HashMap::<String, VecDeque<usize>>::new();
+            a2.iter().enumerate().for_each(|(i2, item)| unpaired.entry(by(item)).or_default().push_back(i2));
+            a1.iter().enumerate().for_each(|(i1, item)| {
+                if let Some(i2) = unpaired.get_mut(&by(item)).and_then(VecDeque::pop_front) {
+                    paired[i1] = Some(i2);
+                    claimed[i2] = true;
+                }
+            });
+        }
+        // Without a key, the rest are paired by position
+        if key.is_none() {
+            let mut rest = (0..a2.len()).filter(|&i2| !claimed[i2]).collect::<VecDeque<usize>>();
+            paired.iter_mut().filter(|i2| i2.is_none()).for_each(|paired| {
+                if let Some(i2) = rest.pop_front() {
+                    *paired = Some(i2);
+                    claimed[i2] = true;
+                }
+            });
+        }
+
+        let mut items = paired.into_iter().enumerate().map(|(i1, i2)| (Some(i1), i2)).collect::<Vec<_>>();
+        items.extend((0..a2.len()).filter(|&i2| !claimed[i2]).map(|i2| (None, Some(i2))));
+        items
+    }
+}
+
+/// Print the differences of an entry, one per line, with their values
+/// truncated to TRUNCATED_LENGTH characters if asked
+pub fn print(entry: &str, diffs: &[JsonDifference], truncate: bool) {
+    let value = |v: Option<String>| match v {
+        None => "(missing)".to_string(),
+        Some(v) => match v.char_indices().nth(TRUNCATED_LENGTH) {
+            Some((i, _)) if truncate => format!("{}…", &v[..i]),
+            _ => v,
+        },
+    };
+
+    diffs.iter().for_each(|d| {
+        let (old, new) = d.values();
+        println!("{} [{}]: {} -> {}", d.path, d.kind(), value(old), value(new));
+    });
+    println!("Found {} differences in {}", diffs.len(), entry);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn ignoring(ignore: &[&str]) -> JsonDiff {
+        JsonDiff::new(ArrayAlignment::Index, vec![], &ignore.iter().map(|p| p.to_string()).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn ignores_paths_by_member() {
+        let json_diff = ignoring(&["/fields/*/date", "/a~1b"]);
+        assert!(json_diff.ignores("/fields/0/date"));
+        assert!(json_diff.ignores("/fields/x/date"));
+        assert!(!json_diff.ignores("/fields/0"));
+        assert!(!json_diff.ignores("/fields/0/date/year"));
+        assert!(json_diff.ignores("/a~1b"));
+        assert!(!json_diff.ignores("/a/b"));
+    }
+
+    #[test]
+    fn ignoring_every_top_level_item_keeps_the_root() {
+        let json_diff = ignoring(&["/*"]);
+        assert!(!json_diff.ignores(""));
+
+        let (v1, v2) = (json!([1, {"a": 1}]), json!([2, {"a": 2}]));
+        assert!(json_diff.diff("", Some(&v1), Some(&v2)).is_empty());
+        let (v1, v2) = (json!(1), json!(2));
+        assert_eq!(json_diff.diff("", Some(&v1), Some(&v2)).len(), 1);
+    }
+
+    #[test]
+    fn pairs_items_by_position() {
+        let (a1, a2) = (vec![json!(1), json!(2), json!(3)], vec![json!(3), json!(2)]);
+        assert_eq!(ignoring(&[]).pair_items(&a1, &a2), vec![(Some(0), Some(0)), (Some(1), Some(1)), (Some(2), None)]);
+    }
+
+    #[test]
+    fn pairs_unordered_items_by_value_then_position() {
+        let json_diff = JsonDiff { arrays: ArrayAlignment::Unordered, ..JsonDiff::default() };
+        let (a1, a2) = (vec![json!(1), json!(2), json!(3)], vec![json!(3), json!(4), json!(1), json!(5)]);
+        assert_eq!(json_diff.pair_items(&a1, &a2), vec![(Some(0), Some(2)), (Some(1), Some(1)), (Some(2), Some(0)), (None, Some(3))]);
+    }
+
+    #[test]
+    fn pairs_keyed_items_by_key() {
+        let json_diff = JsonDiff { keys: vec!["name".to_string(), "id".to_string()], ..JsonDiff::default() };
+        let a1 = vec![json!({"id": 1, "v": "a"}), json!({"id": 2, "v": "b"}), json!({"id": 3, "v": "c"})];
+        let a2 = vec![json!({"id": 2, "v": "x"}), json!({"id": 4, "v": "a"})];
+        // name isn't in every item, so they're paired by id, and items of other ids aren't paired by position
+        assert_eq!(json_diff.pair_items(&a1, &a2), vec![(Some(0), None), (Some(1), Some(0)), (Some(2), None), (None, Some(1))]);
+    }
+
+    #[test]
+    fn pairs_duplicate_items_in_order() {
+        let json_diff = JsonDiff { keys: vec!["id".to_string()], ..JsonDiff::default() };
+        let a1 = vec![json!({"id": 1, "v": "a"}), json!({"id": 1, "v": "b"}), json!({"id": 1, "v": "c"})];
+        let a2 = vec![json!({"id": 1, "v": "b"}), json!({"id": 1, "v": "a"})];
+        assert_eq!(json_diff.pair_items(&a1, &a2), vec![(Some(0), Some(0)), (Some(1), Some(1)), (Some(2), None)]);
+
+        let json_diff = JsonDiff { arrays: ArrayAlignment::Unordered, ..JsonDiff::default() };
+        let (a1, a2) = (vec![json!(1), json!(1), json!(2)], vec![json!(2), json!(1)]);
+        assert_eq!(json_diff.pair_items(&a1, &a2), vec![(Some(0), Some(1)), (Some(1), None), (Some(2), Some(0))]);
+    }
+}
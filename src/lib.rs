@@ -0,0 +1,74 @@
+//! The diff of registry exports behind the diffmig commands, for other users
+//! of it, eg. to build clinical data without JSON for tests of comparison
+//! rules, to follow a diff as it goes, or to read saved reports back
+
+pub mod aggregate;
+pub mod archive;
+pub mod batch;
+pub mod bench;
+pub mod builder;
+pub mod calculated;
+pub mod cde_definitions;
+pub mod check;
+pub mod cli;
+pub mod clinical_data;
+pub mod cohorts;
+pub mod contexts;
+pub mod config;
+pub mod consents;
+pub mod dedupe;
+pub mod diff;
+pub mod diff_report;
+pub mod entries;
+pub mod expect;
+pub mod explain;
+pub mod histogram;
+pub mod history;
+pub mod index;
+pub mod integrity;
+pub mod json_diff;
+pub mod fixture;
+pub mod gate;
+pub mod generate;
+pub mod interner;
+pub mod interrupt;
+pub mod manifest;
+pub mod mapped;
+pub mod metadata;
+pub mod mongo;
+pub mod notify;
+pub mod numbers;
+pub mod observer;
+pub mod prompt;
+pub mod registries;
+pub mod renames;
+pub mod renumbered;
+pub mod retired;
+pub mod report;
+pub mod report_diff;
+pub mod review;
+pub mod run;
+pub mod schema;
+pub mod selftest;
+pub mod since;
+pub mod skipped_forms;
+pub mod split;
+pub mod structure;
+pub mod suppressions;
+pub mod text;
+pub mod tree;
+pub mod triage;
+pub mod unparsed;
+pub mod profile;
+pub mod progress;
+pub mod migrated_registry;
+pub mod output;
+pub mod pager;
+pub mod patch;
+pub mod permitted;
+pub mod patient_map;
+pub mod patients;
+pub mod pipeline;
+pub mod presort;
+pub mod prefetch;
+pub mod plugins;
@@ -1,139 +1,48 @@
-mod clinical_data;
-mod diff;
-mod prompt;
-mod migrated_registry;
-
-use clap::{App, Arg};
-use indicatif::{ProgressBar, ProgressStyle, ProgressFinish};
-use itertools::{Itertools, EitherOrBoth};
+use clap::{CommandFactory, Parser};
 use std::error::Error;
-use std::fs::File;
-use std::io::{BufReader, Read, Seek};
-use std::path::Path;
-use std::process;
-use zip::ZipArchive;
-use zip::read::ZipFile;
-
-use crate::clinical_data::{PatientSlice};
-use crate::diff::Diff;
-
-fn get_zip_archive(zip_path: &str) -> Result<ZipArchive<impl Read + Seek>, Box<dyn Error>> {
-    let file = File::open(Path::new(zip_path))?;
-
-    Ok(ZipArchive::new(BufReader::new(file))?)
-}
-
-fn get_zip_reader<'a>(archive: &'a mut ZipArchive<impl Read + Seek>) -> Result<(String, ZipFile<'a>), Box<dyn Error>> {
-    let clinical_data_path = archive.file_names().find(|p| {
-        let path_split = p.split("/").collect::<Vec<&str>>();
-        match &path_split[..] {
-            [_, "registry_data", "clinical_data", "rdrf_clinicaldata.json"] => true,
-            _ => false,
-        }
-    }).ok_or("rdrf_clinicaldata.json file not found in zip")?.to_string();
-
-    Ok((clinical_data_path.clone(), archive.by_name(clinical_data_path.as_str())?))
-}
-
-fn zip_diff(old_iter: impl Iterator<Item=PatientSlice>, new_iter: impl Iterator<Item=PatientSlice>) -> usize {
-    let mut skip_input = false;
-
-    old_iter.zip_longest(new_iter).map(|pair| {
-        match pair {
-            EitherOrBoth::Both(old, new) => {
-                match old.diff(&new) {
-                    None => None,
-                    Some(diffs) => {
-                        diffs.iter().for_each(|d| eprintln!("{:#?}", d));
-                        if !skip_input {
-                            match prompt::input() {
-                                prompt::Response::All => skip_input = true,
-                                prompt::Response::Yes => {}
-                                prompt::Response::No => process::exit(0)
-                            }
-                        }
-                        Some(diffs.len())
-                    }
-                }
-            }
-            EitherOrBoth::Left(_) => {
-                panic!("New ran out of slices!")
-            }
-            EitherOrBoth::Right(_) => {
-                panic!("Old ran out of slices!")
-            }
-        }
-    }).flatten().sum()
-}
-
-fn diff_clinical_data(old_path: String, new_path: String, cdes_only: bool) -> Result<usize, Box<dyn Error>> {
-    let mut old_archive = get_zip_archive(old_path.as_str())?;
-    let mut new_archive = get_zip_archive(new_path.as_str())?;
-
-    let (old_path, old_reader) = get_zip_reader(&mut old_archive)?;
-    let (new_path, new_reader) = get_zip_reader(&mut new_archive)?;
-
-    if old_path != new_path {
-        log::error!("Registry clinical data paths don't match");
-        log::debug!("Old path: {}", old_path);
-        log::debug!("New path: {}", new_path);
-        panic!()
-    }
-
-    let pb = ProgressBar::new(old_reader.size());
-    let old_reader = pb.wrap_read(old_reader);
-    pb.set_style(ProgressStyle::default_bar()
-        .template("Reading [{elapsed_precise} / {duration_precise} ({eta})] {wide_bar:.cyan/blue} {bytes}/{total_bytes}")
-        .progress_chars("##-")
-        .on_finish(ProgressFinish::AtCurrentPos)
-    );
-
-    let old_iter = migrated_registry::MigratedRegistry::from(old_reader, cdes_only);
-    let new_iter = migrated_registry::MigratedRegistry::from(new_reader, cdes_only);
-
-    Ok(zip_diff(old_iter, new_iter))
-}
+use std::io;
 
+use diffmig::cli::{Cli, Command};
+use diffmig::output;
+use diffmig::run::{self, annotate_difference, batch_command, bench_clinical_data, check_exports, compare_reports, explain_difference, generate_fixture, histogram_clinical_data, index_clinical_data, infer_structure, inspect_clinical_data, selftest_export, validate_clinical_data};
 
 fn main() -> Result<(), Box<dyn Error>> {
-    let args = App::new("diffmig")
-        .version("0.1.0")
-        .about("Find differences between two registry migrations of the same data")
-        .arg(Arg::with_name("old_zip")
-            .help("The path of the old zip file")
-            .required(true)
-        )
-        .arg(Arg::with_name("new_zip")
-            .help("The path of the new zip file")
-            .required(true)
-        )
-        .arg(Arg::with_name("cdes_only")
-            .help("Only compare 'cdes' clinical datum variants")
-            .long("cdes")
-            .takes_value(false)
-            .required(false)
-        )
-        .arg(Arg::with_name("debug")
-            .help("Print debug output")
-            .long("debug")
-            .takes_value(false)
-            .required(false)
-        )
-        .get_matches();
-
-    let old_zip = args.value_of("old_zip").unwrap();
-    let new_zip = args.value_of("new_zip").unwrap();
-    let cdes_only = args.is_present("cdes_only");
+    let cli = Cli::parse();
 
     env_logger::builder()
-        .filter_level(match args.is_present("debug") {
+        .filter_level(match cli.debug {
             true => log::LevelFilter::Debug,
             false => log::LevelFilter::Error
         })
         .init();
 
-    let total = diff_clinical_data(old_zip.into(), new_zip.into(), cdes_only)?;
-    println!("Found {} differences", total);
-
-    Ok(())
+    let password = cli.password.as_deref();
+    match cli.command {
+        Command::Diff(args) => run::diff(*args, password, vec![]),
+        Command::Check(args) => check_exports(&args.old_zip, &args.new_zip, &args.registry_code, args.records, password),
+        Command::Validate(args) => validate_clinical_data(&args.zip, password),
+        Command::Inspect(args) => inspect_clinical_data(&args.zip, args.records, password),
+        Command::Index(args) => index_clinical_data(&args.zip, &args.registry_code, password),
+        Command::Batch(args) => batch_command(args, password, cli.debug),
+        Command::Report(args) => output::print_sqlite_summary(&args.path, args.run),
+        Command::Annotate(args) => annotate_difference(args),
+        Command::Explain(args) => explain_difference(args, password),
+        Command::CompareReports(args) => compare_reports(args),
+        Command::Selftest(args) => selftest_export(args, password),
+        Command::GenFixture(args) => generate_fixture(args),
+        Command::Bench(args) => bench_clinical_data(&args.zip, &args.registry_code, args.iterations, password),
+        Command::Histogram(args) => histogram_clinical_data(
+            &args.zip,
+            &args.registry_code,
+            &args.cdes.iter().map(String::as_str).collect::<Vec<&str>>(),
+            args.new_zip.as_deref(),
+            args.on_parse_error,
+            password,
+        ),
+        Command::Schema(args) => infer_structure(&args.zip, &args.registry_code, password),
+        Command::Completions { shell } => {
+            clap_complete::generate(shell, &mut Cli::command(), "diffmig", &mut io::stdout());
+            Ok(())
+        }
+    }
 }
@@ -0,0 +1,79 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::{self, File};
+
+use crate::metadata::{self, InputFile};
+
+/// The options that only change how a run reads the exports or shows its
+/// differences, not which differences it finds
+const PRESENTATION: [&str; 16] = [
+    "mmap", "read_buffer", "pipeline", "presort", "inner_parallelism", "group_by", "flat", "prompt_every", "clean_out",
+    "slow_patient", "review_sample", "examples", "detail", "patch", "old_label", "new_label",
+];
+
+/// What a diff was run with, written beside its reports so any report can be
+/// traced back to the invocation that made it
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RunManifest {
+    /// The version of diffmig the run was made with
+    pub version: String,
+    /// The command line, as given
+    pub args: Vec<String>,
+    /// The options the diff ran with, after the config's were merged, by name
+    pub options: BTreeMap<&'static str, String>,
+    /// The exports, hashed as for the report's metadata
+    pub exports: Vec<InputFile>,
+    /// The config, with its suppressions, and the other files read, by the option they were given by
+    pub files: BTreeMap<&'static str, InputFile>,
+    /// The SHA-256 of the version, options and inputs, which the reports'
+    /// metadata gives so a report can be matched with its manifest
+    pub reproducibility_hash: String,
+    /// The SHA-256 of the version, and the options and files that decide
+    /// which differences are found, so a later run --since only reuses the
+    /// differences of a run that found them the same way
+    pub comparison_hash: String,
+}
+
+impl RunManifest {
+    /// The manifest of a run of this version with the options and files given
+    pub fn new(options: BTreeMap<&'static str, String>, files: BTreeMap<&'static str, InputFile>) -> RunManifest {
+        let version = env!("CARGO_PKG_VERSION").to_string();
+        let compared = serde_json::json!({
+            "version": &version,
+            "options": options.iter().filter(|(option, _)| !PRESENTATION.contains(option)).collect::<BTreeMap<_, _>>(),
+            "files": files.iter().filter(|(option, _)| **option != "since").collect::<BTreeMap<_, _>>(),
+        });
+        let comparison_hash = metadata::sha256(compared.to_string().as_bytes());
+
+        RunManifest { version, args: std::env::args().collect(), options, files, comparison_hash, ..RunManifest::default() }
+    }
+
+    /// The manifest with the digests of the exports and its reproducibility hash
+    ///
+    /// The command line is left out of the hash, so runs given the same
+    /// options by the config or by arguments hash the same
+    pub fn with_exports(self, exports: Vec<InputFile>) -> RunManifest {
+        let hashed = serde_json::json!({
+            "version": &self.version,
+            "options": &self.options,
+            "exports": &exports,
+            "files": &self.files,
+        });
+        let reproducibility_hash = metadata::sha256(hashed.to_string().as_bytes());
+
+        RunManifest { exports, reproducibility_hash, ..self }
+    }
+
+    pub fn write(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        let file = File::create(path).map_err(|e| format!("Failed creating {}: {}", path, e))?;
+        serde_json::to_writer_pretty(file, self)?;
+        Ok(())
+    }
+}
+
+/// A file an option gives, and its SHA-256
+pub fn input_file(path: &str) -> Result<InputFile, Box<dyn Error>> {
+    let bytes = fs::read(path).map_err(|e| format!("Failed reading {}: {}", path, e))?;
+    Ok(InputFile { path: path.to_string(), sha256: metadata::sha256(&bytes) })
+}
@@ -0,0 +1,40 @@
+use memmap2::Mmap;
+use std::error::Error;
+use std::fs::File;
+use zip::CompressionMethod;
+use zip::read::ZipFile;
+
+/// A stored (uncompressed) zip entry, read straight out of a memory map of
+/// its archive rather than through buffered reads of the file
+pub struct MappedEntry {
+    map: Mmap,
+    start: usize,
+    end: usize,
+}
+
+impl MappedEntry {
+    /// Map the entry of the archive at zip_path, or None if it's compressed
+    /// and so has to be inflated as it's read
+    pub fn open(zip_path: &str, entry: &ZipFile) -> Result<Option<MappedEntry>, Box<dyn Error>> {
+        if entry.compression() != CompressionMethod::Stored {
+            log::debug!("Not mapping {}, it's compressed with {:?}", entry.name(), entry.compression());
+            return Ok(None);
+        }
+
+        let file = File::open(zip_path)?;
+        // Safety: the map is only read, and exports aren't written to while they're diffed
+        let map = unsafe { Mmap::map(&file)? };
+
+        let start = entry.data_start() as usize;
+        let end = start + entry.size() as usize;
+        if end > map.len() {
+            return Err(format!("Entry {} runs past the end of {}", entry.name(), zip_path).into());
+        }
+
+        Ok(Some(MappedEntry { map, start, end }))
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.map[self.start..self.end]
+    }
+}
@@ -0,0 +1,132 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::error::Error;
+use std::fs::File;
+use std::io;
+use time::OffsetDateTime;
+use time::format_description::well_known::Rfc3339;
+
+use crate::split;
+
+/// Where and from what a report was made, so reports of different runs (eg.
+/// nightly diffs of new export builds) can be told apart
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RunMetadata {
+    /// The version of diffmig the run was made with
+    pub version: String,
+    pub host: String,
+    pub registry: Option<String>,
+    /// The SHA-256 of the config file, if one was read
+    pub config_sha256: Option<String>,
+    /// The reproducibility hash of the run's manifest, which records how it was invoked
+    #[serde(default)]
+    pub reproducibility_hash: Option<String>,
+    /// The comparison hash of the run's manifest, of the options that decide which differences are found
+    #[serde(default)]
+    pub comparison_hash: Option<String>,
+    pub old_zip: InputFile,
+    pub new_zip: InputFile,
+    /// What the exports were called, read back by later commands of the report
+    #[serde(default)]
+    pub labels: Labels,
+    /// When the run started and finished, in RFC 3339 (UTC)
+    pub started: String,
+    pub finished: String,
+}
+
+/// What the exports are called in reports and prompts, eg. by the systems
+/// they were exported from, rather than "old" and "new"
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Labels {
+    pub old: String,
+    pub new: String,
+}
+
+impl Default for Labels {
+    fn default() -> Labels {
+        Labels { old: "old".to_string(), new: "new".to_string() }
+    }
+}
+
+impl Labels {
+    /// The old label as a heading, eg. "Old" rather than "old"
+    pub fn old_heading(&self) -> String {
+        heading(&self.old)
+    }
+
+    pub fn new_heading(&self) -> String {
+        heading(&self.new)
+    }
+}
+
+fn heading(label: &str) -> String {
+    let mut chars = label.chars();
+    chars.next().map_or(String::new(), |c| c.to_uppercase().chain(chars).collect())
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct InputFile {
+    pub path: String,
+    /// The SHA-256 of the file, or of its parts in order if it's split
+    pub sha256: String,
+}
+
+impl RunMetadata {
+    /// The metadata of a run starting now, its inputs and finish to be filled in
+    pub fn start(registry: Option<String>, config_sha256: Option<String>) -> RunMetadata {
+        RunMetadata {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            host: hostname::get().map(|h| h.to_string_lossy().into_owned()).unwrap_or_default(),
+            registry,
+            config_sha256,
+            started: now(),
+            ..RunMetadata::default()
+        }
+    }
+}
+
+impl RunMetadata {
+    /// The metadata as labelled rows, for reports read by people
+    pub fn rows(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("diffmig version", self.version.clone()),
+            ("Host", self.host.clone()),
+            ("Registry", self.registry.clone().unwrap_or_default()),
+            ("Config SHA-256", self.config_sha256.clone().unwrap_or_default()),
+            ("Reproducibility hash", self.reproducibility_hash.clone().unwrap_or_default()),
+            ("Comparison hash", self.comparison_hash.clone().unwrap_or_default()),
+            ("Old zip", self.old_zip.path.clone()),
+            ("Old zip SHA-256", self.old_zip.sha256.clone()),
+            ("New zip", self.new_zip.path.clone()),
+            ("New zip SHA-256", self.new_zip.sha256.clone()),
+            ("Old label", self.labels.old.clone()),
+            ("New label", self.labels.new.clone()),
+            ("Started", self.started.clone()),
+            ("Finished", self.finished.clone()),
+        ]
+    }
+}
+
+/// The current time in RFC 3339 (UTC)
+pub fn now() -> String {
+    OffsetDateTime::now_utc().format(&Rfc3339).unwrap_or_default()
+}
+
+pub fn sha256(bytes: &[u8]) -> String {
+    hex(&Sha256::digest(bytes))
+}
+
+/// The SHA-256 of an export, hashing the parts of a split one in order
+pub fn sha256_of_zip(zip_path: &str) -> Result<InputFile, Box<dyn Error>> {
+    let mut hasher = Sha256::new();
+    for path in split::part_paths(zip_path)? {
+        let mut file = File::open(&path).map_err(|e| format!("Failed opening {}: {}", path, e))?;
+        io::copy(&mut file, &mut hasher)?;
+    }
+
+    Ok(InputFile { path: zip_path.to_string(), sha256: hex(&hasher.finalize()) })
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
@@ -1,25 +1,172 @@
-use serde_json::{Value, from_str, to_string_pretty};
+use clap::ValueEnum;
+use std::collections::BTreeMap;
 use std::io::{BufReader, Read, BufRead};
 use std::iter::Peekable;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
 
-use crate::clinical_data::{PatientSlice, ClinicalDatum, ClinicalDatumVariant};
+use crate::clinical_data::{PatientSlice, ClinicalDatum};
+use crate::contexts::Contexts;
+use crate::entries::Entries;
+use crate::fixture::{self, ClinicalDatumRecord, ParseError};
+use crate::interner::Interner;
+use crate::mongo;
+use crate::profile::{self, Phase};
+use crate::report;
+
+/// What to do with a record that fails to parse
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum OnParseError {
+    Panic,
+    Skip,
+    Collect,
+    /// Collect the errors, and compare the raw JSON of each record that failed
+    /// to parse with the other export's record of the same pk
+    Compare,
+}
+
+impl OnParseError {
+    /// Whether the errors are kept to be reported once the registry is read
+    pub fn collects(&self) -> bool {
+        matches!(self, OnParseError::Collect | OnParseError::Compare)
+    }
+}
+
+/// A collection of clinical data records that diffmig can compare
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Collection {
+    Cdes,
+    History,
+}
+
+impl Collection {
+    pub const ALL: [Collection; 2] = [Collection::Cdes, Collection::History];
+
+    /// The collection of a record with the given name, if it's one diffmig knows
+    fn named(name: &str) -> Option<Collection> {
+        match name {
+            "cdes" => Some(Collection::Cdes),
+            "history" => Some(Collection::History),
+            _ => None
+        }
+    }
+}
+
+/// The layout of an export's clinical data
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ExportFormat {
+    /// The rdrf_clinicaldata.json fixture of a Django RDRF's export
+    Django,
+    /// A legacy Mongo RDRF's 'cdes' collection, exported by mongoexport to cdes.json
+    MongoRaw,
+}
+
+impl ExportFormat {
+    /// Whether a path of an archive is of the clinical data of an export of the format
+    pub fn is_clinical_data(&self, path: &str) -> bool {
+        let path_split = path.split('/').collect::<Vec<&str>>();
+        match self {
+            ExportFormat::Django => matches!(&path_split[..], [_, "registry_data", "clinical_data", "rdrf_clinicaldata.json"]),
+            ExportFormat::MongoRaw => matches!(&path_split[..], [.., "cdes.json"]),
+        }
+    }
+
+    /// The text of each record of an export's clinical data, as a record of
+    /// a Django export
+    pub fn records<'a>(&self, reader: impl Read + 'a) -> Box<dyn Iterator<Item=String> + 'a> {
+        match self {
+            ExportFormat::Django => Box::new(MigratedRegistry::read_array_file_to_records(reader)),
+            ExportFormat::MongoRaw => Box::new(mongo::records(reader)),
+        }
+    }
+}
+
+/// Which of an export's clinical data records are read, and what's kept of them
+#[derive(Debug, Clone)]
+pub struct RecordFilter {
+    /// Only records of the registry with the code, though older exports don't give it
+    pub registry_code: Option<String>,
+    pub collections: Vec<Collection>,
+    /// Count records of collections diffmig doesn't know, rather than silently skipping them
+    pub strict_collections: bool,
+    /// Keep the members of each record that aren't read into its clinical datum
+    pub extra_fields: bool,
+    /// The entries the records are read from, where they're of several
+    pub entries: Entries,
+}
+
+impl Default for RecordFilter {
+    fn default() -> Self {
+        RecordFilter { registry_code: None, collections: Collection::ALL.to_vec(), strict_collections: false, extra_fields: false, entries: Entries::default() }
+    }
+}
+
+pub type ParseErrors = Arc<Mutex<Vec<ParseError>>>;
+pub type RecordCount = Arc<AtomicUsize>;
+/// The number of records of each unknown collection
+pub type CollectionCounts = Arc<Mutex<BTreeMap<String, usize>>>;
 
 pub struct MigratedRegistry<'a> {
     iterator: Box<Peekable<Box<dyn Iterator<Item=ClinicalDatum> + 'a>>>,
+    parse_errors: ParseErrors,
+    records_read: RecordCount,
+    unknown_collections: CollectionCounts,
+    contexts: Arc<Contexts>,
 }
 
 impl<'a> MigratedRegistry<'a> {
-    pub fn from(reader: impl Read + 'a, cdes_only: bool) -> MigratedRegistry<'a> {
-        let values = Self::read_array_file_to_values(reader);
-        let clinical_data = Self::map_values_to_clinical_data(values, cdes_only);
+    /// Read the clinical data of an export that the filter lets through,
+    /// keeping the raw JSON of each section if keep_raw is set
+    pub fn from(reader: impl Read + 'a, filter: RecordFilter, on_parse_error: OnParseError, interner: Interner, keep_raw: bool) -> MigratedRegistry<'a> {
+        Self::from_records(Self::read_array_file_to_records(reader), filter, on_parse_error, interner, keep_raw)
+    }
+
+    /// Read clinical data from the text of each record, eg. of records
+    /// changed since they were read from an export
+    pub fn from_records(records: impl Iterator<Item=String> + 'a, filter: RecordFilter, on_parse_error: OnParseError, interner: Interner, keep_raw: bool) -> MigratedRegistry<'a> {
+        Self::from_positioned_records(records.enumerate(), filter, on_parse_error, interner, keep_raw)
+    }
+
+    /// Read clinical data from the text of each record and its position in
+    /// the export, eg. of records sorted by patient
+    pub fn from_positioned_records(records: impl Iterator<Item=(usize, String)> + 'a, filter: RecordFilter, on_parse_error: OnParseError, interner: Interner, keep_raw: bool) -> MigratedRegistry<'a> {
+        let parse_errors = ParseErrors::default();
+        let unknown_collections = CollectionCounts::default();
+        let records_read = RecordCount::default();
+        let counter = records_read.clone();
+        let records = records.inspect(move |_| {
+            counter.fetch_add(1, Ordering::Relaxed);
+        });
+        let clinical_data = Self::map_records_to_clinical_data(records, filter, on_parse_error, parse_errors.clone(), unknown_collections.clone(), interner, keep_raw);
 
         let iterator = Box::new(clinical_data.peekable());
 
-        MigratedRegistry { iterator }
+        MigratedRegistry { iterator, parse_errors, records_read, unknown_collections, contexts: Arc::default() }
+    }
+
+    /// Pair clinical data by their contexts' form groups, rather than by their forms
+    pub fn with_contexts(self, contexts: Arc<Contexts>) -> MigratedRegistry<'a> {
+        MigratedRegistry { contexts, ..self }
+    }
+
+    /// The errors of records skipped with OnParseError::Collect or Compare, filled as the registry is read
+    pub fn parse_errors(&self) -> ParseErrors {
+        self.parse_errors.clone()
+    }
+
+    /// The number of records read so far, whether or not they parsed
+    pub fn records_read(&self) -> RecordCount {
+        self.records_read.clone()
+    }
+
+    /// The records skipped for being of an unknown collection, by collection, filled
+    /// as the registry is read when RecordFilter::strict_collections is set
+    pub fn unknown_collections(&self) -> CollectionCounts {
+        self.unknown_collections.clone()
     }
 
     /// Takes a reader of a large JSON array, and returns an iterator that
-    /// reads each element sequentially
+    /// reads the text of each element sequentially
     ///
     /// serde_json won't read a large array of arbitrary values sequentially
     /// (ie. one at a time rather than all at once).
@@ -32,24 +179,35 @@ impl<'a> MigratedRegistry<'a> {
     ///
     /// https://docs.serde.rs/serde_json/de/struct.StreamDeserializer.html
     ///
-    /// Reading sequentially reduces the memory usage for large migrations
+    /// Reading sequentially reduces the memory usage for large migrations,
+    /// and leaving each element as text lets it be deserialized without
+    /// copying its strings
     ///
     /// This function only works with JSON arrays structured the same
     /// way as in registry exports, so won't support other large arrays
     /// with different indentation etc.
-    pub fn read_array_file_to_values(reader: impl Read + 'a) -> impl Iterator<Item=Value> + 'a {
+    pub fn read_array_file_to_records(reader: impl Read + 'a) -> impl Iterator<Item=String> + 'a {
+        Self::read_array_files_to_records(reader).map(|(_, record)| record)
+    }
+
+    /// The text of each record of arrays read one after another, as from
+    /// entries::Concatenated, with the position of the array it's in
+    pub fn read_array_files_to_records(reader: impl Read + 'a) -> impl Iterator<Item=(usize, String)> + 'a {
         let reader = BufReader::new(reader);
         let mut partial = Vec::<String>::new();
-        reader.lines().scan(Option::<Value>::None, move |_complete, line| {
+        reader.lines().scan(0usize, move |arrays, line| {
             match line.expect("Failed reading line from file").as_str() {
-                "[" => Some(None),
-                "]" => None,
+                // Arrays of entries read one after another follow each other
+                "[" | "[]" => {
+                    *arrays += 1;
+                    Some(None)
+                }
+                "]" | "" => Some(None),
                 "    }" | "    }," => {
                     partial.push("}".to_string());
-                    let value = from_str::<Value>(&partial.join("\n"))
-                        .expect("Failed parsing JSON array entry");
+                    let record = partial.join("\n");
                     partial.clear();
-                    Some(Some(value))
+                    Some(Some(((*arrays).saturating_sub(1), record)))
                 }
                 l => {
                     partial.push(l.to_string());
@@ -59,23 +217,63 @@ impl<'a> MigratedRegistry<'a> {
         }).flatten()
     }
 
-    pub fn map_values_to_clinical_data(values: impl Iterator<Item=Value> + 'a, cdes_only: bool) -> Box<dyn Iterator<Item=ClinicalDatum> + 'a> {
-        let data = values.filter_map(|value| match ClinicalDatum::from(&value) {
-            Ok(cd) => cd,
-            Err(e) => {
-                log::error!("Error parsing clinical datum: {:#?}", e);
-                log::debug!("Original value: {}", to_string_pretty(&value).unwrap());
-                panic!()
+    pub fn map_records_to_clinical_data(records: impl Iterator<Item=(usize, String)> + 'a, filter: RecordFilter, on_parse_error: OnParseError, parse_errors: ParseErrors, unknown_collections: CollectionCounts, mut interner: Interner, keep_raw: bool) -> Box<dyn Iterator<Item=ClinicalDatum> + 'a> {
+        let data = records.filter_map(move |(i, text)| {
+            let datum = profile::time(Phase::JsonParse, || ClinicalDatumRecord::parse(&text))
+                .and_then(|record| match (&filter.registry_code, &record.fields.registry_code) {
+                    // Records of other registries are left out, but older exports don't give the code
+                    (Some(code), Some(record_code)) if code != record_code => Ok(None),
+                    _ => match Collection::named(&record.fields.collection) {
+                        Some(collection) if filter.collections.contains(&collection) =>
+                            profile::time(Phase::Construct, || ClinicalDatum::from(&record, &mut interner, keep_raw)),
+                        Some(_) => Ok(None),
+                        None => {
+                            if filter.strict_collections {
+                                *unknown_collections.lock().unwrap().entry(record.fields.collection.to_string()).or_default() += 1;
+                            }
+                            Ok(None)
+                        }
+                    },
+                });
+
+            match (datum, on_parse_error) {
+                (Ok(cd), _) => cd.map(|mut cd| {
+                    cd.hash = report::fnv1a(report::FNV_OFFSET, text.as_bytes());
+                    // Where several entries are read, the record is given by its position in its entry
+                    match filter.entries.locate(i) {
+                        Some((entry, position)) => {
+                            cd.entry = Some(entry);
+                            cd.record = position as u32;
+                        }
+                        None => cd.record = i as u32,
+                    }
+                    cd.size = text.len() as u32;
+                    if filter.extra_fields {
+                        cd.extra = fixture::extra_fields(&text);
+                    }
+                    cd
+                }),
+                (Err(e), OnParseError::Panic) => {
+                    log::error!("Error parsing clinical datum: {}", e);
+                    log::debug!("Original value: {}", text);
+                    panic!()
+                }
+                (Err(e), OnParseError::Skip) => {
+                    log::error!("Skipping clinical datum: {}", e);
+                    None
+                }
+                (Err(e), OnParseError::Collect) => {
+                    parse_errors.lock().unwrap().push(e);
+                    None
+                }
+                (Err(e), OnParseError::Compare) => {
+                    parse_errors.lock().unwrap().push(e.with_raw(text.clone()));
+                    None
+                }
             }
         });
 
-        match cdes_only {
-            true => Box::new(data.filter_map(|cd| match cd.variant {
-                ClinicalDatumVariant::History => None,
-                ClinicalDatumVariant::CDEs => Some(cd)
-            })),
-            false => Box::new(data)
-        }
+        Box::new(data)
     }
 }
 
@@ -83,18 +281,19 @@ impl<'a> Iterator for MigratedRegistry<'a> {
     type Item = PatientSlice;
 
     fn next(&mut self) -> Option<Self::Item> {
-        return match self.iterator.next() {
+        match self.iterator.next() {
             None => None,
             Some(first_cd) => {
                 let mut slice = PatientSlice::from(first_cd.patient);
-                slice.add(first_cd);
+                slice.add(self.contexts.key(&first_cd), first_cd);
 
                 loop {
                     match self.iterator.peek() {
                         None => break,
                         Some(cd) => {
-                            match slice.can_add(&cd) {
-                                true => slice.add(self.iterator.next().unwrap()),
+                            let context = self.contexts.key(cd);
+                            match slice.can_add(&context, cd) {
+                                true => slice.add(context, self.iterator.next().unwrap()),
                                 false => break,
                             };
                         }
@@ -103,6 +302,6 @@ impl<'a> Iterator for MigratedRegistry<'a> {
 
                 Some(slice)
             }
-        };
+        }
     }
 }
@@ -0,0 +1,76 @@
+use serde_json::{json, Map, Value};
+use std::io::{BufRead, BufReader, Read};
+use time::OffsetDateTime;
+use time::format_description::well_known::Rfc3339;
+
+/// Takes a reader of a legacy RDRF's Mongo 'cdes' collection, as mongoexport
+/// writes it (one document per line), and returns an iterator of the text of
+/// each patient's document as a record of a Django export's clinical data
+///
+/// The documents have no Django wrapper, so each is given one: its pk is its
+/// line of the dump, as Mongo's ObjectIds aren't numbers, and its collection
+/// is 'cdes'. Documents of models other than patients are left out, and
+/// documents that aren't JSON objects are passed on as they are to fail parsing
+/// The JSON pointers of differences are into these records, not the dump
+///
+/// Patients are compared in order, so the dump must be sorted by patient
+/// (eg. mongoexport --collection cdes --sort '{"django_id": 1}')
+pub fn records<'a>(reader: impl Read + 'a) -> impl Iterator<Item=String> + 'a {
+    BufReader::new(reader).lines()
+        .map(|line| line.expect("Failed reading line from file"))
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .filter_map(|(i, line)| match serde_json::from_str::<Map<String, Value>>(&line) {
+            Ok(document) => django_record(i + 1, document),
+            Err(_) => Some(line),
+        })
+}
+
+/// The record of a Django export that a document of the 'cdes' collection
+/// was migrated to, if it's a patient's
+fn django_record(pk: usize, mut document: Map<String, Value>) -> Option<String> {
+    match document.get("django_model").and_then(Value::as_str) {
+        Some("Patient") | None => {}
+        Some(_) => return None,
+    }
+
+    // Fields that are missing are left out, so the record fails parsing at them
+    let mut fields = Map::new();
+    fields.insert("collection".to_string(), json!("cdes"));
+    for field in ["django_id", "context_id"] {
+        if let Some(value) = document.remove(field) {
+            fields.insert(field.to_string(), relaxed(value));
+        }
+    }
+
+    let mut data = Map::new();
+    if let Some(forms) = document.remove("forms") {
+        data.insert("forms".to_string(), forms);
+    }
+    if let Some(timestamp) = document.remove("timestamp") {
+        data.insert("timestamp".to_string(), relaxed(timestamp));
+    }
+    fields.insert("data".to_string(), Value::Object(data));
+
+    Some(json!({ "pk": pk, "fields": fields }).to_string())
+}
+
+/// A value of Mongo's canonical extended JSON as mongoexport's relaxed mode
+/// writes it, eg. {"$numberLong": "12"} as 12
+fn relaxed(value: Value) -> Value {
+    let (key, inner) = match &value {
+        Value::Object(o) if o.len() == 1 => o.iter().next().unwrap(),
+        _ => return value,
+    };
+
+    match (key.as_str(), inner) {
+        ("$numberInt" | "$numberLong", Value::String(n)) => n.parse::<i64>().map_or(value.clone(), Value::from),
+        ("$date", Value::String(date)) => Value::String(date.clone()),
+        // Canonical dates are milliseconds since the epoch
+        ("$date", Value::Object(_)) => relaxed(inner.clone()).as_i64()
+            .and_then(|ms| OffsetDateTime::from_unix_timestamp_nanos(ms as i128 * 1_000_000).ok())
+            .and_then(|date| date.format(&Rfc3339).ok())
+            .map_or(value.clone(), Value::String),
+        _ => value,
+    }
+}
@@ -0,0 +1,52 @@
+use serde_json::{json, Value};
+use std::error::Error;
+
+use crate::report::Summary;
+
+/// How a run ended
+pub enum Outcome<'a> {
+    Finished(&'a Summary),
+    Failed(&'a str),
+}
+
+/// Post the outcome of a run to a webhook, as the summary JSON or as a Slack
+/// message
+pub fn post(url: &str, slack: bool, outcome: &Outcome) -> Result<(), Box<dyn Error>> {
+    let payload = match slack {
+        true => json!({ "text": slack_text(outcome) }),
+        false => payload(outcome),
+    };
+
+    ureq::post(url)
+        .set("Content-Type", "application/json")
+        .send_string(&payload.to_string())
+        .map_err(|e| format!("Failed notifying {}: {}", url, e))?;
+
+    Ok(())
+}
+
+fn payload(outcome: &Outcome) -> Value {
+    match outcome {
+        Outcome::Finished(summary) => json!({
+            "status": "finished",
+            "patients": summary.patients,
+            "differing": summary.differing_patients,
+            "diffs": summary.differences,
+            "by_severity": summary.by_severity,
+            "truncated": summary.truncated,
+            "metadata": summary.metadata,
+        }),
+        Outcome::Failed(error) => json!({ "status": "failed", "error": error }),
+    }
+}
+
+fn slack_text(outcome: &Outcome) -> String {
+    match outcome {
+        Outcome::Finished(summary) => format!(
+            "diffmig finished{}: {} differences in {} of {} patients",
+            if summary.truncated { " early" } else { "" },
+            summary.differences, summary.differing_patients, summary.patients
+        ),
+        Outcome::Failed(error) => format!("diffmig failed: {}", error),
+    }
+}
@@ -0,0 +1,57 @@
+use clap::ValueEnum;
+use serde::Deserialize;
+
+/// How the old export writes numbers stored as strings, by the separators of
+/// a locale, so "1,5" can be read as the 1.5 the migration converts it to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NumberLocale {
+    /// A decimal point, with commas between thousands, eg. "1,234.5"
+    En,
+    /// A decimal comma, with points between thousands, eg. "1.234,5"
+    De,
+    /// A decimal comma, with spaces between thousands, eg. "1 234,5"
+    Fr,
+}
+
+impl NumberLocale {
+    /// The decimal separator, and the separators of groups of thousands
+    fn separators(self) -> (char, &'static [char]) {
+        match self {
+            NumberLocale::En => ('.', &[',']),
+            NumberLocale::De => (',', &['.']),
+            NumberLocale::Fr => (',', &[' ', '\u{a0}', '\u{202f}']),
+        }
+    }
+
+    /// The number a string gives in the locale, if it's one
+    ///
+    /// Separators of thousands must separate groups of three digits, so a
+    /// separator that can't be one (eg. "1.5" with De) isn't read as one
+    pub fn parse(self, text: &str) -> Option<f64> {
+        let (decimal, grouping) = self.separators();
+        let text = text.trim();
+        let unsigned = text.strip_prefix(['-', '+']).unwrap_or(text);
+
+        let (integer, fraction) = match unsigned.split_once(decimal) {
+            Some((integer, fraction)) => (integer, Some(fraction)),
+            None => (unsigned, None),
+        };
+        let digits = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_ascii_digit());
+        let groups = integer.split(grouping).collect::<Vec<&str>>();
+        let grouped = match &groups[..] {
+            [integer] => digits(integer),
+            [first, rest @ ..] => digits(first) && first.len() <= 3 && rest.iter().all(|g| digits(g) && g.len() == 3),
+            [] => false,
+        };
+        if !grouped || fraction.is_some_and(|f| !digits(f)) {
+            return None;
+        }
+
+        let sign = match text.starts_with('-') {
+            true => "-",
+            false => "",
+        };
+        format!("{}{}.{}", sign, groups.concat(), fraction.unwrap_or("0")).parse().ok()
+    }
+}
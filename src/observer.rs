@@ -0,0 +1,90 @@
+use std::error::Error;
+
+use crate::output::ReportWriter;
+use crate::report::{DifferenceRecord, Summary};
+
+/// Follows a diff as it goes, eg. to stream its differences into a database
+/// or UI rather than waiting on a report
+///
+/// A patient whose clinical data spans several slices is started and
+/// completed once for each slice, and again for their history and other
+/// models if they're compared apart
+///
+/// ```no_run
+/// # use clap::Parser;
+/// # use std::error::Error;
+/// use diffmig::cli::{Cli, Command};
+/// use diffmig::observer::DiffObserver;
+/// use diffmig::report::DifferenceRecord;
+///
+/// struct Printer;
+///
+/// impl DiffObserver for Printer {
+///     fn on_difference(&mut self, patient: u32, difference: &DifferenceRecord) -> Result<(), Box<dyn Error>> {
+///         println!("patient {}: {}", patient, difference.id());
+///         Ok(())
+///     }
+/// }
+///
+/// let args = match Cli::parse_from(["diffmig", "diff", "old.zip", "new.zip"]).command {
+///     Command::Diff(args) => *args,
+///     _ => unreachable!(),
+/// };
+/// diffmig::run::diff(args, None, vec![Box::new(Printer)])?;
+/// # Ok::<(), Box<dyn Error>>(())
+/// ```
+pub trait DiffObserver {
+    fn on_patient_start(&mut self, _patient: u32, _ids: &str) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    fn on_difference(&mut self, _patient: u32, _difference: &DifferenceRecord) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    /// Called once the patient's differences are given, with how many there were
+    fn on_patient_complete(&mut self, _patient: u32, _ids: &str, _differences: usize) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    fn on_finished(&mut self, _summary: &Summary) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+}
+
+impl<O: DiffObserver + ?Sized> DiffObserver for Box<O> {
+    fn on_patient_start(&mut self, patient: u32, ids: &str) -> Result<(), Box<dyn Error>> {
+        (**self).on_patient_start(patient, ids)
+    }
+
+    fn on_difference(&mut self, patient: u32, difference: &DifferenceRecord) -> Result<(), Box<dyn Error>> {
+        (**self).on_difference(patient, difference)
+    }
+
+    fn on_patient_complete(&mut self, patient: u32, ids: &str, differences: usize) -> Result<(), Box<dyn Error>> {
+        (**self).on_patient_complete(patient, ids, differences)
+    }
+
+    fn on_finished(&mut self, summary: &Summary) -> Result<(), Box<dyn Error>> {
+        (**self).on_finished(summary)
+    }
+}
+
+/// An observer as an output of a diff, so it's called as the reports are
+/// written, as run::diff does with its observers
+pub struct Observed<O: DiffObserver>(pub O);
+
+impl<O: DiffObserver> ReportWriter for Observed<O> {
+    fn start(&mut self, patient: u32, ids: &str) -> Result<(), Box<dyn Error>> {
+        self.0.on_patient_start(patient, ids)
+    }
+
+    fn patient(&mut self, patient: u32, ids: &str, differences: &[DifferenceRecord]) -> Result<(), Box<dyn Error>> {
+        differences.iter().try_for_each(|d| self.0.on_difference(patient, d))?;
+        self.0.on_patient_complete(patient, ids, differences.len())
+    }
+
+    fn finish(&mut self, summary: &Summary) -> Result<(), Box<dyn Error>> {
+        self.0.on_finished(summary)
+    }
+}
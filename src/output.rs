@@ -0,0 +1,910 @@
+use handlebars::Handlebars;
+use itertools::Itertools;
+use rusqlite::{Connection, OpenFlags, NO_PARAMS, params};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+
+use crate::metadata::{Labels, RunMetadata};
+use crate::report::{CdeExamples, CohortTotals, DedupedChange, DifferenceKind, DifferenceRecord, Location, RenumberedPatient, Severity, SlowPatient, Summary};
+use crate::skipped_forms::SkippedForm;
+use crate::suppressions::SuppressionUse;
+
+/// A destination for the report of a run, written to as each patient is compared
+pub trait ReportWriter {
+    /// Note that a patient's slice, or their other models, are about to be compared
+    fn start(&mut self, _patient: u32, _ids: &str) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    fn patient(&mut self, patient: u32, ids: &str, differences: &[DifferenceRecord]) -> Result<(), Box<dyn Error>>;
+
+    /// Note the hashes of the old and new clinical data of a patient's slice,
+    /// and how many differences the run counted in it, for a later run to
+    /// tell whether it changed
+    fn slice(&mut self, _patient: u32, _ids: &str, _hashes: (u64, u64), _differences: usize) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    fn finish(&mut self, summary: &Summary) -> Result<(), Box<dyn Error>>;
+}
+
+/// Create a writer from an output spec of the form "<format>:<path>", the
+/// reports people read calling the exports by their labels
+pub fn from_spec(spec: &str, labels: &Labels) -> Result<Box<dyn ReportWriter>, Box<dyn Error>> {
+    let (format, path) = match spec.find(':') {
+        Some(i) => (&spec[..i], &spec[i + 1..]),
+        None => return Err(format!("Output '{}' should be of the form <format>:<path>", spec).into())
+    };
+
+    match format {
+        "sqlite" => Ok(Box::new(SqliteWriter::create(path)?)),
+        "summary" => Ok(Box::new(SummaryWriter::new(path))),
+        "json" => Ok(Box::new(JsonWriter::create(path)?)),
+        "csv" => Ok(Box::new(CsvWriter::create(path)?)),
+        "html" => Ok(Box::new(HtmlWriter::create(path, labels.clone())?)),
+        "markdown" => Ok(Box::new(MarkdownWriter::new(path, labels.clone()))),
+        _ => Err(format!("Unknown output format '{}'", format).into())
+    }
+}
+
+/// The latest run of an SQLite report
+pub fn latest_sqlite_run(connection: &Connection, path: &str) -> Result<i64, Box<dyn Error>> {
+    let run = connection.query_row("SELECT MAX(run) FROM runs", NO_PARAMS, |row| row.get::<_, Option<i64>>(0))
+        .map_err(|e| format!("{} has no run ids, it was made by an older diffmig: {}", path, e))?;
+
+    Ok(run.ok_or_else(|| format!("{} has no finished runs", path))?)
+}
+
+/// Print the summary stats of a run of an SQLite report, the latest if none
+/// is given, and how many of its differences are of each kind
+pub fn print_sqlite_summary(path: &str, run: Option<i64>) -> Result<(), Box<dyn Error>> {
+    let connection = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let run = match run {
+        Some(run) => run,
+        None => latest_sqlite_run(&connection, path)?,
+    };
+    let runs = connection.query_row("SELECT COUNT(*) FROM runs", NO_PARAMS, |row| row.get::<_, i64>(0))?;
+    println!("Run {} of the {} runs in {}", run, runs, path);
+    println!();
+
+    let mut summary = connection.prepare("SELECT key, value FROM summary WHERE run = ?1")?;
+    let rows = summary.query_map(params![run], |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?)))?;
+    for row in rows {
+        let (key, value) = row?;
+        println!("{:<20} {:>10}", key, value);
+    }
+
+    let mut metadata = connection.prepare("SELECT key, value FROM metadata WHERE run = ?1")?;
+    let rows = metadata.query_map(params![run], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?;
+    println!();
+    for row in rows {
+        let (key, value) = row?;
+        println!("{:<20} {}", key, value);
+    }
+
+    let mut kinds = connection.prepare("SELECT kind, COUNT(*) FROM differences WHERE run = ?1 GROUP BY kind ORDER BY COUNT(*) DESC")?;
+    let rows = kinds.query_map(params![run], |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?)))?;
+    println!();
+    println!("{:<20} {:>10}", "Kind", "Differences");
+    for row in rows {
+        let (kind, count) = row?;
+        println!("{:<20} {:>10}", kind, count);
+    }
+
+    Ok(())
+}
+
+/// Writes differences, patients and summary stats into tables of an SQLite
+/// database, alongside the reports of earlier runs already in it, each row
+/// having the id of its run so runs (eg. of several migration attempts) can
+/// be queried together
+///
+/// A run's rows are written in one transaction, so only finished runs are
+/// kept. The hashes of each slice's clinical data are kept too, so a run with
+/// --since the report can reuse the differences of slices that haven't changed
+pub struct SqliteWriter {
+    connection: Connection,
+    run: i64,
+}
+
+impl SqliteWriter {
+    pub fn create(path: &str) -> Result<SqliteWriter, Box<dyn Error>> {
+        let connection = Connection::open(path)?;
+
+        let tables = connection.query_row("SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = 'differences'", NO_PARAMS, |row| row.get::<_, i64>(0))?;
+        let runs = connection.query_row("SELECT COUNT(*) FROM pragma_table_info('differences') WHERE name = 'run'", NO_PARAMS, |row| row.get::<_, i64>(0))?;
+        if tables > 0 && runs == 0 {
+            return Err(format!("{} has a report made by an older diffmig without run ids, so can't be added to", path).into());
+        }
+
+        connection.execute_batch("
+            CREATE TABLE IF NOT EXISTS runs (
+                run INTEGER PRIMARY KEY
+            );
+            CREATE TABLE IF NOT EXISTS patients (
+                run INTEGER NOT NULL,
+                patient INTEGER NOT NULL,
+                ids TEXT NOT NULL,
+                differences INTEGER NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS differences (
+                id INTEGER PRIMARY KEY,
+                run INTEGER NOT NULL,
+                patient INTEGER NOT NULL,
+                ids TEXT NOT NULL,
+                context TEXT NOT NULL,
+                form TEXT,
+                section TEXT,
+                cde TEXT,
+                field TEXT,
+                kind TEXT NOT NULL,
+                old TEXT,
+                new TEXT,
+                detail TEXT,
+                old_timestamp TEXT,
+                new_timestamp TEXT,
+                old_pointer TEXT,
+                new_pointer TEXT
+            );
+            CREATE INDEX IF NOT EXISTS differences_patient ON differences (run, patient);
+            CREATE INDEX IF NOT EXISTS differences_cde ON differences (run, cde);
+            CREATE TABLE IF NOT EXISTS summary (
+                run INTEGER NOT NULL,
+                key TEXT NOT NULL,
+                value INTEGER NOT NULL,
+                PRIMARY KEY (run, key)
+            );
+            CREATE TABLE IF NOT EXISTS metadata (
+                run INTEGER NOT NULL,
+                key TEXT NOT NULL,
+                value TEXT NOT NULL,
+                PRIMARY KEY (run, key)
+            );
+            CREATE TABLE IF NOT EXISTS slices (
+                run INTEGER NOT NULL,
+                patient INTEGER NOT NULL,
+                ids TEXT NOT NULL,
+                old_hash TEXT NOT NULL,
+                new_hash TEXT NOT NULL,
+                differences INTEGER NOT NULL
+            );
+            BEGIN;
+            INSERT INTO runs DEFAULT VALUES;
+        ")?;
+        let run = connection.last_insert_rowid();
+
+        Ok(SqliteWriter { connection, run })
+    }
+}
+
+impl ReportWriter for SqliteWriter {
+    fn patient(&mut self, patient: u32, ids: &str, differences: &[DifferenceRecord]) -> Result<(), Box<dyn Error>> {
+        self.connection.prepare_cached("INSERT INTO patients (run, patient, ids, differences) VALUES (?1, ?2, ?3, ?4)")?
+            .execute(params![self.run, patient, ids, differences.len() as i64])?;
+
+        let mut insert = self.connection.prepare_cached("
+            INSERT INTO differences (run, patient, ids, context, form, section, cde, field, kind, old, new, detail, old_timestamp, new_timestamp, old_pointer, new_pointer)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16)
+        ")?;
+        for d in differences {
+            let l = &d.location;
+            insert.execute(params![
+                self.run, l.patient, l.ids, l.context, l.form, l.section, l.cde, l.field, d.kind.to_string(), d.old, d.new, d.detail,
+                l.old_timestamp, l.new_timestamp, l.old_pointer, l.new_pointer
+            ])?;
+        }
+
+        Ok(())
+    }
+
+    fn slice(&mut self, patient: u32, ids: &str, (old, new): (u64, u64), differences: usize) -> Result<(), Box<dyn Error>> {
+        self.connection.prepare_cached("INSERT INTO slices (run, patient, ids, old_hash, new_hash, differences) VALUES (?1, ?2, ?3, ?4, ?5, ?6)")?
+            .execute(params![self.run, patient, ids, format!("{:016x}", old), format!("{:016x}", new), differences as i64])?;
+
+        Ok(())
+    }
+
+    fn finish(&mut self, summary: &Summary) -> Result<(), Box<dyn Error>> {
+        let mut insert = self.connection.prepare("INSERT INTO summary (run, key, value) VALUES (?1, ?2, ?3)")?;
+        insert.execute(params![self.run, "patients", summary.patients as i64])?;
+        insert.execute(params![self.run, "differing_patients", summary.differing_patients as i64])?;
+        insert.execute(params![self.run, "differences", summary.differences as i64])?;
+        insert.execute(params![self.run, "truncated", summary.truncated as i64])?;
+        insert.execute(params![self.run, "interrupted", summary.interrupted as i64])?;
+        drop(insert);
+
+        let mut insert = self.connection.prepare("INSERT INTO metadata (run, key, value) VALUES (?1, ?2, ?3)")?;
+        for (key, value) in summary.metadata.rows() {
+            insert.execute(params![self.run, key, value])?;
+        }
+        drop(insert);
+
+        self.connection.execute_batch("COMMIT;")?;
+
+        Ok(())
+    }
+}
+
+/// Writes the totals of a run as a small JSON object once it's finished, for
+/// polling by dashboards
+///
+/// The file is replaced in one rename, so a reader never sees it half written
+pub struct SummaryWriter {
+    path: String,
+}
+
+#[derive(Serialize)]
+struct SummaryFile<'a> {
+    patients: usize,
+    differing: usize,
+    diffs: usize,
+    by_severity: &'a BTreeMap<Severity, usize>,
+    by_kind: &'a BTreeMap<DifferenceKind, usize>,
+    by_form: &'a BTreeMap<String, usize>,
+    worst_patients: Vec<WorstPatient>,
+    truncated: bool,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    by_cohort: &'a BTreeMap<String, CohortTotals>,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    suppressions: &'a [SuppressionUse],
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    skipped_forms: &'a BTreeMap<String, SkippedForm>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    retired: &'a BTreeMap<String, usize>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    examples: &'a BTreeMap<String, CdeExamples>,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    deduplicated: &'a [DedupedChange],
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    renumbered: &'a [RenumberedPatient],
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    slow_patients: &'a [SlowPatient],
+    metadata: &'a RunMetadata,
+}
+
+#[derive(Serialize)]
+struct WorstPatient {
+    patient: u32,
+    score: f64,
+}
+
+impl<'a> SummaryFile<'a> {
+    fn new(summary: &'a Summary) -> SummaryFile<'a> {
+        SummaryFile {
+            patients: summary.patients,
+            differing: summary.differing_patients,
+            diffs: summary.differences,
+            by_severity: &summary.by_severity,
+            by_kind: &summary.by_kind,
+            by_form: &summary.by_form,
+            worst_patients: summary.worst_patients.iter().map(|(patient, score)| WorstPatient { patient: *patient, score: *score }).collect(),
+            truncated: summary.truncated,
+            by_cohort: &summary.by_cohort,
+            suppressions: &summary.suppressions,
+            skipped_forms: &summary.skipped_forms,
+            retired: &summary.retired,
+            examples: &summary.examples,
+            deduplicated: &summary.deduplicated,
+            renumbered: &summary.renumbered,
+            slow_patients: &summary.slow_patients,
+            metadata: &summary.metadata,
+        }
+    }
+}
+
+impl SummaryWriter {
+    pub fn new(path: &str) -> SummaryWriter {
+        SummaryWriter { path: path.to_string() }
+    }
+}
+
+impl ReportWriter for SummaryWriter {
+    fn patient(&mut self, _patient: u32, _ids: &str, _differences: &[DifferenceRecord]) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    fn finish(&mut self, summary: &Summary) -> Result<(), Box<dyn Error>> {
+        let file = SummaryFile::new(summary);
+
+        let partial = format!("{}.partial", self.path);
+        fs::write(&partial, serde_json::to_string(&file)?)?;
+        fs::rename(&partial, &self.path)?;
+
+        Ok(())
+    }
+}
+
+/// Writes differences as a JSON array of records, one at a time, so memory
+/// stays bounded however many differences there are
+pub struct JsonWriter<W: Write = BufWriter<File>> {
+    writer: W,
+    first: bool,
+}
+
+/// A difference with its id, so it can be annotated with `diffmig annotate`
+#[derive(Serialize)]
+struct JsonRecord<'a> {
+    id: String,
+    #[serde(flatten)]
+    record: &'a DifferenceRecord,
+}
+
+/// The metadata of the run, told apart from the differences by its only key
+#[derive(Serialize)]
+struct JsonMetadata<'a> {
+    metadata: &'a RunMetadata,
+}
+
+impl JsonWriter {
+    pub fn create(path: &str) -> Result<JsonWriter, Box<dyn Error>> {
+        JsonWriter::new(BufWriter::new(File::create(path)?))
+    }
+}
+
+impl<W: Write> JsonWriter<W> {
+    pub fn new(mut writer: W) -> Result<JsonWriter<W>, Box<dyn Error>> {
+        writer.write_all(b"[")?;
+
+        Ok(JsonWriter { writer, first: true })
+    }
+
+    pub fn records(&mut self, differences: &[DifferenceRecord]) -> Result<(), Box<dyn Error>> {
+        for d in differences {
+            match self.first {
+                true => self.first = false,
+                false => self.writer.write_all(b",")?,
+            }
+            self.writer.write_all(b"\n")?;
+            serde_json::to_writer(&mut self.writer, &JsonRecord { id: d.id(), record: d })?;
+        }
+
+        Ok(())
+    }
+
+    /// End the report with the run's metadata, if it's known
+    pub fn close(&mut self, metadata: Option<&RunMetadata>) -> Result<(), Box<dyn Error>> {
+        if let Some(metadata) = metadata {
+            if !self.first {
+                self.writer.write_all(b",")?;
+            }
+            self.writer.write_all(b"\n")?;
+            serde_json::to_writer(&mut self.writer, &JsonMetadata { metadata })?;
+        }
+        self.writer.write_all(b"\n]\n")?;
+        self.writer.flush()?;
+
+        Ok(())
+    }
+}
+
+impl<W: Write> ReportWriter for JsonWriter<W> {
+    fn patient(&mut self, _patient: u32, _ids: &str, differences: &[DifferenceRecord]) -> Result<(), Box<dyn Error>> {
+        self.records(differences)
+    }
+
+    /// The run's metadata is the last element, as its finish is only known now
+    fn finish(&mut self, summary: &Summary) -> Result<(), Box<dyn Error>> {
+        self.close(Some(&summary.metadata))
+    }
+}
+
+const COLUMNS: [&str; 17] = [
+    "patient", "ids", "context", "form", "section", "cde", "field", "kind", "old", "new", "detail",
+    "old_timestamp", "new_timestamp", "old_pointer", "new_pointer", "id", "triage",
+];
+
+/// The columns of a difference, in the order of COLUMNS
+fn columns(d: &DifferenceRecord) -> [String; 17] {
+    let l = &d.location;
+    let text = |v: &Option<String>| v.clone().unwrap_or_default();
+    [
+        l.patient.to_string(), l.ids.clone(), l.context.clone(), text(&l.form), text(&l.section), text(&l.cde),
+        text(&l.field), d.kind.to_string(), text(&d.old), text(&d.new), text(&d.detail),
+        text(&l.old_timestamp), text(&l.new_timestamp), text(&l.old_pointer), text(&l.new_pointer), d.id(), d.triage.map(|t| t.to_string()).unwrap_or_default(),
+    ]
+}
+
+/// Writes differences as CSV rows, with the same columns as the SQLite
+/// differences table, as each patient is compared
+pub struct CsvWriter {
+    writer: BufWriter<File>,
+}
+
+impl CsvWriter {
+    pub fn create(path: &str) -> Result<CsvWriter, Box<dyn Error>> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "{}", COLUMNS.join(","))?;
+
+        Ok(CsvWriter { writer })
+    }
+
+    /// Quote a field if it holds a separator, quote or line break
+    fn field(value: &str) -> String {
+        match value.contains([',', '"', '\n', '\r']) {
+            true => format!("\"{}\"", value.replace('"', "\"\"")),
+            false => value.to_string(),
+        }
+    }
+}
+
+impl ReportWriter for CsvWriter {
+    fn patient(&mut self, _patient: u32, _ids: &str, differences: &[DifferenceRecord]) -> Result<(), Box<dyn Error>> {
+        for d in differences {
+            writeln!(self.writer, "{}", columns(d).iter().map(|c| CsvWriter::field(c)).join(","))?;
+        }
+
+        Ok(())
+    }
+
+    fn finish(&mut self, _summary: &Summary) -> Result<(), Box<dyn Error>> {
+        self.writer.flush()?;
+
+        Ok(())
+    }
+}
+
+/// The path of a part of a report split into several files, numbered before
+/// its extension
+fn part_path(path: &str, part: usize) -> String {
+    match path.rsplit_once('.') {
+        Some((stem, extension)) if !extension.contains('/') => format!("{}-{}.{}", stem, part, extension),
+        _ => format!("{}-{}", path, part),
+    }
+}
+
+/// The most bytes of patient sections in an HTML report file, past which the
+/// rest go in further files, as browsers struggle with much larger pages
+const HTML_PART_LIMIT: u64 = 50 * 1024 * 1024;
+
+/// Writes an HTML page with a section per differing patient, each written as
+/// the patient is compared, and the totals at the end
+///
+/// A report too big for one page is split into parts of consecutive
+/// patients, and the page is replaced by an index of the parts
+pub struct HtmlWriter {
+    path: String,
+    writer: BufWriter<File>,
+    /// The bytes of patient sections written to the current file
+    written: u64,
+    /// The first and last patient of the current file, if it has any
+    patients: Option<(u32, u32)>,
+    /// The files finished so far, once the report is split
+    parts: Vec<HtmlPart>,
+    labels: Labels,
+}
+
+struct HtmlPart {
+    path: String,
+    patients: Option<(u32, u32)>,
+}
+
+impl HtmlWriter {
+    pub fn create(path: &str, labels: Labels) -> Result<HtmlWriter, Box<dyn Error>> {
+        Ok(HtmlWriter { path: path.to_string(), writer: HtmlWriter::start(path)?, written: 0, patients: None, parts: vec![], labels })
+    }
+
+    /// Create a page, writing its heading
+    fn start(path: &str) -> Result<BufWriter<File>, Box<dyn Error>> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "<!DOCTYPE html>")?;
+        writeln!(writer, "<html><head><meta charset=\"utf-8\"><title>diffmig report</title></head><body>")?;
+        writeln!(writer, "<h1>diffmig report</h1>")?;
+
+        Ok(writer)
+    }
+
+    /// The path of a part of the report, eg. report-2.html for report.html
+    fn part_path(&self, part: usize) -> String {
+        part_path(&self.path, part)
+    }
+
+    /// Finish the current file as a part, moving the first one aside for the index
+    fn finish_part(&mut self) -> Result<(), Box<dyn Error>> {
+        writeln!(self.writer, "</body></html>")?;
+        self.writer.flush()?;
+
+        let path = self.part_path(self.parts.len() + 1);
+        if self.parts.is_empty() {
+            fs::rename(&self.path, &path)?;
+        }
+        self.parts.push(HtmlPart { path, patients: self.patients.take() });
+
+        Ok(())
+    }
+
+    /// The heading of a column, with the exports' columns called by their labels
+    fn heading(&self, column: &str) -> String {
+        let label = |label: &str, rest: &str| match rest {
+            "" => label.to_string(),
+            rest => format!("{} {}", label, rest.trim_start_matches('_')),
+        };
+        match (column.strip_prefix("old"), column.strip_prefix("new")) {
+            (Some(rest), _) => label(&self.labels.old, rest),
+            (_, Some(rest)) => label(&self.labels.new, rest),
+            _ => column.to_string(),
+        }
+    }
+
+    fn escape(text: &str) -> String {
+        text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+    }
+}
+
+impl ReportWriter for HtmlWriter {
+    fn patient(&mut self, patient: u32, ids: &str, differences: &[DifferenceRecord]) -> Result<(), Box<dyn Error>> {
+        if differences.is_empty() {
+            return Ok(());
+        }
+
+        if self.written > HTML_PART_LIMIT {
+            self.finish_part()?;
+            self.writer = HtmlWriter::start(&self.part_path(self.parts.len() + 1))?;
+            self.written = 0;
+        }
+
+        let mut section = String::new();
+        section.push_str(&format!("<section><h2>Patient {} ({})</h2><table>\n", patient, HtmlWriter::escape(ids)));
+        section.push_str(&format!("<tr>{}</tr>\n", COLUMNS[2..].iter().map(|c| format!("<th>{}</th>", HtmlWriter::escape(&self.heading(c)))).join("")));
+        for d in differences {
+            let cells = columns(d)[2..].iter().map(|c| format!("<td>{}</td>", HtmlWriter::escape(c))).join("");
+            section.push_str(&format!("<tr>{}</tr>\n", cells));
+            let l = &d.location;
+            if l.old_raw.is_some() || l.new_raw.is_some() {
+                let raw = |r: &Option<String>| r.as_deref().map_or("(missing)".to_string(), HtmlWriter::escape);
+                section.push_str(&format!("<tr><td colspan=\"{}\"><details><summary>Raw JSON</summary><pre>{}: {}</pre><pre>{}: {}</pre></details></td></tr>\n",
+                    COLUMNS.len() - 2, HtmlWriter::escape(&self.labels.old), raw(&l.old_raw), HtmlWriter::escape(&self.labels.new), raw(&l.new_raw)));
+            }
+        }
+        section.push_str("</table></section>\n");
+
+        self.writer.write_all(section.as_bytes())?;
+        self.written += section.len() as u64;
+        self.patients = Some(self.patients.map_or((patient, patient), |(first, _)| (first, patient)));
+
+        Ok(())
+    }
+
+    fn finish(&mut self, summary: &Summary) -> Result<(), Box<dyn Error>> {
+        if !self.parts.is_empty() {
+            self.finish_part()?;
+            self.writer = HtmlWriter::start(&self.path)?;
+            writeln!(self.writer, "<h2>Parts</h2><ul>")?;
+            for part in &self.parts {
+                let name = part.path.rsplit('/').next().unwrap_or(&part.path);
+                let patients = part.patients.map_or("no patients".to_string(), |(first, last)| format!("patients {} to {}", first, last));
+                writeln!(self.writer, "<li><a href=\"{}\">{}</a> ({})</li>", HtmlWriter::escape(name), HtmlWriter::escape(name), patients)?;
+            }
+            writeln!(self.writer, "</ul>")?;
+        }
+
+        writeln!(self.writer, "<h2>Summary</h2><p>{} patients, {} differing, {} differences</p>",
+            summary.patients, summary.differing_patients, summary.differences)?;
+        if !summary.by_cohort.is_empty() {
+            writeln!(self.writer, "<table><tr><th>cohort</th><th>patients</th><th>differing</th><th>differences</th></tr>")?;
+            for (cohort, t) in &summary.by_cohort {
+                writeln!(self.writer, "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                    HtmlWriter::escape(cohort), t.patients, t.differing_patients, t.differences)?;
+            }
+            writeln!(self.writer, "</table>")?;
+        }
+        if !summary.examples.is_empty() {
+            let labels = &self.labels;
+            writeln!(self.writer, "<h3>Examples of CDEs differing in many patients</h3>")?;
+            writeln!(self.writer, "<table><tr><th>cde</th><th>patients</th><th>patient</th><th>{}</th><th>{}</th></tr>",
+                HtmlWriter::escape(&labels.old), HtmlWriter::escape(&labels.new))?;
+            for (cde, e) in &summary.examples {
+                for x in &e.examples {
+                    writeln!(self.writer, "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                        HtmlWriter::escape(cde), e.patients, x.patient,
+                        HtmlWriter::escape(x.old.as_deref().unwrap_or_default()), HtmlWriter::escape(x.new.as_deref().unwrap_or_default()))?;
+                }
+            }
+            writeln!(self.writer, "</table>")?;
+        }
+        if !summary.deduplicated.is_empty() {
+            let labels = &self.labels;
+            writeln!(self.writer, "<h3>Changes found more than once, each reported once</h3>")?;
+            writeln!(self.writer, "<table><tr><th>cde</th><th>kind</th><th>{}</th><th>{}</th><th>patients</th><th>differences</th><th>sample</th></tr>",
+                HtmlWriter::escape(&labels.old), HtmlWriter::escape(&labels.new))?;
+            for c in &summary.deduplicated {
+                writeln!(self.writer, "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                    HtmlWriter::escape(&c.cde), c.kind, HtmlWriter::escape(c.old.as_deref().unwrap_or_default()),
+                    HtmlWriter::escape(c.new.as_deref().unwrap_or_default()), c.patients, c.differences, c.sample.iter().join(", "))?;
+            }
+            writeln!(self.writer, "</table>")?;
+        }
+        if !summary.renumbered.is_empty() {
+            let labels = &self.labels;
+            writeln!(self.writer, "<h3>Unmatched patients likely renumbered</h3>")?;
+            writeln!(self.writer, "<table><tr><th>{} patient</th><th>{} patient</th><th>forms</th><th>shared values</th><th>similarity</th></tr>",
+                HtmlWriter::escape(&labels.old), HtmlWriter::escape(&labels.new))?;
+            for r in &summary.renumbered {
+                writeln!(self.writer, "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{:.3}</td></tr>", r.old, r.new, r.forms, r.shared_values, r.similarity)?;
+            }
+            writeln!(self.writer, "</table>")?;
+        }
+        if !summary.slow_patients.is_empty() {
+            let labels = &self.labels;
+            writeln!(self.writer, "<h3>Slow patients</h3>")?;
+            writeln!(self.writer, "<table><tr><th>patient</th><th>clinical data</th><th>ms</th><th>{} bytes</th><th>{} bytes</th></tr>",
+                HtmlWriter::escape(&labels.old), HtmlWriter::escape(&labels.new))?;
+            for s in &summary.slow_patients {
+                writeln!(self.writer, "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>", s.patient, s.ids, s.elapsed_ms, s.old_bytes, s.new_bytes)?;
+            }
+            writeln!(self.writer, "</table>")?;
+        }
+        if summary.truncated {
+            writeln!(self.writer, "<p><strong>Truncated:</strong> the diff stopped early, so not every patient was compared</p>")?;
+        }
+        writeln!(self.writer, "<h2>Run</h2><table>")?;
+        for (key, value) in summary.metadata.rows() {
+            writeln!(self.writer, "<tr><th>{}</th><td>{}</td></tr>", key, HtmlWriter::escape(&value))?;
+        }
+        writeln!(self.writer, "</table>")?;
+        writeln!(self.writer, "</body></html>")?;
+        self.writer.flush()?;
+
+        Ok(())
+    }
+}
+
+/// The most bytes of patient sections in a Markdown report, leaving room for
+/// the summary within GitHub's 65536 character limit on comments
+const MARKDOWN_LIMIT: usize = 60_000;
+
+/// Writes a Markdown summary table and a collapsed section per differing
+/// patient, for posting as a merge request comment
+///
+/// Sections are kept until the run is finished, as the summary goes first.
+/// Sections that would no longer fit in a comment go in further files of
+/// the same size, listed at the end of the report
+pub struct MarkdownWriter {
+    path: String,
+    sections: String,
+    omitted: usize,
+    /// The sections of each further file
+    parts: Vec<String>,
+    labels: Labels,
+}
+
+impl MarkdownWriter {
+    pub fn new(path: &str, labels: Labels) -> MarkdownWriter {
+        MarkdownWriter { path: path.to_string(), sections: String::new(), omitted: 0, parts: vec![], labels }
+    }
+
+    /// The path of a further file of the report, eg. report-2.md for report.md
+    fn part_path(&self, part: usize) -> String {
+        part_path(&self.path, part)
+    }
+
+    /// Escape the characters that would break out of a table cell
+    fn cell(text: &str) -> String {
+        text.replace('|', "\\|").replace('\n', "<br>").replace('\r', "")
+    }
+}
+
+impl ReportWriter for MarkdownWriter {
+    fn patient(&mut self, patient: u32, ids: &str, differences: &[DifferenceRecord]) -> Result<(), Box<dyn Error>> {
+        if differences.is_empty() {
+            return Ok(());
+        }
+
+        let mut section = format!("<details><summary>Patient {} ({}): {} differences</summary>\n\n", patient, ids, differences.len());
+        section.push_str(&format!("| Location | Kind | {} | {} |\n|---|---|---|---|\n",
+            MarkdownWriter::cell(&self.labels.old_heading()), MarkdownWriter::cell(&self.labels.new_heading())));
+        differences.iter().for_each(|d| {
+            let location = d.location.path();
+            let value = |v: &Option<String>| v.as_deref().map_or("*missing*".to_string(), MarkdownWriter::cell);
+            section.push_str(&format!("| {} | {} | {} | {} |\n", MarkdownWriter::cell(&location), d.kind, value(&d.old), value(&d.new)));
+        });
+        section.push_str("\n</details>\n");
+
+        if self.sections.len() + section.len() <= MARKDOWN_LIMIT {
+            self.sections.push_str(&section);
+            return Ok(());
+        }
+
+        self.omitted += 1;
+        match self.parts.last_mut() {
+            Some(part) if part.len() + section.len() <= MARKDOWN_LIMIT => part.push_str(&section),
+            _ => self.parts.push(section),
+        }
+
+        Ok(())
+    }
+
+    fn finish(&mut self, summary: &Summary) -> Result<(), Box<dyn Error>> {
+        let mut report = String::from("## diffmig report\n\n| | |\n|---|---:|\n");
+        report.push_str(&format!("| Patients | {} |\n", summary.patients));
+        report.push_str(&format!("| Differing patients | {} |\n", summary.differing_patients));
+        report.push_str(&format!("| Differences | {} |\n", summary.differences));
+        summary.by_severity.iter().for_each(|(severity, count)| {
+            report.push_str(&format!("| {:?} severity | {} |\n", severity, count));
+        });
+        if !summary.by_cohort.is_empty() {
+            report.push_str("\n| Cohort | Patients | Differing | Differences |\n|---|---:|---:|---:|\n");
+            summary.by_cohort.iter().for_each(|(cohort, t)| {
+                report.push_str(&format!("| {} | {} | {} | {} |\n", MarkdownWriter::cell(cohort), t.patients, t.differing_patients, t.differences));
+            });
+        }
+        if !summary.examples.is_empty() {
+            let labels = &self.labels;
+            report.push_str(&format!("\n| CDE | Patients | Example patient | {} | {} |\n|---|---:|---:|---|---|\n",
+                MarkdownWriter::cell(&labels.old_heading()), MarkdownWriter::cell(&labels.new_heading())));
+            summary.examples.iter().for_each(|(cde, e)| e.examples.iter().for_each(|x| {
+                report.push_str(&format!("| {} | {} | {} | {} | {} |\n", MarkdownWriter::cell(cde), e.patients, x.patient,
+                    MarkdownWriter::cell(x.old.as_deref().unwrap_or_default()), MarkdownWriter::cell(x.new.as_deref().unwrap_or_default())));
+            }));
+        }
+        if !summary.deduplicated.is_empty() {
+            let labels = &self.labels;
+            report.push_str(&format!("\n| Repeated change | Kind | {} | {} | Patients | Differences | Sample |\n|---|---|---|---|---:|---:|---|\n",
+                MarkdownWriter::cell(&labels.old_heading()), MarkdownWriter::cell(&labels.new_heading())));
+            summary.deduplicated.iter().for_each(|c| {
+                report.push_str(&format!("| {} | {} | {} | {} | {} | {} | {} |\n", MarkdownWriter::cell(&c.cde), c.kind,
+                    MarkdownWriter::cell(c.old.as_deref().unwrap_or_default()), MarkdownWriter::cell(c.new.as_deref().unwrap_or_default()),
+                    c.patients, c.differences, c.sample.iter().join(", ")));
+            });
+        }
+        if !summary.renumbered.is_empty() {
+            let labels = &self.labels;
+            report.push_str(&format!("\n| {} patient, likely renumbered | {} patient | Forms | Shared values | Similarity |\n|---:|---:|---:|---:|---:|\n",
+                MarkdownWriter::cell(&labels.old_heading()), MarkdownWriter::cell(&labels.new_heading())));
+            summary.renumbered.iter().for_each(|r| {
+                report.push_str(&format!("| {} | {} | {} | {} | {:.3} |\n", r.old, r.new, r.forms, r.shared_values, r.similarity));
+            });
+        }
+        if !summary.slow_patients.is_empty() {
+            let labels = &self.labels;
+            report.push_str(&format!("\n| Slow patient | Clinical data | ms | {} bytes | {} bytes |\n|---:|---|---:|---:|---:|\n",
+                MarkdownWriter::cell(&labels.old_heading()), MarkdownWriter::cell(&labels.new_heading())));
+            summary.slow_patients.iter().for_each(|s| {
+                report.push_str(&format!("| {} | {} | {} | {} | {} |\n", s.patient, s.ids, s.elapsed_ms, s.old_bytes, s.new_bytes));
+            });
+        }
+        if !summary.skipped_forms.is_empty() {
+            report.push_str("\n| Intentionally skipped form | Reason | Clinical data |\n|---|---|---:|\n");
+            summary.skipped_forms.iter().for_each(|(form, s)| {
+                report.push_str(&format!("| {} | {} | {} |\n", MarkdownWriter::cell(form), s.reason, s.clinical_data));
+            });
+        }
+        if !summary.retired.is_empty() {
+            report.push_str(&format!("\n| Retired CDE | Sections absent from {} |\n|---|---:|\n", MarkdownWriter::cell(&self.labels.new)));
+            summary.retired.iter().for_each(|(code, absent)| report.push_str(&format!("| {} | {} |\n", MarkdownWriter::cell(code), absent)));
+        }
+        if !summary.suppressions.is_empty() {
+            report.push_str("\n| Suppression | Matches | Audit |\n|---|---:|---|\n");
+            summary.suppressions.iter().for_each(|s| {
+                let expired = match s.expired && s.matches > 0 {
+                    true => " **(expired)**",
+                    false => "",
+                };
+                report.push_str(&format!("| {}{} | {} | {} |\n", MarkdownWriter::cell(&s.suppression.code), expired, s.matches, MarkdownWriter::cell(&s.audit())));
+            });
+        }
+        if summary.truncated {
+            report.push_str("\n**The diff stopped early, so not every patient was compared.**\n");
+        }
+        report.push_str("\n<details><summary>Run</summary>\n\n| | |\n|---|---|\n");
+        summary.metadata.rows().iter().for_each(|(key, value)| report.push_str(&format!("| {} | {} |\n", key, MarkdownWriter::cell(value))));
+        report.push_str("\n</details>\n");
+
+        report.push('\n');
+        report.push_str(&self.sections);
+        if self.omitted > 0 {
+            let paths = (2..self.parts.len() + 2).map(|part| self.part_path(part)).collect::<Vec<String>>();
+            report.push_str(&format!("\n{} more patient sections aren't shown, to fit in a comment. They're in {}.\n", self.omitted, paths.join(", ")));
+            for (part, path) in self.parts.iter().zip(&paths) {
+                fs::write(path, part)?;
+            }
+        }
+
+        fs::write(&self.path, report)?;
+
+        Ok(())
+    }
+}
+
+/// Renders a Handlebars template of the user's with the differences of every
+/// patient and the summary once the run is finished, for report layouts the
+/// other formats don't give
+///
+/// Unlike the other writers, every difference is kept until then
+pub struct TemplateWriter {
+    path: String,
+    registry: Handlebars<'static>,
+    labels: Labels,
+    patients: Vec<TemplatePatient>,
+}
+
+/// A patient's differences, as the template is given them
+#[derive(Serialize)]
+struct TemplatePatient {
+    patient: u32,
+    ids: String,
+    differences: Vec<DifferenceRecord>,
+}
+
+/// What the template is rendered with
+#[derive(Serialize)]
+struct TemplateData<'a> {
+    labels: &'a Labels,
+    patients: &'a [TemplatePatient],
+    summary: SummaryFile<'a>,
+}
+
+impl TemplateWriter {
+    /// Compile the template first, so a broken one fails the run before the diff
+    pub fn create(template: &str, path: &str, labels: Labels) -> Result<TemplateWriter, Box<dyn Error>> {
+        let mut registry = Handlebars::new();
+        registry.register_template_file("report", template).map_err(|e| format!("Failed reading template {}: {}", template, e))?;
+
+        Ok(TemplateWriter { path: path.to_string(), registry, labels, patients: vec![] })
+    }
+}
+
+impl ReportWriter for TemplateWriter {
+    fn patient(&mut self, patient: u32, ids: &str, differences: &[DifferenceRecord]) -> Result<(), Box<dyn Error>> {
+        if !differences.is_empty() {
+            self.patients.push(TemplatePatient { patient, ids: ids.to_string(), differences: differences.to_vec() });
+        }
+
+        Ok(())
+    }
+
+    fn finish(&mut self, summary: &Summary) -> Result<(), Box<dyn Error>> {
+        let data = TemplateData { labels: &self.labels, patients: &self.patients, summary: SummaryFile::new(summary) };
+        let report = self.registry.render("report", &data).map_err(|e| format!("Failed rendering template: {}", e))?;
+        fs::write(&self.path, report)?;
+
+        Ok(())
+    }
+}
+
+/// Collects differences by CDE across patients and prints them once the run
+/// is finished, so a CDE that's systematically broken stands out
+///
+/// Differences that aren't of a CDE are grouped by the field, section, form
+/// or context they're of instead
+#[derive(Default)]
+pub struct CdeGroupWriter {
+    groups: BTreeMap<String, Vec<DifferenceRecord>>,
+}
+
+impl CdeGroupWriter {
+    fn key(location: &Location) -> String {
+        match (&location.cde, &location.field, &location.section, &location.form) {
+            (Some(cde), ..) => cde.clone(),
+            (None, Some(field), ..) => format!("{}.{}", location.context, field),
+            (None, None, Some(section), _) => format!("section {}", section),
+            (None, None, None, Some(form)) => format!("form {}", form),
+            (None, None, None, None) => location.context.clone(),
+        }
+    }
+}
+
+impl ReportWriter for CdeGroupWriter {
+    fn patient(&mut self, _patient: u32, _ids: &str, differences: &[DifferenceRecord]) -> Result<(), Box<dyn Error>> {
+        differences.iter().for_each(|d| self.groups.entry(CdeGroupWriter::key(&d.location)).or_default().push(d.clone()));
+
+        Ok(())
+    }
+
+    fn finish(&mut self, _summary: &Summary) -> Result<(), Box<dyn Error>> {
+        let value = |v: &Option<String>| v.as_deref().map_or("(missing)".to_string(), |v| v.escape_debug().to_string());
+
+        self.groups.iter().for_each(|(key, differences)| {
+            let patients = differences.iter().map(|d| d.location.patient).collect::<BTreeSet<u32>>();
+            println!("{} ({} differences, {} patients)", key, differences.len(), patients.len());
+            differences.iter().for_each(|d| {
+                let triage = d.triage.map(|t| format!(" ({})", t)).unwrap_or_default();
+                println!("  patient {} [{}]{}: {} -> {}", d.location.patient, d.kind, triage, value(&d.old), value(&d.new));
+            });
+        });
+
+        Ok(())
+    }
+}
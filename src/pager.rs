@@ -0,0 +1,44 @@
+use std::env;
+use std::io::{self, IsTerminal, Write};
+use std::process::{Command, Stdio};
+use terminal_size::{terminal_size, Height};
+
+/// Show text written for the terminal, through $PAGER (or less -R) if it's
+/// taller than the terminal, so it can be read before the prompt that follows
+/// rather than scrolling off the screen
+///
+/// An empty $PAGER turns paging off, as does the pager failing to start
+pub fn show(text: &str) {
+    let height = match terminal_size() {
+        Some((_, Height(height))) if io::stderr().is_terminal() => height as usize,
+        _ => 0,
+    };
+    if height == 0 || text.lines().count() < height || !page(text) {
+        eprint!("{}", text);
+    }
+}
+
+/// Write the text to the pager, returning whether it could be started
+fn page(text: &str) -> bool {
+    let pager = env::var("PAGER").unwrap_or_else(|_| "less -R".to_string());
+    let mut words = pager.split_whitespace();
+    let program = match words.next() {
+        Some(program) => program,
+        None => return false,
+    };
+
+    let mut child = match Command::new(program).args(words).stdin(Stdio::piped()).spawn() {
+        Ok(child) => child,
+        Err(e) => {
+            log::debug!("Failed starting the pager {}: {}", pager, e);
+            return false;
+        }
+    };
+    // The pager can be quit before it's read everything
+    if let Some(mut stdin) = child.stdin.take() {
+        let _ = stdin.write_all(text.as_bytes());
+    }
+    let _ = child.wait();
+
+    true
+}
@@ -0,0 +1,86 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use crate::report::{DifferenceKind, DifferenceRecord};
+
+/// The export whose value of a CDE a reviewer decided is correct
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Side {
+    Old,
+    New,
+}
+
+/// The value a CDE of a patient's clinical data should have, as decided
+/// from one of its differences
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Correction {
+    /// The id of the difference it was decided from
+    pub id: String,
+    pub context: String,
+    pub form: Option<String>,
+    pub section: Option<String>,
+    pub cde: String,
+    pub correct: Side,
+    /// The correct side's value, as the report shows it, or none if the CDE shouldn't be there
+    pub value: Option<String>,
+}
+
+/// The intended corrections of the migration, by patient, for its fix-up
+/// scripts to apply
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Patch {
+    patients: BTreeMap<u32, Vec<Correction>>,
+}
+
+impl Patch {
+    /// Read a patch file, or start an empty one if it doesn't exist yet, so
+    /// corrections add up over several sessions
+    pub fn load(path: &str) -> Result<Patch, Box<dyn Error>> {
+        if !Path::new(path).exists() {
+            return Ok(Patch::default());
+        }
+
+        let text = fs::read_to_string(path).map_err(|e| format!("Failed reading patch {}: {}", path, e))?;
+        Ok(serde_json::from_str(&text).map_err(|e| format!("Invalid patch {}: {}", path, e))?)
+    }
+
+    /// Write the patch file, replacing it in one rename
+    pub fn save(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        let partial = format!("{}.partial", path);
+        fs::write(&partial, serde_json::to_string_pretty(self)?)?;
+        fs::rename(&partial, path)?;
+
+        Ok(())
+    }
+
+    /// Whether a difference is of a single CDE's values, which a correction can fix
+    pub fn correctable(record: &DifferenceRecord) -> bool {
+        record.location.cde.is_some() && record.kind != DifferenceKind::History
+    }
+
+    /// Correct a CDE to the value of a side of its difference, replacing an
+    /// earlier correction of the same difference
+    pub fn correct(&mut self, record: &DifferenceRecord, side: Side) {
+        let l = &record.location;
+        let correction = Correction {
+            id: record.id(),
+            context: l.context.clone(),
+            form: l.form.clone(),
+            section: l.section.clone(),
+            cde: l.cde.clone().unwrap_or_default(),
+            correct: side,
+            value: match side {
+                Side::Old => record.old.clone(),
+                Side::New => record.new.clone(),
+            },
+        };
+
+        let corrections = self.patients.entry(l.patient).or_default();
+        corrections.retain(|c| c.id != correction.id);
+        corrections.push(correction);
+    }
+}
@@ -0,0 +1,85 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::error::Error;
+use std::fs;
+use std::sync::Mutex;
+
+use crate::clinical_data::PatientSlice;
+
+/// The new ids of patients whose primary keys were reassigned by the new
+/// system, from a CSV file of old_id,new_id lines (with an optional header)
+///
+/// The old export's patients are renumbered to their new ids as they're
+/// read, so they're compared by who they are rather than by their pk. The
+/// ids of either export that the map doesn't give are noted to be reported
+#[derive(Debug, Default)]
+pub struct PatientMap {
+    new_ids: HashMap<u32, u32>,
+    mapped: HashSet<u32>,
+    unmapped_old: Mutex<BTreeSet<u32>>,
+    unmapped_new: Mutex<BTreeSet<u32>>,
+}
+
+impl PatientMap {
+    pub fn load(path: &str) -> Result<PatientMap, Box<dyn Error>> {
+        let text = fs::read_to_string(path)?;
+        let mut new_ids = HashMap::new();
+        let mut mapped = HashSet::new();
+
+        for (i, line) in text.lines().enumerate().filter(|(_, l)| !l.trim().is_empty()) {
+            let ids = line.split(',').map(|id| id.trim().trim_matches('"').parse::<u32>()).collect::<Vec<_>>();
+            let (old, new) = match ids[..] {
+                [Ok(old), Ok(new)] => (old, new),
+                // A header
+                _ if i == 0 => continue,
+                _ => return Err(format!("Line {} isn't an old id and a new id: {}", i + 1, line).into()),
+            };
+
+            if new_ids.insert(old, new).is_some() {
+                return Err(format!("Line {} maps old patient {} again", i + 1, old).into());
+            }
+            if !mapped.insert(new) {
+                return Err(format!("Line {} maps to new patient {} again", i + 1, new).into());
+            }
+        }
+
+        Ok(PatientMap { new_ids, mapped, ..PatientMap::default() })
+    }
+
+    /// The new id of an old patient, or the same id if the map doesn't give one
+    pub fn old_id(&self, id: u32) -> u32 {
+        match self.new_ids.get(&id) {
+            Some(&new) => new,
+            None => {
+                self.unmapped_old.lock().unwrap().insert(id);
+                id
+            }
+        }
+    }
+
+    /// The old id of a new patient, if the map gives one
+    pub fn old_patient(&self, id: u32) -> Option<u32> {
+        self.new_ids.iter().find(|(_, &new)| new == id).map(|(&old, _)| old)
+    }
+
+    /// Note a new patient if no old patient is mapped to them
+    pub fn note_new(&self, id: u32) {
+        if !self.mapped.contains(&id) {
+            self.unmapped_new.lock().unwrap().insert(id);
+        }
+    }
+
+    pub fn old_slice(&self, slice: PatientSlice) -> PatientSlice {
+        let patient = self.old_id(slice.patient);
+        slice.renumbered(patient)
+    }
+
+    pub fn new_slice(&self, slice: PatientSlice) -> PatientSlice {
+        self.note_new(slice.patient);
+        slice
+    }
+
+    /// The ids of the old and new patients that the map didn't give, of those read
+    pub fn unmapped(&self) -> (BTreeSet<u32>, BTreeSet<u32>) {
+        (self.unmapped_old.lock().unwrap().clone(), self.unmapped_new.lock().unwrap().clone())
+    }
+}
@@ -0,0 +1,168 @@
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Read;
+
+use crate::diff::{Diff, DiffOptions};
+use crate::fixture::{self, ParseError};
+use crate::migrated_registry::MigratedRegistry;
+use crate::report::{DifferenceKind, DifferenceRecord, Location};
+
+/// Fields that identify a patient, whose values are redacted from differences
+/// unless asked for
+const IDENTIFYING: [&str; 17] = [
+    "family_name", "given_names", "maiden_name", "umrn", "date_of_birth", "place_of_birth",
+    "address", "suburb", "postcode", "email", "home_phone", "mobile_phone", "work_phone",
+    "next_of_kin_family_name", "next_of_kin_given_names", "next_of_kin_address", "next_of_kin_phone",
+];
+
+const REDACTED: &str = "<redacted>";
+
+/// A record of the patients.json fixture
+#[derive(Debug, Deserialize)]
+struct PatientRecord {
+    pk: i64,
+    fields: BTreeMap<String, Value>,
+}
+
+/// A patient's demographics, working groups and consent flags, keyed by field name
+#[derive(Debug)]
+pub struct Patient {
+    pub id: u32,
+    fields: BTreeMap<String, Value>,
+}
+
+impl Patient {
+    /// Read every patient of the fixture, keyed by the id their clinical data refers to
+    pub fn read_all(reader: impl Read) -> Result<BTreeMap<u32, Patient>, ParseError> {
+        MigratedRegistry::read_array_file_to_records(reader).map(|text| {
+            let record = fixture::parse_at::<PatientRecord>(&text, "")?;
+            let id = record.pk as u32;
+            Ok((id, Patient { id, fields: record.fields }))
+        }).collect()
+    }
+
+    fn display(field: &str, value: &Value, options: &DiffOptions) -> String {
+        match (options.redact && IDENTIFYING.contains(&field), value) {
+            (true, _) => REDACTED.to_string(),
+            (false, Value::String(s)) => s.to_string(),
+            (false, v) => v.to_string(),
+        }
+    }
+}
+
+/// Arrays (eg. working groups) are compared as sets
+fn field_eq(v1: &Value, v2: &Value) -> bool {
+    match (v1, v2) {
+        (Value::Array(a1), Value::Array(a2)) => {
+            a1.iter().map(Value::to_string).collect::<BTreeSet<String>>()
+                == a2.iter().map(Value::to_string).collect::<BTreeSet<String>>()
+        }
+        (v1, v2) => v1 == v2
+    }
+}
+
+#[derive(Debug)]
+pub enum PatientDifferenceType<'a> {
+    /// The patient's id on the side they're in, so no identifying fields are shown
+    Missing(Option<u32>, Option<u32>),
+    /// A field's value on each side, already redacted if it identifies the patient
+    Field(&'a str, Option<String>, Option<String>),
+}
+
+#[derive(Debug)]
+pub struct PatientDifference<'a> {
+    patient: u32,
+    diff: PatientDifferenceType<'a>,
+}
+
+impl<'a> Diff<'a> for Patient {
+    type Difference = PatientDifference<'a>;
+
+    fn diff(&'a self, comp: &'a Self, options: &DiffOptions) -> Option<Vec<Self::Difference>> {
+        let fields = self.fields.keys().chain(comp.fields.keys())
+            .filter(|k| !options.ignores(k))
+            .collect::<BTreeSet<&String>>();
+
+        let diffs = fields.into_iter().filter_map(|k| {
+            match (self.fields.get(k), comp.fields.get(k)) {
+                (Some(v1), Some(v2)) if field_eq(v1, v2) => None,
+                (v1, v2) => Some(PatientDifferenceType::Field(
+                    k,
+                    v1.map(|v| Patient::display(k, v, options)),
+                    v2.map(|v| Patient::display(k, v, options)),
+                ))
+            }
+        }).collect::<Vec<PatientDifferenceType>>();
+
+        match diffs.is_empty() {
+            true => None,
+            false => Some(diffs.into_iter().map(|d| PatientDifference { patient: self.id, diff: d }).collect())
+        }
+    }
+}
+
+/// A model compared patient by patient, alongside the patient's clinical data
+pub trait PatientModel {
+    /// Every patient id in either export
+    fn ids(&self) -> BTreeSet<u32>;
+
+    /// The differences of a patient, each flattened into a record
+    fn diff_patient(&self, id: u32, options: &DiffOptions) -> Vec<ModelDifference>;
+}
+
+pub struct ModelDifference {
+    pub record: DifferenceRecord,
+}
+
+/// The patients of both exports
+pub struct Demographics {
+    old: BTreeMap<u32, Patient>,
+    new: BTreeMap<u32, Patient>,
+}
+
+impl Demographics {
+    pub fn new(old: BTreeMap<u32, Patient>, new: BTreeMap<u32, Patient>) -> Demographics {
+        Demographics { old, new }
+    }
+}
+
+impl PatientModel for Demographics {
+    fn ids(&self) -> BTreeSet<u32> {
+        self.old.keys().chain(self.new.keys()).copied().collect()
+    }
+
+    fn diff_patient(&self, id: u32, options: &DiffOptions) -> Vec<ModelDifference> {
+        let diffs = match (self.old.get(&id), self.new.get(&id)) {
+            (Some(p1), Some(p2)) => p1.diff(p2, options).unwrap_or_default(),
+            (None, None) => vec![],
+            (p1, p2) => {
+                let diff = PatientDifferenceType::Missing(p1.map(|p| p.id), p2.map(|p| p.id));
+                vec![PatientDifference { patient: id, diff }]
+            }
+        };
+
+        diffs.iter().map(|d| ModelDifference { record: d.record() }).collect()
+    }
+}
+
+impl<'a> PatientDifference<'a> {
+    /// Flatten the difference into a record, under the "patients" context
+    pub fn record(&self) -> DifferenceRecord {
+        let location = Location { patient: self.patient, context: "patients".to_string(), ..Location::default() };
+        let (location, kind, old, new) = match &self.diff {
+            PatientDifferenceType::Missing(p1, p2) => {
+                (location, DifferenceKind::Missing, p1.map(|p| p.to_string()), p2.map(|p| p.to_string()))
+            }
+            PatientDifferenceType::Field(field, v1, v2) => {
+                let kind = match (v1, v2) {
+                    (Some(_), Some(_)) => DifferenceKind::Equality,
+                    _ => DifferenceKind::Missing,
+                };
+                (Location { field: Some(field.to_string()), ..location }, kind, v1.clone(), v2.clone())
+            }
+        };
+
+        DifferenceRecord { location, kind, old, new, detail: None, triage: None }
+    }
+}
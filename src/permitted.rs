@@ -0,0 +1,79 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::io::Read;
+
+use crate::cde_definitions;
+use crate::clinical_data::CDEValue;
+use crate::fixture::{self, ParseError};
+use crate::migrated_registry::MigratedRegistry;
+
+/// A record of a permitted value fixture
+#[derive(Debug, Deserialize)]
+struct PermittedValueRecord {
+    model: String,
+    #[serde(default)]
+    fields: PermittedValueFields,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct PermittedValueFields {
+    code: Option<String>,
+    value: Option<String>,
+    pv_group: Option<String>,
+}
+
+/// The permitted values of the CDEs of an export's definitions, so values
+/// given by code on one side (eg. "pv_1") and by label on the other (eg.
+/// "Mild") are compared as the same
+#[derive(Debug, Default)]
+pub struct PermittedValues {
+    /// The code of each CDE's permitted value group, by CDE code
+    groups: HashMap<String, String>,
+    /// The label of each permitted value, by its group's code and its code
+    labels: HashMap<String, HashMap<String, String>>,
+}
+
+impl PermittedValues {
+    /// Add the permitted value groups of the CDEs of a CDE definition fixture
+    pub fn read_definitions(&mut self, reader: impl Read) -> Result<(), ParseError> {
+        for definition in cde_definitions::read(reader)? {
+            if let (Some(code), Some(group)) = (definition.code, definition.pv_group) {
+                self.groups.insert(code, group);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Add the labels of the permitted values of a permitted value fixture
+    pub fn read_values(&mut self, reader: impl Read) -> Result<(), ParseError> {
+        for text in MigratedRegistry::read_array_file_to_records(reader) {
+            let record = fixture::parse_at::<PermittedValueRecord>(&text, "")?;
+            if !record.model.ends_with("cdepermittedvalue") {
+                continue;
+            }
+
+            let fields = record.fields;
+            match (fields.pv_group, fields.code, fields.value) {
+                (Some(group), Some(code), Some(label)) => self.labels.entry(group).or_default().insert(code, label),
+                (_, code, _) => return Err(ParseError::new("/fields", format!("permitted value {} has no group, code or label", code.unwrap_or_default()))),
+            };
+        }
+
+        Ok(())
+    }
+
+    /// A CDE's value with its permitted value codes given by their labels,
+    /// if the CDE has permitted values and the value has any of their codes
+    pub fn labelled(&self, cde: &str, value: &CDEValue) -> Option<CDEValue> {
+        let labels = self.groups.get(cde).and_then(|g| self.labels.get(g))?;
+
+        match value {
+            CDEValue::String(s) => labels.get(s).map(|l| CDEValue::String(l.clone())),
+            CDEValue::Range(r) if r.iter().any(|v| labels.contains_key(v)) => {
+                Some(CDEValue::Range(r.iter().map(|v| labels.get(v).unwrap_or(v).clone()).collect()))
+            }
+            _ => None
+        }
+    }
+}
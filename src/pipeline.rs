@@ -0,0 +1,89 @@
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::thread::{Scope, ScopedJoinHandle};
+
+use crate::clinical_data::PatientSlice;
+use crate::contexts::Contexts;
+use crate::interner::Interner;
+use crate::migrated_registry::{CollectionCounts, MigratedRegistry, OnParseError, ParseErrors, RecordCount, RecordFilter};
+
+/// The most records waiting to be parsed on each side
+const RECORDS_BOUND: usize = 1024;
+/// The most patient slices waiting to be diffed on each side
+const SLICES_BOUND: usize = 64;
+
+/// The patient slices of an export read by a pipeline of threads, so reading
+/// (eg. from network storage) and decompression overlaps with parsing, and
+/// both overlap with diffing on the thread iterating the slices
+///
+/// Stages are connected by bounded channels, so a slow diff holds back
+/// reading rather than buffering the export in memory. When the slices stop
+/// being iterated, each stage stops at its next send
+///
+/// The stages are threads rather than async tasks: zip entries are only read
+/// through blocking readers and parsing is CPU-bound, so an async runtime
+/// would run every stage on blocking threads of its own anyway
+pub struct PipelinedRegistry<'scope> {
+    slices: Receiver<PatientSlice>,
+    pub parse_errors: ParseErrors,
+    pub records_read: RecordCount,
+    pub unknown_collections: CollectionCounts,
+    reader: ScopedJoinHandle<'scope, Result<(), String>>,
+}
+
+impl<'scope> PipelinedRegistry<'scope> {
+    /// Start the stages of a side, read sends the text of each record of the
+    /// export and its position in it until it's read or the send fails
+    pub fn spawn<'env>(
+        scope: &'scope Scope<'scope, 'env>,
+        read: impl FnOnce(SyncSender<(usize, String)>) -> Result<(), String> + Send + 'scope,
+        filter: RecordFilter,
+        on_parse_error: OnParseError,
+        interner: Interner,
+        keep_raw: bool,
+        contexts: Arc<Contexts>,
+    ) -> PipelinedRegistry<'scope> {
+        let (records_tx, records_rx) = mpsc::sync_channel(RECORDS_BOUND);
+        let (slices_tx, slices) = mpsc::sync_channel(SLICES_BOUND);
+        let (handles_tx, handles_rx) = mpsc::channel();
+
+        let reader = scope.spawn(move || read(records_tx));
+        scope.spawn(move || {
+            let registry = MigratedRegistry::from_positioned_records(records_rx.into_iter(), filter, on_parse_error, interner, keep_raw).with_contexts(contexts);
+            let _ = handles_tx.send((registry.parse_errors(), registry.records_read(), registry.unknown_collections()));
+            for slice in registry {
+                if slices_tx.send(slice).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let (parse_errors, records_read, unknown_collections) = handles_rx.recv().expect("Parse stage stopped before starting");
+
+        PipelinedRegistry { slices, parse_errors, records_read, unknown_collections, reader }
+    }
+
+    /// Stop the stages, returning the error of the reading stage if it failed
+    pub fn finish(self) -> Result<(), String> {
+        drop(self.slices);
+        self.reader.join().map_err(|_| "Reading stage panicked".to_string())?
+    }
+}
+
+impl<'scope> Iterator for PipelinedRegistry<'scope> {
+    type Item = PatientSlice;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.slices.recv().ok()
+    }
+}
+
+/// Send the text of each record of an export and its position in it,
+/// stopping early if the records are no longer received
+pub fn send_records(positioned: impl Iterator<Item=(usize, String)>, records: SyncSender<(usize, String)>) {
+    for record in positioned {
+        if records.send(record).is_err() {
+            break;
+        }
+    }
+}
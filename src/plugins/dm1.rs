@@ -0,0 +1,28 @@
+use crate::clinical_data::CDEValue;
+use crate::plugins::RegistryPlugin;
+
+/// CDEs calculated from others when a form is saved, so they're recomputed
+/// by the new system rather than migrated
+const CALCULATED: [&str; 1] = ["CDEBMI"];
+
+/// The Myotonic Dystrophy registry
+#[derive(Debug)]
+pub struct DM1;
+
+impl RegistryPlugin for DM1 {
+    fn registry_code(&self) -> &'static str {
+        "DM1"
+    }
+
+    /// The old system saved some numeric CDEs as text
+    fn normalize(&self, _cde: &str, value: &CDEValue) -> Option<CDEValue> {
+        match value {
+            CDEValue::String(s) => s.trim().parse::<f64>().ok().map(CDEValue::Number),
+            _ => None
+        }
+    }
+
+    fn ignores(&self, code: &str) -> bool {
+        CALCULATED.contains(&code)
+    }
+}
@@ -0,0 +1,40 @@
+use std::fmt;
+use std::sync::Arc;
+
+use crate::clinical_data::{CDEValue, ProtoContext};
+
+#[cfg(feature = "dm1")]
+mod dm1;
+
+/// Registry specific quirks of a migration, kept out of the generic diff
+///
+/// Each hook defaults to changing nothing
+pub trait RegistryPlugin: fmt::Debug + Send + Sync {
+    /// The code of the registry the plugin applies to
+    fn registry_code(&self) -> &'static str;
+
+    /// A value to compare in place of a CDE's value, if it needs normalizing
+    fn normalize(&self, _cde: &str, _value: &CDEValue) -> Option<CDEValue> {
+        None
+    }
+
+    /// The context a clinical datum is matched to the other export's by
+    fn context(&self, context: ProtoContext) -> ProtoContext {
+        context
+    }
+
+    /// Whether the differences of a form, section or CDE are ignored
+    fn ignores(&self, _code: &str) -> bool {
+        false
+    }
+}
+
+/// The plugin compiled in for a registry, if any
+pub fn for_registry(registry_code: &str) -> Option<Arc<dyn RegistryPlugin>> {
+    let plugins: Vec<Arc<dyn RegistryPlugin>> = vec![
+        #[cfg(feature = "dm1")]
+        Arc::new(dm1::DM1),
+    ];
+
+    plugins.into_iter().find(|p| p.registry_code() == registry_code)
+}
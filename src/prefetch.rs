@@ -0,0 +1,148 @@
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::sync::mpsc::{self, Receiver};
+use std::thread::{self, JoinHandle};
+
+/// The size of each read of the file
+const CHUNK: usize = 1 << 20;
+
+/// The chunks read ahead, and the thread reading them, which gives the file back once stopped
+type Reading = (Receiver<io::Result<Vec<u8>>>, JoinHandle<File>);
+
+/// A file read ahead of where it's read by a thread of its own, in large
+/// reads into a buffer of up to capacity bytes, so reading from slow storage
+/// (eg. NFS) overlaps with decompression and parsing rather than waiting on
+/// many small reads
+///
+/// A seek anywhere but the data already read ahead restarts the reading
+/// thread at the new position, so this suits a file read sequentially after
+/// a few seeks, like a zip entry
+pub struct PrefetchReader {
+    /// The file, while no thread is reading it
+    file: Option<File>,
+    len: u64,
+    reading: Option<Reading>,
+    chunk: Vec<u8>,
+    /// Where the chunk starts in the file, and where it's read up to
+    chunk_start: u64,
+    offset: usize,
+    capacity: usize,
+}
+
+impl PrefetchReader {
+    pub fn new(file: File, capacity: usize) -> io::Result<PrefetchReader> {
+        let len = file.metadata()?.len();
+        Ok(PrefetchReader { file: Some(file), len, reading: None, chunk: vec![], chunk_start: 0, offset: 0, capacity })
+    }
+
+    fn position(&self) -> u64 {
+        self.chunk_start + self.offset as u64
+    }
+
+    /// Start reading ahead from where the file is read up to
+    fn start(&mut self) -> io::Result<()> {
+        let mut file = match self.file.take() {
+            Some(file) => file,
+            None => return Ok(()),
+        };
+        file.seek(SeekFrom::Start(self.position()))?;
+
+        let (chunks, receiver) = mpsc::sync_channel((self.capacity / CHUNK).max(1));
+        let thread = thread::spawn(move || {
+            loop {
+                let mut chunk = vec![0; CHUNK];
+                let read = match read_full(&mut file, &mut chunk) {
+                    Ok(read) => read,
+                    Err(e) => {
+                        let _ = chunks.send(Err(e));
+                        break;
+                    }
+                };
+                chunk.truncate(read);
+                // Stopped once the file's read or the reader seeks elsewhere
+                if read == 0 || chunks.send(Ok(chunk)).is_err() {
+                    break;
+                }
+            }
+            file
+        });
+        self.reading = Some((receiver, thread));
+
+        Ok(())
+    }
+
+    /// Stop reading ahead, dropping what was read ahead
+    fn stop(&mut self) -> io::Result<()> {
+        if let Some((receiver, thread)) = self.reading.take() {
+            drop(receiver);
+            self.file = Some(thread.join().map_err(|_| io::Error::other("Reading ahead panicked"))?);
+        }
+        Ok(())
+    }
+}
+
+/// Fill the buffer, short only at the end of the file
+fn read_full(file: &mut File, buf: &mut [u8]) -> io::Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match file.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(read)
+}
+
+impl Read for PrefetchReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.offset == self.chunk.len() {
+            if self.reading.is_none() {
+                self.start()?;
+            }
+            let next = match &self.reading {
+                Some((receiver, _)) => receiver.recv().ok().transpose()?,
+                None => None,
+            };
+            match next {
+                Some(chunk) => {
+                    self.chunk_start += self.chunk.len() as u64;
+                    self.chunk = chunk;
+                    self.offset = 0;
+                }
+                // The end of the file, which the thread stopped at
+                None => return Ok(0),
+            }
+        }
+
+        let read = buf.len().min(self.chunk.len() - self.offset);
+        buf[..read].copy_from_slice(&self.chunk[self.offset..self.offset + read]);
+        self.offset += read;
+
+        Ok(read)
+    }
+}
+
+impl Seek for PrefetchReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::End(n) => self.len.checked_add_signed(n),
+            SeekFrom::Current(n) => self.position().checked_add_signed(n),
+        }.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Seek before the start of the file"))?;
+
+        // Where it's read up to or within the chunk read ahead, so the reading carries on
+        if target == self.position() || (target >= self.chunk_start && target < self.chunk_start + self.chunk.len() as u64) {
+            self.offset = (target - self.chunk_start) as usize;
+            return Ok(target);
+        }
+
+        self.stop()?;
+        self.chunk.clear();
+        self.chunk_start = target;
+        self.offset = 0;
+
+        Ok(target)
+    }
+}
@@ -0,0 +1,270 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Lines, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::fixture;
+use crate::interrupt;
+
+/// The number of presorts started, so each spills to a directory of its own
+static SORTS: AtomicUsize = AtomicUsize::new(0);
+/// The most runs merged at once, so the files open stay well within limits
+const MERGE_FAN_IN: usize = 64;
+
+/// The order records are sorted in, by patient and then by their position in
+/// the export, records that don't give their patient going last so they fail
+/// parsing as they would have
+fn key(position: usize, record: &str) -> (i64, usize) {
+    let patient = fixture::patient(record).unwrap_or(i64::MAX);
+    (patient, position)
+}
+
+/// Records with their positions in the export
+pub type Positioned<'a> = Box<dyn Iterator<Item=(usize, String)> + 'a>;
+
+/// A record as it's spilled, one JSON array per line
+type Spilled = (i64, usize, String);
+
+/// The first error reading spilled runs back as their records are merged,
+/// which ends the records early, shared with whatever iterates them so it
+/// can stop rather than compare the records that weren't read
+#[derive(Debug, Clone, Default)]
+pub struct ReadFailure(Arc<Mutex<Option<String>>>);
+
+impl ReadFailure {
+    pub fn failed(&self) -> bool {
+        self.0.lock().unwrap().is_some()
+    }
+
+    /// The error, if the records ended early
+    pub fn result(&self) -> Result<(), String> {
+        match self.0.lock().unwrap().clone() {
+            Some(e) => Err(format!("Failed reading presorted records: {}", e)),
+            None => Ok(()),
+        }
+    }
+
+    /// The records until one fails, keeping its error
+    fn until_failed<'a>(&self, records: impl Iterator<Item=io::Result<Spilled>> + 'a) -> Positioned<'a> {
+        let failure = self.clone();
+        Box::new(records.map_while(move |spilled| match spilled {
+            Ok((_, position, record)) => Some((position, record)),
+            Err(e) => {
+                *failure.0.lock().unwrap() = Some(e.to_string());
+                None
+            }
+        }))
+    }
+}
+
+/// Write sorted records to a run of the spill directory
+fn spill(dir: &SpillDir, run: usize, records: impl Iterator<Item=io::Result<Spilled>>) -> io::Result<PathBuf> {
+    let path = dir.path.join(format!("run-{}.ndjson", run));
+    let mut writer = BufWriter::new(File::create(&path)?);
+    for spilled in records {
+        serde_json::to_writer(&mut writer, &spilled?)?;
+        writer.write_all(b"\n")?;
+    }
+    writer.flush()?;
+
+    Ok(path)
+}
+
+/// Open the runs to be merged
+fn open(runs: &[PathBuf]) -> io::Result<Vec<Lines<BufReader<File>>>> {
+    runs.iter().map(|path| Ok(BufReader::new(File::open(path)?).lines())).collect()
+}
+
+/// The records of an export with their positions in it, sorted by patient if
+/// run_bytes is given, so an export that isn't ordered by patient can be
+/// sliced as one that is
+///
+/// Records are sorted in runs of about run_bytes of text, each written to a
+/// temporary file as NDJSON unless the whole export fits in one, and the runs
+/// are merged as the records are iterated, so memory stays bounded by the
+/// run's size rather than the export's. Every MERGE_FAN_IN runs are merged
+/// into one as they're spilled. A run has at least one record, however
+/// large. If reading a run back fails as it's merged, the records end there
+/// and failure has the error
+pub fn records<'a>(records: impl Iterator<Item=String> + 'a, run_bytes: Option<usize>, failure: &ReadFailure) -> io::Result<Positioned<'a>> {
+    let run_bytes = match run_bytes {
+        Some(run_bytes) => run_bytes,
+        None => return Ok(Box::new(records.enumerate())),
+    };
+
+    interrupt::stoppable();
+    let mut records = records.enumerate().peekable();
+    let mut runs = vec![];
+    let mut spilled = 0;
+    let mut dir = None;
+    loop {
+        let mut run = vec![];
+        let mut bytes = 0;
+        while let Some((position, record)) = records.next_if(|_| run.is_empty() || bytes < run_bytes) {
+            bytes += record.len();
+            let (patient, position) = key(position, &record);
+            run.push((patient, position, record));
+        }
+        run.sort_unstable_by_key(|(patient, position, _)| (*patient, *position));
+
+        if runs.is_empty() && records.peek().is_none() {
+            return Ok(Box::new(run.into_iter().map(|(_, position, record)| (position, record))));
+        }
+
+        let dir = match &dir {
+            Some(dir) => dir,
+            None => dir.insert(SpillDir::create()?),
+        };
+        log::debug!("Spilling a run of {} records", run.len());
+        runs.push(spill(dir, spilled, run.into_iter().map(Ok))?);
+        spilled += 1;
+        if runs.len() == MERGE_FAN_IN {
+            let merged = spill(dir, spilled, Merge::new(open(&runs)?)?)?;
+            runs.drain(..).try_for_each(fs::remove_file)?;
+            runs.push(merged);
+            spilled += 1;
+        }
+
+        // The records not yet spilled wouldn't be compared after an interrupt
+        if records.peek().is_none() || interrupt::interrupted() {
+            break;
+        }
+    }
+
+    let merge = Merge { _dir: dir, ..Merge::new(open(&runs)?)? };
+    Ok(failure.until_failed(merge))
+}
+
+/// A temporary directory of spilled runs, removed once they're merged
+struct SpillDir {
+    path: PathBuf,
+}
+
+impl SpillDir {
+    fn create() -> io::Result<SpillDir> {
+        let path = std::env::temp_dir().join(format!("diffmig-presort-{}-{}", std::process::id(), SORTS.fetch_add(1, Ordering::Relaxed)));
+        fs::create_dir_all(&path)?;
+
+        Ok(SpillDir { path })
+    }
+}
+
+impl Drop for SpillDir {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_dir_all(&self.path) {
+            log::error!("Failed removing {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// The records of sorted runs, merged in order
+struct Merge {
+    runs: Vec<Lines<BufReader<File>>>,
+    /// The next record of each run that has one, by its key and run
+    heads: BinaryHeap<Reverse<(i64, usize, usize)>>,
+    /// The text of the records in heads, by run
+    texts: Vec<Option<String>>,
+    /// The directory of the runs, removed once they're merged, if they're the last
+    _dir: Option<SpillDir>,
+}
+
+impl Merge {
+    fn new(runs: Vec<Lines<BufReader<File>>>) -> io::Result<Merge> {
+        let texts = vec![None; runs.len()];
+        let mut merge = Merge { runs, heads: BinaryHeap::new(), texts, _dir: None };
+        (0..merge.runs.len()).try_for_each(|run| merge.advance(run))?;
+        Ok(merge)
+    }
+
+    /// Read the next record of a run into heads, if it has one
+    fn advance(&mut self, run: usize) -> io::Result<()> {
+        if let Some(line) = self.runs[run].next() {
+            let (patient, position, record) = serde_json::from_str::<Spilled>(&line?)?;
+            self.heads.push(Reverse((patient, position, run)));
+            self.texts[run] = Some(record);
+        }
+
+        Ok(())
+    }
+}
+
+impl Iterator for Merge {
+    type Item = io::Result<Spilled>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Reverse((patient, position, run)) = self.heads.pop()?;
+        let record = self.texts[run].take().expect("A run's head has no record");
+
+        Some(self.advance(run).map(|_| (patient, position, record)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A clinical data record of a patient, padded to be about len bytes
+    fn record(patient: i64, len: usize) -> String {
+        let record = format!("{{\"fields\": {{\"django_id\": {}}}, \"pad\": \"\"}}", patient);
+        record.replace("\"\"", &format!("\"{}\"", "x".repeat(len.saturating_sub(record.len()))))
+    }
+
+    /// Presort records of the patients given, checking they come out sorted
+    /// by patient and then position, with none lost
+    fn presorted(exported: Vec<String>, run_bytes: usize) {
+        let mut expected = exported.iter().cloned().enumerate().collect::<Vec<_>>();
+        expected.sort_by_key(|(position, record)| key(*position, record));
+
+        let failure = ReadFailure::default();
+        let sorted = records(exported.into_iter(), Some(run_bytes), &failure).unwrap().collect::<Vec<_>>();
+        assert_eq!(failure.result(), Ok(()));
+        assert_eq!(sorted, expected);
+    }
+
+    #[test]
+    fn merges_spilled_runs() {
+        let patients = [5, 3, 9, 3, 1, 7, 5, 2, 8, 1, 4, 6];
+        assert_eq!((fixture::patient(&record(5, 40)), record(5, 40).len()), (Some(5), 40));
+        presorted(patients.iter().map(|&p| record(p, 40)).collect(), 100);
+    }
+
+    #[test]
+    fn merges_more_runs_than_at_once() {
+        // A record a run, so the runs are merged into one every MERGE_FAN_IN of them
+        let records = (0..MERGE_FAN_IN * 2 + 10).map(|i| record((i * 37 % 23) as i64, 30)).collect::<Vec<_>>();
+        presorted(records, 1);
+    }
+
+    #[test]
+    fn runs_have_records_larger_than_them() {
+        let records = vec![record(4, 30), record(2, 5000), record(3, 30), record(1, 30), record(2, 30)];
+        presorted(records, 100);
+    }
+
+    #[test]
+    fn records_without_patients_go_last() {
+        presorted(vec![record(2, 30), "{\"fields\": {}}".to_string(), record(1, 30)], 50);
+    }
+
+    #[test]
+    fn corrupt_runs_end_the_records_with_their_error() {
+        let dir = SpillDir::create().unwrap();
+        let runs = (0..2).map(|run| {
+            let records = (0..3).map(|i| Ok((i, run * 3 + i as usize, record(i, 30))));
+            spill(&dir, run, records).unwrap()
+        }).collect::<Vec<_>>();
+        // The second run's last record is cut short
+        let text = fs::read_to_string(&runs[1]).unwrap();
+        fs::write(&runs[1], &text[..text.len() - 10]).unwrap();
+
+        let failure = ReadFailure::default();
+        let merged = failure.until_failed(Merge::new(open(&runs).unwrap()).unwrap()).collect::<Vec<_>>();
+        assert!(failure.failed());
+        assert!(failure.result().unwrap_err().starts_with("Failed reading presorted records"));
+        assert!(merged.len() < 6);
+        assert!(merged.windows(2).all(|w| key(w[0].0, &w[0].1) <= key(w[1].0, &w[1].1)));
+    }
+}
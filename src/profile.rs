@@ -0,0 +1,106 @@
+use std::io::Read;
+use std::cmp::Reverse;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+const SLOWEST_PATIENTS: usize = 10;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Phase {
+    ZipRead,
+    JsonParse,
+    Construct,
+    Diff,
+    Render,
+}
+
+const PHASES: [(Phase, &str); 5] = [
+    (Phase::ZipRead, "Zip read"),
+    (Phase::JsonParse, "JSON parse"),
+    (Phase::Construct, "Struct construction"),
+    (Phase::Diff, "Diff"),
+    (Phase::Render, "Render"),
+];
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static PHASE_NANOS: [AtomicU64; 5] = [
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+];
+static SLOWEST: Mutex<Vec<(Duration, u32)>> = Mutex::new(Vec::new());
+
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Run f, adding the time it took to the given phase when profiling is enabled
+pub fn time<T>(phase: Phase, f: impl FnOnce() -> T) -> T {
+    if !enabled() {
+        return f();
+    }
+
+    let start = Instant::now();
+    let result = f();
+    PHASE_NANOS[phase as usize].fetch_add(start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+    result
+}
+
+/// Record the time taken to read, parse and diff a single patient, keeping
+/// only the slowest patients seen so far
+pub fn record_patient(patient: u32, elapsed: Duration) {
+    if !enabled() {
+        return;
+    }
+
+    let mut slowest = SLOWEST.lock().unwrap();
+    slowest.push((elapsed, patient));
+    slowest.sort_by_key(|(d, _)| Reverse(*d));
+    slowest.truncate(SLOWEST_PATIENTS);
+}
+
+pub fn report() {
+    let phases = PHASES.iter().map(|(phase, name)| {
+        (*name, Duration::from_nanos(PHASE_NANOS[*phase as usize].load(Ordering::Relaxed)))
+    }).collect::<Vec<(&str, Duration)>>();
+    let total = phases.iter().map(|(_, d)| *d).sum::<Duration>();
+
+    println!("Time spent per phase:");
+    phases.iter().for_each(|(name, d)| {
+        let percent = match total.as_nanos() {
+            0 => 0.0,
+            t => d.as_nanos() as f64 / t as f64 * 100.0,
+        };
+        println!("  {:<20} {:>12.3?} {:>6.1}%", name, d, percent);
+    });
+
+    println!("Slowest patients:");
+    SLOWEST.lock().unwrap().iter().for_each(|(d, patient)| {
+        println!("  {:<20} {:>12.3?}", patient, d);
+    });
+}
+
+/// Wraps a reader, attributing the time spent reading to the zip read phase
+pub struct TimedReader<R: Read> {
+    inner: R,
+}
+
+impl<R: Read> TimedReader<R> {
+    pub fn new(inner: R) -> TimedReader<R> {
+        TimedReader { inner }
+    }
+}
+
+impl<R: Read> Read for TimedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let inner = &mut self.inner;
+        time(Phase::ZipRead, || inner.read(buf))
+    }
+}
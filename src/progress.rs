@@ -0,0 +1,128 @@
+use indicatif::{ProgressBar, ProgressFinish, ProgressStyle};
+use std::io::Read;
+use std::sync::{Arc, OnceLock};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::metadata::Labels;
+use crate::migrated_registry::RecordCount;
+
+/// Which export a reader reads
+#[derive(Debug, Clone, Copy)]
+pub enum Side {
+    Old,
+    New,
+}
+
+#[derive(Default)]
+struct SideProgress {
+    /// What the export is called
+    label: String,
+    size: u64,
+    read: AtomicU64,
+    records: OnceLock<RecordCount>,
+}
+
+impl SideProgress {
+    fn fraction(&self) -> f64 {
+        match self.size {
+            0 => 1.0,
+            size => self.read.load(Ordering::Relaxed) as f64 / size as f64
+        }
+    }
+
+    /// The records left to read, going by the average size of those read so far
+    fn records_left(&self) -> Option<u64> {
+        let read = self.read.load(Ordering::Relaxed);
+        match self.records.get().map_or(0, |r| r.load(Ordering::Relaxed)) as u64 {
+            0 => None,
+            records => Some(self.size.saturating_sub(read) * records / read.max(1))
+        }
+    }
+}
+
+/// A progress bar over both exports, showing whichever is further behind
+///
+/// Either can stall (eg. on a network filesystem) while the other waits on
+/// it, so following only one would misreport how far the diff has got
+///
+/// With the number of patients expected, the bar follows the patients
+/// compared instead, as compression ratios vary too much across an export
+/// for the bytes read to give a good ETA
+pub struct Progress {
+    bar: ProgressBar,
+    sides: [SideProgress; 2],
+    expected_patients: Option<u64>,
+}
+
+impl Progress {
+    pub fn new(old_size: u64, new_size: u64, expected_patients: Option<u64>, labels: &Labels) -> Arc<Progress> {
+        let bar = ProgressBar::new(expected_patients.unwrap_or(old_size));
+        let counts = match expected_patients {
+            Some(_) => "{pos}/{len} patients",
+            None => "{bytes}/{total_bytes}",
+        };
+        bar.set_style(ProgressStyle::default_bar()
+            .template(&format!("Reading {{msg}} [{{elapsed_precise}} / {{duration_precise}} ({{eta}})] {{wide_bar:.cyan/blue}} {}", counts))
+            .progress_chars("##-")
+            .on_finish(ProgressFinish::AtCurrentPos)
+        );
+
+        let sides = [
+            SideProgress { label: labels.old.clone(), size: old_size, ..SideProgress::default() },
+            SideProgress { label: labels.new.clone(), size: new_size, ..SideProgress::default() },
+        ];
+
+        Arc::new(Progress { bar, sides, expected_patients })
+    }
+
+    pub fn wrap_read<R: Read>(self: &Arc<Progress>, side: Side, inner: R) -> ProgressReader<R> {
+        ProgressReader { inner, side, progress: self.clone() }
+    }
+
+    /// Estimate the records left on a side from the count of those read
+    pub fn track_records(&self, side: Side, records: RecordCount) {
+        let _ = self.sides[side as usize].records.set(records);
+    }
+
+    /// Follow the number of patients compared so far, if the bar counts patients
+    pub fn patients_compared(&self, patients: u64) {
+        if let Some(expected) = self.expected_patients {
+            // The estimate can be short, eg. if it includes only one export's patients
+            self.bar.set_length(expected.max(patients));
+            self.bar.set_position(patients);
+        }
+    }
+
+    fn add(&self, side: Side, bytes: u64) {
+        self.sides[side as usize].read.fetch_add(bytes, Ordering::Relaxed);
+
+        let slowest = match self.sides[0].fraction() <= self.sides[1].fraction() {
+            true => &self.sides[0],
+            false => &self.sides[1],
+        };
+
+        if self.expected_patients.is_none() {
+            self.bar.set_length(slowest.size);
+            self.bar.set_position(slowest.read.load(Ordering::Relaxed));
+        }
+        self.bar.set_message(match slowest.records_left() {
+            Some(left) => format!("{}, ~{} records left", slowest.label, left),
+            None => slowest.label.clone(),
+        });
+    }
+}
+
+/// Counts the bytes read from an export towards its side of the progress bar
+pub struct ProgressReader<R: Read> {
+    inner: R,
+    side: Side,
+    progress: Arc<Progress>,
+}
+
+impl<R: Read> Read for ProgressReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.progress.add(self.side, read as u64);
+        Ok(read)
+    }
+}
@@ -1,15 +1,20 @@
 use std::io::{Write, stdin, stdout};
 
+use crate::metadata::Labels;
+use crate::patch::Side;
+
 pub enum Response {
     All,
     Yes,
     No,
+    /// Stop the diff, still reporting what's been found so far
+    Quit,
 }
 
 pub fn input() -> Response {
     let mut input = String::new();
     loop {
-        print!("\x1b[1;34mContinue [(Y)es|(n)o|(a)ll]? \x1b[0m");
+        print!("\x1b[1;34mContinue [(Y)es|(n)o|(a)ll|(q)uit]? \x1b[0m");
         stdout().flush().ok();
         stdin().read_line(&mut input).expect("Failed reading input");
 
@@ -17,6 +22,24 @@ pub fn input() -> Response {
             "y" | "yes" | "" => return Response::Yes,
             "n" | "no" => return Response::No,
             "a" | "all" => return Response::All,
+            "q" | "quit" => return Response::Quit,
+            _ => input.clear()
+        }
+    }
+}
+
+/// Ask which side of a difference is correct, if either
+pub fn correct_side(labels: &Labels) -> Option<Side> {
+    let mut input = String::new();
+    loop {
+        print!("\x1b[1;34mCorrect side [(o) {}|(n) {}|(S)kip]? \x1b[0m", labels.old, labels.new);
+        stdout().flush().ok();
+        stdin().read_line(&mut input).expect("Failed reading input");
+
+        match input.to_ascii_lowercase().trim() {
+            "o" | "old" => return Some(Side::Old),
+            "n" | "new" => return Some(Side::New),
+            "s" | "skip" | "" => return None,
             _ => input.clear()
         }
     }
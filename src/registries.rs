@@ -0,0 +1,38 @@
+use serde::Deserialize;
+use std::collections::BTreeSet;
+use std::io::Read;
+
+use crate::fixture::{self, ParseError};
+use crate::migrated_registry::MigratedRegistry;
+
+/// A record of the registry definition fixture
+#[derive(Debug, Deserialize)]
+struct RegistryRecord {
+    model: String,
+    #[serde(default)]
+    fields: RegistryFields,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct RegistryFields {
+    code: Option<String>,
+}
+
+/// The codes of the registries of a registry definition fixture
+pub fn codes(reader: impl Read) -> Result<BTreeSet<String>, ParseError> {
+    let mut codes = BTreeSet::new();
+
+    for text in MigratedRegistry::read_array_file_to_records(reader) {
+        let record = fixture::parse_at::<RegistryRecord>(&text, "")?;
+        if !record.model.ends_with(".registry") {
+            continue;
+        }
+
+        match record.fields.code {
+            Some(code) => codes.insert(code),
+            None => return Err(ParseError::new("/fields/code", "registry has no code")),
+        };
+    }
+
+    Ok(codes)
+}
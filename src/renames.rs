@@ -0,0 +1,49 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+
+/// Names changed by the migration, mapping the old export's names to the new
+/// export's, so renamed entities are compared against each other
+///
+/// ```yaml
+/// forms:
+///   ClinicalForm: Clinical
+/// sections:
+///   SEC0001: DiagnosisSection
+/// cdes:
+///   CDE00001: DiagnosisDate
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Renames {
+    #[serde(default)]
+    forms: HashMap<String, String>,
+    #[serde(default)]
+    sections: HashMap<String, String>,
+    #[serde(default)]
+    cdes: HashMap<String, String>,
+}
+
+impl Renames {
+    pub fn load(path: &str) -> Result<Renames, Box<dyn Error>> {
+        let text = fs::read_to_string(path).map_err(|e| format!("Failed reading renames {}: {}", path, e))?;
+
+        Ok(serde_yaml::from_str(&text).map_err(|e| format!("Invalid renames {}: {}", path, e))?)
+    }
+
+    /// The new name of a form of the old export
+    pub fn form<'a>(&'a self, name: &'a str) -> &'a str {
+        self.forms.get(name).map_or(name, String::as_str)
+    }
+
+    /// The new code of a section of the old export
+    pub fn section<'a>(&'a self, code: &'a str) -> &'a str {
+        self.sections.get(code).map_or(code, String::as_str)
+    }
+
+    /// The new code of a CDE of the old export
+    pub fn cde<'a>(&'a self, code: &'a str) -> &'a str {
+        self.cdes.get(code).map_or(code, String::as_str)
+    }
+}
@@ -0,0 +1,101 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use crate::clinical_data::{ClinicalDatumVariant, PatientSlice};
+use crate::diff::DiffOptions;
+use crate::report::RenumberedPatient;
+
+/// The forms of a patient's current clinical data, and each of its CDE
+/// values as "form/section/cde=value"
+#[derive(Debug, Default)]
+struct Fingerprint {
+    forms: BTreeSet<String>,
+    values: HashSet<String>,
+}
+
+impl Fingerprint {
+    fn add(&mut self, slice: &PatientSlice, options: &DiffOptions) {
+        slice.clinical_data()
+            .map(|(_, datum)| datum)
+            .filter(|datum| matches!(datum.variant, ClinicalDatumVariant::CDEs))
+            .flat_map(|datum| datum.located_cdes())
+            .filter(|(form, section, cde)| !options.skips_form(form) && !options.ignores(section) && !options.ignores(cde.code()))
+            .for_each(|(form, section, cde)| {
+                self.forms.insert(form.to_string());
+                self.values.insert(format!("{}/{}/{}={}", form, section, cde.code(), cde.value()));
+            });
+    }
+
+    /// The number of values both have, and the fraction they are of either's values (Jaccard)
+    fn similarity(&self, other: &Fingerprint) -> (usize, f64) {
+        let shared = self.values.intersection(&other.values).count();
+        match self.values.len() + other.values.len() - shared {
+            0 => (0, 1.0),
+            all => (shared, shared as f64 / all as f64),
+        }
+    }
+}
+
+/// Patients with their fingerprints, by the forms they have
+type ByForms<'a> = HashMap<&'a BTreeSet<String>, Vec<(u32, &'a Fingerprint)>>;
+
+/// The clinical data of the patients whose slices were paired with another
+/// patient's, so the old and new patients left unmatched can be checked for
+/// one that's likely the other renumbered, eg. by a broken id mapping
+#[derive(Debug)]
+pub struct Renumbering {
+    /// The fraction of their values an old and new patient must share
+    min_similarity: f64,
+    old: HashMap<u32, Fingerprint>,
+    new: HashMap<u32, Fingerprint>,
+    /// The patients whose slices were paired with their own
+    paired: HashSet<u32>,
+}
+
+impl Renumbering {
+    pub fn new(min_similarity: f64) -> Renumbering {
+        Renumbering { min_similarity, old: HashMap::new(), new: HashMap::new(), paired: HashSet::new() }
+    }
+
+    /// Note a pair of slices the diff compared, keeping their clinical data if they're of different patients
+    pub fn pair(&mut self, old: &PatientSlice, new: &PatientSlice, options: &DiffOptions) {
+        match old.patient == new.patient {
+            true => {
+                self.paired.insert(old.patient);
+            }
+            false => {
+                self.old.entry(old.patient).or_default().add(old, options);
+                self.new.entry(new.patient).or_default().add(new, options);
+            }
+        }
+    }
+
+    /// The patients of one side that weren't paired with their own slices, by their forms
+    ///
+    /// Patients in both exports weren't renumbered, even if their slices were paired with others'
+    fn unmatched<'a>(&self, side: &'a HashMap<u32, Fingerprint>, other: &HashMap<u32, Fingerprint>) -> ByForms<'a> {
+        side.iter()
+            .filter(|&(id, f)| !self.paired.contains(id) && !other.contains_key(id) && !f.values.is_empty())
+            .fold(ByForms::new(), |mut by_forms, (id, f)| {
+                by_forms.entry(&f.forms).or_default().push((*id, f));
+                by_forms
+            })
+    }
+
+    /// The unmatched old and new patients with the same forms and at least
+    /// the minimum similarity of values, most similar first
+    pub fn candidates(&self) -> Vec<RenumberedPatient> {
+        let (old, new) = (self.unmatched(&self.old, &self.new), self.unmatched(&self.new, &self.old));
+
+        let mut candidates = old.iter()
+            .filter_map(|(forms, old)| new.get(forms).map(|new| (forms, old, new)))
+            .flat_map(|(forms, old, new)| old.iter().flat_map(move |(o, f1)| new.iter().map(move |(n, f2)| {
+                let (shared, similarity) = f1.similarity(f2);
+                RenumberedPatient { old: *o, new: *n, forms: forms.len(), shared_values: shared, similarity }
+            })))
+            .filter(|c| c.similarity >= self.min_similarity)
+            .collect::<Vec<RenumberedPatient>>();
+        candidates.sort_by(|a, b| b.similarity.total_cmp(&a.similarity).then((a.old, a.new).cmp(&(b.old, b.new))));
+
+        candidates
+    }
+}
@@ -0,0 +1,388 @@
+use clap::ValueEnum;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use crate::metadata::RunMetadata;
+use crate::skipped_forms::SkippedForm;
+use crate::suppressions::SuppressionUse;
+use crate::triage::Disposition;
+
+/// What kind of difference a record describes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+#[value(rename_all = "snake_case")]
+pub enum DifferenceKind {
+    /// Present on only one side
+    Missing,
+    /// Differently shaped on each side
+    Variant,
+    /// Differing values
+    Equality,
+    Patient,
+    Code,
+    AllowMultiple,
+    Name,
+    /// Saved earlier in the new migration than in the old
+    Timestamp,
+    /// Differing free text, even once normalized
+    Text,
+    /// Differing values of a CDE calculated from others
+    Calculated,
+    /// A new value other than what an expected change of the old value gives
+    Unexpected,
+    /// History snapshots dropped, added or reordered, or a CDE's values over them changed
+    History,
+    /// A form, section or CDE matched once its code was normalized, but coded differently
+    KeyCase,
+    /// A string cut short on one side
+    Truncated,
+    /// Numbers that are equal once rounded, but written differently
+    Formatting,
+    /// A row of a multiple section at a different position on each side
+    Reordered,
+    /// A history snapshot saved by someone else, at another time or in another context
+    Attribution,
+    /// Two CDEs of a section whose values were exchanged
+    SwappedValues,
+}
+
+impl fmt::Display for DifferenceKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            DifferenceKind::Missing => "missing",
+            DifferenceKind::Variant => "variant",
+            DifferenceKind::Equality => "equality",
+            DifferenceKind::Patient => "patient",
+            DifferenceKind::Code => "code",
+            DifferenceKind::AllowMultiple => "allow_multiple",
+            DifferenceKind::Name => "name",
+            DifferenceKind::Timestamp => "timestamp",
+            DifferenceKind::Text => "text",
+            DifferenceKind::Calculated => "calculated",
+            DifferenceKind::Unexpected => "unexpected",
+            DifferenceKind::History => "history",
+            DifferenceKind::KeyCase => "key_case",
+            DifferenceKind::Truncated => "truncated",
+            DifferenceKind::Formatting => "formatting",
+            DifferenceKind::Reordered => "reordered",
+            DifferenceKind::Attribution => "attribution",
+            DifferenceKind::SwappedValues => "swapped_values",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl DifferenceKind {
+    /// The comparison that finds differences of the kind
+    pub fn rule(&self) -> &'static str {
+        match self {
+            DifferenceKind::Missing => "A clinical datum, form, section, CDE, patient or field is present in only one export",
+            DifferenceKind::Variant => "A CDE's values are differently shaped, eg. a number in one export and a string or range in the other",
+            DifferenceKind::Equality => "Values of the same shape aren't equal, numbers by more than the tolerance and strings once collated",
+            DifferenceKind::Patient => "The clinical data paired by the slice is of a different patient in each export",
+            DifferenceKind::Code => "A paired section or CDE has a different code in each export",
+            DifferenceKind::AllowMultiple => "A section allows multiple rows in one export but not in the other",
+            DifferenceKind::Name => "A paired form has a different name in each export",
+            DifferenceKind::Timestamp => "The new clinical datum was saved before the old one (with --timestamps)",
+            DifferenceKind::Text => "Free text differs even once its whitespace and HTML are normalized (with --normalize-text)",
+            DifferenceKind::Calculated => "A CDE calculated from others has differing values, other than by formatting or being cut short",
+            DifferenceKind::Unexpected => "The new value isn't what the config's expected change of the CDE gives of the old value",
+            DifferenceKind::History => "History snapshots were dropped, added or reordered, or a CDE's values over them changed (with --history-sequence)",
+            DifferenceKind::KeyCase => "A form, section or CDE's code differs only by case or surrounding whitespace (with --normalize-keys)",
+            DifferenceKind::Truncated => "A string is a prefix of the other, as when the value was cut off by a column too short for it",
+            DifferenceKind::Formatting => "Numbers (or strings of them) are written differently, but are the same number, or equal once rounded to --formatting-precision",
+            DifferenceKind::Reordered => "A row of a multiple section moved, matched by its values (with --row-order) or by its key CDE (with --row-key)",
+            DifferenceKind::Attribution => "Paired history snapshots have a different username, context, or timestamps further apart than the tolerance (with --history-metadata)",
+            DifferenceKind::SwappedValues => "Two CDEs of a section both differ, the new value of each being the old value of the other, as when their fields were mapped the wrong way around",
+        }
+    }
+
+    pub fn severity(&self) -> Severity {
+        match self {
+            DifferenceKind::Patient | DifferenceKind::Code | DifferenceKind::Missing | DifferenceKind::History
+                | DifferenceKind::Truncated => Severity::High,
+            DifferenceKind::Variant | DifferenceKind::Equality | DifferenceKind::AllowMultiple
+                | DifferenceKind::Timestamp | DifferenceKind::Text | DifferenceKind::Unexpected
+                | DifferenceKind::Reordered | DifferenceKind::Attribution | DifferenceKind::SwappedValues => Severity::Medium,
+            DifferenceKind::Name | DifferenceKind::Calculated | DifferenceKind::KeyCase => Severity::Low,
+            DifferenceKind::Formatting => Severity::FormattingOnly,
+        }
+    }
+}
+
+/// How much a kind of difference matters to the migrated data
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// Data lost or attributed to the wrong patient
+    High,
+    /// Data changed in value or shape
+    Medium,
+    /// Naming, or values the new system recomputes
+    Low,
+    /// The same value, written differently
+    #[serde(rename = "formatting_only")]
+    FormattingOnly,
+}
+
+impl Severity {
+    pub const ALL: [Severity; 4] = [Severity::High, Severity::Medium, Severity::Low, Severity::FormattingOnly];
+
+    /// How much a difference of the severity counts toward a patient's score
+    pub fn weight(&self) -> f64 {
+        match self {
+            Severity::High => 10.0,
+            Severity::Medium => 3.0,
+            Severity::Low => 1.0,
+            Severity::FormattingOnly => 0.0,
+        }
+    }
+}
+
+/// How badly a patient's data was migrated, from 0 for a faithful migration,
+/// adding each difference's severity weighted by the importance of its CDE
+/// (or field), which defaults to 1
+pub fn score(records: &[DifferenceRecord], weights: &HashMap<String, f64>) -> f64 {
+    records.iter().map(|r| {
+        let code = r.location.cde.as_ref().or(r.location.field.as_ref());
+        let weight = code.and_then(|c| weights.get(c)).copied().unwrap_or(1.0);
+        r.kind.severity().weight() * weight
+    }).sum()
+}
+
+/// The number of worst scoring patients kept for the summary
+pub const WORST_PATIENTS: usize = 10;
+
+/// Where in a patient's clinical data a difference was found, filled as deep
+/// as the difference goes, eg. a missing form has no section or CDE
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Location {
+    pub patient: u32,
+    pub ids: String,
+    pub context: String,
+    pub form: Option<String>,
+    pub section: Option<String>,
+    pub cde: Option<String>,
+    /// The field of a model other than clinical data, eg. a patient's "sex"
+    pub field: Option<String>,
+    /// When the clinical datum was last saved on each side, so it's clear which is stale
+    pub old_timestamp: Option<String>,
+    pub new_timestamp: Option<String>,
+    /// The raw JSON of the section on each side, with --raw-context
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub old_raw: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new_raw: Option<String>,
+    /// JSON pointers (RFC 6901) to the difference in each export's list of
+    /// records, eg. "/12/fields/data/forms/3/sections/1/cdes/7/value", as
+    /// the fragment of the record's entry where the clinical data is of
+    /// several, eg. "clinical_data/part2.json#/12/fields/data/forms/3"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub old_pointer: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new_pointer: Option<String>,
+}
+
+impl Location {
+    /// Where the difference is, from its context down, eg. "FormA / sec1 / CDEAge"
+    pub fn path(&self) -> String {
+        [Some(&self.context), self.form.as_ref(), self.section.as_ref(), self.cde.as_ref(), self.field.as_ref()]
+            .iter().flatten().join(" / ")
+    }
+}
+
+/// A single difference flattened out of the nested difference types, owning
+/// its values so it can outlive the patient slices it was found in
+///
+/// For a missing entity, the side it's present on holds its name (or value,
+/// for a CDE)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DifferenceRecord {
+    pub location: Location,
+    pub kind: DifferenceKind,
+    pub old: Option<String>,
+    pub new: Option<String>,
+    /// How the values differ, where that's not obvious from them, eg. an inline text diff
+    pub detail: Option<String>,
+    /// What a reviewer decided about the difference in an earlier run, if they triaged it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub triage: Option<Disposition>,
+}
+
+impl DifferenceRecord {
+    /// An id of the difference that's the same in every run that finds it
+    ///
+    /// It's a 64 bit FNV-1a hash of where the difference is and its values,
+    /// leaving out the clinical datum pks and timestamps, which a migration
+    /// run again can change
+    pub fn id(&self) -> String {
+        let l = &self.location;
+        let kind = self.kind.to_string();
+        let patient = l.patient.to_string();
+        let fields = [
+            Some(patient.as_str()), Some(l.context.as_str()), l.form.as_deref(), l.section.as_deref(), l.cde.as_deref(),
+            l.field.as_deref(), Some(kind.as_str()), self.old.as_deref(), self.new.as_deref(),
+        ];
+
+        let hash = fields.iter().fold(FNV_OFFSET, |hash, field| {
+            // Every field ends with a separator, and missing ones are told apart from empty ones
+            fnv1a(fnv1a(hash, field.map_or(&[0xff][..], str::as_bytes)), &[0])
+        });
+
+        format!("{:016x}", hash)
+    }
+}
+
+/// The hash of no bytes, to start a 64 bit FNV-1a hash from
+pub const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+
+/// A 64 bit FNV-1a hash continued over bytes, which unlike Rust's hashers is
+/// the same in every build, so it can be kept across runs
+pub fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, b| (hash ^ *b as u64).wrapping_mul(0x100000001b3))
+}
+
+/// How much of each difference's values is kept, trading memory and report
+/// size against detail on very large, very broken migrations
+///
+/// A difference's id is a hash of its values, so differences found at one
+/// level don't match those of another in a triage file or compare-reports
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum Detail {
+    /// Where each difference is and its kind, with values left empty so the side that has one still shows
+    Codes,
+    /// Values and inline diffs truncated to their first TRUNCATED_LENGTH characters
+    Values,
+    /// Full values, and the raw JSON of the section on each side (same as --raw-context)
+    Raw,
+}
+
+/// The characters of a value kept at Detail::Values
+pub const TRUNCATED_LENGTH: usize = 64;
+
+impl DifferenceRecord {
+    /// Drop what the detail level doesn't keep of the difference
+    pub fn retain(&mut self, detail: Detail) {
+        let retain = |v: &mut Option<String>| match (detail, v.as_mut()) {
+            (Detail::Codes, Some(v)) => *v = String::new(),
+            (Detail::Values, Some(v)) => {
+                if let Some((i, _)) = v.char_indices().nth(TRUNCATED_LENGTH) {
+                    v.truncate(i);
+                    v.push('…');
+                }
+            }
+            _ => {}
+        };
+
+        retain(&mut self.old);
+        retain(&mut self.new);
+        match detail {
+            Detail::Codes => self.detail = None,
+            _ => retain(&mut self.detail),
+        }
+    }
+}
+
+/// Totals of the patients of a cohort
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CohortTotals {
+    pub patients: usize,
+    pub differing_patients: usize,
+    pub differences: usize,
+    pub by_severity: BTreeMap<Severity, usize>,
+}
+
+/// A patient's difference of a CDE, given as an example of the CDE's differences
+#[derive(Debug, Clone, Serialize)]
+pub struct Example {
+    pub patient: u32,
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+/// Examples of the differences of a CDE that differs in many patients
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CdeExamples {
+    /// The number of patients the CDE differs in
+    pub patients: usize,
+    /// Of the first patients found, one each
+    pub examples: Vec<Example>,
+}
+
+/// A change of a CDE's value found more than once, which --dedupe reports once
+#[derive(Debug, Clone, Serialize)]
+pub struct DedupedChange {
+    pub cde: String,
+    pub kind: DifferenceKind,
+    pub old: Option<String>,
+    pub new: Option<String>,
+    /// The number of patients the change was found in, and times it was found
+    pub patients: usize,
+    pub differences: usize,
+    /// The first patients it was found in
+    pub sample: Vec<u32>,
+}
+
+/// An old and a new patient left unmatched who are likely the same patient
+/// renumbered, having the same forms and nearly the same CDE values
+#[derive(Debug, Clone, Serialize)]
+pub struct RenumberedPatient {
+    pub old: u32,
+    pub new: u32,
+    /// The number of forms both have
+    pub forms: usize,
+    /// The number of CDE values both have, and the fraction they are of either's values
+    pub shared_values: usize,
+    pub similarity: f64,
+}
+
+/// A patient whose slice took longer than --slow-patient-ms to read, parse
+/// and diff, with the size of its records on each side
+#[derive(Debug, Clone, Serialize)]
+pub struct SlowPatient {
+    pub patient: u32,
+    pub ids: String,
+    pub elapsed_ms: u64,
+    pub old_bytes: usize,
+    pub new_bytes: usize,
+}
+
+/// Totals of a whole run
+#[derive(Debug, Default)]
+pub struct Summary {
+    pub patients: usize,
+    pub differing_patients: usize,
+    pub differences: usize,
+    pub by_severity: BTreeMap<Severity, usize>,
+    pub by_kind: BTreeMap<DifferenceKind, usize>,
+    /// The differences of each form, by name, leaving out those of other models
+    pub by_form: BTreeMap<String, usize>,
+    /// The highest scoring patients and their scores, worst first
+    pub worst_patients: Vec<(u32, f64)>,
+    /// Whether the run stopped early at --max-differing-patients, so not every patient was compared
+    pub truncated: bool,
+    /// Whether the run was stopped by SIGINT or SIGTERM, which also truncates it
+    pub interrupted: bool,
+    /// The number of differences triaged in an earlier run, by disposition
+    pub by_triage: BTreeMap<Disposition, usize>,
+    /// The totals of each cohort of a --cohorts file, by "dimension=label"
+    pub by_cohort: BTreeMap<String, CohortTotals>,
+    /// How each suppression of the ignore list was used, by code
+    pub suppressions: Vec<SuppressionUse>,
+    /// The forms left out for being flagged as questionnaires or abbreviated, by name
+    pub skipped_forms: BTreeMap<String, SkippedForm>,
+    /// The sections of the new export each retired CDE was absent from, by code
+    pub retired: BTreeMap<String, usize>,
+    /// Examples of the CDEs differing in more than --examples-min-patients patients, by code
+    pub examples: BTreeMap<String, CdeExamples>,
+    /// The changes found more than once with --dedupe, in the most patients first
+    pub deduplicated: Vec<DedupedChange>,
+    /// The unmatched patients likely renumbered with --find-renumbered, most similar first
+    pub renumbered: Vec<RenumberedPatient>,
+    /// The slices over the --slow-patient-ms budget, slowest first
+    pub slow_patients: Vec<SlowPatient>,
+    /// Where and from what the run was made
+    pub metadata: RunMetadata,
+}
@@ -0,0 +1,66 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+
+use crate::diff_report::DiffReport;
+use crate::report::DifferenceRecord;
+
+/// The differences of the reports of two migration attempts, by whether the
+/// later attempt fixed them, introduced them or still has them
+///
+/// Differences are matched by id, which is the same in every run that finds
+/// them. A difference found more times in one report than the other (eg. in
+/// two of a patient's contexts) is counted as fixed or new that many times
+#[derive(Debug, Default)]
+pub struct ReportDiff {
+    pub fixed: Vec<DifferenceRecord>,
+    pub new: Vec<DifferenceRecord>,
+    pub persisting: Vec<DifferenceRecord>,
+}
+
+impl ReportDiff {
+    pub fn from(old_report: &str, new_report: &str) -> Result<ReportDiff, Box<dyn Error>> {
+        let by_id = |path: &str| -> Result<BTreeMap<String, Vec<DifferenceRecord>>, Box<dyn Error>> {
+            let mut records = BTreeMap::<String, Vec<DifferenceRecord>>::new();
+            for record in DiffReport::open(path)? {
+                records.entry(record.id()).or_default().push(record);
+            }
+            Ok(records)
+        };
+        let (mut old, mut new) = (by_id(old_report)?, by_id(new_report)?);
+
+        let mut diff = ReportDiff::default();
+        for (id, old_records) in old.iter_mut() {
+            let new_records = new.remove(id).unwrap_or_default();
+            let persisting = old_records.len().min(new_records.len());
+            diff.fixed.extend(old_records.drain(persisting..));
+            diff.new.extend(new_records.into_iter().skip(persisting));
+            diff.persisting.append(old_records);
+        }
+        diff.new.extend(new.into_values().flatten());
+
+        let by_location = |r1: &DifferenceRecord, r2: &DifferenceRecord| (r1.location.patient, r1.location.path()).cmp(&(r2.location.patient, r2.location.path()));
+        diff.fixed.sort_by(by_location);
+        diff.new.sort_by(by_location);
+        diff.persisting.sort_by(by_location);
+
+        Ok(diff)
+    }
+
+    /// Print the fixed and new differences, and the persisting ones if asked
+    pub fn print(&self, persisting: bool) {
+        let print = |heading: &str, records: &[DifferenceRecord]| {
+            println!("{} ({}):", heading, records.len());
+            records.iter().for_each(|r| {
+                let value = |v: &Option<String>| v.clone().unwrap_or_else(|| "(none)".to_string());
+                println!("  {} patient {:<10} {} {}: {} -> {}", r.id(), r.location.patient, r.location.path(), r.kind, value(&r.old), value(&r.new));
+            });
+        };
+
+        print("Fixed", &self.fixed);
+        print("New", &self.new);
+        match persisting {
+            true => print("Persisting", &self.persisting),
+            false => println!("Persisting ({})", self.persisting.len()),
+        }
+    }
+}
@@ -0,0 +1,34 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// The CDEs retired in the new system, which may be absent from the new
+/// export, counting the sections each is absent from so they're reported
+/// apart from missing CDEs
+///
+/// A retired CDE the new export still has is compared as any other
+#[derive(Debug, Default)]
+pub struct RetiredCdes {
+    codes: BTreeMap<String, AtomicUsize>,
+}
+
+impl RetiredCdes {
+    pub fn new(codes: impl IntoIterator<Item=String>) -> RetiredCdes {
+        RetiredCdes { codes: codes.into_iter().map(|code| (code, AtomicUsize::new(0))).collect() }
+    }
+
+    /// Whether a CDE's absence from the new export is allowed, counting it
+    pub fn allows_absent(&self, code: &str) -> bool {
+        match self.codes.get(code) {
+            Some(absent) => {
+                absent.fetch_add(1, Ordering::Relaxed);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The sections each retired CDE was absent from so far, by code
+    pub fn counts(&self) -> BTreeMap<String, usize> {
+        self.codes.iter().map(|(code, absent)| (code.clone(), absent.load(Ordering::Relaxed))).collect()
+    }
+}
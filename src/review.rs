@@ -0,0 +1,80 @@
+use std::collections::HashSet;
+
+use crate::clinical_data::PatientSlice;
+use crate::metadata::Labels;
+
+/// A reproducible random sample of the patient slices found identical, kept
+/// for spot checks as matching records can still both be wrong the same way
+pub struct Review {
+    size: usize,
+    rng: SplitMix64,
+    seen: usize,
+    sample: Vec<(PatientSlice, PatientSlice)>,
+}
+
+impl Review {
+    pub fn new(size: usize, seed: u64) -> Review {
+        Review { size, rng: SplitMix64(seed), seen: 0, sample: Vec::with_capacity(size) }
+    }
+
+    /// Consider an identical pair of slices for the sample, keeping each pair
+    /// seen with equal probability (reservoir sampling)
+    pub fn offer(&mut self, old: PatientSlice, new: PatientSlice) {
+        match self.sample.len() < self.size {
+            true => self.sample.push((old, new)),
+            false => {
+                let i = (self.rng.next_u64() % (self.seen as u64 + 1)) as usize;
+                if i < self.size {
+                    self.sample[i] = (old, new);
+                }
+            }
+        }
+        self.seen += 1;
+    }
+
+    /// Print each sampled pair with old on the left and new on the right,
+    /// leaving out patients found to differ in a later slice or another model
+    pub fn print(&self, differing_patients: &HashSet<u32>, labels: &Labels) {
+        let mut sample = self.sample.iter().filter(|(old, _)| !differing_patients.contains(&old.patient)).collect::<Vec<_>>();
+        sample.sort_by_key(|(old, _)| old.patient);
+
+        println!("Reviewing {} of {} identical patient slices", sample.len(), self.seen);
+        sample.into_iter().for_each(|(old, new)| {
+            println!();
+            println!("Patient {} ({})", old.patient, old.ids());
+            print_side_by_side(&format!("{}\n{:#?}", labels.old_heading(), old), &format!("{}\n{:#?}", labels.new_heading(), new));
+        });
+    }
+}
+
+/// The widest the left column gets, beyond which its lines are cut short
+const COLUMN_WIDTH: usize = 80;
+
+fn print_side_by_side(left: &str, right: &str) {
+    let width = left.lines().map(|l| l.chars().count()).max().unwrap_or(0).min(COLUMN_WIDTH);
+    let (mut left, mut right) = (left.lines(), right.lines());
+
+    loop {
+        match (left.next(), right.next()) {
+            (None, None) => break,
+            (l, r) => {
+                let l = l.unwrap_or("").chars().take(width).collect::<String>();
+                println!("{:<width$} | {}", l, r.unwrap_or(""), width = width)
+            }
+        }
+    }
+}
+
+/// A small seeded generator, so a sample can be drawn again from the same seed
+/// (https://prng.di.unimi.it/splitmix64.c)
+pub struct SplitMix64(pub u64);
+
+impl SplitMix64 {
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+}
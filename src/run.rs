@@ -0,0 +1,1842 @@
+//! The commands of diffmig, for its binary and for other users of the diff,
+//! eg. to follow a diff with observers
+
+use clap::ValueEnum;
+use itertools::{Itertools, EitherOrBoth};
+use rayon::ThreadPoolBuilder;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::error::Error;
+use std::fs;
+use std::io::{BufReader, Read, Seek};
+use std::path::Path;
+use std::process;
+use std::sync::Arc;
+use std::sync::mpsc::SyncSender;
+use std::thread;
+use std::time::{Duration, Instant};
+use zip::read::ZipFile;
+
+use crate::{aggregate, batch, bench, calculated, explain, fixture, gate, generate, interrupt, manifest, metadata, notify, output, pager, pipeline, plugins, presort, profile, prompt, registries, report, selftest, triage};
+use crate::aggregate::{Aggregates, Thresholds};
+use crate::archive::{Archive, ArchiveFile};
+use crate::entries::{Entries, EntryGlob};
+use crate::batch::Batch;
+use crate::check::Sample;
+use crate::cli::{AnnotateArgs, BatchArgs, CompareReportsArgs, DiffArgs, ExplainArgs, GenFixtureArgs, GroupBy, Model, PromptEvery, SelftestArgs};
+use crate::clinical_data::{PatientSlice};
+use crate::cohorts::Cohorts;
+use crate::config::Config;
+use crate::contexts::Contexts;
+use crate::consents::{ConsentFixtures, ConsentModel, Consents};
+use crate::dedupe::Dedupe;
+use crate::diff::{Diff, DiffOptions};
+use crate::gate::Gates;
+use crate::generate::FixtureSpec;
+use crate::expect::Transform;
+use crate::histogram::Histogram;
+use crate::history::HistoryCheck;
+use crate::index::Index;
+use crate::integrity::{IntegrityCheck, IntegrityViolation};
+use crate::interner::Interner;
+use crate::json_diff::{self as json, JsonDiff};
+use crate::mapped::MappedEntry;
+use crate::manifest::RunManifest;
+use crate::metadata::{InputFile, Labels, RunMetadata};
+use crate::notify::Outcome;
+use crate::observer::{DiffObserver, Observed};
+use crate::migrated_registry::{Collection, CollectionCounts, ExportFormat, MigratedRegistry, OnParseError, ParseErrors, RecordFilter};
+use crate::output::{CdeGroupWriter, ReportWriter, TemplateWriter};
+use crate::patients::{Demographics, ModelDifference, Patient, PatientModel};
+use crate::renames::Renames;
+use crate::renumbered::Renumbering;
+use crate::retired::RetiredCdes;
+use crate::patient_map::PatientMap;
+use crate::patch::Patch;
+use crate::permitted::PermittedValues;
+use crate::report::{CdeExamples, CohortTotals, Detail, DifferenceKind, DifferenceRecord, Example, Severity, SlowPatient, Summary};
+use crate::report_diff::ReportDiff;
+use crate::review::Review;
+use crate::tree::Rendering;
+use crate::triage::{Annotation, Disposition, Triage};
+use crate::unparsed::Unparsed;
+use crate::schema::Schema;
+use crate::structure::Structure;
+use crate::since::Since;
+use crate::skipped_forms::SkippedForms;
+use crate::suppressions::{Suppression, Suppressions};
+use crate::profile::{Phase, TimedReader};
+use crate::pipeline::PipelinedRegistry;
+use crate::presort::{Positioned, ReadFailure};
+use crate::progress::{Progress, Side};
+
+/// The entry of an archive's clinical data, the one given or where its format has it
+fn get_clinical_data_path(archive: &Archive<impl Read + Seek>, format: ExportFormat, entry: Option<&str>) -> Result<String, Box<dyn Error>> {
+    if let Some(entry) = entry {
+        return match archive.file_names().any(|p| p == entry) {
+            true => Ok(entry.to_string()),
+            false => Err(format!("{} not found in zip", entry).into()),
+        };
+    }
+
+    Ok(archive.file_names().find(|p| format.is_clinical_data(p)).ok_or(match format {
+        ExportFormat::Django => "rdrf_clinicaldata.json file not found in zip",
+        ExportFormat::MongoRaw => "cdes.json file not found in zip",
+    })?.to_string())
+}
+
+fn get_zip_reader<'a>(archive: &'a mut Archive<impl Read + Seek>) -> Result<(String, ZipFile<'a>), Box<dyn Error>> {
+    get_format_reader(archive, ExportFormat::Django, None)
+}
+
+fn get_format_reader<'a>(archive: &'a mut Archive<impl Read + Seek>, format: ExportFormat, entry: Option<&str>) -> Result<(String, ZipFile<'a>), Box<dyn Error>> {
+    let clinical_data_path = get_clinical_data_path(archive, format, entry)?;
+
+    Ok((clinical_data_path.clone(), archive.by_name(clinical_data_path.as_str())?))
+}
+
+/// How the exports are read before they're compared
+struct ReadOptions {
+    models: Vec<Model>,
+    mmap: bool,
+    /// The bytes each zip is read ahead of the parser, if it's read ahead
+    read_buffer: Option<usize>,
+    /// The password of encrypted entries of the exports
+    password: Option<String>,
+    /// Whether each export is read and parsed on threads of its own
+    pipeline: bool,
+    /// The format of the old export's clinical data
+    old_format: ExportFormat,
+    /// The bytes of records sorted at a time, if the records are sorted by patient before they're compared
+    presort: Option<usize>,
+    /// The only patients diffed, by their new ids, if not all of them
+    patients: Vec<u32>,
+    /// The entries the clinical data is read from, if it's sharded into several
+    entry_glob: Option<EntryGlob>,
+    /// The entry of each export's clinical data, if not where its format has it
+    old_entry: Option<String>,
+    new_entry: Option<String>,
+    collections: Vec<Collection>,
+    strict_collections: bool,
+    /// Whether each side's slices are checked for the ordering the diff assumes
+    check_integrity: bool,
+    /// Whether the members of the records that aren't read into their data are compared
+    extra_fields: bool,
+    /// Whether forms the definitions flag as questionnaires or abbreviated are left out
+    skip_flagged_forms: bool,
+    on_parse_error: OnParseError,
+    schema_records: Option<usize>,
+    group_by: GroupBy,
+    /// Whether each patient's differences are shown one per line, rather than as a tree
+    flat: bool,
+    /// How often to ask whether to continue, and the differences between prompts with PromptEvery::NDiffs
+    prompt_every: (PromptEvery, usize),
+    /// Whether a change found again is only counted, rather than reported again
+    dedupe: bool,
+    /// The file the ids of the patients without differences are written to, if any
+    clean_out: Option<String>,
+    /// The similarity unmatched patients must have to be reported as likely renumbered, if they're looked for
+    find_renumbered: Option<f64>,
+    /// The time a patient's slice can take to read, parse and diff before it's reported as slow, if any
+    slow_patient: Option<Duration>,
+    /// The size and seed of the sample of identical patients to review
+    review_sample: Option<(usize, u64)>,
+    /// The number of examples given of each CDE, and the patients it must differ in more than
+    examples: Option<(usize, usize)>,
+    /// Whether the raw JSON of sections is kept to show with their differences
+    raw_context: bool,
+    /// How much of each difference's values is kept, if not all of them
+    detail: Option<Detail>,
+    max_differing_patients: Option<usize>,
+    /// The registry codes of the clinical data compared of each export, if they're restricted
+    old_code: Option<String>,
+    new_code: Option<String>,
+    renames: Arc<Renames>,
+    /// The new ids of the old export's patients, if they were reassigned
+    patient_map: Option<PatientMap>,
+    /// Whether forms, sections and CDEs are matched by their normalized codes
+    normalize_keys: bool,
+    /// The triage file of earlier runs' annotations, if any
+    triage: Option<String>,
+    /// The CSV file of patients' cohorts, if any
+    cohorts: Option<String>,
+    /// The file of corrections to write as differences are shown, if any
+    patch: Option<String>,
+    expected_patients: Option<u64>,
+    /// The metadata of the run, its inputs and finish filled in by the diff
+    metadata: RunMetadata,
+    /// The options and files of the run, its exports' digests filled in by
+    /// the diff, and the path it's written to
+    manifest: RunManifest,
+    manifest_path: String,
+    /// The differences of an earlier run to reuse for slices that haven't changed
+    since: Option<Arc<Since>>,
+}
+
+type ClinicalDataReader<'a> = (String, u64, Box<dyn Read + 'a>);
+
+/// The path, size and a reader of the clinical data of an archive, from the
+/// entry given or where its format has it, sliced from a map of the archive
+/// if mmap is set and the entry is stored (and not encrypted, or in a split
+/// archive), or of the entries matching a glob read one after another, which
+/// are given to entries
+#[allow(clippy::too_many_arguments)]
+fn get_clinical_data_reader<'a>(zip_path: &str, archive: &'a mut Archive<BufReader<ArchiveFile>>, format: ExportFormat, entry: Option<&str>, map: &'a mut Option<MappedEntry>, mmap: bool, entry_glob: Option<&EntryGlob>, entries: &Entries) -> Result<ClinicalDataReader<'a>, Box<dyn Error>> {
+    if let Some(glob) = entry_glob {
+        let names = glob.entries(archive)?;
+        entries.read(&names);
+        // Each entry is followed by a newline
+        let size = names.iter().map(|name| Ok(archive.by_name(name)?.size() + 1)).sum::<Result<u64, Box<dyn Error>>>()?;
+        if mmap {
+            log::debug!("Not mapping the {} entries matching {}, they're read one after another", names.len(), glob.as_str());
+        }
+        return Ok((glob.as_str().to_string(), size, Box::new(archive.concatenated(names))));
+    }
+
+    let encrypted = archive.encrypted(&get_clinical_data_path(archive, format, entry)?);
+    let split = archive.split();
+    let (path, reader) = get_format_reader(archive, format, entry)?;
+    let size = reader.size();
+
+    match (mmap, encrypted, split) {
+        (true, false, false) => *map = MappedEntry::open(zip_path, &reader)?,
+        (true, true, _) => log::debug!("Not mapping {}, it's encrypted", path),
+        (true, _, true) => log::debug!("Not mapping {}, it's in a split archive", path),
+        (false, _, _) => {}
+    }
+
+    match map {
+        Some(map) => Ok((path, size, Box::new(map.bytes()))),
+        None => Ok((path, size, Box::new(reader)))
+    }
+}
+
+/// The index of a zip's clinical data, if it has one that's up to date
+///
+/// Only an export's single entry of clinical data is indexed
+fn clinical_data_index(archive: &mut Archive<impl Read + Seek>, zip: &str, format: ExportFormat, entry: Option<&str>, entry_glob: Option<&EntryGlob>) -> Result<Option<Index>, Box<dyn Error>> {
+    match (format, entry_glob) {
+        (ExportFormat::Django, None) => {
+            let (entry, file) = get_format_reader(archive, format, entry)?;
+            Index::load(zip, &entry, file.size(), file.crc32())
+        }
+        _ => Ok(None),
+    }
+}
+
+/// The text of each record of a side's clinical data, noting where each
+/// entry's records start if it's of several entries of an export
+fn format_records<'a>(reader: impl Read + 'a, format: ExportFormat, entries: &Entries) -> Box<dyn Iterator<Item=String> + 'a> {
+    match (format, entries.is_empty()) {
+        (ExportFormat::Django, false) => Box::new(entries.records(reader)),
+        _ => format.records(reader),
+    }
+}
+
+/// The records of a side's clinical data with their positions in the export,
+/// either only those of the patients given, in the order given, by the zip's
+/// index if it has one, or all of them, sorted by patient if they're presorted
+#[allow(clippy::too_many_arguments)]
+fn side_records<'a>(zip: &str, reader: impl Read + 'a, format: ExportFormat, entries: &Entries, index: Option<Index>, patients: Vec<u32>, presort: Option<usize>, failure: &ReadFailure) -> Result<Positioned<'a>, Box<dyn Error>> {
+    if patients.is_empty() {
+        return Ok(presort::records(format_records(reader, format, entries), presort, failure)?);
+    }
+
+    match index {
+        Some(index) => {
+            if let Some(missing) = patients.iter().find(|p| !index.patients.contains_key(p)) {
+                return Err(format!("{} has no clinical data of patient {}", zip, missing).into());
+            }
+            Ok(Box::new(index.records(reader, &patients)?.into_iter()))
+        }
+        None => {
+            eprintln!("Warning: {} isn't indexed, so all its records are read for --patient, see diffmig index", zip);
+            let order = |record: &str| fixture::patient(record).and_then(|p| patients.iter().position(|&id| id as i64 == p));
+            let records = format_records(reader, format, entries).enumerate()
+                .filter_map(|(position, record)| Some((order(&record)?, position, record)))
+                .sorted_by_key(|(i, position, _)| (*i, *position))
+                .map(|(_, position, record)| (position, record));
+            Ok(Box::new(records))
+        }
+    }
+}
+
+/// The running totals of a diff, written to the outputs patient by patient
+struct Tally<'o> {
+    outputs: &'o mut [Box<dyn ReportWriter>],
+    skip_input: bool,
+    /// Whether differences are shown grouped once the diff is finished, rather than patient by patient
+    grouped: bool,
+    /// How each patient's differences are shown, if they're shown patient by patient
+    rendering: Rendering,
+    /// How often to ask whether to continue, and the differences between prompts with PromptEvery::NDiffs
+    prompt_every: (PromptEvery, usize),
+    /// The differences shown since the last prompt
+    unprompted: usize,
+    /// The changes reported so far, if changes found again are left out
+    dedupe: Option<Dedupe>,
+    /// The clinical data of patients paired with others, if likely renumbered patients are looked for
+    renumbering: Option<Renumbering>,
+    /// The time a slice can take before it's reported as slow, and the slices that took longer
+    slow_patient: Option<Duration>,
+    slow_patients: Vec<SlowPatient>,
+    /// The patient whose differences are yet to be confirmed, with PromptEvery::Patient
+    pending: Option<u32>,
+    /// Whether the reviewer quit, so the rest aren't compared
+    quit: bool,
+    patients: HashSet<u32>,
+    differing_patients: HashSet<u32>,
+    differences: usize,
+    by_severity: BTreeMap<Severity, usize>,
+    by_kind: BTreeMap<DifferenceKind, usize>,
+    by_form: BTreeMap<String, usize>,
+    weights: HashMap<String, f64>,
+    /// The scores of the differing patients
+    scores: HashMap<u32, f64>,
+    /// The sample of identical patients to review, if one was asked for
+    review: Option<Review>,
+    /// The number of differing patients to stop at, if any
+    max_differing_patients: Option<usize>,
+    /// The number of examples given of each CDE, and the patients it must differ in more than
+    example_limits: Option<(usize, usize)>,
+    /// The patients each CDE differs in and the first of their differences, if examples are given
+    by_cde: HashMap<String, (HashSet<u32>, Vec<Example>)>,
+    /// The differences triaged in earlier runs
+    triage: Triage,
+    by_triage: BTreeMap<Disposition, usize>,
+    cohorts: Cohorts,
+    /// The differences of each cohort, whose patients are counted from the sets of patients
+    by_cohort: BTreeMap<String, CohortTotals>,
+    /// The progress bar of the exports being read, followed by patients compared if it counts them
+    progress: Option<Arc<Progress>>,
+    /// How much of each difference's values is kept, if not all of them
+    detail: Option<Detail>,
+    /// The differences of an earlier run to reuse for slices that haven't changed
+    since: Option<Arc<Since>>,
+    /// The number of slices whose differences were reused
+    reused: usize,
+    /// The error that ended either export's presorted records early, if one did
+    read_failure: ReadFailure,
+    /// The corrections decided as differences are shown, and the file they're written to
+    patch: Option<(Patch, String)>,
+    labels: Labels,
+}
+
+impl<'o> Tally<'o> {
+    fn new(outputs: &'o mut [Box<dyn ReportWriter>], grouped: bool, weights: HashMap<String, f64>) -> Tally<'o> {
+        Tally {
+            outputs,
+            skip_input: grouped,
+            grouped,
+            rendering: Rendering::new(false),
+            prompt_every: (PromptEvery::Slice, 0),
+            unprompted: 0,
+            dedupe: None,
+            renumbering: None,
+            slow_patient: None,
+            slow_patients: vec![],
+            pending: None,
+            quit: false,
+            patients: HashSet::new(),
+            differing_patients: HashSet::new(),
+            differences: 0,
+            by_severity: Severity::ALL.iter().map(|s| (*s, 0)).collect(),
+            by_kind: BTreeMap::new(),
+            by_form: BTreeMap::new(),
+            weights,
+            scores: HashMap::new(),
+            review: None,
+            max_differing_patients: None,
+            example_limits: None,
+            by_cde: HashMap::new(),
+            triage: Triage::default(),
+            by_triage: BTreeMap::new(),
+            cohorts: Cohorts::default(),
+            by_cohort: BTreeMap::new(),
+            progress: None,
+            detail: None,
+            since: None,
+            reused: 0,
+            read_failure: ReadFailure::default(),
+            patch: None,
+            labels: Labels::default(),
+        }
+    }
+
+    /// Note a slice that took longer than the budget to read, parse and diff, if there's one
+    fn time(&mut self, old: &PatientSlice, new: &PatientSlice, ids: &str, elapsed: Duration) {
+        if self.slow_patient.is_some_and(|budget| elapsed > budget) {
+            let slow = SlowPatient { patient: old.patient, ids: ids.to_string(), elapsed_ms: elapsed.as_millis() as u64, old_bytes: old.size(), new_bytes: new.size() };
+            eprintln!("Warning: patient {} ({}) took {} ms to read, parse and diff, with {} bytes of records in {} and {} in {}",
+                slow.patient, slow.ids, slow.elapsed_ms, slow.old_bytes, self.labels.old, slow.new_bytes, self.labels.new);
+            self.slow_patients.push(slow);
+        }
+    }
+
+    /// Tell the outputs a patient is about to be compared
+    fn start(&mut self, patient: u32, ids: &str) -> Result<(), Box<dyn Error>> {
+        self.outputs.iter_mut().try_for_each(|o| o.start(patient, ids))
+    }
+
+    /// Count and write the differences of a patient, asking whether to
+    /// continue if there are any
+    fn patient(&mut self, patient: u32, ids: &str, records: &mut [DifferenceRecord]) -> Result<(), Box<dyn Error>> {
+        if let Some(detail) = self.detail {
+            records.iter_mut().for_each(|r| r.retain(detail));
+        }
+        self.triage.classify(records);
+        records.iter().filter_map(|r| r.triage).for_each(|t| *self.by_triage.entry(t).or_insert(0) += 1);
+        let records = &*records;
+
+        self.patients.insert(patient);
+        if let Some(progress) = &self.progress {
+            progress.patients_compared(self.patients.len() as u64);
+        }
+        if !records.is_empty() {
+            self.differing_patients.insert(patient);
+            self.differences += records.len();
+            records.iter().for_each(|r| *self.by_severity.entry(r.kind.severity()).or_insert(0) += 1);
+            records.iter().for_each(|r| *self.by_kind.entry(r.kind).or_insert(0) += 1);
+            records.iter().filter_map(|r| r.location.form.as_ref()).for_each(|form| *self.by_form.entry(form.clone()).or_insert(0) += 1);
+            // A patient's clinical data can span several slices, so their scores add up
+            *self.scores.entry(patient).or_insert(0.0) += report::score(records, &self.weights);
+            for cohort in self.cohorts.of(patient) {
+                let totals = self.by_cohort.entry(cohort).or_default();
+                totals.differences += records.len();
+                records.iter().for_each(|r| *totals.by_severity.entry(r.kind.severity()).or_insert(0) += 1);
+            }
+            if let Some((limit, _)) = self.example_limits {
+                for record in records {
+                    let cde = match &record.location.cde {
+                        Some(cde) => cde,
+                        None => continue,
+                    };
+                    let (patients, examples) = self.by_cde.entry(cde.clone()).or_default();
+                    if patients.insert(patient) && examples.len() < limit {
+                        examples.push(Example { patient, old: record.old.clone(), new: record.new.clone() });
+                    }
+                }
+            }
+        }
+
+        // Changes already reported are only counted
+        let deduped;
+        let records = match &mut self.dedupe {
+            Some(dedupe) => {
+                deduped = records.iter().filter(|r| !dedupe.repeats(patient, r)).cloned().collect::<Vec<DifferenceRecord>>();
+                &deduped[..]
+            }
+            None => records,
+        };
+
+        self.outputs.iter_mut().try_for_each(|o| o.patient(patient, ids, records))?;
+
+        self.show(patient, ids, records)?;
+
+        Ok(())
+    }
+
+    /// Show the differences of a patient's slice as they're found, asking
+    /// whether to continue as often as --prompt-every says
+    fn show(&mut self, patient: u32, ids: &str, records: &[DifferenceRecord]) -> Result<(), Box<dyn Error>> {
+        if self.grouped || records.is_empty() {
+            return Ok(());
+        }
+
+        // Each form's differences are confirmed before the next form's are shown
+        let parts = match self.prompt_every.0 {
+            PromptEvery::Form => records.iter().map(|r| &r.location.form).unique()
+                .map(|form| records.iter().filter(|r| r.location.form == *form).collect())
+                .collect::<Vec<Vec<&DifferenceRecord>>>(),
+            _ => vec![records.iter().collect()],
+        };
+        for part in parts {
+            let text = profile::time(Phase::Render, || self.rendering.render(patient, ids, &part));
+            if self.skip_input {
+                eprint!("{}", text);
+                continue;
+            }
+            // Paged while they're being reviewed, before the prompt to continue
+            pager::show(&text);
+
+            if let Some((patch, path)) = &mut self.patch {
+                for record in part.iter().filter(|r| Patch::correctable(r)) {
+                    let value = |v: &Option<String>| v.clone().unwrap_or_else(|| "(none)".to_string());
+                    println!("{} {}: {} -> {}", record.id(), record.location.path(), value(&record.old), value(&record.new));
+                    if let Some(side) = prompt::correct_side(&self.labels) {
+                        patch.correct(record, side);
+                    }
+                }
+                // Saved as the patient's done, as answering no exits
+                patch.save(path)?;
+            }
+
+            self.unprompted += part.len();
+            match self.prompt_every {
+                (PromptEvery::Slice, _) | (PromptEvery::Form, _) => self.ask(),
+                (PromptEvery::Patient, _) => self.pending = Some(patient),
+                (PromptEvery::NDiffs, diffs) if self.unprompted >= diffs => self.ask(),
+                (PromptEvery::NDiffs, _) => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Ask whether to continue about the differences shown since the last prompt
+    fn ask(&mut self) {
+        self.unprompted = 0;
+        self.pending = None;
+        match prompt::input() {
+            prompt::Response::All => self.skip_input = true,
+            prompt::Response::Yes => {}
+            prompt::Response::No => process::exit(0),
+            prompt::Response::Quit => {
+                self.quit = true;
+                self.skip_input = true;
+            }
+        }
+    }
+
+    /// With --prompt-every patient, ask about the last differing patient once
+    /// the slices of another are reached, as a patient's clinical data can
+    /// span several slices
+    fn reach(&mut self, patient: u32) {
+        if self.pending.is_some_and(|pending| pending != patient) {
+            self.ask();
+        }
+    }
+
+    /// Write the hashes of a slice's clinical data and its number of
+    /// differences, for later runs to tell whether it changed
+    fn slice(&mut self, patient: u32, ids: &str, hashes: (u64, u64), differences: usize) -> Result<(), Box<dyn Error>> {
+        self.outputs.iter_mut().try_for_each(|o| o.slice(patient, ids, hashes, differences))
+    }
+
+    /// Whether enough patients differ, or the diff was interrupted or quit, so the rest aren't compared
+    fn truncated(&self) -> bool {
+        interrupt::interrupted() || self.quit || self.read_failure.failed() || self.max_differing_patients.is_some_and(|max| self.differing_patients.len() >= max)
+    }
+
+    fn summary(&self) -> Summary {
+        let mut by_cohort = self.by_cohort.clone();
+        for patient in &self.patients {
+            for cohort in self.cohorts.of(*patient) {
+                let totals = by_cohort.entry(cohort).or_default();
+                totals.patients += 1;
+                if self.differing_patients.contains(patient) {
+                    totals.differing_patients += 1;
+                }
+            }
+        }
+
+        Summary {
+            patients: self.patients.len(),
+            differing_patients: self.differing_patients.len(),
+            differences: self.differences,
+            by_severity: self.by_severity.clone(),
+            by_kind: self.by_kind.clone(),
+            by_form: self.by_form.clone(),
+            worst_patients: self.scores.iter()
+                .map(|(p, s)| (*p, *s))
+                .sorted_by(|(p1, s1), (p2, s2)| s2.total_cmp(s1).then(p1.cmp(p2)))
+                .take(report::WORST_PATIENTS)
+                .collect(),
+            truncated: self.truncated(),
+            interrupted: interrupt::interrupted(),
+            by_triage: self.by_triage.clone(),
+            by_cohort,
+            suppressions: vec![],
+            skipped_forms: BTreeMap::new(),
+            retired: BTreeMap::new(),
+            examples: match self.example_limits {
+                Some((_, min_patients)) => self.by_cde.iter()
+                    .filter(|(_, (patients, _))| patients.len() > min_patients)
+                    .map(|(cde, (patients, examples))| (cde.clone(), CdeExamples { patients: patients.len(), examples: examples.clone() }))
+                    .collect(),
+                None => BTreeMap::new(),
+            },
+            deduplicated: self.dedupe.as_ref().map(Dedupe::changes).unwrap_or_default(),
+            renumbered: self.renumbering.as_ref().map(Renumbering::candidates).unwrap_or_default(),
+            slow_patients: self.slow_patients.iter().sorted_by(|a, b| b.elapsed_ms.cmp(&a.elapsed_ms)).cloned().collect(),
+            metadata: RunMetadata::default(),
+        }
+    }
+}
+
+fn zip_diff(old_iter: impl Iterator<Item=PatientSlice>, new_iter: impl Iterator<Item=PatientSlice>, patient_models: &[Box<dyn PatientModel>], options: &DiffOptions, tally: &mut Tally) -> Result<usize, Box<dyn Error>> {
+    let mut started = Instant::now();
+    let mut total = 0;
+    let mut history = options.history_sequence.then(HistoryCheck::default);
+
+    interrupt::stoppable();
+    for pair in old_iter.zip_longest(new_iter) {
+        if let EitherOrBoth::Both(old, _) = &pair {
+            tally.reach(old.patient);
+        }
+        if tally.truncated() {
+            break;
+        }
+
+        total += match pair {
+            EitherOrBoth::Both(old, new) => {
+                if let Some(finished) = history.as_mut().and_then(|h| h.add(&old, &new)) {
+                    total += history_patient(finished, options, tally)?;
+                }
+
+                if let Some(renumbering) = &mut tally.renumbering {
+                    renumbering.pair(&old, &new, options);
+                }
+
+                let (ids, hashes) = (old.ids(), (old.hash(), new.hash()));
+                tally.start(old.patient, &ids)?;
+                // Slices unchanged since the earlier run have the same differences
+                let reused = tally.since.as_ref().and_then(|s| s.differences(old.patient, &ids, hashes));
+                let diffs = match reused {
+                    Some(_) => None,
+                    None => profile::time(Phase::Diff, || old.diff(&new, options)),
+                };
+                // A patient's clinical data can span several slices, but their other models are only compared once
+                let model_diffs = match tally.patients.contains(&old.patient) {
+                    true => vec![],
+                    false => profile::time(Phase::Diff, || {
+                        patient_models.iter().flat_map(|m| m.diff_patient(old.patient, options)).filter(|d| options.reports(d.record.kind)).collect::<Vec<ModelDifference>>()
+                    })
+                };
+
+                let mut records = match reused {
+                    Some(reused) => {
+                        tally.reused += 1;
+                        reused
+                    }
+                    None => diffs.iter().flatten().flat_map(|d| d.records()).collect::<Vec<DifferenceRecord>>(),
+                };
+                // The differences of a slice are nested, so they're counted by their records, as kinds are left out by them
+                records.retain(|r| options.reports(r.kind));
+                let clinical = records.len();
+                records.extend(model_diffs.iter().map(|d| d.record.clone()));
+                profile::record_patient(old.patient, started.elapsed());
+                tally.time(&old, &new, &ids, started.elapsed());
+
+                tally.slice(old.patient, &ids, hashes, clinical)?;
+                tally.patient(old.patient, &ids, &mut records)?;
+                started = Instant::now();
+
+                let count = clinical + model_diffs.len();
+                if let (Some(review), true) = (&mut tally.review, records.is_empty()) {
+                    review.offer(old, new);
+                }
+
+                count
+            }
+            EitherOrBoth::Left(_) => {
+                panic!("{} ran out of slices!", tally.labels.new_heading())
+            }
+            EitherOrBoth::Right(_) => {
+                panic!("{} ran out of slices!", tally.labels.old_heading())
+            }
+        };
+    }
+
+    if let Some(finished) = history.as_mut().and_then(HistoryCheck::finish) {
+        total += history_patient(finished, options, tally)?;
+    }
+    tally.read_failure.result()?;
+
+    Ok(total)
+}
+
+/// Write the history differences of a patient, found once all their slices are compared
+fn history_patient((patient, mut records): (u32, Vec<DifferenceRecord>), options: &DiffOptions, tally: &mut Tally) -> Result<usize, Box<dyn Error>> {
+    records.retain(|r| options.reports(r.kind));
+    tally.start(patient, "")?;
+    tally.patient(patient, "", &mut records)?;
+
+    Ok(records.len())
+}
+
+/// Diff the other models of the patients that weren't compared alongside
+/// their clinical data, of only the patients given if any are
+fn diff_remaining_patients(patient_models: &[Box<dyn PatientModel>], patients: &[u32], options: &DiffOptions, tally: &mut Tally) -> Result<usize, Box<dyn Error>> {
+    let remaining = patient_models.iter()
+        .flat_map(|m| m.ids())
+        .filter(|id| !tally.patients.contains(id) && (patients.is_empty() || patients.contains(id)))
+        .collect::<BTreeSet<u32>>();
+
+    let mut total = 0;
+
+    interrupt::stoppable();
+    for id in remaining {
+        tally.reach(id);
+        if tally.truncated() {
+            break;
+        }
+
+        tally.start(id, "")?;
+        let diffs = profile::time(Phase::Diff, || {
+            patient_models.iter().flat_map(|m| m.diff_patient(id, options)).filter(|d| options.reports(d.record.kind)).collect::<Vec<ModelDifference>>()
+        });
+        let mut records = diffs.iter().map(|d| d.record.clone()).collect::<Vec<DifferenceRecord>>();
+
+        tally.patient(id, "", &mut records)?;
+
+        total += diffs.len();
+    }
+
+    Ok(total)
+}
+
+fn check_schema(old_archive: &mut Archive<impl Read + Seek>, new_archive: &mut Archive<impl Read + Seek>, read: &ReadOptions, records: usize) -> Result<(), Box<dyn Error>> {
+    let old_schema = Schema::scan(get_format_reader(old_archive, read.old_format, read.old_entry.as_deref())?.1, read.old_format, records);
+    let new_schema = Schema::scan(get_format_reader(new_archive, ExportFormat::Django, read.new_entry.as_deref())?.1, ExportFormat::Django, records);
+
+    match old_schema.diff(&new_schema, &DiffOptions::default()) {
+        None => println!("No schema drift found in the first {} records", records),
+        Some(diffs) => {
+            println!("Found {} schema differences in the first {} records:", diffs.len(), records);
+            diffs.iter().for_each(|d| println!("  {}", d));
+        }
+    }
+
+    Ok(())
+}
+
+fn get_patients_reader<'a>(archive: &'a mut Archive<impl Read + Seek>) -> Result<ZipFile<'a>, Box<dyn Error>> {
+    let patients_path = archive.file_names().find(|p| {
+        let path_split = p.split('/').collect::<Vec<&str>>();
+        matches!(&path_split[..], [_, "registry_data", .., "patients.json"])
+    }).ok_or("patients.json file not found in zip")?.to_string();
+
+    archive.by_name(patients_path.as_str())
+}
+
+/// Read the consent fixtures of an archive, ie. the JSON files under
+/// registry_data whose names start with "consent"
+fn read_consents(archive: &mut Archive<impl Read + Seek>) -> Result<Consents, Box<dyn Error>> {
+    let paths = archive.file_names().filter(|p| {
+        let path_split = p.split('/').collect::<Vec<&str>>();
+        matches!(&path_split[..], [_, "registry_data", .., name] if name.starts_with("consent") && name.ends_with(".json"))
+    }).map(String::from).collect::<Vec<String>>();
+
+    if paths.is_empty() {
+        return Err("No consent fixtures found in zip".into());
+    }
+
+    let mut fixtures = ConsentFixtures::default();
+    for path in paths {
+        log::debug!("Reading consents from {}", path);
+        fixtures.read(archive.by_name(&path)?)?;
+    }
+
+    Ok(fixtures.consents()?)
+}
+
+/// Read the contexts of an archive, numbered within their form groups, or
+/// none if it doesn't have the context fixture
+fn read_contexts(archive: &mut Archive<impl Read + Seek>) -> Result<Contexts, Box<dyn Error>> {
+    let fixture = |archive: &Archive<_>, name: &str| archive.file_names().find(|p| {
+        let path_split = p.split('/').collect::<Vec<&str>>();
+        matches!(&path_split[..], [_, "registry_data", .., n] if *n == name)
+    }).map(String::from);
+
+    let contexts_path = match fixture(archive, "rdrf_rdrfcontext.json") {
+        Some(path) => path,
+        None => return Ok(Contexts::default()),
+    };
+    let form_groups = match fixture(archive, "rdrf_contextformgroup.json") {
+        Some(path) => {
+            let mut text = vec![];
+            archive.by_name(&path)?.read_to_end(&mut text)?;
+            Some(text)
+        }
+        None => None,
+    };
+    log::debug!("Reading contexts from {}", contexts_path);
+
+    Ok(Contexts::read(archive.by_name(&contexts_path)?, form_groups.as_deref())?)
+}
+
+/// The codes of the registries an archive defines, or none if it doesn't
+/// have the registry definition fixture
+fn read_registry_codes(archive: &mut Archive<impl Read + Seek>) -> Result<Option<BTreeSet<String>>, Box<dyn Error>> {
+    let path = match archive.file_names().find(|p| p.rsplit('/').next() == Some("rdrf_registry.json")) {
+        Some(path) => path.to_string(),
+        None => return Ok(None),
+    };
+    log::debug!("Reading registry definitions from {}", path);
+
+    Ok(Some(registries::codes(archive.by_name(&path)?)?))
+}
+
+/// Check that each export defines the registry its clinical data is read
+/// for, if it has the registry definition fixture, so a mismatched code fails
+/// with the codes each export has rather than finding no clinical data
+fn check_registries(exports: &[(&str, &str)], password: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let mut mismatches = vec![];
+    for (zip_path, registry_code) in exports {
+        match read_registry_codes(&mut Archive::open(zip_path, password)?)? {
+            Some(codes) if !codes.contains(*registry_code) => {
+                let defined = match codes.is_empty() {
+                    true => "no registries".to_string(),
+                    false => codes.iter().join(", "),
+                };
+                mismatches.push(format!("{} defines {}, not {}", zip_path, defined, registry_code));
+            }
+            _ => {}
+        }
+    }
+
+    match mismatches.is_empty() {
+        true => Ok(()),
+        false => Err(format!("The registry code doesn't match the exports' registry definitions: {}", mismatches.join("; ")).into()),
+    }
+}
+
+/// Read the calculated CDEs of the CDE definition fixtures of an archive, if
+/// it has any
+fn read_calculated_cdes(archive: &mut Archive<impl Read + Seek>) -> Result<HashSet<String>, Box<dyn Error>> {
+    let paths = archive.file_names().filter(|p| {
+        let path_split = p.split('/').collect::<Vec<&str>>();
+        matches!(&path_split[..], [_, "registry_data", .., name] if name.contains("commondataelement") && name.ends_with(".json"))
+    }).map(String::from).collect::<Vec<String>>();
+
+    let mut codes = HashSet::new();
+    for path in paths {
+        log::debug!("Reading CDE definitions from {}", path);
+        codes.extend(calculated::from_definitions(archive.by_name(&path)?)?);
+    }
+
+    Ok(codes)
+}
+
+/// Add the forms flagged as questionnaires or abbreviated by the form
+/// definition fixtures of an archive, if it has them
+fn read_skipped_forms(archive: &mut Archive<impl Read + Seek>, skipped: &mut SkippedForms) -> Result<(), Box<dyn Error>> {
+    let paths = archive.file_names().filter(|p| {
+        let path_split = p.split('/').collect::<Vec<&str>>();
+        matches!(&path_split[..], [_, "registry_data", .., name] if name.contains("registryform") && name.ends_with(".json"))
+    }).map(String::from).collect::<Vec<String>>();
+
+    for path in paths {
+        log::debug!("Reading form definitions from {}", path);
+        skipped.read_definitions(archive.by_name(&path)?)?;
+    }
+
+    Ok(())
+}
+
+/// Add the permitted values of the CDE definition and permitted value
+/// fixtures of an archive, if it has them
+fn read_permitted_values(archive: &mut Archive<impl Read + Seek>, permitted: &mut PermittedValues) -> Result<(), Box<dyn Error>> {
+    let paths = |archive: &Archive<_>, fixture: &str| archive.file_names().filter(|p| {
+        let path_split = p.split('/').collect::<Vec<&str>>();
+        matches!(&path_split[..], [_, "registry_data", .., name] if name.contains(fixture) && name.ends_with(".json"))
+    }).map(String::from).collect::<Vec<String>>();
+
+    for path in paths(archive, "commondataelement") {
+        permitted.read_definitions(archive.by_name(&path)?)?;
+    }
+    for path in paths(archive, "cdepermittedvalue") {
+        log::debug!("Reading permitted values from {}", path);
+        permitted.read_values(archive.by_name(&path)?)?;
+    }
+
+    Ok(())
+}
+
+fn diff_exports(old_path: String, new_path: String, read: &ReadOptions, options: &DiffOptions, outputs: &mut [Box<dyn ReportWriter>]) -> Result<(usize, Summary), Box<dyn Error>> {
+    // The exports are hashed for the report's metadata while they're diffed
+    let hashes = {
+        let (old_path, new_path) = (old_path.clone(), new_path.clone());
+        let hash = |path: &str| metadata::sha256_of_zip(path).map_err(|e| e.to_string());
+        thread::spawn(move || Ok::<_, String>((hash(&old_path)?, hash(&new_path)?)))
+    };
+    let exports = [(&old_path, &read.old_code), (&new_path, &read.new_code)].iter()
+        .filter_map(|(path, code)| Some((path.as_str(), code.as_deref()?)))
+        .collect::<Vec<(&str, &str)>>();
+    check_registries(&exports, read.password.as_deref())?;
+    let mut old_archive = Archive::open_prefetching(old_path.as_str(), read.password.as_deref(), read.read_buffer)?;
+    let mut new_archive = Archive::open_prefetching(new_path.as_str(), read.password.as_deref(), read.read_buffer)?;
+
+    if let Some(records) = read.schema_records {
+        check_schema(&mut old_archive, &mut new_archive, read, records)?;
+    }
+
+    let mut calculated = options.calculated.clone();
+    calculated.extend(read_calculated_cdes(&mut old_archive)?);
+    calculated.extend(read_calculated_cdes(&mut new_archive)?);
+    // The old export may give permitted values by code and the new one by label, so both are read
+    let mut permitted_values = PermittedValues::default();
+    read_permitted_values(&mut old_archive, &mut permitted_values)?;
+    read_permitted_values(&mut new_archive, &mut permitted_values)?;
+    let mut skipped_forms = SkippedForms::default();
+    if read.skip_flagged_forms {
+        read_skipped_forms(&mut old_archive, &mut skipped_forms)?;
+        read_skipped_forms(&mut new_archive, &mut skipped_forms)?;
+    }
+    let options = &DiffOptions { calculated, permitted_values: Arc::new(permitted_values), skipped_forms: Arc::new(skipped_forms), ..options.clone() };
+
+    let mut patient_models: Vec<Box<dyn PatientModel>> = vec![];
+    // Old patients are compared as the new patients the map gives, if there is one
+    let patient_map = read.patient_map.as_ref();
+    let old_id = |id: u32| patient_map.map_or(id, |m| m.old_id(id));
+    let old_slice = |slice: PatientSlice| match patient_map {
+        Some(map) => map.old_slice(slice),
+        None => slice,
+    };
+    let new_slice = |slice: PatientSlice| match patient_map {
+        Some(map) => map.new_slice(slice),
+        None => slice,
+    };
+
+    let mut expected_patients = read.expected_patients;
+    if read.models.contains(&Model::Patients) {
+        let old_patients = Patient::read_all(get_patients_reader(&mut old_archive)?)?.into_iter().map(|(id, mut patient)| {
+            patient.id = old_id(id);
+            (patient.id, patient)
+        }).collect::<BTreeMap<u32, Patient>>();
+        let new_patients = Patient::read_all(get_patients_reader(&mut new_archive)?)?;
+        if let Some(map) = patient_map {
+            new_patients.keys().for_each(|&id| map.note_new(id));
+        }
+        expected_patients = expected_patients.or(Some(old_patients.len() as u64));
+        patient_models.push(Box::new(Demographics::new(old_patients, new_patients)));
+    }
+    if read.models.contains(&Model::Consents) {
+        patient_models.push(Box::new(ConsentModel::new(read_consents(&mut old_archive)?.renumbered(old_id), read_consents(&mut new_archive)?)));
+    }
+
+    let mut tally = Tally::new(outputs, read.group_by == GroupBy::Cde, options.weights.clone());
+    tally.detail = read.detail;
+    tally.rendering = Rendering::new(read.flat);
+    tally.prompt_every = read.prompt_every;
+    tally.dedupe = read.dedupe.then(Dedupe::default);
+    tally.renumbering = read.find_renumbered.map(Renumbering::new);
+    tally.slow_patient = read.slow_patient;
+    tally.since = read.since.clone();
+    tally.review = read.review_sample.map(|(size, seed)| Review::new(size, seed));
+    tally.max_differing_patients = read.max_differing_patients;
+    tally.example_limits = read.examples;
+    tally.labels = read.metadata.labels.clone();
+    if let Some(path) = &read.triage {
+        tally.triage = Triage::load(path)?;
+    }
+    if let Some(path) = &read.patch {
+        tally.patch = Some((Patch::load(path)?, path.clone()));
+    }
+    if let Some(path) = &read.cohorts {
+        tally.cohorts = Cohorts::load(path).map_err(|e| format!("Failed reading {}: {}", path, e))?;
+    }
+    let mut total = 0;
+
+    if read.models.contains(&Model::Clinical) {
+        // Clinical data is only paired by its contexts' form groups if both exports give them
+        let (old_contexts, new_contexts) = match (read_contexts(&mut old_archive)?, read_contexts(&mut new_archive)?) {
+            (old, new) if old.is_empty() || new.is_empty() => (Arc::default(), Arc::default()),
+            (old, new) => (Arc::new(old), Arc::new(new)),
+        };
+
+        let (old_zip, new_zip) = (old_path, new_path);
+        // The old export gives the patients by their old ids
+        let old_patients = read.patients.iter().map(|&id| patient_map.and_then(|m| m.old_patient(id)).unwrap_or(id)).collect::<Vec<u32>>();
+        let (old_index, new_index) = match read.patients.is_empty() {
+            true => (None, None),
+            false => (
+                clinical_data_index(&mut old_archive, &old_zip, read.old_format, read.old_entry.as_deref(), read.entry_glob.as_ref())?,
+                clinical_data_index(&mut new_archive, &new_zip, ExportFormat::Django, read.new_entry.as_deref(), read.entry_glob.as_ref())?,
+            ),
+        };
+        let (mut old_map, mut new_map) = (None, None);
+        let (old_entries, new_entries) = (Entries::default(), Entries::default());
+        let (old_path, old_size, old_reader) = get_clinical_data_reader(&old_zip, &mut old_archive, read.old_format, read.old_entry.as_deref(), &mut old_map, read.mmap, read.entry_glob.as_ref(), &old_entries)?;
+        let (new_path, new_size, new_reader) = get_clinical_data_reader(&new_zip, &mut new_archive, ExportFormat::Django, read.new_entry.as_deref(), &mut new_map, read.mmap, read.entry_glob.as_ref(), &new_entries)?;
+
+        // A legacy dump's clinical data isn't where an export's is, nor are
+        // entries given of the one zip
+        let entries_given = read.old_entry.is_some() || read.new_entry.is_some();
+        if read.old_format == ExportFormat::Django && !entries_given && old_path != new_path {
+            log::error!("Registry clinical data paths don't match");
+            log::debug!("Old path: {}", old_path);
+            log::debug!("New path: {}", new_path);
+            panic!()
+        }
+
+        let progress = Progress::new(old_size, new_size, expected_patients, &read.metadata.labels);
+        tally.progress = Some(progress.clone());
+
+        let filter = |registry_code: &Option<String>, entries: &Entries| RecordFilter {
+            registry_code: registry_code.clone(),
+            collections: read.collections.clone(),
+            strict_collections: read.strict_collections,
+            extra_fields: read.extra_fields,
+            entries: entries.clone(),
+        };
+        // Only the old export's names are renamed, to the new export's
+        let old_interner = Interner::with_renames(read.renames.clone()).normalizing_keys(read.normalize_keys);
+        // History is only checked against 'cdes' clinical data if both are read
+        let pairs = Collection::ALL.iter().all(|c| read.collections.contains(c));
+        let (mut old_check, mut new_check) = match read.check_integrity {
+            true => (Some(IntegrityCheck::new(pairs)), Some(IntegrityCheck::new(pairs))),
+            false => (None, None),
+        };
+        let check = |check: &mut Option<IntegrityCheck>, slice: &PatientSlice| if let Some(check) = check {
+            check.add(slice);
+        };
+
+        let (old_errors, new_errors, old_unknown, new_unknown) = match read.pipeline {
+            false => {
+                let old_reader = progress.wrap_read(Side::Old, TimedReader::new(old_reader));
+                let new_reader = progress.wrap_read(Side::New, TimedReader::new(new_reader));
+                let old_records = side_records(&old_zip, old_reader, read.old_format, &old_entries, old_index, old_patients, read.presort, &tally.read_failure)?;
+                let new_records = side_records(&new_zip, new_reader, ExportFormat::Django, &new_entries, new_index, read.patients.clone(), read.presort, &tally.read_failure)?;
+                let old_iter = MigratedRegistry::from_positioned_records(old_records, filter(&read.old_code, &old_entries), read.on_parse_error, old_interner, read.raw_context).with_contexts(old_contexts);
+                let new_iter = MigratedRegistry::from_positioned_records(new_records, filter(&read.new_code, &new_entries), read.on_parse_error, Interner::new().normalizing_keys(read.normalize_keys), read.raw_context).with_contexts(new_contexts);
+                progress.track_records(Side::Old, old_iter.records_read());
+                progress.track_records(Side::New, new_iter.records_read());
+                let handles = (old_iter.parse_errors(), new_iter.parse_errors(), old_iter.unknown_collections(), new_iter.unknown_collections());
+
+                let (old_iter, new_iter) = (old_iter.inspect(|s| check(&mut old_check, s)), new_iter.inspect(|s| check(&mut new_check, s)));
+                total += zip_diff(old_iter.map(old_slice), new_iter.map(new_slice), &patient_models, options, &mut tally)?;
+                handles
+            }
+            true => {
+                // Each reading stage opens its own archive, as a zip entry's reader can't be sent between threads
+                drop((old_reader, new_reader));
+                drop((old_index, new_index));
+                let read_failure = tally.read_failure.clone();
+                let stage = |zip: String, side: Side, format: ExportFormat, entry: Option<String>, patients: Vec<u32>, entries: Entries| {
+                    let (progress, mmap, password, read_buffer, entry_glob, presort) = (progress.clone(), read.mmap, read.password.clone(), read.read_buffer, read.entry_glob.clone(), read.presort);
+                    let failure = read_failure.clone();
+                    move |records: SyncSender<(usize, String)>| -> Result<(), String> {
+                        let mut archive = Archive::open_prefetching(&zip, password.as_deref(), read_buffer).map_err(|e| e.to_string())?;
+                        let index = match patients.is_empty() {
+                            true => None,
+                            false => clinical_data_index(&mut archive, &zip, format, entry.as_deref(), entry_glob.as_ref()).map_err(|e| e.to_string())?,
+                        };
+                        let mut map = None;
+                        let (_, _, reader) = get_clinical_data_reader(&zip, &mut archive, format, entry.as_deref(), &mut map, mmap, entry_glob.as_ref(), &entries).map_err(|e| e.to_string())?;
+                        let reader = progress.wrap_read(side, TimedReader::new(reader));
+                        let positioned = side_records(&zip, reader, format, &entries, index, patients, presort, &failure).map_err(|e| format!("Failed reading records: {}", e))?;
+                        pipeline::send_records(positioned, records);
+                        Ok(())
+                    }
+                };
+
+                thread::scope(|scope| -> Result<_, Box<dyn Error>> {
+                    let mut old_iter = PipelinedRegistry::spawn(scope, stage(old_zip, Side::Old, read.old_format, read.old_entry.clone(), old_patients, old_entries.clone()), filter(&read.old_code, &old_entries), read.on_parse_error, old_interner, read.raw_context, old_contexts);
+                    let mut new_iter = PipelinedRegistry::spawn(scope, stage(new_zip, Side::New, ExportFormat::Django, read.new_entry.clone(), read.patients.clone(), new_entries.clone()), filter(&read.new_code, &new_entries), read.on_parse_error, Interner::new().normalizing_keys(read.normalize_keys), read.raw_context, new_contexts);
+                    progress.track_records(Side::Old, old_iter.records_read.clone());
+                    progress.track_records(Side::New, new_iter.records_read.clone());
+                    let handles = (old_iter.parse_errors.clone(), new_iter.parse_errors.clone(), old_iter.unknown_collections.clone(), new_iter.unknown_collections.clone());
+
+                    let old_slices = (&mut old_iter).inspect(|s| check(&mut old_check, s)).map(old_slice);
+                    let new_slices = (&mut new_iter).inspect(|s| check(&mut new_check, s)).map(new_slice);
+                    total += zip_diff(old_slices, new_slices, &patient_models, options, &mut tally)?;
+                    old_iter.finish()?;
+                    new_iter.finish()?;
+                    Ok(handles)
+                })?
+            }
+        };
+
+        let labels = &read.metadata.labels;
+        if read.on_parse_error.collects() {
+            report_parse_errors(&labels.old, &old_errors);
+            report_parse_errors(&labels.new, &new_errors);
+        }
+        if let OnParseError::Compare = read.on_parse_error {
+            Unparsed::compare(&old_errors.lock().unwrap(), &new_errors.lock().unwrap()).print(labels);
+        }
+        if read.strict_collections {
+            report_unknown_collections(&labels.old, &old_unknown);
+            report_unknown_collections(&labels.new, &new_unknown);
+        }
+        if let (Some(old_check), Some(new_check)) = (old_check, new_check) {
+            report_integrity_violations(&labels.old, &old_check.finish());
+            report_integrity_violations(&labels.new, &new_check.finish());
+        }
+        if read.since.is_some() {
+            println!("Reused the differences of {} unchanged slices", tally.reused);
+        }
+    }
+
+    total += diff_remaining_patients(&patient_models, &read.patients, options, &mut tally)?;
+    read.patients.iter().filter(|id| !tally.patients.contains(id))
+        .for_each(|id| eprintln!("Warning: patient {} isn't in either export, so wasn't compared", id));
+
+    if let Some(map) = patient_map {
+        let (old, new) = map.unmapped();
+        report_unmapped_patients(&read.metadata.labels.old, &old);
+        report_unmapped_patients(&read.metadata.labels.new, &new);
+    }
+
+    let mut summary = tally.summary();
+    if let Some(path) = &read.clean_out {
+        let clean = tally.patients.difference(&tally.differing_patients).sorted().collect::<Vec<&u32>>();
+        fs::write(path, clean.iter().map(|id| format!("{}\n", id)).collect::<String>()).map_err(|e| format!("Failed writing {}: {}", path, e))?;
+        println!("Wrote the ids of the {} patients without differences to {}", clean.len(), path);
+    }
+    let (old_zip, new_zip) = hashes.join().map_err(|_| "Hashing the exports panicked")??;
+    let manifest = read.manifest.clone().with_exports(vec![old_zip.clone(), new_zip.clone()]);
+    manifest.write(&read.manifest_path)?;
+    summary.metadata = RunMetadata {
+        old_zip,
+        new_zip,
+        reproducibility_hash: Some(manifest.reproducibility_hash),
+        comparison_hash: Some(manifest.comparison_hash),
+        finished: metadata::now(),
+        ..read.metadata.clone()
+    };
+    summary.suppressions = options.ignore.uses();
+    summary.skipped_forms = options.skipped_forms.counts();
+    summary.retired = options.retired.counts();
+    summary.suppressions.iter().filter(|s| s.expired && s.matches > 0).for_each(|s| {
+        eprintln!("Warning: the suppression of {} expired but still left it out {} times ({})", s.suppression.code, s.matches, s.audit());
+    });
+    if let Some(review) = &tally.review {
+        review.print(&tally.differing_patients, &read.metadata.labels);
+    }
+    outputs.iter_mut().try_for_each(|o| o.finish(&summary))?;
+
+    Ok((total, summary))
+}
+
+/// Compare the aggregates of each CDE of the exports, reading them at once
+fn aggregate_check(old_zip: &str, new_zip: &str, read: &ReadOptions, options: &DiffOptions, thresholds: &Thresholds) -> Result<(), Box<dyn Error>> {
+    let aggregates = |zip: &str, format: ExportFormat, entry: Option<&str>, registry_code: &Option<String>, interner: Interner| -> Result<(Aggregates, ParseErrors), String> {
+        let mut archive = Archive::open_prefetching(zip, read.password.as_deref(), read.read_buffer).map_err(|e| e.to_string())?;
+        let mut map = None;
+        let (_, _, reader) = get_clinical_data_reader(zip, &mut archive, format, entry, &mut map, read.mmap, read.entry_glob.as_ref(), &Entries::default()).map_err(|e| e.to_string())?;
+        let filter = RecordFilter {
+            registry_code: registry_code.clone(),
+            collections: read.collections.clone(),
+            strict_collections: read.strict_collections,
+            ..RecordFilter::default()
+        };
+        let registry = MigratedRegistry::from_records(format.records(reader), filter, read.on_parse_error, interner, false);
+        let errors = registry.parse_errors();
+
+        Ok((Aggregates::from(registry, options), errors))
+    };
+
+    // Only the old export's names are renamed, to the new export's
+    let (old, new) = thread::scope(|scope| {
+        let old = scope.spawn(|| aggregates(old_zip, read.old_format, read.old_entry.as_deref(), &read.old_code, Interner::with_renames(read.renames.clone())));
+        let new = aggregates(new_zip, ExportFormat::Django, read.new_entry.as_deref(), &read.new_code, Interner::new());
+        (old.join().map_err(|_| "Reading the old export panicked".to_string()).and_then(|r| r), new)
+    });
+    let ((old, old_errors), (new, new_errors)) = (old?, new?);
+
+    let labels = &read.metadata.labels;
+    if read.on_parse_error.collects() {
+        report_parse_errors(&labels.old, &old_errors);
+        report_parse_errors(&labels.new, &new_errors);
+    }
+
+    let divergences = aggregate::compare(&old, &new, thresholds);
+    aggregate::print(&old, &new, &divergences, labels);
+
+    Ok(())
+}
+
+/// Compare entries of the exports as plain JSON, without reading them as clinical data
+fn raw_diff(old_zip: &str, new_zip: &str, entries: &[String], read: &ReadOptions, json_diff: &JsonDiff) -> Result<(), Box<dyn Error>> {
+    let value = |zip: &str, entry: &str| -> Result<serde_json::Value, Box<dyn Error>> {
+        let mut archive = Archive::open(zip, read.password.as_deref())?;
+        let (_, reader) = get_format_reader(&mut archive, ExportFormat::Django, Some(entry))?;
+        serde_json::from_reader(BufReader::new(reader)).map_err(|e| format!("Failed parsing {} in {}: {}", entry, zip, e).into())
+    };
+
+    for entry in entries {
+        let (old, new) = (value(old_zip, entry)?, value(new_zip, entry)?);
+        let diffs = json_diff.diff("", Some(&old), Some(&new));
+        json::print(entry, &diffs, read.detail != Some(Detail::Raw));
+    }
+
+    Ok(())
+}
+
+fn report_parse_errors(side: &str, errors: &ParseErrors) {
+    let errors = errors.lock().unwrap();
+    println!("Skipped {} unparseable records in {}", errors.len(), side);
+    errors.iter().for_each(|e| println!("  {}", e));
+}
+
+fn report_unmapped_patients(side: &str, ids: &BTreeSet<u32>) {
+    if !ids.is_empty() {
+        println!("{} patients in {} aren't in the patient map: {}", ids.len(), side, ids.iter().join(", "));
+    }
+}
+
+fn report_integrity_violations(side: &str, violations: &[IntegrityViolation]) {
+    println!("Found {} integrity violations in {}", violations.len(), side);
+    violations.iter().for_each(|v| println!("  {}", v));
+}
+
+fn report_unknown_collections(side: &str, counts: &CollectionCounts) {
+    let counts = counts.lock().unwrap();
+    println!("Skipped {} records of unknown collections in {}", counts.values().sum::<usize>(), side);
+    counts.iter().for_each(|(collection, count)| println!("  {}: {}", collection, count));
+}
+
+fn histogram_of(zip_path: &str, registry_code: &str, cdes: &[&str], on_parse_error: OnParseError, password: Option<&str>) -> Result<Histogram, Box<dyn Error>> {
+    let mut archive = Archive::open(zip_path, password)?;
+    let (_, reader) = get_zip_reader(&mut archive)?;
+
+    let histogram = Histogram::from(reader, registry_code, cdes, on_parse_error);
+    if on_parse_error.collects() {
+        report_parse_errors(zip_path, &histogram.parse_errors);
+    }
+
+    Ok(histogram)
+}
+
+pub fn histogram_clinical_data(zip_path: &str, registry_code: &str, cdes: &[&str], new_zip_path: Option<&str>, on_parse_error: OnParseError, password: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let exports = std::iter::once(zip_path).chain(new_zip_path).map(|path| (path, registry_code)).collect::<Vec<(&str, &str)>>();
+    check_registries(&exports, password)?;
+    let histogram = histogram_of(zip_path, registry_code, cdes, on_parse_error, password)?;
+    let comp = new_zip_path.map(|path| histogram_of(path, registry_code, cdes, on_parse_error, password)).transpose()?;
+
+    histogram.print(comp.as_ref());
+
+    Ok(())
+}
+
+pub fn infer_structure(zip_path: &str, registry_code: &str, password: Option<&str>) -> Result<(), Box<dyn Error>> {
+    check_registries(&[(zip_path, registry_code)], password)?;
+    let mut archive = Archive::open(zip_path, password)?;
+    let (path, reader) = get_zip_reader(&mut archive)?;
+
+    println!("Structure of the clinical data of {} in {}", registry_code, path);
+    Structure::from(reader, registry_code)?.print();
+
+    Ok(())
+}
+
+/// Sample an export, printing what was found and returning the entries of
+/// the archive alongside the sample
+fn sample_export(zip_path: &str, registry_code: &str, records: usize, password: Option<&str>) -> Result<(BTreeSet<String>, Sample), Box<dyn Error>> {
+    let mut archive = Archive::open(zip_path, password)?;
+    let entries = archive.file_names()
+        .filter(|e| !e.ends_with('/'))
+        .map(String::from)
+        .collect::<BTreeSet<String>>();
+    let (path, reader) = get_zip_reader(&mut archive)?;
+    let size = reader.size();
+
+    let sample = Sample::from(reader, registry_code, records);
+    println!("{}: {} ({} bytes), ~{} records", zip_path, path, size, sample.estimate_records(size));
+    sample.parse_errors.iter().for_each(|e| println!("  {}", e));
+
+    Ok((entries, sample))
+}
+
+pub fn check_exports(old_zip: &str, new_zip: &str, registry_code: &str, records: usize, password: Option<&str>) -> Result<(), Box<dyn Error>> {
+    check_registries(&[(old_zip, registry_code), (new_zip, registry_code)], password)?;
+    let (old_entries, old_sample) = sample_export(old_zip, registry_code, records, password)?;
+    let (new_entries, new_sample) = sample_export(new_zip, registry_code, records, password)?;
+
+    old_entries.difference(&new_entries).for_each(|e| println!("Entry only in old: {}", e));
+    new_entries.difference(&old_entries).for_each(|e| println!("Entry only in new: {}", e));
+
+    old_sample.definitions.difference(&new_sample.definitions).for_each(|d| println!("[{}] CDE only in old: {}", registry_code, d));
+    new_sample.definitions.difference(&old_sample.definitions).for_each(|d| println!("[{}] CDE only in new: {}", registry_code, d));
+
+    if old_sample.definitions.is_empty() && new_sample.definitions.is_empty() {
+        println!("[{}] No clinical data in the first {} records", registry_code, records);
+    }
+
+    let failed = old_sample.parse_errors.len() + new_sample.parse_errors.len();
+    match failed {
+        0 => Ok(()),
+        _ => Err(format!("{} of the sampled records failed to parse", failed).into())
+    }
+}
+
+pub fn validate_clinical_data(zip_path: &str, password: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let mut archive = Archive::open(zip_path, password)?;
+    let (_, reader) = get_zip_reader(&mut archive)?;
+
+    let registry = MigratedRegistry::from(reader, RecordFilter::default(), OnParseError::Collect, Interner::new(), false);
+    let errors = registry.parse_errors();
+    let patients = registry.count();
+
+    println!("Read {} patient slices", patients);
+    report_parse_errors(zip_path, &errors);
+
+    let failed = errors.lock().unwrap().len();
+    match failed {
+        0 => Ok(()),
+        _ => Err(format!("{} records of {} failed to parse", failed, zip_path).into())
+    }
+}
+
+pub fn inspect_clinical_data(zip_path: &str, records: usize, password: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let mut archive = Archive::open(zip_path, password)?;
+    let (path, reader) = get_zip_reader(&mut archive)?;
+
+    println!("Structure of the first {} records of {}", records, path);
+    Schema::scan(reader, ExportFormat::Django, records).print();
+
+    Ok(())
+}
+
+pub fn index_clinical_data(zip_path: &str, registry_code: &str, password: Option<&str>) -> Result<(), Box<dyn Error>> {
+    check_registries(&[(zip_path, registry_code)], password)?;
+    let mut archive = Archive::open(zip_path, password)?;
+    let (entry, reader) = get_zip_reader(&mut archive)?;
+
+    let (size, crc32) = (reader.size(), reader.crc32());
+    let index = Index::build(&entry, size, crc32, registry_code, reader)?;
+    let path = Index::path(zip_path);
+    index.save(&path)?;
+
+    println!("Indexed {} records of {} patients of {} in {}", index.patients.values().map(Vec::len).sum::<usize>(), index.patients.len(), entry, path);
+    if index.unparsed > 0 {
+        println!("{} records didn't parse, so aren't indexed", index.unparsed);
+    }
+
+    Ok(())
+}
+
+pub fn bench_clinical_data(zip_path: &str, registry_code: &str, iterations: usize, password: Option<&str>) -> Result<(), Box<dyn Error>> {
+    check_registries(&[(zip_path, registry_code)], password)?;
+    let mut archive = Archive::open(zip_path, password)?;
+    let (path, mut reader) = get_zip_reader(&mut archive)?;
+
+    let mut data = Vec::with_capacity(reader.size() as usize);
+    reader.read_to_end(&mut data)?;
+
+    println!("Benchmarking {} of {}", path, zip_path);
+    bench::run(&data, registry_code, iterations.max(1));
+
+    Ok(())
+}
+
+pub fn generate_fixture(args: GenFixtureArgs) -> Result<(), Box<dyn Error>> {
+    let spec = FixtureSpec {
+        registry_code: args.registry_code,
+        patients: args.patients,
+        forms: args.forms,
+        sections: args.sections,
+        multi_sections: args.multi_sections,
+        cdes: args.cdes,
+        differences: args.differences,
+        seed: args.seed,
+    };
+    let fixture = spec.generate()?;
+    generate::write(&args.dir, &fixture)?;
+
+    println!("Wrote {} patients with {} injected differences to {}", spec.patients, fixture.injected.len(), args.dir);
+
+    Ok(())
+}
+
+pub fn selftest_export(args: SelftestArgs, password: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let settings = Config::load(args.config.as_deref())?.settings(Some(&args.registry_code));
+    let mut options = DiffOptions::default();
+    if let Some(tolerance) = settings.tolerance {
+        options.tolerance = tolerance;
+    }
+    options.formatting_precision = settings.formatting_precision;
+    options.number_locale = settings.number_locale;
+    options.ignore = Arc::new(Suppressions::new(settings.ignore.unwrap_or_default().into_iter().map(Suppression::from))?);
+    options.expect = Arc::new(settings.expect.unwrap_or_default().into_iter()
+        .map(|(code, spec)| Ok((code.clone(), Transform::compile(&code, spec)?)))
+        .collect::<Result<HashMap<String, Transform>, Box<dyn Error>>>()?);
+    options.plugin = plugins::for_registry(&args.registry_code);
+
+    check_registries(&[(&args.zip, &args.registry_code)], password)?;
+    let mut archive = Archive::open(&args.zip, password)?;
+    let (_, reader) = get_zip_reader(&mut archive)?;
+    let records = MigratedRegistry::read_array_file_to_records(reader).collect::<Vec<String>>();
+
+    let (mutated, injected) = selftest::mutate(&records, &args.registry_code, args.mutations, options.tolerance, args.seed);
+    let undetected = selftest::undetected(records, mutated, &injected, &args.registry_code, &options);
+
+    println!("Injected {} mutations, {} detected", injected.len(), injected.len() - undetected.len());
+    undetected.iter().for_each(|m| println!("  Not detected: {}", m));
+
+    match (injected.len(), undetected.len()) {
+        (0, _) => Err(format!("No clinical data of {} to mutate", args.registry_code).into()),
+        (_, 0) => Ok(()),
+        (_, missed) => Err(format!("{} injected mutations weren't detected", missed).into()),
+    }
+}
+
+pub fn batch_command(args: BatchArgs, password: Option<&str>, debug: bool) -> Result<(), Box<dyn Error>> {
+    let batch = Batch::load(&args.batch)?;
+    let concurrency = args.concurrency.or(batch.concurrency).unwrap_or(batch::DEFAULT_CONCURRENCY);
+    let dir = Path::new(&args.out_dir);
+
+    let outcomes = batch.run(dir, concurrency, password, debug)?;
+    batch::summarize(&outcomes, dir)
+}
+
+pub fn annotate_difference(args: AnnotateArgs) -> Result<(), Box<dyn Error>> {
+    if !triage::report_has(&args.report, &args.id)? {
+        return Err(format!("No difference {} in {}", args.id, args.report).into());
+    }
+
+    let mut triage = Triage::load(&args.triage)?;
+    triage.annotate(&args.id, Annotation { state: args.state, note: args.note });
+    triage.save(&args.triage)?;
+    println!("Marked {} as {} in {}", args.id, args.state, args.triage);
+
+    Ok(())
+}
+
+pub fn compare_reports(args: CompareReportsArgs) -> Result<(), Box<dyn Error>> {
+    // Which export builds the reports are of, if they were written with their run's metadata
+    for (side, path) in [("Old", &args.old_report), ("New", &args.new_report)] {
+        if let Some(m) = triage::report_metadata(path)? {
+            let sha = m.new_zip.sha256.get(..12).unwrap_or(&m.new_zip.sha256);
+            println!("{} report: finished {}, new export {} ({})", side, m.finished, m.new_zip.path, sha);
+        }
+    }
+
+    let diff = ReportDiff::from(&args.old_report, &args.new_report)?;
+    diff.print(args.persisting);
+
+    Ok(())
+}
+
+pub fn explain_difference(args: ExplainArgs, password: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let record = triage::report_record(&args.report, &args.id)?
+        .ok_or_else(|| format!("No difference {} in {}", args.id, args.report))?;
+    let settings = Config::load(args.config.as_deref())?.settings(args.registry.as_deref());
+
+    let mut rule_settings = vec![];
+    match record.kind {
+        DifferenceKind::Equality => rule_settings.push(format!("Tolerance: {}, unless the diff was given --tolerance",
+            settings.tolerance.unwrap_or(DiffOptions::default().tolerance))),
+        DifferenceKind::Formatting => rule_settings.push(format!("Formatting precision: {}, unless the diff was given --formatting-precision",
+            settings.formatting_precision.map_or("(not in the config)".to_string(), |p| format!("{} decimal places", p)))),
+        DifferenceKind::Unexpected => {
+            let spec = record.location.cde.as_ref().and_then(|c| settings.expect.as_ref()?.get(c));
+            rule_settings.push(format!("Expected change: {}", spec.map_or("(not in the config)".to_string(), |s| format!("{:?}", s))));
+        }
+        _ => {}
+    }
+    // The exports are called what the diff called them
+    let labels = triage::report_metadata(&args.report)?.map(|m| m.labels).unwrap_or_default();
+    explain::print_difference(&record, &rule_settings, &labels);
+
+    if !explain::is_clinical(&record) {
+        println!();
+        println!("Only the clinical data a difference is in is read back from the exports");
+        return Ok(());
+    }
+
+    let old_zip = args.old_zip.or(settings.old_zip)
+        .ok_or("No old zip given, either as an argument or in the config")?;
+    let new_zip = args.new_zip.or(settings.new_zip)
+        .ok_or("No new zip given, either as an argument or in the config")?;
+    let renames = Arc::new(args.renames.as_deref().map(Renames::load).transpose()?.unwrap_or_default());
+    let patient_map = args.patient_map.as_deref()
+        .map(|path| PatientMap::load(path).map_err(|e| format!("Failed reading {}: {}", path, e)))
+        .transpose()?;
+    let plugin = args.registry.as_deref().and_then(plugins::for_registry);
+
+    let mut old_archive = Archive::open(&old_zip, password)?;
+    let mut new_archive = Archive::open(&new_zip, password)?;
+    let (old_contexts, new_contexts) = match (read_contexts(&mut old_archive)?, read_contexts(&mut new_archive)?) {
+        (old, new) if old.is_empty() || new.is_empty() => (Arc::default(), Arc::default()),
+        (old, new) => (Arc::new(old), Arc::new(new)),
+    };
+
+    // Each export is read up to the patient's slice, keeping the raw JSON of its sections, or
+    // only the patient's records if it's indexed
+    let patient = record.location.patient;
+    let slice = |archive: &mut Archive<_>, zip: &str, interner: Interner, contexts: Arc<Contexts>, old: bool| -> Result<Option<PatientSlice>, Box<dyn Error>> {
+        let (entry, reader) = get_zip_reader(archive)?;
+        let registry = match Index::load(zip, &entry, reader.size(), reader.crc32())? {
+            Some(index) => {
+                // The old export is indexed by the patient's old id
+                let indexed = match (old, &patient_map) {
+                    (true, Some(map)) => map.old_patient(patient).unwrap_or(patient),
+                    _ => patient,
+                };
+                MigratedRegistry::from_positioned_records(index.records(reader, &[indexed])?.into_iter(), RecordFilter::default(), OnParseError::Skip, interner, true)
+            }
+            None => MigratedRegistry::from(reader, RecordFilter::default(), OnParseError::Skip, interner, true),
+        }.with_contexts(contexts);
+        Ok(registry
+            .map(|s| match (old, &patient_map) {
+                (true, Some(map)) => map.old_slice(s),
+                _ => s,
+            })
+            .find(|s| s.patient == patient))
+    };
+    let old_slice = slice(&mut old_archive, &old_zip, Interner::with_renames(renames), old_contexts, true)?;
+    let new_slice = slice(&mut new_archive, &new_zip, Interner::new(), new_contexts, false)?;
+
+    let (old_datum, new_datum) = explain::paired_data(&record, old_slice.as_ref(), new_slice.as_ref(), &plugin);
+    explain::print_datum(&labels.old_heading(), &record, old_datum);
+    explain::print_datum(&labels.new_heading(), &record, new_datum);
+
+    Ok(())
+}
+
+/// The options a diff runs with, after the config's were merged, for its
+/// manifest, sets sorted so the same options always hash the same
+fn effective_options(read: &ReadOptions, options: &DiffOptions) -> BTreeMap<&'static str, String> {
+    let options = vec![
+        ("tolerance", options.tolerance.to_string()),
+        ("formatting_precision", format!("{:?}", options.formatting_precision)),
+        ("number_locale", format!("{:?}", options.number_locale)),
+        ("json_diff", format!("{:?}", options.json_diff)),
+        ("ignore", options.ignore.uses().iter().map(|s| s.suppression.code.as_str()).join(", ")),
+        ("timestamps", options.timestamps.to_string()),
+        ("history_sequence", options.history_sequence.to_string()),
+        ("history_metadata", options.history_metadata.to_string()),
+        ("history_time_tolerance", options.history_time_tolerance.to_string()),
+        ("normalize_text", options.normalize_text.to_string()),
+        ("collation", format!("{:?}", options.collation)),
+        ("inner_parallelism", format!("{:?}", options.form_pool.as_ref().map(|p| p.current_num_threads()))),
+        ("redact", options.redact.to_string()),
+        ("consent_time_tolerance", options.consent_time_tolerance.to_string()),
+        ("plugin", format!("{:?}", options.plugin.as_ref().map(|p| p.registry_code()))),
+        ("calculated", options.calculated.iter().sorted().join(", ")),
+        ("skip_calculated", options.skip_calculated.to_string()),
+        ("expect", options.expect.keys().sorted().join(", ")),
+        ("weights", options.weights.iter().sorted_by(|(a, _), (b, _)| a.cmp(b)).map(|(code, w)| format!("{}={}", code, w)).join(", ")),
+        ("row_order", options.row_order.to_string()),
+        ("row_keys", options.row_keys.iter().sorted().map(|(section, cde)| format!("{}={}", section, cde)).join(", ")),
+        ("excluded_kinds", options.excluded_kinds.iter().sorted().join(", ")),
+        ("retired", options.retired.counts().keys().join(", ")),
+    ];
+    let read = vec![
+        ("models", format!("{:?}", read.models)),
+        ("mmap", read.mmap.to_string()),
+        ("read_buffer", format!("{:?}", read.read_buffer)),
+        ("pipeline", read.pipeline.to_string()),
+        ("old_format", format!("{:?}", read.old_format)),
+        ("presort", format!("{:?}", read.presort)),
+        ("patients", read.patients.iter().join(", ")),
+        ("entry_glob", format!("{:?}", read.entry_glob.as_ref().map(EntryGlob::as_str))),
+        ("old_entry", format!("{:?}", read.old_entry)),
+        ("new_entry", format!("{:?}", read.new_entry)),
+        ("collections", format!("{:?}", read.collections)),
+        ("strict_collections", read.strict_collections.to_string()),
+        ("check_integrity", read.check_integrity.to_string()),
+        ("extra_fields", read.extra_fields.to_string()),
+        ("skip_flagged_forms", read.skip_flagged_forms.to_string()),
+        ("on_parse_error", format!("{:?}", read.on_parse_error)),
+        ("schema_records", format!("{:?}", read.schema_records)),
+        ("group_by", format!("{:?}", read.group_by)),
+        ("flat", read.flat.to_string()),
+        ("prompt_every", format!("{:?}", read.prompt_every)),
+        ("dedupe", read.dedupe.to_string()),
+        ("clean_out", format!("{:?}", read.clean_out)),
+        ("find_renumbered", format!("{:?}", read.find_renumbered)),
+        ("slow_patient", format!("{:?}", read.slow_patient)),
+        ("review_sample", format!("{:?}", read.review_sample)),
+        ("examples", format!("{:?}", read.examples)),
+        ("raw_context", read.raw_context.to_string()),
+        ("detail", format!("{:?}", read.detail)),
+        ("max_differing_patients", format!("{:?}", read.max_differing_patients)),
+        ("old_code", format!("{:?}", read.old_code)),
+        ("new_code", format!("{:?}", read.new_code)),
+        ("normalize_keys", read.normalize_keys.to_string()),
+        ("patch", format!("{:?}", read.patch)),
+        ("expected_patients", format!("{:?}", read.expected_patients)),
+        ("registry", format!("{:?}", read.metadata.registry)),
+        ("old_label", read.metadata.labels.old.clone()),
+        ("new_label", read.metadata.labels.new.clone()),
+    ];
+
+    options.into_iter().chain(read).collect()
+}
+
+/// Diff the exports the args give, following the diff with observers besides
+/// the outputs the args give
+pub fn diff(args: DiffArgs, password: Option<&str>, observers: Vec<Box<dyn DiffObserver>>) -> Result<(), Box<dyn Error>> {
+    let DiffArgs { inputs, comparison, reporting } = args;
+    let config = Config::load(inputs.config.as_deref())?;
+    let settings = config.settings(inputs.registry.as_deref());
+
+    let old_zip = inputs.old_zip.or(settings.old_zip)
+        .ok_or("No old zip given, either as an argument or in the config")?;
+    let new_zip = inputs.new_zip.or(settings.new_zip)
+        .ok_or("No new zip given, either as an argument or in the config")?;
+
+    let mut options = DiffOptions::default();
+    if let Some(tolerance) = comparison.tolerance.or(settings.tolerance) {
+        options.tolerance = tolerance;
+    }
+    options.formatting_precision = comparison.formatting_precision.or(settings.formatting_precision);
+    options.number_locale = comparison.number_locale.or(settings.number_locale);
+    options.timestamps = comparison.timestamps;
+    options.history_sequence = comparison.history_sequence;
+    options.history_metadata = comparison.history_metadata;
+    options.history_time_tolerance = comparison.history_time_tolerance;
+    options.normalize_text = comparison.normalize_text;
+    options.collation = comparison.collation;
+    options.redact = !comparison.show_identifying;
+    options.consent_time_tolerance = comparison.consent_time_tolerance;
+    if let Some(threads) = comparison.inner_parallelism {
+        options.form_pool = Some(Arc::new(ThreadPoolBuilder::new().num_threads(threads).build()?));
+    }
+    options.ignore = Arc::new(Suppressions::new(match comparison.ignore.is_empty() {
+        true => settings.ignore.unwrap_or_default().into_iter().map(Suppression::from).collect(),
+        false => comparison.ignore.into_iter().map(Suppression::of).collect::<Vec<Suppression>>(),
+    })?);
+    options.expect = Arc::new(settings.expect.unwrap_or_default().into_iter()
+        .map(|(code, spec)| Ok((code.clone(), Transform::compile(&code, spec)?)))
+        .collect::<Result<HashMap<String, Transform>, Box<dyn Error>>>()?);
+    options.weights = settings.weights.unwrap_or_default();
+    options.retired = Arc::new(RetiredCdes::new(settings.retired.unwrap_or_default()));
+    if let Some(path) = &comparison.calculated_cdes {
+        options.calculated = calculated::from_file(path).map_err(|e| format!("Failed reading {}: {}", path, e))?;
+    }
+    options.skip_calculated = comparison.skip_calculated;
+    options.row_order = comparison.row_order;
+    options.row_keys = settings.row_keys.unwrap_or_default();
+    for row_key in &comparison.row_key {
+        let (section, cde) = row_key.split_once('=').ok_or_else(|| format!("--row-key {} should be of the form SECTION=CDE", row_key))?;
+        options.row_keys.insert(section.to_string(), cde.to_string());
+    }
+    let only_types = comparison.only_types;
+    options.excluded_kinds = match only_types.is_empty() {
+        true => comparison.exclude_types.into_iter().collect(),
+        false => DifferenceKind::value_variants().iter().filter(|k| !only_types.contains(k)).copied().collect(),
+    };
+    options.json_diff = Arc::new(JsonDiff::new(comparison.raw_arrays, comparison.raw_key, &comparison.raw_ignore));
+    options.plugin = inputs.registry.as_deref().and_then(plugins::for_registry);
+    if let Some(plugin) = &options.plugin {
+        log::debug!("Using the {} registry plugin", plugin.registry_code());
+    }
+
+    if reporting.patch.is_some() && reporting.group_by == GroupBy::Cde {
+        return Err("--patch asks about each patient's differences as they're shown, so needs --group-by patient".into());
+    }
+
+    let mut read = ReadOptions {
+        models: comparison.models,
+        mmap: inputs.mmap,
+        read_buffer: inputs.read_buffer_mb.map(|mb| mb << 20),
+        pipeline: !inputs.sequential,
+        old_format: inputs.old_format,
+        entry_glob: inputs.entry_glob.as_deref().map(EntryGlob::new).transpose()?,
+        presort: inputs.presort.then_some((inputs.presort_run_mb as usize) << 20),
+        patients: comparison.patient.iter().copied().unique().collect(),
+        old_entry: inputs.old_entry,
+        new_entry: inputs.new_entry,
+        password: password.map(String::from),
+        collections: match comparison.cdes_only {
+            true => vec![Collection::Cdes],
+            false => comparison.collections,
+        },
+        strict_collections: comparison.strict_collections,
+        check_integrity: comparison.check_integrity,
+        extra_fields: comparison.extra_fields,
+        skip_flagged_forms: !comparison.compare_skipped_forms,
+        on_parse_error: comparison.on_parse_error,
+        schema_records: match reporting.schema_check {
+            true => Some(reporting.schema_records),
+            false => None
+        },
+        group_by: reporting.group_by,
+        flat: reporting.flat,
+        prompt_every: (reporting.prompt_every, reporting.prompt_diffs),
+        dedupe: reporting.dedupe,
+        review_sample: reporting.review_sample.map(|size| (size, reporting.seed)),
+        examples: reporting.examples.map(|examples| (examples, reporting.examples_min_patients)),
+        raw_context: reporting.raw_context || reporting.detail == Some(Detail::Raw),
+        detail: reporting.detail,
+        max_differing_patients: match comparison.fail_fast {
+            true => Some(1),
+            false => comparison.max_differing_patients,
+        },
+        old_code: inputs.old_code,
+        new_code: inputs.new_code,
+        cohorts: reporting.cohorts,
+        patch: reporting.patch,
+        clean_out: reporting.clean_out,
+        find_renumbered: reporting.find_renumbered.then_some(reporting.renumbered_similarity),
+        slow_patient: reporting.slow_patient_ms.map(Duration::from_millis),
+        normalize_keys: comparison.normalize_keys || settings.normalize_keys.unwrap_or(false),
+        expected_patients: reporting.expected_patients,
+        triage: reporting.triage.or_else(|| Some(triage::DEFAULT_TRIAGE.to_string()).filter(|p| Path::new(p).exists())),
+        renames: Arc::new(inputs.renames.as_deref().map(Renames::load).transpose()?.unwrap_or_default()),
+        patient_map: inputs.patient_map.as_deref()
+            .map(|path| PatientMap::load(path).map_err(|e| format!("Failed reading {}: {}", path, e)))
+            .transpose()?,
+        metadata: RunMetadata {
+            labels: Labels {
+                old: reporting.old_label.or(settings.old_label).unwrap_or_else(|| Labels::default().old),
+                new: reporting.new_label.or(settings.new_label).unwrap_or_else(|| Labels::default().new),
+            },
+            ..RunMetadata::start(inputs.registry.clone(), config.sha256.clone())
+        },
+        manifest: RunManifest::default(),
+        manifest_path: reporting.run_manifest,
+        since: None,
+    };
+    let files = [
+        ("renames", &inputs.renames),
+        ("patient_map", &inputs.patient_map),
+        ("calculated_cdes", &comparison.calculated_cdes),
+        ("triage", &read.triage),
+        ("cohorts", &read.cohorts),
+        ("since", &comparison.since),
+    ].iter()
+        .filter_map(|(option, path)| Some((*option, manifest::input_file(path.as_deref()?))))
+        .map(|(option, file)| Ok((option, file?)))
+        .chain(config.path.iter().map(|path| Ok(("config", InputFile { path: path.clone(), sha256: config.sha256.clone().unwrap_or_default() }))))
+        .collect::<Result<BTreeMap<&str, InputFile>, Box<dyn Error>>>()?;
+    read.manifest = RunManifest::new(effective_options(&read, &options), files);
+    // Loaded before the outputs are created, as they can replace the report
+    read.since = comparison.since.as_deref()
+        .map(|path| Since::load(path, &read.manifest.comparison_hash).map_err(|e| format!("Failed reading {}: {}", path, e)))
+        .transpose()?
+        .map(Arc::new);
+
+    if comparison.aggregate_check {
+        let thresholds = Thresholds {
+            null_rate: comparison.max_null_rate_change,
+            distinct: comparison.max_distinct_change,
+            mean: comparison.max_mean_change,
+            tolerance: options.tolerance,
+        };
+        return aggregate_check(&old_zip, &new_zip, &read, &options, &thresholds);
+    }
+    if !comparison.raw_diff.is_empty() {
+        return raw_diff(&old_zip, &new_zip, &comparison.raw_diff, &read, &options.json_diff);
+    }
+
+    let mut output_specs = match reporting.output.is_empty() {
+        true => settings.output.unwrap_or_default(),
+        false => reporting.output
+    };
+    if let Some(path) = reporting.summary_out {
+        output_specs.push(format!("summary:{}", path));
+    }
+    let mut outputs = output_specs.iter()
+        .map(|spec| output::from_spec(spec, &read.metadata.labels))
+        .collect::<Result<Vec<Box<dyn ReportWriter>>, Box<dyn Error>>>()?;
+    if let Some(template) = &reporting.template {
+        let path = reporting.template_out.clone().unwrap_or_else(|| match template.rsplit_once('.') {
+            Some((stem, extension)) if !extension.contains('/') && !stem.is_empty() && !stem.ends_with('/') => stem.to_string(),
+            _ => format!("{}.out", template),
+        });
+        outputs.push(Box::new(TemplateWriter::create(template, &path, read.metadata.labels.clone())?));
+    }
+    if read.group_by == GroupBy::Cde {
+        outputs.push(Box::<CdeGroupWriter>::default());
+    }
+    outputs.extend(observers.into_iter().map(|o| Box::new(Observed(o)) as Box<dyn ReportWriter>));
+    let gates = reporting.gate.as_deref().map(Gates::load).transpose()?;
+
+    if reporting.profile {
+        profile::enable();
+    }
+    interrupt::install()?;
+
+    let result = diff_exports(old_zip, new_zip, &read, &options, &mut outputs);
+    if let Some(url) = &reporting.notify_webhook {
+        let outcome = match &result {
+            Ok((_, summary)) => Outcome::Finished(summary),
+            Err(e) => Outcome::Failed(&e.to_string()),
+        };
+        if let Err(e) = notify::post(url, reporting.notify_slack, &outcome) {
+            log::error!("{}", e);
+        }
+    }
+    let (total, summary) = result?;
+    println!("Found {} differences", total);
+    if let Some(hash) = &summary.metadata.reproducibility_hash {
+        println!("Reproducibility hash {}, of the run manifest {}", hash, read.manifest_path);
+    }
+    if let Some(truncated) = summary.by_kind.get(&DifferenceKind::Truncated) {
+        println!("{} differences are of strings cut short", truncated);
+    }
+    summary.by_triage.iter().for_each(|(state, count)| println!("{} differences triaged as {} before", count, state));
+    match (summary.interrupted, summary.truncated) {
+        (true, _) => println!("Interrupted after {} patients, so the report is partial", summary.patients),
+        (false, true) => println!("Stopped after {} differing patients, so the report is partial", summary.differing_patients),
+        (false, false) => {}
+    }
+    if !summary.worst_patients.is_empty() {
+        println!("Worst patients:");
+        summary.worst_patients.iter().for_each(|(patient, score)| println!("  patient {:<10} score {}", patient, score));
+    }
+    if !summary.examples.is_empty() {
+        let labels = &summary.metadata.labels;
+        let value = |v: &Option<String>| v.as_deref().map_or("(missing)".to_string(), |v| v.escape_debug().to_string());
+        println!("Examples of CDEs differing in many patients ({} -> {}):", labels.old, labels.new);
+        summary.examples.iter().for_each(|(cde, e)| {
+            println!("  {} ({} patients)", cde, e.patients);
+            e.examples.iter().for_each(|x| println!("    patient {}: {} -> {}", x.patient, value(&x.old), value(&x.new)));
+        });
+    }
+    if !summary.deduplicated.is_empty() {
+        let value = |v: &Option<String>| v.as_deref().map_or("(missing)".to_string(), |v| v.escape_debug().to_string());
+        println!("Changes found more than once, each reported once:");
+        summary.deduplicated.iter().for_each(|c| {
+            println!("  {} [{}] {} -> {}: {} patients, {} differences, eg. patients {}",
+                c.cde, c.kind, value(&c.old), value(&c.new), c.patients, c.differences, c.sample.iter().join(", "))
+        });
+    }
+    if !summary.renumbered.is_empty() {
+        let labels = &summary.metadata.labels;
+        println!("Unmatched patients likely renumbered ({} -> {}):", labels.old, labels.new);
+        summary.renumbered.iter().for_each(|r| {
+            println!("  patient {} -> {}: {} forms, {} values shared ({:.1}% similar)", r.old, r.new, r.forms, r.shared_values, r.similarity * 100.0)
+        });
+    }
+    if !summary.slow_patients.is_empty() {
+        let labels = &summary.metadata.labels;
+        println!("Patients slow to read, parse and diff:");
+        summary.slow_patients.iter().for_each(|s| {
+            println!("  patient {} ({}): {} ms, {} bytes in {}, {} in {}", s.patient, s.ids, s.elapsed_ms, s.old_bytes, labels.old, s.new_bytes, labels.new)
+        });
+    }
+    if !summary.by_cohort.is_empty() {
+        println!("By cohort:");
+        summary.by_cohort.iter().for_each(|(cohort, t)| {
+            println!("  {:<30} {} patients, {} differing, {} differences", cohort, t.patients, t.differing_patients, t.differences)
+        });
+    }
+
+    if !summary.skipped_forms.is_empty() {
+        println!("Intentionally skipped forms:");
+        summary.skipped_forms.iter().for_each(|(form, s)| println!("  {:<30} {:<14} {} clinical data", form, s.reason, s.clinical_data));
+    }
+
+    if !summary.retired.is_empty() {
+        println!("Retired CDEs:");
+        summary.retired.iter().for_each(|(code, absent)| println!("  {:<30} absent from {} sections of {}", code, absent, summary.metadata.labels.new));
+    }
+
+    if !summary.suppressions.is_empty() {
+        println!("Suppressions:");
+        summary.suppressions.iter().for_each(|s| {
+            let used = match s.matches {
+                0 => "unused".to_string(),
+                n => format!("used {} times", n),
+            };
+            println!("  {:<30} {:<16} {}", s.suppression.code, used, s.audit());
+        });
+    }
+
+    if profile::enabled() {
+        profile::report();
+    }
+    if summary.interrupted {
+        process::exit(interrupt::EXIT_CODE);
+    }
+
+    if let Some(gates) = gates {
+        let results = gates.evaluate(&summary);
+        gate::print(&results);
+        // A partial report can't show the run passed, whatever its totals
+        match (results.iter().filter(|r| !r.passed).count(), summary.truncated) {
+            (0, false) => println!("Passed all {} gates", results.len()),
+            (0, true) => return Err("Passed the gates, but of a partial report".into()),
+            (failed, _) => return Err(format!("Failed {} of {} gates", failed, results.len()).into()),
+        }
+    }
+
+    Ok(())
+}
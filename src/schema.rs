@@ -0,0 +1,150 @@
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::io::Read;
+
+use crate::diff::{Diff, DiffOptions};
+use crate::migrated_registry::ExportFormat;
+
+type Types = BTreeSet<&'static str>;
+
+/// The structure observed in the first records of an export
+///
+/// Array indices are collapsed, so every CDE of every section shares a path,
+/// eg. "fields.data.forms[].sections[].cdes[].value"
+#[derive(Debug, Default)]
+pub struct Schema {
+    collections: BTreeSet<String>,
+    fields: BTreeMap<(String, String), Types>,
+}
+
+impl Schema {
+    pub fn scan(reader: impl Read, format: ExportFormat, records: usize) -> Schema {
+        let mut schema = Schema::default();
+
+        format.records(reader).take(records).for_each(|text| {
+            match serde_json::from_str::<Value>(&text) {
+                Ok(value) => {
+                    let collection = value.pointer("/fields/collection")
+                        .and_then(Value::as_str)
+                        .unwrap_or("")
+                        .to_string();
+                    schema.add(&collection, "", &value);
+                    schema.collections.insert(collection);
+                }
+                Err(e) => log::error!("Skipping unparseable record in schema check: {}", e)
+            }
+        });
+
+        schema
+    }
+
+    /// Print the types seen at each field of each collection
+    pub fn print(&self) {
+        self.collections.iter().for_each(|collection| {
+            println!("{}", collection);
+            self.fields.iter()
+                .filter(|((c, path), _)| c == collection && !path.is_empty())
+                .for_each(|((_, path), types)| println!("  {:<60} {}", path, join(types)));
+        });
+    }
+
+    fn add(&mut self, collection: &str, path: &str, value: &Value) {
+        let value_type = match value {
+            Value::Null => None,
+            Value::Bool(_) => Some("bool"),
+            Value::Number(_) => Some("number"),
+            Value::String(_) => Some("string"),
+            Value::Array(_) => Some("array"),
+            Value::Object(_) => Some("object"),
+        };
+
+        let types = self.fields.entry((collection.to_string(), path.to_string())).or_default();
+        if let Some(t) = value_type {
+            types.insert(t);
+        }
+
+        match value {
+            Value::Array(a) => {
+                a.iter().for_each(|v| self.add(collection, &format!("{}[]", path), v))
+            }
+            Value::Object(o) => {
+                o.iter().for_each(|(k, v)| match path {
+                    "" => self.add(collection, k, v),
+                    path => self.add(collection, &format!("{}.{}", path, k), v),
+                })
+            }
+            _ => {}
+        }
+    }
+}
+
+fn join(types: &Types) -> String {
+    match types.is_empty() {
+        true => "null".to_string(),
+        false => types.iter().copied().collect::<Vec<&str>>().join("|")
+    }
+}
+
+#[derive(Debug)]
+pub enum SchemaDifference<'a> {
+    Collection(Option<&'a str>, Option<&'a str>),
+    Field(&'a str, Option<&'a str>, Option<&'a str>),
+    Types(&'a str, &'a str, &'a Types, &'a Types),
+}
+
+impl<'a> Diff<'a> for Schema {
+    type Difference = SchemaDifference<'a>;
+
+    fn diff(&'a self, comp: &'a Self, _options: &DiffOptions) -> Option<Vec<Self::Difference>> {
+        let mut diffs = vec![];
+
+        self.collections.symmetric_difference(&comp.collections).for_each(|c| {
+            match self.collections.contains(c) {
+                true => diffs.push(SchemaDifference::Collection(Some(c), None)),
+                false => diffs.push(SchemaDifference::Collection(None, Some(c))),
+            }
+        });
+
+        // Fields of collections only one side has are already covered above
+        let shared = |(collection, _): &(String, String)| {
+            self.collections.contains(collection) && comp.collections.contains(collection)
+        };
+
+        self.fields.iter().filter(|(k, _)| shared(k)).for_each(|((collection, path), t1)| {
+            match comp.fields.get(&(collection.clone(), path.clone())) {
+                None => diffs.push(SchemaDifference::Field(collection, Some(path), None)),
+                Some(t2) => {
+                    // Null says nothing about the layout, so a side that has only seen nulls matches anything
+                    if !t1.is_empty() && !t2.is_empty() && t1 != t2 {
+                        diffs.push(SchemaDifference::Types(collection, path, t1, t2))
+                    }
+                }
+            }
+        });
+
+        comp.fields.keys().filter(|k| shared(k) && !self.fields.contains_key(*k)).for_each(|(collection, path)| {
+            diffs.push(SchemaDifference::Field(collection, None, Some(path)))
+        });
+
+        match diffs.is_empty() {
+            true => None,
+            false => Some(diffs)
+        }
+    }
+}
+
+impl<'a> fmt::Display for SchemaDifference<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SchemaDifference::Collection(Some(c), _) => write!(f, "Collection '{}' only in old", c),
+            SchemaDifference::Collection(_, Some(c)) => write!(f, "Collection '{}' only in new", c),
+            SchemaDifference::Field(c, Some(p), _) => write!(f, "[{}] Field '{}' only in old", c, p),
+            SchemaDifference::Field(c, _, Some(p)) => write!(f, "[{}] Field '{}' only in new", c, p),
+            SchemaDifference::Types(c, p, t1, t2) => {
+                write!(f, "[{}] Field '{}' is {} in old but {} in new", c, p, join(t1), join(t2))
+            }
+            SchemaDifference::Collection(None, None) | SchemaDifference::Field(_, None, None) => Ok(())
+        }
+    }
+}
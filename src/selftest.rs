@@ -0,0 +1,194 @@
+use serde_json::{json, Value};
+use std::collections::{BTreeSet, HashSet};
+use std::fmt;
+
+use crate::diff::{Diff, DiffOptions};
+use crate::fixture::ClinicalDatumRecord;
+use crate::interner::Interner;
+use crate::migrated_registry::{MigratedRegistry, OnParseError, RecordFilter};
+use crate::report::DifferenceRecord;
+use crate::review::SplitMix64;
+
+/// A change injected into a copy of an export, which a diff against the
+/// original should find
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Mutation {
+    /// A CDE's value changed
+    Value { patient: u32, form: String, section: String, cde: String },
+    /// A CDE left out of its section
+    DroppedCde { patient: u32, form: String, section: String, cde: String },
+    /// Every record of a patient left out
+    DroppedPatient { patient: u32 },
+}
+
+impl fmt::Display for Mutation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Mutation::Value { patient, form, section, cde } => write!(f, "changed {}/{}/{} of patient {}", form, section, cde, patient),
+            Mutation::DroppedCde { patient, form, section, cde } => write!(f, "dropped {}/{}/{} of patient {}", form, section, cde, patient),
+            Mutation::DroppedPatient { patient } => write!(f, "dropped patient {}", patient),
+        }
+    }
+}
+
+impl Mutation {
+    fn patient(&self) -> u32 {
+        match self {
+            Mutation::Value { patient, .. } | Mutation::DroppedCde { patient, .. } | Mutation::DroppedPatient { patient } => *patient,
+        }
+    }
+
+    /// Whether a difference found is of the mutation
+    fn found_by(&self, record: &DifferenceRecord) -> bool {
+        let l = &record.location;
+        match self {
+            Mutation::Value { patient, form, section, cde } | Mutation::DroppedCde { patient, form, section, cde } => {
+                l.patient == *patient && l.form.as_ref() == Some(form) && l.section.as_ref() == Some(section) && l.cde.as_ref() == Some(cde)
+            }
+            Mutation::DroppedPatient { .. } => false,
+        }
+    }
+}
+
+/// A value that differs from the original by more than the tolerance
+fn mutated(value: &Value, tolerance: f64) -> Value {
+    match value {
+        Value::Null => json!("mutated"),
+        Value::Bool(b) => json!(!b),
+        Value::Number(n) => json!(n.as_f64().unwrap_or(0.0) + 1.0 + tolerance * 2.0),
+        Value::String(s) => json!(format!("{} (mutated)", s)),
+        Value::Array(a) => json!(a.iter().cloned().chain([json!("mutated")]).collect::<Vec<Value>>()),
+        Value::Object(_) => json!("mutated"),
+    }
+}
+
+/// Inject mutations into a copy of the clinical data records of a registry,
+/// returning the mutated records and the mutations
+///
+/// Only the CDEs of single sections of 'cdes' records are mutated, and each
+/// CDE at most once
+pub fn mutate(records: &[String], registry_code: &str, mutations: usize, tolerance: f64, seed: u64) -> (Vec<String>, Vec<Mutation>) {
+    // The records that can be mutated, and their patients
+    let candidates = records.iter().enumerate().filter_map(|(i, text)| {
+        let record = ClinicalDatumRecord::parse(text).ok()?;
+        let of_registry = record.fields.registry_code.as_deref().is_none_or(|code| code == registry_code);
+        (of_registry && record.fields.collection == "cdes").then_some((i, record.fields.django_id as u32))
+    }).collect::<Vec<(usize, u32)>>();
+
+    let mut rng = SplitMix64(seed);
+    let mut values = records.iter().map(|_| None).collect::<Vec<Option<Value>>>();
+    let mut injected = vec![];
+    let mut dropped = BTreeSet::new();
+    let mut used = HashSet::new();
+
+    // Give up on records without CDEs to mutate after enough misses
+    let mut attempts = 0;
+    while injected.len() < mutations && !candidates.is_empty() && attempts < mutations * 100 {
+        attempts += 1;
+        let (i, patient) = candidates[(rng.next_u64() % candidates.len() as u64) as usize];
+        if dropped.contains(&patient) {
+            continue;
+        }
+
+        let kind = rng.next_u64() % 3;
+        if kind == 2 {
+            // Dropping a patient would hide the other mutations of them
+            if !injected.iter().any(|m: &Mutation| m.patient() == patient) {
+                dropped.insert(patient);
+                injected.push(Mutation::DroppedPatient { patient });
+            }
+            continue;
+        }
+
+        let value = values[i].get_or_insert_with(|| serde_json::from_str(&records[i]).unwrap_or(Value::Null));
+        let forms = match value.pointer_mut("/fields/data/forms").and_then(Value::as_array_mut) {
+            Some(forms) if !forms.is_empty() => forms,
+            _ => continue,
+        };
+        let f = (rng.next_u64() % forms.len() as u64) as usize;
+        let form = &mut forms[f];
+        let form_name = form["name"].as_str().unwrap_or_default().to_string();
+        let sections = match form["sections"].as_array_mut() {
+            Some(sections) if !sections.is_empty() => sections,
+            _ => continue,
+        };
+        let s = (rng.next_u64() % sections.len() as u64) as usize;
+        let section = &mut sections[s];
+        if section["allow_multiple"].as_bool() != Some(false) {
+            continue;
+        }
+        let section_code = section["code"].as_str().unwrap_or_default().to_string();
+        let cdes = match section["cdes"].as_array_mut() {
+            Some(cdes) if !cdes.is_empty() => cdes,
+            _ => continue,
+        };
+        let c = (rng.next_u64() % cdes.len() as u64) as usize;
+        let cde = cdes[c]["code"].as_str().unwrap_or_default().to_string();
+        if !used.insert((patient, form_name.clone(), section_code.clone(), cde.clone())) {
+            continue;
+        }
+
+        injected.push(match kind {
+            0 => {
+                cdes[c]["value"] = mutated(&cdes[c]["value"], tolerance);
+                Mutation::Value { patient, form: form_name, section: section_code, cde }
+            }
+            _ => {
+                cdes.remove(c);
+                Mutation::DroppedCde { patient, form: form_name, section: section_code, cde }
+            }
+        });
+    }
+
+    let mutated = records.iter().zip(values).filter_map(|(text, value)| {
+        let patient = ClinicalDatumRecord::parse(text).map(|r| r.fields.django_id as u32).ok();
+        match (patient, value) {
+            (Some(patient), _) if dropped.contains(&patient) => None,
+            (_, Some(value)) => Some(value.to_string()),
+            (_, None) => Some(text.clone()),
+        }
+    }).collect();
+
+    (mutated, injected)
+}
+
+/// Diff the original records against the mutated ones, returning the
+/// mutations that weren't found
+///
+/// Slices are compared in order as in a diff, but a dropped patient's slices
+/// are only checked to not be matched, so the slices after them still line up
+pub fn undetected(original: Vec<String>, mutated: Vec<String>, injected: &[Mutation], registry_code: &str, options: &DiffOptions) -> Vec<Mutation> {
+    let registry = |records: Vec<String>| {
+        let filter = RecordFilter { registry_code: Some(registry_code.to_string()), ..RecordFilter::default() };
+        MigratedRegistry::from_records(records.into_iter(), filter, OnParseError::Skip, Interner::new(), false)
+    };
+    let dropped = injected.iter().filter_map(|m| match m {
+        Mutation::DroppedPatient { patient } => Some(*patient),
+        _ => None,
+    }).collect::<HashSet<u32>>();
+
+    let mut new = registry(mutated).peekable();
+    let mut found = vec![];
+    let mut missed_patients = HashSet::new();
+
+    for old in registry(original) {
+        if dropped.contains(&old.patient) {
+            // A diff compares the slice against whatever's next, so finds a patient difference
+            if new.peek().is_some_and(|n| n.patient == old.patient) {
+                missed_patients.insert(old.patient);
+            }
+            continue;
+        }
+
+        if let Some(new) = new.next() {
+            if let Some(diffs) = old.diff(&new, options) {
+                found.extend(diffs.iter().flat_map(|d| d.records()));
+            }
+        }
+    }
+
+    injected.iter().filter(|m| match m {
+        Mutation::DroppedPatient { patient } => missed_patients.contains(patient),
+        m => !found.iter().any(|r| m.found_by(r)),
+    }).cloned().collect()
+}
@@ -0,0 +1,95 @@
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::error::Error;
+
+use crate::output;
+use crate::report::{DifferenceKind, DifferenceRecord, Location};
+
+/// The clinical data differences of each patient slice of an earlier run's
+/// SQLite report, and the hashes of the slice's clinical data on each side,
+/// so slices that haven't changed since reuse them rather than being
+/// compared again
+///
+/// They're only reused from a run with the same comparison hash, of the
+/// options and files that decide which differences are found. Differences of
+/// the patients and consents models, and of history sequences, are always
+/// found again
+#[derive(Debug, Default)]
+pub struct Since {
+    /// By patient and the pks of the slice's clinical data
+    slices: HashMap<(u32, String), Slice>,
+}
+
+#[derive(Debug)]
+struct Slice {
+    hashes: (u64, u64),
+    records: Vec<DifferenceRecord>,
+}
+
+impl Since {
+    /// Load the slices of the latest run of a report, or none if it compared
+    /// the exports differently, by its comparison hash
+    pub fn load(path: &str, comparison_hash: &str) -> Result<Since, Box<dyn Error>> {
+        let connection = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+        let run = output::latest_sqlite_run(&connection, path)?;
+
+        let hash = connection.query_row("SELECT value FROM metadata WHERE run = ?1 AND key = 'Comparison hash'", params![run], |row| row.get::<_, String>(0)).optional()?;
+        if hash.as_deref() != Some(comparison_hash) {
+            eprintln!("Warning: {} was made with different options, config or files, so every patient is compared again", path);
+            return Ok(Since::default());
+        }
+
+        let hash = |text: String| u64::from_str_radix(&text, 16);
+        let mut slices = HashMap::new();
+        let mut query = connection.prepare("SELECT patient, ids, old_hash, new_hash FROM slices WHERE run = ?1")?;
+        let rows = query.query_map(params![run], |row| {
+            Ok((row.get::<_, u32>(0)?, row.get::<_, String>(1)?, row.get::<_, String>(2)?, row.get::<_, String>(3)?))
+        })?;
+        for row in rows {
+            let (patient, ids, old, new) = row?;
+            slices.insert((patient, ids), Slice { hashes: (hash(old)?, hash(new)?), records: vec![] });
+        }
+
+        let mut query = connection.prepare("
+            SELECT patient, ids, context, form, section, cde, field, kind, old, new, detail, old_timestamp, new_timestamp, old_pointer, new_pointer
+            FROM differences WHERE run = ?1 ORDER BY id
+        ")?;
+        let rows = query.query_map(params![run], |row| {
+            let location = Location {
+                patient: row.get(0)?,
+                ids: row.get(1)?,
+                context: row.get(2)?,
+                form: row.get(3)?,
+                section: row.get(4)?,
+                cde: row.get(5)?,
+                field: row.get(6)?,
+                old_timestamp: row.get(11)?,
+                new_timestamp: row.get(12)?,
+                old_pointer: row.get(13)?,
+                new_pointer: row.get(14)?,
+                ..Location::default()
+            };
+            Ok((location, row.get::<_, String>(7)?, row.get(8)?, row.get(9)?, row.get(10)?))
+        })?;
+        for row in rows {
+            let (location, kind, old, new, detail) = row?;
+            // The differences of other models and of history aren't of a slice
+            if let Some(slice) = slices.get_mut(&(location.patient, location.ids.clone())) {
+                let kind = serde_json::from_value::<DifferenceKind>(Value::String(kind))?;
+                slice.records.push(DifferenceRecord { location, kind, old, new, detail, triage: None });
+            }
+        }
+
+        Ok(Since { slices })
+    }
+
+    /// The differences of a slice in the report, if its clinical data had the
+    /// same hashes
+    pub fn differences(&self, patient: u32, ids: &str, hashes: (u64, u64)) -> Option<Vec<DifferenceRecord>> {
+        match self.slices.get(&(patient, ids.to_string())) {
+            Some(slice) if slice.hashes == hashes => Some(slice.records.clone()),
+            _ => None,
+        }
+    }
+}
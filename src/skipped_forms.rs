@@ -0,0 +1,81 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::fixture::{self, ParseError};
+use crate::migrated_registry::MigratedRegistry;
+
+/// A record of the form definition fixture
+#[derive(Debug, Deserialize)]
+struct FormRecord {
+    model: String,
+    #[serde(default)]
+    fields: FormFields,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct FormFields {
+    name: Option<String>,
+    #[serde(default)]
+    is_questionnaire: bool,
+    #[serde(default)]
+    abbreviated: bool,
+}
+
+/// Why a form was skipped, and the clinical data it was skipped in
+#[derive(Debug, Clone, Serialize)]
+pub struct SkippedForm {
+    pub reason: &'static str,
+    pub clinical_data: usize,
+}
+
+/// The forms the registry definitions flag as questionnaires or abbreviated,
+/// which aren't migrated on purpose, counting the clinical data each is
+/// skipped in so they're reported apart from missing forms
+#[derive(Debug, Default)]
+pub struct SkippedForms {
+    forms: BTreeMap<String, (&'static str, AtomicUsize)>,
+}
+
+impl SkippedForms {
+    /// Add the flagged forms of a form definition fixture
+    pub fn read_definitions(&mut self, reader: impl Read) -> Result<(), ParseError> {
+        for text in MigratedRegistry::read_array_file_to_records(reader) {
+            let record = fixture::parse_at::<FormRecord>(&text, "")?;
+            if !record.model.ends_with("registryform") {
+                continue;
+            }
+
+            let reason = match (record.fields.is_questionnaire, record.fields.abbreviated) {
+                (true, _) => "questionnaire",
+                (false, true) => "abbreviated",
+                (false, false) => continue,
+            };
+            match record.fields.name {
+                Some(name) => self.forms.entry(name).or_insert((reason, AtomicUsize::new(0))),
+                None => return Err(ParseError::new("/fields/name", format!("{} form has no name", reason))),
+            };
+        }
+
+        Ok(())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.forms.contains_key(name)
+    }
+
+    /// Count a clinical datum the form is skipped in
+    pub fn skip(&self, name: &str) {
+        if let Some((_, skipped)) = self.forms.get(name) {
+            skipped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Why each form is skipped and the clinical data it was skipped in so far, by name
+    pub fn counts(&self) -> BTreeMap<String, SkippedForm> {
+        self.forms.iter()
+            .map(|(name, (reason, skipped))| (name.clone(), SkippedForm { reason, clinical_data: skipped.load(Ordering::Relaxed) }))
+            .collect()
+    }
+}
@@ -0,0 +1,295 @@
+use std::convert::TryInto;
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+const END_SIGNATURE: u32 = 0x0605_4b50;
+const ZIP64_END_SIGNATURE: u32 = 0x0606_4b50;
+const ZIP64_LOCATOR_SIGNATURE: u32 = 0x0706_4b50;
+const CENTRAL_HEADER_SIGNATURE: u32 = 0x0201_4b50;
+const ZIP64_EXTRA_ID: u16 = 0x0001;
+/// The size of an end of central directory record without its comment
+const END_SIZE: usize = 22;
+const CENTRAL_HEADER_SIZE: usize = 46;
+
+/// The parts of a split (spanned) archive, eg. export.z01, export.z02 and
+/// export.zip, read as the one unsplit archive they'd be recombined into
+///
+/// The local headers and data of entries are read from the parts as they are.
+/// The central directory, whose offsets are relative to the part they're in,
+/// is rewritten in memory with offsets into the whole, and read after them
+pub struct SplitArchive {
+    parts: Vec<File>,
+    /// Where each part starts in the whole
+    starts: Vec<u64>,
+    /// Where the original central directory starts in the whole, so the end of the parts read
+    directory_start: u64,
+    /// The rewritten central directory and its end records
+    directory: Vec<u8>,
+    position: u64,
+}
+
+/// The end of central directory of a split archive, with the zip64 fields
+/// in place of the ones they extend
+struct End {
+    disks: u32,
+    directory_disk: u32,
+    entries: u64,
+    directory_size: u64,
+    directory_offset: u64,
+}
+
+fn u16_at(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes(bytes[at..at + 2].try_into().unwrap())
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+}
+
+fn u64_at(bytes: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap())
+}
+
+/// Whether the bytes at an offset are a signature, without running past them
+fn signature_at(bytes: &[u8], at: usize, signature: u32) -> bool {
+    bytes.len() >= at + 4 && u32_at(bytes, at) == signature
+}
+
+impl End {
+    /// Find the end records of the last part of an archive, or None if it's
+    /// the only part
+    fn find(last: &mut File) -> Result<Option<End>, Box<dyn Error>> {
+        // The end record is at most a maximum length comment from the end
+        let size = last.metadata()?.len();
+        let tail_start = size.saturating_sub((END_SIZE + u16::MAX as usize) as u64);
+        let mut tail = vec![];
+        last.seek(SeekFrom::Start(tail_start))?;
+        last.read_to_end(&mut tail)?;
+
+        // Left to the zip reader to report if it isn't found
+        let at = match (0..tail.len().saturating_sub(END_SIZE - 1)).rev().find(|&at| signature_at(&tail, at, END_SIGNATURE)) {
+            Some(at) => at,
+            None => return Ok(None),
+        };
+        let disk = u16_at(&tail, at + 4);
+        let mut end = End {
+            disks: disk as u32 + 1,
+            directory_disk: u16_at(&tail, at + 6) as u32,
+            entries: u16_at(&tail, at + 10) as u64,
+            directory_size: u32_at(&tail, at + 12) as u64,
+            directory_offset: u32_at(&tail, at + 16) as u64,
+        };
+
+        // The zip64 locator is just before the end record, and gives the disk count
+        if at >= 20 && signature_at(&tail, at - 20, ZIP64_LOCATOR_SIGNATURE) {
+            let locator = at - 20;
+            let (zip64_disk, zip64_offset, disks) = (u32_at(&tail, locator + 4), u64_at(&tail, locator + 8), u32_at(&tail, locator + 16));
+            if disks <= 1 {
+                return Ok(None);
+            }
+            if zip64_disk != disks - 1 {
+                return Err("Zip64 end of central directory record isn't in the last part".into());
+            }
+            let record = (zip64_offset.checked_sub(tail_start).ok_or("Zip64 end of central directory record is out of reach")?) as usize;
+            if !signature_at(&tail, record, ZIP64_END_SIGNATURE) || tail.len() < record + 56 {
+                return Err("Invalid zip64 end of central directory record".into());
+            }
+            end = End {
+                disks,
+                directory_disk: u32_at(&tail, record + 20),
+                entries: u64_at(&tail, record + 32),
+                directory_size: u64_at(&tail, record + 40),
+                directory_offset: u64_at(&tail, record + 48),
+            };
+        }
+
+        match end.disks > 1 {
+            true => Ok(Some(end)),
+            false => Ok(None)
+        }
+    }
+}
+
+/// The path of a part of a split archive, eg. export.z01 for the first of
+/// export.zip
+fn part_path(zip_path: &str, part: u32) -> String {
+    let stem = zip_path.strip_suffix(".zip").or_else(|| zip_path.strip_suffix(".ZIP")).unwrap_or(zip_path);
+    format!("{}.z{:02}", stem, part)
+}
+
+/// The paths of the parts of the archive at zip_path in order, which is
+/// only zip_path if it isn't split
+pub fn part_paths(zip_path: &str) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut last = File::open(Path::new(zip_path)).map_err(|e| format!("Failed opening {}: {}", zip_path, e))?;
+    let disks = End::find(&mut last)?.map_or(1, |end| end.disks);
+
+    Ok((1..disks).map(|part| part_path(zip_path, part)).chain(Some(zip_path.to_string())).collect())
+}
+
+impl SplitArchive {
+    /// Open the parts of the split archive whose last part is at zip_path,
+    /// or None if it isn't split
+    pub fn open(zip_path: &str) -> Result<Option<SplitArchive>, Box<dyn Error>> {
+        let mut last = File::open(Path::new(zip_path)).map_err(|e| format!("Failed opening {}: {}", zip_path, e))?;
+        let end = match End::find(&mut last)? {
+            Some(end) => end,
+            None => return Ok(None),
+        };
+
+        let mut parts = (1..end.disks).map(|part| {
+            let path = part_path(zip_path, part);
+            File::open(&path).map_err(|e| format!("Failed opening part {} of {} of {}: {}", part, end.disks, zip_path, e))
+        }).collect::<Result<Vec<File>, String>>()?;
+        parts.push(last);
+        log::debug!("Reading {} as {} parts", zip_path, parts.len());
+
+        let mut starts = vec![0];
+        for part in &parts {
+            starts.push(starts[starts.len() - 1] + part.metadata()?.len());
+        }
+
+        let directory_start = starts.get(end.directory_disk as usize).ok_or("Central directory is in a missing part")? + end.directory_offset;
+        let total = starts[starts.len() - 1];
+
+        // Read the original directory through the parts, as it can span them, before it's replaced
+        let mut archive = SplitArchive { parts, starts, directory_start: total, directory: vec![], position: directory_start };
+        let mut directory = vec![0; end.directory_size as usize];
+        archive.read_exact(&mut directory)?;
+        archive.directory = archive.rewrite(&directory, end.entries, directory_start)?;
+        archive.directory_start = directory_start;
+        archive.position = 0;
+
+        Ok(Some(archive))
+    }
+
+    /// The central directory with the offsets of entries into the whole
+    /// archive, followed by zip64 end records, as the offsets may be past 4 GB
+    fn rewrite(&self, directory: &[u8], entries: u64, directory_start: u64) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut rewritten = Vec::with_capacity(directory.len() + 128);
+        let mut at = 0;
+
+        for _ in 0..entries {
+            if !signature_at(directory, at, CENTRAL_HEADER_SIGNATURE) || directory.len() < at + CENTRAL_HEADER_SIZE {
+                return Err("Invalid central directory header".into());
+            }
+            let header = &directory[at..at + CENTRAL_HEADER_SIZE];
+            let (name_length, extra_length, comment_length) = (u16_at(header, 28) as usize, u16_at(header, 30) as usize, u16_at(header, 32) as usize);
+            let name_end = at + CENTRAL_HEADER_SIZE + name_length;
+            let extra_end = name_end + extra_length;
+            let entry_end = extra_end + comment_length;
+            if directory.len() < entry_end {
+                return Err("Central directory header runs past the directory".into());
+            }
+
+            // Fields too big for the header are in the zip64 extra field, in this order
+            let (size, compressed_size, offset, disk) = (u32_at(header, 24), u32_at(header, 20), u32_at(header, 42), u16_at(header, 34));
+            let mut zip64 = vec![];
+            let mut extra = vec![];
+            let mut e = name_end;
+            while e + 4 <= extra_end {
+                let (id, length) = (u16_at(directory, e), u16_at(directory, e + 2) as usize);
+                let data = &directory[(e + 4).min(extra_end)..(e + 4 + length).min(extra_end)];
+                match id {
+                    ZIP64_EXTRA_ID => zip64.extend_from_slice(data),
+                    _ => extra.extend_from_slice(&directory[e..(e + 4 + length).min(extra_end)]),
+                }
+                e += 4 + length;
+            }
+
+            let mut zip64_fields = zip64.chunks(8).filter(|c| c.len() == 8).map(|c| u64_at(c, 0));
+            let size = match size { u32::MAX => zip64_fields.next().ok_or("Missing zip64 size")?, s => s as u64 };
+            let compressed_size = match compressed_size { u32::MAX => zip64_fields.next().ok_or("Missing zip64 compressed size")?, s => s as u64 };
+            let offset = match offset { u32::MAX => zip64_fields.next().ok_or("Missing zip64 offset")?, o => o as u64 };
+            let disk = match disk {
+                u16::MAX => zip64[zip64.len().saturating_sub(4)..].try_into().map(u32::from_le_bytes).map_err(|_| "Missing zip64 disk")?,
+                d => d as u32,
+            };
+            let offset = self.starts.get(disk as usize).ok_or("Entry is in a missing part")? + offset;
+
+            let mut header = header.to_vec();
+            let mut zip64 = vec![];
+            for (value, field) in [(size, 24), (compressed_size, 20), (offset, 42)] {
+                match value >= u32::MAX as u64 {
+                    true => {
+                        zip64.extend_from_slice(&value.to_le_bytes());
+                        header[field..field + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+                    }
+                    false => header[field..field + 4].copy_from_slice(&(value as u32).to_le_bytes()),
+                }
+            }
+            header[34..36].copy_from_slice(&0u16.to_le_bytes());
+            if !zip64.is_empty() {
+                let mut field = ZIP64_EXTRA_ID.to_le_bytes().to_vec();
+                field.extend_from_slice(&(zip64.len() as u16).to_le_bytes());
+                field.extend_from_slice(&zip64);
+                extra.splice(0..0, field);
+            }
+            header[30..32].copy_from_slice(&(extra.len() as u16).to_le_bytes());
+
+            rewritten.extend_from_slice(&header);
+            rewritten.extend_from_slice(&directory[at + CENTRAL_HEADER_SIZE..name_end]);
+            rewritten.extend_from_slice(&extra);
+            rewritten.extend_from_slice(&directory[extra_end..entry_end]);
+            at = entry_end;
+        }
+
+        let size = rewritten.len() as u64;
+        let zip64_end = directory_start + size;
+        let record = |fields: &[&[u8]]| fields.concat();
+
+        let end = record(&[
+            &ZIP64_END_SIGNATURE.to_le_bytes(), &44u64.to_le_bytes(), &45u16.to_le_bytes(), &45u16.to_le_bytes(),
+            &0u32.to_le_bytes(), &0u32.to_le_bytes(), &entries.to_le_bytes(), &entries.to_le_bytes(),
+            &size.to_le_bytes(), &directory_start.to_le_bytes(),
+            &ZIP64_LOCATOR_SIGNATURE.to_le_bytes(), &0u32.to_le_bytes(), &zip64_end.to_le_bytes(), &1u32.to_le_bytes(),
+            &END_SIGNATURE.to_le_bytes(), &0u16.to_le_bytes(), &0u16.to_le_bytes(),
+            &(entries.min(u16::MAX as u64) as u16).to_le_bytes(), &(entries.min(u16::MAX as u64) as u16).to_le_bytes(),
+            &(size.min(u32::MAX as u64) as u32).to_le_bytes(), &(directory_start.min(u32::MAX as u64) as u32).to_le_bytes(),
+            &0u16.to_le_bytes(),
+        ]);
+        rewritten.extend_from_slice(&end);
+
+        Ok(rewritten)
+    }
+
+    fn len(&self) -> u64 {
+        self.directory_start + self.directory.len() as u64
+    }
+}
+
+impl Read for SplitArchive {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position >= self.directory_start {
+            let at = ((self.position - self.directory_start) as usize).min(self.directory.len());
+            let n = (&self.directory[at..]).read(buf)?;
+            self.position += n as u64;
+            return Ok(n);
+        }
+
+        // The part the position is in, and how much of it's left before the directory
+        let part = self.starts.iter().rposition(|&start| start <= self.position).unwrap_or(0).min(self.parts.len() - 1);
+        let end = self.starts[part + 1].min(self.directory_start);
+        let n = buf.len().min((end - self.position) as usize);
+        self.parts[part].seek(SeekFrom::Start(self.position - self.starts[part]))?;
+        let n = self.parts[part].read(&mut buf[..n])?;
+        self.position += n as u64;
+
+        Ok(n)
+    }
+}
+
+impl Seek for SplitArchive {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::End(n) => self.len().checked_add_signed(n),
+            SeekFrom::Current(n) => self.position.checked_add_signed(n),
+        };
+
+        self.position = position.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Seek before the start of a split archive"))?;
+        Ok(self.position)
+    }
+}
+
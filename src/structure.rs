@@ -0,0 +1,98 @@
+use itertools::Itertools;
+use std::collections::BTreeMap;
+use std::io::Read;
+
+use crate::clinical_data::{CDEValue, ClinicalDatum, ClinicalDatumVariant};
+use crate::fixture::{ClinicalDatumRecord, ParseError};
+use crate::interner::Interner;
+use crate::migrated_registry::MigratedRegistry;
+
+/// The forms, sections and CDEs observed across the current ('cdes') clinical
+/// data of every patient of a registry, for when its definition fixtures are
+/// missing or stale
+#[derive(Debug, Default)]
+pub struct Structure {
+    /// The number of clinical data read
+    data: usize,
+    forms: BTreeMap<String, FormStructure>,
+}
+
+#[derive(Debug, Default)]
+struct FormStructure {
+    /// The number of clinical data with the form
+    data: usize,
+    sections: BTreeMap<String, SectionStructure>,
+}
+
+#[derive(Debug, Default)]
+struct SectionStructure {
+    /// The number of clinical data with the section
+    data: usize,
+    cdes: BTreeMap<String, CDEStructure>,
+}
+
+#[derive(Debug, Default)]
+struct CDEStructure {
+    /// The number of values, counting each row of a multiple section
+    values: usize,
+    nulls: usize,
+    types: BTreeMap<&'static str, usize>,
+}
+
+impl Structure {
+    pub fn from(reader: impl Read, registry_code: &str) -> Result<Structure, ParseError> {
+        let mut structure = Structure::default();
+        let mut interner = Interner::new();
+
+        for text in MigratedRegistry::read_array_file_to_records(reader) {
+            let record = ClinicalDatumRecord::parse(&text)?;
+            match &record.fields.registry_code {
+                Some(code) if code == registry_code => {}
+                _ => continue
+            }
+
+            if let Some(datum) = ClinicalDatum::from(&record, &mut interner, false)? {
+                if let ClinicalDatumVariant::CDEs = datum.variant {
+                    structure.add(&datum);
+                }
+            }
+        }
+
+        Ok(structure)
+    }
+
+    fn add(&mut self, datum: &ClinicalDatum) {
+        self.data += 1;
+        datum.proto_context().iter().for_each(|form| self.forms.entry(form.to_string()).or_default().data += 1);
+        datum.section_paths().for_each(|(form, section)| {
+            self.forms.entry(form.to_string()).or_default().sections.entry(section.to_string()).or_default().data += 1;
+        });
+        datum.located_cdes().for_each(|(form, section, cde)| {
+            let structure = self.forms.entry(form.to_string()).or_default()
+                .sections.entry(section.to_string()).or_default()
+                .cdes.entry(cde.code().to_string()).or_default();
+            structure.values += 1;
+            if let CDEValue::Null = cde.value() {
+                structure.nulls += 1;
+            }
+            *structure.types.entry(cde.value().type_name()).or_insert(0) += 1;
+        });
+    }
+
+    /// Print each form, its sections and their CDEs, with how many clinical
+    /// data have them and the types and null rate of each CDE's values
+    pub fn print(&self) {
+        println!("{} clinical data", self.data);
+        self.forms.iter().for_each(|(name, form)| {
+            println!("{} ({} clinical data)", name, form.data);
+            form.sections.iter().for_each(|(code, section)| {
+                println!("  {} ({} clinical data)", code, section.data);
+                section.cdes.iter().for_each(|(code, cde)| {
+                    let types = cde.types.iter().map(|(t, n)| format!("{} {}", t, n)).join(", ");
+                    let null_rate = 100.0 * cde.nulls as f64 / cde.values as f64;
+                    println!("    {:<40} {:>10} values {:>6.1}% null  {}", code, cde.values, null_rate, types);
+                });
+            });
+        });
+    }
+}
@@ -0,0 +1,120 @@
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::consents;
+
+/// A form, section or CDE whose differences are left out of the comparison,
+/// and who left them out, why, and until when
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Suppression {
+    pub code: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// The last day the suppression is meant to apply (YYYY-MM-DD)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires: Option<String>,
+}
+
+impl Suppression {
+    pub fn of(code: String) -> Suppression {
+        Suppression { code, author: None, reason: None, expires: None }
+    }
+}
+
+/// An entry of a config's ignore list, a code or a suppression with its
+/// audit trail
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum IgnoreRule {
+    Code(String),
+    Suppression(Suppression),
+}
+
+impl From<IgnoreRule> for Suppression {
+    fn from(rule: IgnoreRule) -> Suppression {
+        match rule {
+            IgnoreRule::Code(code) => Suppression::of(code),
+            IgnoreRule::Suppression(s) => s,
+        }
+    }
+}
+
+/// How a suppression was used by a run, for auditing what's left out
+#[derive(Debug, Clone, Serialize)]
+pub struct SuppressionUse {
+    #[serde(flatten)]
+    pub suppression: Suppression,
+    /// Whether the suppression's expiry date has passed
+    pub expired: bool,
+    /// How many forms, sections or CDEs it left out of the comparison
+    pub matches: usize,
+}
+
+impl SuppressionUse {
+    /// Who left the code out, why and until when, as far as the config says
+    pub fn audit(&self) -> String {
+        let s = &self.suppression;
+        [
+            s.author.as_ref().map(|a| format!("by {}", a)),
+            s.reason.clone(),
+            s.expires.as_ref().map(|e| match self.expired {
+                true => format!("expired {}", e),
+                false => format!("expires {}", e),
+            }),
+        ].iter().flatten().join(", ")
+    }
+}
+
+/// The suppressions of a run by code, counting how often each matches so
+/// unused and expired ones can be reported
+#[derive(Debug, Default)]
+pub struct Suppressions {
+    rules: BTreeMap<String, (Suppression, bool, AtomicUsize)>,
+}
+
+impl Suppressions {
+    pub fn new(suppressions: impl IntoIterator<Item=Suppression>) -> Result<Suppressions, Box<dyn Error>> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+
+        let rules = suppressions.into_iter().map(|s| {
+            let expired = match &s.expires {
+                // It applies until the end of its last day
+                Some(date) => consents::seconds(date)
+                    .ok_or_else(|| format!("The suppression of {} expires on an invalid date: {}", s.code, date))?
+                    + 86400 <= now,
+                None => false,
+            };
+            Ok((s.code.clone(), (s, expired, AtomicUsize::new(0))))
+        }).collect::<Result<BTreeMap<_, _>, Box<dyn Error>>>()?;
+
+        Ok(Suppressions { rules })
+    }
+
+    /// Whether the differences of a form, section or CDE are left out,
+    /// counting the match
+    pub fn suppresses(&self, code: &str) -> bool {
+        match self.rules.get(code) {
+            Some((_, _, matches)) => {
+                matches.fetch_add(1, Ordering::Relaxed);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// How each suppression was used so far, by code
+    pub fn uses(&self) -> Vec<SuppressionUse> {
+        self.rules.values().map(|(suppression, expired, matches)| SuppressionUse {
+            suppression: suppression.clone(),
+            expired: *expired,
+            matches: matches.load(Ordering::Relaxed),
+        }).collect()
+    }
+}
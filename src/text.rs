@@ -0,0 +1,218 @@
+use clap::ValueEnum;
+use itertools::Itertools;
+use unicode_normalization::UnicodeNormalization;
+use unicode_normalization::char::is_combining_mark;
+
+/// Normalize free text so that values differing only in line endings,
+/// whitespace, HTML markup or HTML entities compare equal
+///
+/// Tags are dropped, with those that break lines (eg. <br>, <p>) becoming a
+/// space, entities are decoded after tags are dropped so escaped markup stays
+/// as text, and every run of whitespace becomes a single space
+pub fn normalize(text: &str) -> String {
+    let decoded = decode_entities(&strip_tags(text));
+
+    decoded.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+/// A way strings that differ in bytes can still be considered equal
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum Collation {
+    /// Compose characters, eg. "e" and a combining acute accent become "é"
+    Nfc,
+    /// Compose characters and replace compatibility characters, eg. "ﬁ" becomes "fi"
+    Nfkc,
+    /// Ignore case, by lowercasing
+    Casefold,
+    /// Ignore accents, eg. "é" becomes "e"
+    AccentInsensitive,
+}
+
+/// Apply each collation to text, in the order given
+pub fn collate(text: &str, collation: &[Collation]) -> String {
+    collation.iter().fold(text.to_string(), |text, c| match c {
+        Collation::Nfc => text.nfc().collect(),
+        Collation::Nfkc => text.nfkc().collect(),
+        Collation::Casefold => text.to_lowercase(),
+        Collation::AccentInsensitive => text.nfd().filter(|c| !is_combining_mark(*c)).nfc().collect(),
+    })
+}
+
+const BREAKING_TAGS: [&str; 9] = ["br", "p", "div", "li", "ul", "ol", "tr", "td", "hr"];
+
+fn strip_tags(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find('<') {
+        stripped.push_str(&rest[..start]);
+        let tag = &rest[start + 1..];
+        let name = tag.trim_start_matches('/');
+
+        match (name.starts_with(|c: char| c.is_ascii_alphabetic()), tag.find('>')) {
+            (true, Some(end)) => {
+                let name = name.split(|c: char| !c.is_ascii_alphanumeric()).next().unwrap_or("");
+                if BREAKING_TAGS.contains(&name.to_ascii_lowercase().as_str()) {
+                    stripped.push(' ');
+                }
+                rest = &tag[end + 1..];
+            }
+            // Not a tag, eg. "x < 5"
+            _ => {
+                stripped.push('<');
+                rest = tag;
+            }
+        }
+    }
+    stripped.push_str(rest);
+
+    stripped
+}
+
+fn decode_entities(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        let entity = &rest[start + 1..];
+
+        let end = entity.find(';').filter(|end| *end <= 10);
+        match end.and_then(|end| decode_entity(&entity[..end]).map(|c| (end, c))) {
+            Some((end, c)) => {
+                decoded.push(c);
+                rest = &entity[end + 1..];
+            }
+            None => {
+                decoded.push('&');
+                rest = entity;
+            }
+        }
+    }
+    decoded.push_str(rest);
+
+    decoded
+}
+
+fn decode_entity(name: &str) -> Option<char> {
+    match name {
+        "amp" => Some('&'),
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "quot" => Some('"'),
+        "apos" => Some('\''),
+        "nbsp" => Some(' '),
+        _ => {
+            let code = match name.strip_prefix("#x").or_else(|| name.strip_prefix("#X")) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                None => name.strip_prefix('#').and_then(|dec| dec.parse::<u32>().ok()),
+            };
+            code.and_then(char::from_u32)
+        }
+    }
+}
+
+/// Strings at least this many characters long are shown as an inline diff
+/// rather than in full
+pub const LONG_TEXT: usize = 80;
+
+pub fn is_long(text: &str) -> bool {
+    text.chars().count() >= LONG_TEXT
+}
+
+/// The most words compared against each other when diffing, beyond which the
+/// differing middle of two texts is shown as replaced wholesale
+const MAX_COMPARISONS: usize = 4_000_000;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Edit {
+    Keep,
+    Remove,
+    Add,
+}
+
+/// A word level diff of two strings, marking removed words [-...-] and added
+/// words {+...+}
+///
+/// Whitespace is kept as its own token, so the diff reproduces both texts
+pub fn inline_diff(old: &str, new: &str) -> String {
+    let old = tokens(old);
+    let new = tokens(new);
+
+    let prefix = old.iter().zip(new.iter()).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..].iter().rev().zip(new[prefix..].iter().rev()).take_while(|(a, b)| a == b).count();
+    let (old_middle, new_middle) = (&old[prefix..old.len() - suffix], &new[prefix..new.len() - suffix]);
+
+    let mut edits = old[..prefix].iter().map(|t| (Edit::Keep, *t)).collect::<Vec<(Edit, &str)>>();
+    edits.extend(diff_tokens(old_middle, new_middle));
+    edits.extend(old[old.len() - suffix..].iter().map(|t| (Edit::Keep, *t)));
+
+    let mut diff = String::new();
+    edits.iter().group_by(|(edit, _)| *edit).into_iter().for_each(|(edit, group)| {
+        let text = group.map(|(_, t)| *t).collect::<String>();
+        match edit {
+            Edit::Keep => diff.push_str(&text),
+            Edit::Remove => diff.push_str(&format!("[-{}-]", text)),
+            Edit::Add => diff.push_str(&format!("{{+{}+}}", text)),
+        }
+    });
+
+    diff
+}
+
+/// Split text into runs of whitespace and runs of everything else
+fn tokens(text: &str) -> Vec<&str> {
+    let mut tokens = vec![];
+    let mut start = 0;
+
+    text.char_indices().skip(1).for_each(|(i, c)| {
+        let prev = text[..i].chars().next_back().unwrap();
+        if prev.is_whitespace() != c.is_whitespace() {
+            tokens.push(&text[start..i]);
+            start = i;
+        }
+    });
+    if start < text.len() {
+        tokens.push(&text[start..]);
+    }
+
+    tokens
+}
+
+/// The edits that turn old into new, from their longest common subsequence
+fn diff_tokens<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<(Edit, &'a str)> {
+    if old.len() * new.len() > MAX_COMPARISONS {
+        return old.iter().map(|t| (Edit::Remove, *t))
+            .chain(new.iter().map(|t| (Edit::Add, *t)))
+            .collect();
+    }
+
+    // lengths[i][j] is the length of the LCS of old[i..] and new[j..]
+    let mut lengths = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    (0..old.len()).rev().for_each(|i| {
+        (0..new.len()).rev().for_each(|j| {
+            lengths[i][j] = match old[i] == new[j] {
+                true => lengths[i + 1][j + 1] + 1,
+                false => lengths[i + 1][j].max(lengths[i][j + 1]),
+            };
+        });
+    });
+
+    let (mut i, mut j) = (0, 0);
+    let mut edits = vec![];
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            edits.push((Edit::Keep, old[i]));
+            i += 1;
+            j += 1;
+        } else if j == new.len() || (i < old.len() && lengths[i + 1][j] >= lengths[i][j + 1]) {
+            edits.push((Edit::Remove, old[i]));
+            i += 1;
+        } else {
+            edits.push((Edit::Add, new[j]));
+            j += 1;
+        }
+    }
+
+    edits
+}
@@ -0,0 +1,134 @@
+use std::io::{self, IsTerminal};
+
+use crate::report::{DifferenceRecord, Location, Severity};
+
+/// How a patient's differences are shown as they're found
+#[derive(Debug, Clone, Copy)]
+pub struct Rendering {
+    /// One line per difference, rather than a tree
+    pub flat: bool,
+    /// Whether kinds are coloured by severity, only when written to a terminal
+    pub colour: bool,
+}
+
+impl Rendering {
+    pub fn new(flat: bool) -> Rendering {
+        Rendering { flat, colour: io::stderr().is_terminal() && std::env::var_os("NO_COLOR").is_none() }
+    }
+
+    /// The differences of a patient's slice, as a tree of their forms,
+    /// sections and CDEs or one per line
+    pub fn render(&self, patient: u32, ids: &str, records: &[&DifferenceRecord]) -> String {
+        match self.flat {
+            true => records.iter()
+                .map(|r| format!("patient {} {} [{}]{}: {}\n", patient, r.location.path(), self.kind(r), triage(r), values(r)))
+                .collect(),
+            false => {
+                let mut tree = Node::default();
+                records.iter().for_each(|r| tree.add(branches(&r.location), r));
+                let heading = match ids.is_empty() {
+                    true => format!("patient {}", patient),
+                    false => format!("patient {} (clinical data {})", patient, ids),
+                };
+                let mut text = format!("{}\n", self.paint("1", &heading));
+                tree.write(self, "", &mut text);
+                text
+            }
+        }
+    }
+
+    fn kind(&self, record: &DifferenceRecord) -> String {
+        let colour = match record.kind.severity() {
+            Severity::High => "31",
+            Severity::Medium => "33",
+            Severity::Low => "36",
+            Severity::FormattingOnly => "2",
+        };
+        self.paint(colour, &record.kind.to_string())
+    }
+
+    fn paint(&self, colour: &str, text: &str) -> String {
+        match self.colour {
+            true => format!("\x1b[{}m{}\x1b[0m", colour, text),
+            false => text.to_string(),
+        }
+    }
+}
+
+/// The forms, sections and CDEs of a patient's differences, in the order
+/// they were found
+#[derive(Default)]
+struct Node<'r> {
+    children: Vec<(String, Node<'r>)>,
+    records: Vec<&'r DifferenceRecord>,
+}
+
+impl<'r> Node<'r> {
+    fn add(&mut self, branches: Vec<String>, record: &'r DifferenceRecord) {
+        let node = branches.into_iter().fold(self, |node, branch| {
+            let i = match node.children.iter().position(|(name, _)| *name == branch) {
+                Some(i) => i,
+                None => {
+                    node.children.push((branch, Node::default()));
+                    node.children.len() - 1
+                }
+            };
+            &mut node.children[i].1
+        });
+        node.records.push(record);
+    }
+
+    fn write(&self, rendering: &Rendering, prefix: &str, text: &mut String) {
+        let count = self.records.len() + self.children.len();
+        let branch = |i: usize| match i + 1 == count {
+            true => ("└── ", format!("{}    ", prefix)),
+            false => ("├── ", format!("{}│   ", prefix)),
+        };
+
+        for (i, record) in self.records.iter().enumerate() {
+            let (branch, indent) = branch(i);
+            text.push_str(&format!("{}{}[{}]{}: {}\n", prefix, branch, rendering.kind(record), triage(record), values(record)));
+            detail(record, rendering, &indent, text);
+        }
+        for (i, (name, node)) in self.children.iter().enumerate() {
+            let (branch, indent) = branch(self.records.len() + i);
+            match (node.children.is_empty(), &node.records[..]) {
+                // A CDE that differs once is shown on the one line
+                (true, [record]) => {
+                    text.push_str(&format!("{}{}{} [{}]{}: {}\n", prefix, branch, name, rendering.kind(record), triage(record), values(record)));
+                    detail(record, rendering, &indent, text);
+                }
+                _ => {
+                    text.push_str(&format!("{}{}{}\n", prefix, branch, name));
+                    node.write(rendering, &indent, text);
+                }
+            }
+        }
+    }
+}
+
+/// The branches of the tree down to a difference, its form, section and
+/// CDE, or the context and field of a model other than clinical data
+fn branches(location: &Location) -> Vec<String> {
+    let top = location.form.as_ref().unwrap_or(&location.context);
+    std::iter::once(top)
+        .chain([&location.section, &location.cde, &location.field].iter().copied().flatten())
+        .cloned()
+        .collect()
+}
+
+fn values(record: &DifferenceRecord) -> String {
+    let value = |v: &Option<String>| v.as_deref().map_or("(missing)".to_string(), |v| v.escape_debug().to_string());
+    format!("{} -> {}", value(&record.old), value(&record.new))
+}
+
+fn triage(record: &DifferenceRecord) -> String {
+    record.triage.map(|t| format!(" ({})", t)).unwrap_or_default()
+}
+
+/// How the values differ, under the difference, if that's given
+fn detail(record: &DifferenceRecord, rendering: &Rendering, prefix: &str, text: &mut String) {
+    if let Some(detail) = &record.detail {
+        text.push_str(&format!("{}  {}\n", prefix, rendering.paint("2", detail)));
+    }
+}
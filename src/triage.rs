@@ -0,0 +1,138 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use crate::metadata::RunMetadata;
+use crate::report::DifferenceRecord;
+
+/// The triage file used when none is given
+pub const DEFAULT_TRIAGE: &str = "diffmig-triage.json";
+
+/// What a reviewer decided about a difference
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Disposition {
+    /// An expected or harmless change
+    Accepted,
+    /// A defect of the migration
+    Bug,
+    /// Needs looking into further
+    NeedsFollowup,
+}
+
+impl fmt::Display for Disposition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Disposition::Accepted => "accepted",
+            Disposition::Bug => "bug",
+            Disposition::NeedsFollowup => "needs-followup",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Annotation {
+    pub state: Disposition,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+}
+
+/// The dispositions reviewers recorded, by difference id, kept across runs so
+/// recurring differences are already classified
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Triage {
+    annotations: BTreeMap<String, Annotation>,
+}
+
+impl Triage {
+    /// Read a triage file, or start an empty one if it doesn't exist yet
+    pub fn load(path: &str) -> Result<Triage, Box<dyn Error>> {
+        if !Path::new(path).exists() {
+            return Ok(Triage::default());
+        }
+
+        let text = fs::read_to_string(path).map_err(|e| format!("Failed reading triage {}: {}", path, e))?;
+        Ok(serde_json::from_str(&text).map_err(|e| format!("Invalid triage {}: {}", path, e))?)
+    }
+
+    /// Write the triage file, replacing it in one rename
+    pub fn save(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        let partial = format!("{}.partial", path);
+        fs::write(&partial, serde_json::to_string_pretty(self)?)?;
+        fs::rename(&partial, path)?;
+
+        Ok(())
+    }
+
+    pub fn annotate(&mut self, id: &str, annotation: Annotation) {
+        self.annotations.insert(id.to_string(), annotation);
+    }
+
+    /// Set the disposition of each difference that was triaged before
+    pub fn classify(&self, records: &mut [DifferenceRecord]) {
+        if self.annotations.is_empty() {
+            return;
+        }
+        records.iter_mut().for_each(|r| r.triage = self.annotations.get(&r.id()).map(|a| a.state));
+    }
+}
+
+/// Whether a JSON report (written with --output json:<path>) has a difference
+/// with the id
+pub fn report_has(path: &str, id: &str) -> Result<bool, Box<dyn Error>> {
+    Ok(report_record(path, id)?.is_some())
+}
+
+/// The start of the line of a JSON report that holds the run's metadata
+const METADATA: &str = "{\"metadata\":";
+
+/// The difference of a JSON report with the id
+pub fn report_record(path: &str, id: &str) -> Result<Option<DifferenceRecord>, Box<dyn Error>> {
+    for record in report_records(path)? {
+        let record = record?;
+        if record.id() == id {
+            return Ok(Some(record));
+        }
+    }
+
+    Ok(None)
+}
+
+/// The differences of a JSON report, read a line at a time as reports can be
+/// large
+pub fn report_records(path: &str) -> Result<impl Iterator<Item=Result<DifferenceRecord, Box<dyn Error>>>, Box<dyn Error>> {
+    let file = File::open(path).map_err(|e| format!("Failed opening {}: {}", path, e))?;
+
+    Ok(BufReader::new(file).lines().filter_map(|line| {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => return Some(Err(e.into())),
+        };
+        let record = line.trim_end_matches(',');
+        match record.starts_with('{') && !record.starts_with(METADATA) {
+            true => Some(serde_json::from_str::<DifferenceRecord>(record).map_err(|e| e.into())),
+            false => None,
+        }
+    }))
+}
+
+/// The metadata of the run a JSON report was written by, if it has any
+pub fn report_metadata(path: &str) -> Result<Option<RunMetadata>, Box<dyn Error>> {
+    let file = File::open(path).map_err(|e| format!("Failed opening {}: {}", path, e))?;
+
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        if line.starts_with(METADATA) {
+            let mut record = serde_json::from_str::<serde_json::Value>(line.trim_end_matches(','))?;
+            return Ok(Some(serde_json::from_value(record["metadata"].take())?));
+        }
+    }
+
+    Ok(None)
+}
@@ -0,0 +1,92 @@
+use itertools::Itertools;
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+use crate::fixture::ParseError;
+use crate::metadata::Labels;
+
+/// How a record that failed to parse compares with the other export's record
+/// of the same pk
+#[derive(Debug, PartialEq)]
+enum Comparison {
+    /// The same JSON once canonicalized
+    Identical,
+    Differs,
+    /// The other export's record parsed, or it has no record of the pk
+    OnlyOld,
+    OnlyNew,
+}
+
+/// The records of either export that failed to parse, by pk, compared by
+/// their raw JSON rather than left out, so records that are unparseable but
+/// identical on both sides aren't taken for differences
+#[derive(Debug, Default)]
+pub struct Unparsed {
+    records: BTreeMap<i64, (Option<i64>, Comparison)>,
+    /// Records too broken to give their pk, which can't be paired
+    unpaired: (usize, usize),
+}
+
+impl Unparsed {
+    pub fn compare(old: &[ParseError], new: &[ParseError]) -> Unparsed {
+        let by_pk = |errors: &[ParseError]| errors.iter()
+            .filter_map(|e| Some((e.pk?, (e.patient, e.raw.as_deref().map(canonical)))))
+            .collect::<BTreeMap<i64, (Option<i64>, Option<String>)>>();
+        let (mut old_records, new_records) = (by_pk(old), by_pk(new));
+        let unpaired = (old.len() - old_records.len(), new.len() - new_records.len());
+
+        let mut records = BTreeMap::new();
+        for (pk, (patient, new)) in new_records {
+            let comparison = match old_records.remove(&pk) {
+                Some((_, old)) if old.is_some() && old == new => Comparison::Identical,
+                Some(_) => Comparison::Differs,
+                None => Comparison::OnlyNew,
+            };
+            records.insert(pk, (patient, comparison));
+        }
+        records.extend(old_records.into_iter().map(|(pk, (patient, _))| (pk, (patient, Comparison::OnlyOld))));
+
+        Unparsed { records, unpaired }
+    }
+
+    /// Print how each record that failed to parse compares with the other side's
+    pub fn print(&self, labels: &Labels) {
+        let count = |comparison: Comparison| self.records.values().filter(|(_, c)| *c == comparison).count();
+        println!("Compared the raw JSON of records that failed to parse in both: {} identical, {} differ",
+            count(Comparison::Identical), count(Comparison::Differs));
+
+        self.records.iter().for_each(|(pk, (patient, comparison))| {
+            let comparison = match comparison {
+                Comparison::Identical => "identical in both".to_string(),
+                Comparison::Differs => "differs".to_string(),
+                Comparison::OnlyOld => format!("failed to parse only in {}", labels.old),
+                Comparison::OnlyNew => format!("failed to parse only in {}", labels.new),
+            };
+            let patient = patient.map_or("?".to_string(), |p| p.to_string());
+            println!("  pk {}, patient {}: {}", pk, patient, comparison);
+        });
+        if self.unpaired != (0, 0) {
+            println!("  {} records in {} and {} in {} aren't JSON, so weren't paired", self.unpaired.0, labels.old, self.unpaired.1, labels.new);
+        }
+    }
+}
+
+/// The JSON of a record with its objects' keys sorted and without whitespace,
+/// or the text trimmed if it isn't JSON
+fn canonical(text: &str) -> String {
+    fn write(value: &Value) -> String {
+        match value {
+            Value::Object(map) => format!("{{{}}}", map.iter()
+                .sorted_by(|(k1, _), (k2, _)| k1.cmp(k2))
+                .map(|(k, v)| format!("{}:{}", Value::String(k.clone()), write(v)))
+                .join(",")),
+            Value::Array(values) => format!("[{}]", values.iter().map(write).join(",")),
+            value => value.to_string(),
+        }
+    }
+
+    match serde_json::from_str::<Value>(text) {
+        Ok(value) => write(&value),
+        Err(_) => text.trim().to_string(),
+    }
+}
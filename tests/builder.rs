@@ -0,0 +1,147 @@
+use diffmig::builder::{self, ClinicalDatumBuilder, SectionBuilder};
+use diffmig::clinical_data::CDEValue;
+use diffmig::diff::{Diff, DiffOptions};
+use diffmig::report::{DifferenceKind, DifferenceRecord};
+
+fn string(s: &str) -> CDEValue {
+    CDEValue::String(s.to_string())
+}
+
+/// The difference records of a patient's datum with a section on each side
+fn diff(old: SectionBuilder, new: SectionBuilder, options: &DiffOptions) -> Vec<DifferenceRecord> {
+    let old = builder::slice(10, vec![ClinicalDatumBuilder::new(1, 10).section("FormA", old).build()]);
+    let new = builder::slice(10, vec![ClinicalDatumBuilder::new(1, 10).section("FormA", new).build()]);
+    old.diff(&new, options).unwrap_or_default().iter().flat_map(|d| d.records()).collect()
+}
+
+/// The kind of the only difference of a CDE's values
+fn kind(old: CDEValue, new: CDEValue) -> DifferenceKind {
+    let records = diff(SectionBuilder::new("sec1").cde("CDE1", old), SectionBuilder::new("sec1").cde("CDE1", new), &DiffOptions::default());
+    assert_eq!(records.len(), 1, "{:?}", records);
+    records[0].kind
+}
+
+#[test]
+fn same_data_has_no_differences() {
+    let section = || SectionBuilder::new("sec1").cde("CDEAge", CDEValue::Number(32.0)).cde("CDENote", string("hello"));
+    assert!(diff(section(), section(), &DiffOptions::default()).is_empty());
+}
+
+#[test]
+fn changed_value_is_located() {
+    let records = diff(
+        SectionBuilder::new("sec1").cde("CDEAge", CDEValue::Number(32.0)).cde("CDENote", string("hello")),
+        SectionBuilder::new("sec1").cde("CDEAge", CDEValue::Number(33.0)).cde("CDENote", string("hello")),
+        &DiffOptions::default(),
+    );
+
+    assert_eq!(records.len(), 1);
+    let record = &records[0];
+    assert_eq!(record.kind, DifferenceKind::Equality);
+    assert_eq!((record.location.patient, record.location.ids.as_str()), (10, "1"));
+    assert_eq!(record.location.form.as_deref(), Some("FormA"));
+    assert_eq!(record.location.section.as_deref(), Some("sec1"));
+    assert_eq!(record.location.cde.as_deref(), Some("CDEAge"));
+}
+
+#[test]
+fn prefix_of_a_string_is_truncated() {
+    assert_eq!(kind(string("Melbourne"), string("Melb")), DifferenceKind::Truncated);
+}
+
+#[test]
+fn prefix_of_a_number_is_not_truncated() {
+    assert_eq!(kind(string("10"), string("100")), DifferenceKind::Equality);
+}
+
+#[test]
+fn same_number_written_differently_is_formatting() {
+    assert_eq!(kind(string("1.50"), string("1.5")), DifferenceKind::Formatting);
+    assert_eq!(kind(string("1.5"), CDEValue::Number(1.5)), DifferenceKind::Formatting);
+}
+
+#[test]
+fn formatting_precision_is_a_tolerance() {
+    let section = |value: &str| SectionBuilder::new("sec1").cde("CDE1", string(value));
+    let options = DiffOptions { formatting_precision: Some(6), ..DiffOptions::default() };
+
+    let records = diff(section("0.1000000001"), section("0.1"), &options);
+    assert_eq!(records.iter().map(|r| r.kind).collect::<Vec<_>>(), vec![DifferenceKind::Formatting]);
+    let records = diff(section("0.1000000001"), section("0.1"), &DiffOptions::default());
+    assert_eq!(records.iter().map(|r| r.kind).collect::<Vec<_>>(), vec![DifferenceKind::Equality]);
+}
+
+#[test]
+fn missing_cde_is_reported() {
+    let records = diff(
+        SectionBuilder::new("sec1").cde("CDE1", string("a")).cde("CDE2", string("b")),
+        SectionBuilder::new("sec1").cde("CDE1", string("a")),
+        &DiffOptions::default(),
+    );
+
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].kind, DifferenceKind::Missing);
+    assert_eq!(records[0].location.cde.as_deref(), Some("CDE2"));
+}
+
+#[test]
+fn rows_of_multiple_sections_are_compared() {
+    let rows = |second: &str| SectionBuilder::multiple("msec")
+        .row().cde("CDEM", string("a"))
+        .row().cde("CDEM", string(second));
+
+    assert!(diff(rows("b"), rows("b"), &DiffOptions::default()).is_empty());
+    assert!(!diff(rows("b"), rows("c"), &DiffOptions::default()).is_empty());
+}
+
+#[test]
+fn rows_of_a_single_section_are_one_row() {
+    let old = SectionBuilder::new("sec1").cde("CDE1", string("a")).row().cde("CDE2", string("b"));
+    let new = SectionBuilder::new("sec1").cde("CDE1", string("a")).cde("CDE2", string("c"));
+    let records = diff(old, new, &DiffOptions::default());
+
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].location.cde.as_deref(), Some("CDE2"));
+}
+
+#[test]
+fn swaps_are_reported_under_their_first_code() {
+    let section = |values: [&str; 5]| values.iter().enumerate()
+        .fold(SectionBuilder::new("sec1"), |s, (i, v)| s.cde(&format!("CDE{}", i + 1), string(v)));
+    // CDE1 and CDE4 are swapped, as are CDE2 and CDE3, and CDE5 changed
+    let records = diff(section(["a", "b", "c", "d", "e"]), section(["d", "c", "b", "a", "f"]), &DiffOptions::default());
+
+    let mut swapped = records.iter().filter(|r| r.kind == DifferenceKind::SwappedValues).filter_map(|r| r.location.cde.as_deref()).collect::<Vec<_>>();
+    swapped.sort();
+    assert_eq!(swapped, ["CDE1", "CDE2"]);
+}
+
+#[test]
+fn calculated_cdes_keep_formatting_and_truncation() {
+    let options = DiffOptions { calculated: vec!["CDEScore".to_string()].into_iter().collect(), ..DiffOptions::default() };
+    let kind = |old, new| {
+        let records = diff(SectionBuilder::new("sec1").cde("CDEScore", old), SectionBuilder::new("sec1").cde("CDEScore", new), &options);
+        records.iter().map(|r| r.kind).collect::<Vec<_>>()
+    };
+
+    assert_eq!(kind(string("12"), string("13")), [DifferenceKind::Calculated]);
+    assert_eq!(kind(string("12"), CDEValue::Bool(true)), [DifferenceKind::Calculated]);
+    assert_eq!(kind(string("1.50"), string("1.5")), [DifferenceKind::Formatting]);
+    assert_eq!(kind(string("Melbourne"), string("Melb")), [DifferenceKind::Truncated]);
+}
+
+#[test]
+fn timestamps_regress_by_instant() {
+    let options = DiffOptions { timestamps: true, ..DiffOptions::default() };
+    let regressed = |old: &str, new: &str| {
+        let old = builder::slice(10, vec![ClinicalDatumBuilder::new(1, 10).timestamp(old).build()]);
+        let new = builder::slice(10, vec![ClinicalDatumBuilder::new(1, 10).timestamp(new).build()]);
+        old.diff(&new, &options).unwrap_or_default().iter().flat_map(|d| d.records()).any(|r| r.kind == DifferenceKind::Timestamp)
+    };
+
+    assert!(!regressed("2021-03-01T00:00:00Z", "2021-03-01T10:00:00+10:00"));
+    assert!(regressed("2021-03-01T00:00:00Z", "2021-03-01T09:00:00+10:00"));
+    assert!(!regressed("2021-03-01T00:00:00.5Z", "2021-03-01T00:00:00.50Z"));
+    assert!(regressed("2021-03-01T00:00:00.50Z", "2021-03-01T00:00:00.1Z"));
+    assert!(!regressed("2021-03-01 00:00:00", "2021-03-01T00:00:01"));
+}
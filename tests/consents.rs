@@ -0,0 +1,35 @@
+use diffmig::consents::{self, ConsentFixtures};
+
+#[test]
+fn timestamps_are_compared_as_instants() {
+    assert!(!consents::timestamps_differ("2021-03-01T10:00:00+10:00", "2021-03-01T00:00:00Z", 0));
+    assert!(!consents::timestamps_differ("2021-03-01 00:00:00", "2021-03-01T00:00:00+00:00", 0));
+    assert!(!consents::timestamps_differ("2021-03-01T00:00:00.5Z", "2021-03-01T00:00:00.500000Z", 0));
+    assert!(consents::timestamps_differ("2021-03-01T00:00:00.5Z", "2021-03-01T00:00:00.6Z", 0));
+    assert!(!consents::timestamps_differ("2021-03-01T00:00:00.5Z", "2021-03-01T00:00:01.4Z", 1));
+    assert!(consents::timestamps_differ("2021-03-01T10:00:00+10:00", "2021-03-01T10:00:00Z", 3600));
+}
+
+#[test]
+fn dates_and_naive_times_are_utc() {
+    assert_eq!(consents::seconds("1970-01-02"), Some(86400));
+    assert_eq!(consents::seconds("1970-01-01T01:00:00.999"), Some(3600));
+    assert_eq!(consents::seconds("1970-01-01T10:00:00+10:00"), Some(0));
+    assert_eq!(consents::seconds("yesterday"), None);
+}
+
+#[test]
+fn duplicate_consent_values_are_rejected() {
+    // Fixtures are read as Django dumps them, a record at a time
+    let record = |model: &str, pk: i64, fields: &str| format!("    {{\n        \"model\": \"{}\",\n        \"pk\": {},\n        \"fields\": {}\n    }},", model, pk, fields);
+    let fixture = ["[".to_string(),
+        record("patients.consentquestion", 1, r#"{"code": "c1"}"#),
+        record("patients.consentvalue", 10, r#"{"patient": 5, "consent_question": 1, "answer": true}"#),
+        record("patients.consentvalue", 11, r#"{"patient": 5, "consent_question": 1, "answer": false}"#),
+        "]".to_string()].join("\n");
+    let mut fixtures = ConsentFixtures::default();
+    fixtures.read(fixture.as_bytes()).unwrap();
+
+    let error = fixtures.consents().unwrap_err();
+    assert_eq!((error.pk, error.patient), (Some(11), Some(5)));
+}
@@ -0,0 +1,63 @@
+use diffmig::diff_report::DiffReport;
+use diffmig::generate::FixtureSpec;
+use diffmig::metadata::RunMetadata;
+use diffmig::report::{DifferenceKind, DifferenceRecord};
+
+/// The differences injected into a small fixture, some of changed values and some of removed CDEs
+fn records() -> Vec<DifferenceRecord> {
+    let spec = FixtureSpec { registry_code: "gen".to_string(), patients: 10, forms: 2, sections: 2, multi_sections: 0, cdes: 4, differences: 12, seed: 7 };
+    spec.generate().unwrap().injected
+}
+
+/// The report as diffmig writes it, and read back
+fn round_trip(report: &DiffReport) -> DiffReport {
+    let mut json = vec![];
+    report.to_writer(&mut json).unwrap();
+    DiffReport::from_reader(json.as_slice()).unwrap()
+}
+
+#[test]
+fn report_reads_back_as_written() {
+    let metadata = RunMetadata { registry: Some("gen".to_string()), ..RunMetadata::default() };
+    let report = DiffReport { records: records(), metadata: Some(metadata) };
+    let read = round_trip(&report);
+
+    assert_eq!(read.len(), report.len());
+    assert_eq!(read.iter().map(DifferenceRecord::id).collect::<Vec<_>>(), report.iter().map(DifferenceRecord::id).collect::<Vec<_>>());
+    assert_eq!(read.metadata.and_then(|m| m.registry).as_deref(), Some("gen"));
+}
+
+#[test]
+fn report_without_metadata_reads_back() {
+    let read = round_trip(&DiffReport { records: records(), metadata: None });
+
+    assert!(read.metadata.is_none());
+    assert_eq!(read.len(), 12);
+}
+
+#[test]
+fn differences_are_queried() {
+    let report = round_trip(&DiffReport { records: records(), ..DiffReport::default() });
+
+    let patients = report.patients_with_diffs();
+    assert!(!patients.is_empty());
+    assert_eq!(patients.iter().map(|p| report.diffs_for_patient(*p).count()).sum::<usize>(), report.len());
+
+    let kinds = [DifferenceKind::Equality, DifferenceKind::Missing];
+    assert_eq!(kinds.iter().map(|k| report.diffs_of_kind(*k).count()).sum::<usize>(), report.len());
+
+    let cde = report.records[0].location.cde.clone().unwrap();
+    assert!(report.diffs_for_cde(&cde).all(|r| r.location.cde.as_deref() == Some(cde.as_str())));
+    assert!(report.diffs_for_cde("CDEMissing").next().is_none());
+
+    let id = report.records[3].id();
+    assert_eq!(report.diff_with_id(&id).map(DifferenceRecord::id), Some(id));
+    assert!(report.diff_with_id("0000").is_none());
+}
+
+#[test]
+fn invalid_element_is_reported() {
+    let error = DiffReport::from_reader(r#"[{"location": 1}]"#.as_bytes()).unwrap_err();
+
+    assert!(error.to_string().starts_with("element 0:"), "{}", error);
+}
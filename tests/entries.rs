@@ -0,0 +1,31 @@
+use diffmig::entries::Entries;
+
+/// A fixture as Django dumps it, of records with the pks given
+fn fixture(pks: &[u32]) -> String {
+    let records = pks.iter().map(|pk| format!("    {{\n        \"model\": \"rdrf.clinicaldata\",\n        \"pk\": {}\n    }}", pk)).collect::<Vec<_>>();
+    match records.is_empty() {
+        true => "[]\n".to_string(),
+        false => format!("[\n{}\n]\n", records.join(",\n")),
+    }
+}
+
+#[test]
+fn records_are_located_in_their_entries() {
+    let entries = Entries::default();
+    entries.read(&["a.json".to_string(), "b.json".to_string(), "c.json".to_string(), "d.json".to_string()]);
+    // Entries are read one after another, each followed by a newline
+    let text = [fixture(&[1, 2]), fixture(&[]), fixture(&[3, 4, 5]), fixture(&[6])].join("\n");
+
+    let records = entries.records(text.as_bytes()).collect::<Vec<String>>();
+    assert_eq!(records.len(), 6);
+    assert!(records[2].contains("\"pk\": 3"));
+
+    let located = (0..6).map(|i| entries.locate(i).map(|(entry, position)| (entry.to_string(), position))).collect::<Vec<_>>();
+    let at = |entry: &str, position| Some((entry.to_string(), position));
+    assert_eq!(located, [at("a.json", 0), at("a.json", 1), at("c.json", 0), at("c.json", 1), at("c.json", 2), at("d.json", 0)]);
+}
+
+#[test]
+fn records_of_one_entry_are_not_located() {
+    assert_eq!(Entries::default().locate(0), None);
+}
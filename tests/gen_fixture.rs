@@ -0,0 +1,50 @@
+use diffmig::diff_report::DiffReport;
+use diffmig::report::DifferenceRecord;
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+/// Generate a fixture with the binary, diff it, and return the ids of the
+/// differences injected and those found
+fn injected_and_found(name: &str, args: &[&str]) -> (Vec<String>, Vec<String>) {
+    let dir = std::env::temp_dir().join(format!("diffmig-{}-{}", name, std::process::id()));
+    let path = |name: &str| -> PathBuf { dir.join(name) };
+    let run = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_diffmig")).args(args).stdin(Stdio::null()).output().unwrap();
+        assert!(output.status.success(), "diffmig {:?} failed: {}", args, String::from_utf8_lossy(&output.stderr));
+    };
+
+    run(&[&["gen-fixture", dir.to_str().unwrap()], args].concat());
+    run(&[
+        "diff", path("old.zip").to_str().unwrap(), path("new.zip").to_str().unwrap(),
+        "--output", &format!("json:{}", path("report.json").display()),
+        "--run-manifest", path("run-manifest.json").to_str().unwrap(),
+    ]);
+
+    let ids = |name: &str| {
+        let mut ids = DiffReport::open(path(name).to_str().unwrap()).unwrap().iter().map(DifferenceRecord::id).collect::<Vec<String>>();
+        ids.sort();
+        ids
+    };
+    let (injected, found) = (ids("injected.json"), ids("report.json"));
+    fs::remove_dir_all(&dir).unwrap();
+
+    (injected, found)
+}
+
+#[test]
+fn diff_finds_exactly_the_injected_differences() {
+    let (injected, found) = injected_and_found("fixture", &["--patients", "30", "--differences", "8", "--seed", "3"]);
+
+    assert_eq!(injected.len(), 8);
+    assert_eq!(found, injected);
+}
+
+#[test]
+fn diff_finds_exactly_the_injected_differences_of_other_seeds() {
+    for seed in ["1", "2", "42"] {
+        let (injected, found) = injected_and_found(&format!("seed-{}", seed), &["--patients", "50", "--differences", "20", "--seed", seed]);
+
+        assert_eq!(found, injected, "seed {}", seed);
+    }
+}
@@ -0,0 +1,69 @@
+use clap::Parser;
+use diffmig::cli::{Cli, Command};
+use diffmig::generate::{self, FixtureSpec};
+use diffmig::observer::DiffObserver;
+use diffmig::report::{DifferenceRecord, Summary};
+use diffmig::run;
+use std::error::Error;
+use std::fs;
+use std::sync::{Arc, Mutex};
+
+/// The events of a diff an observer is given, in order
+#[derive(Debug, Clone, Default)]
+struct Recorder(Arc<Mutex<Vec<String>>>);
+
+impl DiffObserver for Recorder {
+    fn on_patient_start(&mut self, patient: u32, _ids: &str) -> Result<(), Box<dyn Error>> {
+        self.0.lock().unwrap().push(format!("start {}", patient));
+        Ok(())
+    }
+
+    fn on_difference(&mut self, _patient: u32, difference: &DifferenceRecord) -> Result<(), Box<dyn Error>> {
+        self.0.lock().unwrap().push(format!("difference {}", difference.id()));
+        Ok(())
+    }
+
+    fn on_patient_complete(&mut self, patient: u32, _ids: &str, differences: usize) -> Result<(), Box<dyn Error>> {
+        self.0.lock().unwrap().push(format!("complete {} {}", patient, differences));
+        Ok(())
+    }
+
+    fn on_finished(&mut self, summary: &Summary) -> Result<(), Box<dyn Error>> {
+        self.0.lock().unwrap().push(format!("finished {} {}", summary.patients, summary.differences));
+        Ok(())
+    }
+}
+
+#[test]
+fn observers_follow_diffs() {
+    let dir = std::env::temp_dir().join(format!("diffmig-observer-{}", std::process::id()));
+    let path = |name: &str| dir.join(name).to_string_lossy().into_owned();
+    let spec = FixtureSpec { registry_code: "gen".to_string(), patients: 4, forms: 2, sections: 2, multi_sections: 1, cdes: 3, differences: 3, seed: 1 };
+    let fixture = spec.generate().unwrap();
+    generate::write(&path(""), &fixture).unwrap();
+
+    // Two diffs of one process, as the interrupt handler is only installed once
+    let recorders = (0..2).map(|_| {
+        let cli = Cli::parse_from(["diffmig", "diff", &path("old.zip"), &path("new.zip"), "--group-by", "cde", "--run-manifest", &path("run-manifest.json")]);
+        let args = match cli.command {
+            Command::Diff(args) => *args,
+            _ => unreachable!(),
+        };
+        let recorder = Recorder::default();
+        run::diff(args, None, vec![Box::new(recorder.clone())]).unwrap();
+        recorder
+    }).collect::<Vec<Recorder>>();
+    fs::remove_dir_all(&dir).unwrap();
+
+    let mut injected = fixture.injected.iter().map(DifferenceRecord::id).collect::<Vec<String>>();
+    injected.sort();
+    for recorder in recorders {
+        let events = recorder.0.lock().unwrap().clone();
+        let starts = events.iter().filter(|e| e.starts_with("start ")).collect::<Vec<_>>();
+        assert_eq!(starts, ["start 1", "start 2", "start 3", "start 4"]);
+        let mut differences = events.iter().filter_map(|e| e.strip_prefix("difference ")).collect::<Vec<_>>();
+        differences.sort();
+        assert_eq!(differences, injected);
+        assert_eq!(events.last().unwrap(), &format!("finished 4 {}", injected.len()));
+    }
+}
@@ -0,0 +1,60 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Run the binary, returning what it printed
+fn run(args: &[&str]) -> (String, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_diffmig")).args(args).stdin(Stdio::null()).output().unwrap();
+    assert!(output.status.success(), "diffmig {:?} failed: {}", args, String::from_utf8_lossy(&output.stderr));
+    (String::from_utf8_lossy(&output.stdout).into_owned(), String::from_utf8_lossy(&output.stderr).into_owned())
+}
+
+/// Diff a generated fixture into an SQLite report, then diff it again
+/// --since that report with the extra args, returning what the second run printed
+fn diff_again(name: &str, args: &[&str]) -> (String, String) {
+    let dir = std::env::temp_dir().join(format!("diffmig-since-{}-{}", name, std::process::id()));
+    let path = |name: &str| -> PathBuf { dir.join(name) };
+    let arg = |path: &Path| path.to_str().unwrap().to_string();
+
+    run(&["gen-fixture", &arg(&dir), "--patients", "20", "--differences", "6", "--seed", "5"]);
+    let diff = |output: &str, since: &[&str], args: &[&str]| {
+        let (old, new, manifest) = (arg(&path("old.zip")), arg(&path("new.zip")), arg(&path("run-manifest.json")));
+        let output = format!("sqlite:{}", path(output).display());
+        run(&[&["diff", &old, &new, "--group-by", "cde", "--output", &output, "--run-manifest", &manifest], since, args].concat())
+    };
+
+    diff("first.db", &[], &[]);
+    let printed = diff("second.db", &["--since", &arg(&path("first.db"))], args);
+    fs::remove_dir_all(&dir).unwrap();
+
+    printed
+}
+
+/// The number of slices a run reused the differences of
+fn reused(stdout: &str) -> usize {
+    let line = stdout.lines().find(|l| l.starts_with("Reused the differences of ")).expect("no reused slices line");
+    line["Reused the differences of ".len()..].split(' ').next().unwrap().parse().unwrap()
+}
+
+#[test]
+fn unchanged_slices_are_reused() {
+    let (stdout, stderr) = diff_again("same", &[]);
+
+    assert!(reused(&stdout) > 0, "{}", stdout);
+    assert!(!stderr.contains("compared again"), "{}", stderr);
+}
+
+#[test]
+fn slices_are_not_reused_after_an_option_changes() {
+    let (stdout, stderr) = diff_again("tolerance", &["--tolerance", "0.5"]);
+
+    assert_eq!(reused(&stdout), 0);
+    assert!(stderr.contains("so every patient is compared again"), "{}", stderr);
+}
+
+#[test]
+fn slices_are_reused_whatever_the_reports_look_like() {
+    let (stdout, _) = diff_again("labels", &["--old-label", "before", "--flat"]);
+
+    assert!(reused(&stdout) > 0, "{}", stdout);
+}